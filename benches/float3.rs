@@ -0,0 +1,39 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rayt::*;
+use std::sync::Arc;
+
+fn float3(c: &mut Criterion) {
+    let u = Vec3::new(0.3, -1.2, 2.5);
+    let v = Vec3::new(-0.7, 0.4, 1.1);
+    c.bench_function("add", |b| b.iter(|| black_box(u) + black_box(v)));
+    c.bench_function("mul", |b| b.iter(|| black_box(u) * black_box(v)));
+    c.bench_function("dot", |b| b.iter(|| black_box(u).dot(black_box(v))));
+    c.bench_function("cross", |b| b.iter(|| black_box(u).cross(black_box(v))));
+    c.bench_function("length", |b| b.iter(|| black_box(u).length()));
+    c.bench_function("normalize", |b| b.iter(|| black_box(u).normalize()));
+    c.bench_function("lerp", |b| b.iter(|| black_box(u).lerp(black_box(v), 0.25)));
+}
+
+// Float3 の演算がほとんどを占める Sphere::hit (simd フィーチャーの有無で比べる)
+fn sphere(c: &mut Criterion) {
+    let rays = (0..256)
+        .map(|i| {
+            let t = i as f64 / 256.0;
+            Ray::new(Point3::zero(), Vec3::new(t - 0.5, 0.5 - t * t, -1.0))
+        })
+        .collect::<Vec<_>>();
+    let material: Arc<dyn Material> = Arc::new(Lambertian::new(Box::new(ColorTexture::new(
+        Color::fill(0.5),
+    ))));
+    let sphere = Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, material);
+    c.bench_function("sphere_hit", |b| {
+        b.iter(|| {
+            rays.iter()
+                .filter_map(|ray| sphere.hit(black_box(ray), EPS, f64::MAX))
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, float3, sphere);
+criterion_main!(benches);
@@ -0,0 +1,87 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rayt::*;
+use std::sync::Arc;
+
+// 原点から -z 方向へ扇形に広げた光線
+fn fan(count: usize) -> Vec<Ray> {
+    (0..count)
+        .map(|i| {
+            let t = i as f64 / count as f64;
+            Ray::new(Point3::zero(), Vec3::new(t - 0.5, 0.5 - t * t, -1.0))
+        })
+        .collect()
+}
+
+fn material() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Box::new(ColorTexture::new(Color::fill(
+        0.5,
+    )))))
+}
+
+// 光線をすべて当ててみて、当たった数を返す
+fn hits(shape: &dyn Shape, rays: &[Ray]) -> usize {
+    rays.iter()
+        .filter_map(|ray| shape.hit(black_box(ray), EPS, f64::MAX))
+        .count()
+}
+
+fn primitives(c: &mut Criterion) {
+    let rays = fan(256);
+    let sphere = Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, material());
+    // float3 の sphere_hit と結果の置き場所がぶつからないように名前を変える
+    c.bench_function("shape_sphere_hit", |b| b.iter(|| hits(&sphere, &rays)));
+
+    let triangle = Triangle::new(
+        [
+            Point3::new(-0.5, -0.5, -1.0),
+            Point3::new(0.5, -0.5, -1.0),
+            Point3::new(0.0, 0.5, -1.0),
+        ],
+        None,
+        None,
+        material(),
+    );
+    c.bench_function("triangle_hit", |b| b.iter(|| hits(&triangle, &rays)));
+
+    // 6 枚の長方形を順に当てる
+    let cube = Box3D::new(
+        Point3::new(-0.5, -0.5, -1.5),
+        Point3::new(0.5, 0.5, -0.5),
+        material(),
+    );
+    c.bench_function("box_hit", |b| b.iter(|| hits(&cube, &rays)));
+
+    // 外接球で外れる光線を先に落としてから、三角形を 1 枚ずつ当てる
+    let n = 16;
+    let grid = |i: usize, j: usize| {
+        Point3::new(i as f64 / n as f64 - 0.5, j as f64 / n as f64 - 0.5, -1.0)
+    };
+    let triangles = (0..n)
+        .flat_map(|i| (0..n).map(move |j| (i, j)))
+        .flat_map(|(i, j)| {
+            [
+                [grid(i, j), grid(i + 1, j), grid(i + 1, j + 1)],
+                [grid(i, j), grid(i + 1, j + 1), grid(i, j + 1)],
+            ]
+        })
+        .map(|p| Triangle::new(p, None, None, material()))
+        .collect();
+    let mesh = Mesh::new(triangles);
+    c.bench_function("mesh_hit", |b| b.iter(|| hits(&mesh, &rays)));
+}
+
+// RandomScene の形状すべてに、カメラからの一次光線を当てる
+fn traversal(c: &mut Criterion) {
+    let scene = RandomScene::new(Some(1)).unwrap();
+    let camera = scene.camera();
+    reseed(1);
+    let rays = (0..1024)
+        .map(|i| camera.ray((i % 32) as f64 / 31.0, (i / 32) as f64 / 31.0))
+        .collect::<Vec<_>>();
+    c.bench_function("random_scene_world_hit", |b| {
+        b.iter(|| hits(scene.world(), &rays))
+    });
+}
+
+criterion_group!(benches, primitives, traversal);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rayt::*;
+
+// RandomScene の先頭のタイルを 1 spp ずつ描く。乱数を引く回数が多いので、乱数の速さがそのまま出る
+fn random_scene(c: &mut Criterion) {
+    let mut group = c.benchmark_group("random_scene");
+    group.sample_size(10);
+    group.bench_function("tiles", |b| {
+        b.iter_batched(
+            || ProgressiveCanvas::new(RandomScene::new(Some(1)).unwrap()),
+            |mut canvas| canvas.step(4),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, random_scene);
+criterion_main!(benches);
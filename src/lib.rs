@@ -1,5 +1,4 @@
 mod rayt;
 pub use crate::rayt::*;
 
-pub const IMAGE_WIDTH: u32 = 200;
-pub const IMAGE_HEIGHT: u32 = IMAGE_WIDTH / 2;
+pub mod prelude;
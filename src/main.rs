@@ -1,1028 +1,727 @@
-#![allow(dead_code)]
-
-mod rayt;
-
+#[cfg(feature = "window")]
 use std::sync::Arc;
 
-use crate::rayt::*;
-
-struct HitInfo {
-    t: f64,
-    p: Point3,
-    n: Vec3,
-    m: Arc<dyn Material>,
-    u: f64,
-    v: f64,
-}
-
-impl HitInfo {
-    const fn new(t: f64, p: Point3, n: Vec3, m: Arc<dyn Material>, u: f64, v: f64) -> Self {
-        Self { t, p, n, m, u, v }
-    }
-}
-
-trait Shape: Send + Sync {
-    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo>;
-}
-
-struct Sphere {
-    center: Point3,
-    radius: f64,
-    material: Arc<dyn Material>,
-}
-
-impl Sphere {
-    const fn new(center: Point3, radius: f64, material: Arc<dyn Material>) -> Self {
-        Self {
-            center,
-            radius,
-            material,
-        }
-    }
-
-    fn uv(p: Point3) -> (f64, f64) {
-        let phi = p.z().atan2(p.x());
-        let theta = p.y().asin();
-        (1.0 - (phi + PI) / (2.0 * PI), (theta + PI / 2.0) / PI)
-    }
-}
-
-impl Shape for Sphere {
-    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        let oc = ray.origin - self.center;
-        let a = ray.direction.dot(ray.direction);
-        let b = 2.0 * ray.direction.dot(oc);
-        let c = oc.dot(oc) - self.radius.powi(2);
-        let d = b * b - 4.0 * a * c;
-        if d > 0.0 {
-            // こちらの解のほうが始点に近いので先に判定
-            let temp = (-b - d.sqrt()) / (2.0 * a);
-            if t0 < temp && temp < t1 {
-                let p = ray.at(temp);
-                let n = (p - self.center) / self.radius;
-                let (u, v) = Self::uv(n);
-                return Some(HitInfo::new(temp, p, n, Arc::clone(&self.material), u, v));
-            }
-            // 始点から近いほうの解が光線の衝突範囲含まれないときは遠い方の解を評価
-            let temp = (-b + d.sqrt()) / (2.0 * a);
-            if t0 < temp && temp < t1 {
-                let p = ray.at(temp);
-                let n = (p - self.center) / self.radius;
-                let (u, v) = Self::uv(n);
-                return Some(HitInfo::new(temp, p, n, Arc::clone(&self.material), u, v));
+use image::RgbImage;
+use rayt::*;
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::Invalid(message.into())
+}
+
+// 値を取るオプションの値がなければ usage をエラーにする
+fn required<'a>(value: Option<&'a str>, usage: &str) -> Result<&'a str, Error> {
+    value.ok_or_else(|| invalid(usage))
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, arg: &str) -> Result<T, Error> {
+    arg.trim()
+        .parse()
+        .map_err(|_| invalid(format!("{} expects a number, got {:?}", flag, arg)))
+}
+
+// 数値を取るオプション。値がなければ None
+fn parse_option<T: std::str::FromStr>(flag: &str, value: Option<&str>) -> Result<Option<T>, Error> {
+    value.map(|arg| parse_number(flag, arg)).transpose()
+}
+
+fn parse_pixel(arg: &str) -> Result<(u32, u32), Error> {
+    let (x, y) = arg
+        .split_once(',')
+        .ok_or_else(|| invalid("--pixel expects x,y"))?;
+    Ok((parse_number("--pixel", x)?, parse_number("--pixel", y)?))
+}
+
+fn parse_crop(arg: &str) -> Result<Tile, Error> {
+    let values = arg
+        .split(',')
+        .map(|v| parse_number("--crop", v))
+        .collect::<Result<Vec<u32>, Error>>()?;
+    match values[..] {
+        [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => Ok((x0, y0, x1, y1)),
+        _ => Err(invalid("--crop expects x0,y0,x1,y1")),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    pixel: Option<(u32, u32)>,
+    seed: Option<u64>,
+    polarizer: Option<f64>,
+    distortion: LensDistortion,
+    strata: u32,
+    sampler: SamplerKind,
+    clamp: RadianceClamp,
+    photons: usize,
+    glass: Option<f64>,
+    // 光源からの影を透明物体の色で染める (点光源などの形を持たない光源だけ)
+    colored_shadows: bool,
+    stereo: Option<Stereo>,
+    scene: Option<String>,
+    // TOML で書いたシーン (指定すると --scene より優先する)
+    scene_file: Option<String>,
+    // Rhai のスクリプトで組み立てるシーン (script フィーチャーが必要)
+    script: Option<String>,
+    // 描かずにシーンをシーンファイルの形で書き出す
+    export: Option<String>,
+    list_scenes: bool,
+    // 描かずにこのアドレスの --serve からタイルをもらって描く
+    worker: Option<String>,
+    // 描く前にプレビューでカメラを動かして構図を決める
+    fly: bool,
+    // 描画の設定とマテリアルをパネルで調整しながら眺める (保存はしない)
+    tweak: bool,
+    config: RenderConfig,
+}
+
+impl Options {
+    fn parse<S: AsRef<str>>(args: &[S]) -> Result<Self, Error> {
+        let mut options = Self::default();
+        let mut i = 0;
+        while i < args.len() {
+            let flag = args[i].as_ref();
+            let value = args.get(i + 1).map(|arg| arg.as_ref());
+            // 値を取らないフラグは 1 つだけ進める
+            let mut consumed = 2;
+            match flag {
+                "--no-window" => {
+                    options.config.window = false;
+                    consumed = 1;
+                }
+                "--fly" => {
+                    options.fly = true;
+                    consumed = 1;
+                }
+                "--tweak" => {
+                    options.tweak = true;
+                    consumed = 1;
+                }
+                "--colored-shadows" => {
+                    options.colored_shadows = true;
+                    consumed = 1;
+                }
+                // 描画の設定を TOML から読む。それより前に書いた描画の設定は置き換わるので、先頭に書く
+                "--config" => {
+                    let path = required(value, "--config expects a TOML file")?;
+                    options.config = RenderConfig::load(path)?;
+                }
+                "--pixel" => options.pixel = value.map(parse_pixel).transpose()?,
+                "--width" => options.config.width = parse_option(flag, value)?,
+                "--height" => options.config.height = parse_option(flag, value)?,
+                "--samples" => options.config.spp = parse_option(flag, value)?,
+                "--depth" => options.config.max_depth = parse_option(flag, value)?,
+                "--threads" => options.config.threads = parse_option(flag, value)?,
+                "--output" => {
+                    let output = required(value, "--output expects a file name")?;
+                    check_output_format(output)
+                        .map_err(|e| invalid(format!("{}: {}", output, e)))?;
+                    options.config.output = output.to_string();
+                }
+                "--backup" => {
+                    options.config.backup = match value {
+                        Some("back") => Backup::Previous,
+                        Some("timestamp") => Backup::Timestamped,
+                        Some("none") => Backup::Off,
+                        _ => return Err(invalid("--backup expects back, timestamp or none")),
+                    }
+                }
+                "--scene" => options.scene = value.map(String::from),
+                "--scene-file" => options.scene_file = value.map(String::from),
+                "--script" => options.script = value.map(String::from),
+                "--export" => options.export = value.map(String::from),
+                "--list-scenes" => {
+                    options.list_scenes = true;
+                    consumed = 1;
+                }
+                "--crop" => options.config.crop = value.map(parse_crop).transpose()?,
+                "--serve" => options.config.serve = value.map(String::from),
+                "--http" => options.config.http = value.map(String::from),
+                "--worker" => options.worker = value.map(String::from),
+                "--aov" => {
+                    let passes = required(value, "--aov expects normal,depth,albedo,id")?;
+                    options.config.aovs = passes
+                        .split(',')
+                        .map(|pass| {
+                            AovPass::parse(pass.trim())
+                                .ok_or_else(|| invalid(format!("unknown AOV: {}", pass)))
+                        })
+                        .collect::<Result<_, Error>>()?;
+                }
+                "--denoise" => {
+                    options.config.denoise = match value {
+                        Some("bilateral") => Some(Denoiser::default()),
+                        Some("none") => None,
+                        _ => return Err(invalid("--denoise expects bilateral or none")),
+                    }
+                }
+                "--mode" => {
+                    let mode = value.and_then(DebugMode::parse);
+                    options.config.mode =
+                        Some(mode.ok_or_else(|| {
+                            invalid("--mode expects normal, uv, depth or bounces")
+                        })?);
+                }
+                "--metering" => {
+                    let metering = value.and_then(Metering::parse);
+                    options.config.metering = Some(
+                        metering
+                            .ok_or_else(|| invalid("--metering expects off, median or matrix"))?,
+                    );
+                }
+                "--fps" => options.config.fps = parse_option(flag, value)?.unwrap_or(24.0),
+                "--hdr" => {
+                    let hdr = value.filter(|hdr| is_linear_format(hdr));
+                    let hdr = required(hdr, "--hdr expects an .exr or .hdr file name")?;
+                    options.config.hdr = Some(hdr.to_string());
+                }
+                "--strata" => options.strata = parse_option(flag, value)?.unwrap_or(1),
+                "--sampler" => {
+                    options.sampler = match value {
+                        Some("random") => SamplerKind::Random,
+                        Some("pcg") => SamplerKind::Pcg,
+                        Some("halton") => SamplerKind::Halton,
+                        Some("sobol") => SamplerKind::Sobol,
+                        Some("bluenoise") => SamplerKind::BlueNoise,
+                        value => return Err(invalid(format!("unknown sampler: {:?}", value))),
+                    }
+                }
+                // --clamp max または --clamp indirect:max
+                "--clamp" => {
+                    let value = required(value, "--clamp expects max or indirect:max")?;
+                    options.clamp = match value.split_once(':') {
+                        Some(("indirect", max)) => {
+                            RadianceClamp::Indirect(parse_number(flag, max)?)
+                        }
+                        Some((kind, _)) => return Err(invalid(format!("unknown clamp: {}", kind))),
+                        None => RadianceClamp::All(parse_number(flag, value)?),
+                    }
+                }
+                "--photons" => options.photons = parse_option(flag, value)?.unwrap_or(0),
+                "--glass" => options.glass = parse_option(flag, value)?,
+                "--seed" => options.seed = parse_option(flag, value)?,
+                "--polarizer" => options.polarizer = parse_option(flag, value)?,
+                "--working-space" => {
+                    options.config.color.working_space = match value {
+                        Some("srgb") => WorkingSpace::LinearSrgb,
+                        Some("acescg") => WorkingSpace::AcesCg,
+                        Some("aces2065") => WorkingSpace::Aces2065,
+                        value => {
+                            return Err(invalid(format!("unknown working space: {:?}", value)))
+                        }
+                    }
+                }
+                "--tonemap" => {
+                    options.config.color.tone_map = match value {
+                        Some("linear") => ToneMap::Linear,
+                        Some("reinhard") => ToneMap::Reinhard,
+                        Some("aces") => ToneMap::Aces,
+                        _ => return Err(invalid("--tonemap expects linear, reinhard or aces")),
+                    }
+                }
+                "--ev" => options.config.color.exposure = parse_option(flag, value)?.unwrap_or(0.0),
+                "--display" => {
+                    options.config.color.display = match value {
+                        Some("srgb") => DisplayTransform::Srgb,
+                        Some(gamma) => DisplayTransform::Gamma(parse_number(flag, gamma)?),
+                        None => return Err(invalid("--display expects srgb or a gamma value")),
+                    }
+                }
+                // --distortion k1,k2,p1,p2
+                "--distortion" => {
+                    let k = required(value, "--distortion expects k1,k2,p1,p2")?
+                        .split(',')
+                        .map(|k| parse_number(flag, k))
+                        .collect::<Result<Vec<f64>, Error>>()?;
+                    options.distortion = match k[..] {
+                        [k1, k2, p1, p2] => LensDistortion::new(k1, k2, p1, p2),
+                        [k1, k2] => LensDistortion::new(k1, k2, 0.0, 0.0),
+                        _ => return Err(invalid("--distortion expects k1,k2,p1,p2")),
+                    };
+                }
+                // --stereo ipd,convergence[,separate]
+                "--stereo" => {
+                    let usage = "--stereo expects ipd,convergence";
+                    let fields = required(value, usage)?
+                        .split(',')
+                        .map(str::trim)
+                        .collect::<Vec<_>>();
+                    let layout = match fields.get(2) {
+                        None | Some(&"sbs") => StereoLayout::SideBySide,
+                        Some(&"separate") => StereoLayout::Separate,
+                        Some(layout) => {
+                            return Err(invalid(format!("unknown stereo layout: {}", layout)))
+                        }
+                    };
+                    options.stereo = Some(Stereo {
+                        ipd: parse_number(flag, fields[0])?,
+                        convergence: parse_number(
+                            flag,
+                            fields.get(1).ok_or_else(|| invalid(usage))?,
+                        )?,
+                        layout,
+                    });
+                }
+                "--lut" => {
+                    let path = required(value, "--lut expects a .cube file")?;
+                    options.config.color = options
+                        .config
+                        .color
+                        .clone()
+                        .with_view_lut(path)
+                        .map_err(|e| Error::File(format!("{}: {}", path, e)))?;
+                }
+                arg => return Err(invalid(format!("unknown argument: {}", arg))),
             }
+            i += consumed;
         }
-        None
+        Ok(options)
     }
-}
-
-enum RectAxisType {
-    XY,
-    XZ,
-    YZ,
-}
-
-struct Rect {
-    x0: f64,
-    x1: f64,
-    y0: f64,
-    y1: f64,
-    k: f64,
-    axis: RectAxisType,
-    material: Arc<dyn Material>,
-}
 
-impl Rect {
-    fn new(
-        x0: f64,
-        x1: f64,
-        y0: f64,
-        y1: f64,
-        k: f64,
-        axis: RectAxisType,
-        material: Arc<dyn Material>,
-    ) -> Self {
-        Self {
-            x0,
-            x1,
-            y0,
-            y1,
-            k,
-            axis,
-            material,
-        }
+    fn scene(&self) -> Result<CornelBoxScene, Error> {
+        let scene = CornelBoxScene::new()?;
+        let scene = match self.glass {
+            Some(ri) => scene.with_glass_sphere(ri)?,
+            None => scene,
+        };
+        Ok(scene
+            .with_seed(self.seed)
+            .with_polarizer(self.polarizer)
+            .with_distortion(self.distortion)
+            .with_strata(self.strata)
+            .with_sampler(self.sampler)
+            .with_radiance_clamp(self.clamp)
+            .with_colored_shadows(self.colored_shadows)
+            .with_photons(self.photons))
+    }
+
+    fn random_scene(&self) -> Result<RandomScene, Error> {
+        Ok(RandomScene::new(self.seed)?
+            .with_polarizer(self.polarizer)
+            .with_distortion(self.distortion)
+            .with_strata(self.strata)
+            .with_sampler(self.sampler)
+            .with_radiance_clamp(self.clamp)
+            .with_colored_shadows(self.colored_shadows))
+    }
+
+    fn final_scene(&self) -> Result<FinalScene, Error> {
+        Ok(FinalScene::new(self.seed)?
+            .with_polarizer(self.polarizer)
+            .with_distortion(self.distortion)
+            .with_strata(self.strata)
+            .with_sampler(self.sampler)
+            .with_radiance_clamp(self.clamp)
+            .with_colored_shadows(self.colored_shadows))
+    }
+
+    fn simple_scene(&self) -> Result<SimpleScene, Error> {
+        Ok(SimpleScene::new()?
+            .with_seed(self.seed)
+            .with_polarizer(self.polarizer)
+            .with_distortion(self.distortion)
+            .with_strata(self.strata)
+            .with_sampler(self.sampler)
+            .with_radiance_clamp(self.clamp)
+            .with_colored_shadows(self.colored_shadows))
+    }
+
+    // ファイルの [render] の値は、コマンドラインで指定しなかった項目にだけ使う
+    fn file_scene(&mut self, path: &str) -> Result<FileScene, Error> {
+        let scene = FileScene::load(path)?;
+        scene.fill_config(&mut self.config)?;
+        Ok(scene
+            .with_seed(self.seed)
+            .with_polarizer(self.polarizer)
+            .with_distortion(self.distortion)
+            .with_strata(self.strata)
+            .with_sampler(self.sampler)
+            .with_radiance_clamp(self.clamp)
+            .with_colored_shadows(self.colored_shadows))
+    }
+
+    // スクリプトの samples() と max_depth() も、コマンドラインで指定しなかったときだけ使う
+    #[cfg(feature = "script")]
+    fn script_scene(&mut self, path: &str) -> Result<FileScene, Error> {
+        let scene = load_script(path, self.seed)?;
+        scene.fill_config(&mut self.config)?;
+        Ok(scene
+            .with_seed(self.seed)
+            .with_polarizer(self.polarizer)
+            .with_distortion(self.distortion)
+            .with_strata(self.strata)
+            .with_sampler(self.sampler)
+            .with_radiance_clamp(self.clamp)
+            .with_colored_shadows(self.colored_shadows))
+    }
+
+    #[cfg(not(feature = "script"))]
+    fn script_scene(&mut self, _path: &str) -> Result<FileScene, Error> {
+        Err(Error::Invalid(
+            "--script needs rayt built with --features script".into(),
+        ))
     }
-}
 
-impl Shape for Rect {
-    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        let mut origin = ray.origin;
-        let mut direction = ray.direction;
-        let mut axis = Vec3::zaxis();
-        match self.axis {
-            RectAxisType::XY => {}
-            RectAxisType::XZ => {
-                origin = Point3::new(origin.x(), origin.z(), origin.y());
-                direction = Vec3::new(direction.x(), direction.z(), direction.y());
-                axis = Vec3::yaxis();
+    fn render(&self, scene: impl WorldScene + Sync) -> Result<(), Error> {
+        if let Some(path) = &self.export {
+            let skipped = export_scene(&scene, &self.config, path)?;
+            if skipped > 0 {
+                eprintln!(
+                    "{}: skipped {} shapes the scene file can't describe",
+                    path, skipped
+                );
             }
-            RectAxisType::YZ => {
-                origin = Point3::new(origin.y(), origin.z(), origin.x());
-                direction = Vec3::new(direction.y(), direction.z(), direction.x());
-                axis = Vec3::xaxis();
-            }
-        }
-        let t = (self.k - origin.z()) / direction.z();
-        if t < t0 || t > t1 {
-            return None;
+            return Ok(());
         }
-        let x = origin.x() + t * direction.x();
-        let y = origin.y() + t * direction.y();
-        if x < self.x0 || x > self.x1 || y < self.y0 || y > self.y1 {
-            return None;
-        }
-        Some(HitInfo::new(
-            t,
-            ray.at(t),
-            axis,
-            Arc::clone(&self.material),
-            (x - self.x0) / (self.x1 - self.x0),
-            (y - self.y0) / (self.y1 - self.y0),
-        ))
-    }
-}
-
-struct Box3D {
-    p0: Point3,
-    p1: Point3,
-    shapes: ShapeList,
-}
-
-impl Box3D {
-    fn new(p0: Point3, p1: Point3, material: Arc<dyn Material>) -> Self {
-        let mut shapes = ShapeList::new();
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_xy(p0.x(), p1.x(), p0.y(), p1.y(), p1.z())
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_xy(p0.x(), p1.x(), p0.y(), p1.y(), p0.z())
-                .flip_face()
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_xz(p0.x(), p1.x(), p0.z(), p1.z(), p1.y())
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_xz(p0.x(), p1.x(), p0.z(), p1.z(), p0.y())
-                .flip_face()
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_yz(p0.y(), p1.y(), p0.z(), p1.z(), p1.x())
-                .build(),
-        );
-        shapes.push(
-            ShapeBuilder::new()
-                .material(Arc::clone(&material))
-                .rect_yz(p0.y(), p1.y(), p0.z(), p1.z(), p0.x())
-                .flip_face()
-                .build(),
-        );
-
-        Self { p0, p1, shapes }
-    }
-}
-
-impl Shape for Box3D {
-    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        self.shapes.hit(ray, t0, t1)
-    }
-}
-
-struct FlipFace {
-    shape: Box<dyn Shape>,
-}
-
-impl FlipFace {
-    fn new(shape: Box<dyn Shape>) -> Self {
-        Self { shape }
-    }
-}
-
-impl Shape for FlipFace {
-    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        if let Some(hit) = self.shape.hit(ray, t0, t1) {
-            Some(HitInfo { n: -hit.n, ..hit })
-        } else {
-            None
+        if let Some(addr) = &self.worker {
+            return render_worker(scene, &self.config, addr);
         }
-    }
-}
-
-struct Translate {
-    shape: Box<dyn Shape>,
-    offset: Point3,
-}
-
-impl Translate {
-    fn new(shape: Box<dyn Shape>, offset: Point3) -> Self {
-        Self { shape, offset }
-    }
-}
-
-impl Shape for Translate {
-    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        let moved_ray = Ray::new(ray.origin - self.offset, ray.direction);
-        if let Some(hit) = self.shape.hit(&moved_ray, t0, t1) {
-            Some(HitInfo {
-                p: hit.p + self.offset,
-                ..hit
-            })
-        } else {
-            None
+        if self.fly {
+            return self.fly_through(scene);
         }
-    }
-}
-
-struct Rotate {
-    shape: Box<dyn Shape>,
-    quat: Quat,
-}
-
-impl Rotate {
-    fn new(shape: Box<dyn Shape>, axis: Vec3, angle: f64) -> Self {
-        Self {
-            shape,
-            quat: Quat::from_rot(axis, angle.to_radians()),
+        if self.tweak {
+            return self.look_dev(scene);
         }
-    }
-}
-
-impl Shape for Rotate {
-    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        let revq = self.quat.conj();
-        let rotated_ray = Ray::new(revq.rotate(ray.origin), revq.rotate(ray.direction));
-        if let Some(hit) = self.shape.hit(&rotated_ray, t0, t1) {
-            Some(HitInfo {
-                p: self.quat.rotate(hit.p),
-                n: self.quat.rotate(hit.n),
-                ..hit
-            })
-        } else {
-            None
+        let config = self.metered(&scene);
+        match self.stereo {
+            Some(stereo) => render_stereo(scene, stereo, &config),
+            None => render_aa_with_depth(scene, &config),
         }
     }
-}
 
-struct ShapeList {
-    pub objects: Vec<Box<dyn Shape>>,
-}
-
-impl ShapeList {
-    pub fn new() -> Self {
-        Self {
-            objects: Vec::new(),
+    // 測光するシーンは描く前に露出を測って表示し、描くときはその値を使う
+    fn metered(&self, scene: &(impl WorldScene + Sync)) -> RenderConfig {
+        let applied = self.config.apply(scene);
+        let metering = applied.metering();
+        if metering == Metering::Off {
+            return self.config.clone();
         }
-    }
-    pub fn push(&mut self, object: Box<dyn Shape>) {
-        self.objects.push(object);
-    }
-}
-
-impl Shape for ShapeList {
-    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
-        let mut hit_info: Option<HitInfo> = None;
-        let mut closest_so_far = t1;
-        for object in &self.objects {
-            if let Some(info) = object.hit(ray, t0, closest_so_far) {
-                closest_so_far = info.t;
-                hit_info = Some(info);
-            }
+        let exposure = self.config.install(|| auto_exposure(&applied));
+        println!("auto exposure ({:?}): {:+.2} EV", metering, exposure);
+        RenderConfig {
+            metered_exposure: Some(exposure),
+            ..self.config.clone()
         }
-        hit_info
     }
-}
 
-trait Material: Sync + Send {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo>;
-    fn emitted(&self, _ray: &Ray, _hit: &HitInfo) -> Color {
-        Color::zero()
+    #[cfg(feature = "window")]
+    fn fly_through(&self, scene: impl WorldScene + Sync) -> Result<(), Error> {
+        render_fly_through(scene, &self.config)
     }
-}
-
-struct ScatterInfo {
-    ray: Ray,
-    albedo: Color,
-}
 
-impl ScatterInfo {
-    fn new(ray: Ray, albedo: Color) -> Self {
-        Self { ray, albedo }
-    }
-}
-
-struct Lambertian {
-    albedo: Box<dyn Texture>,
-}
-
-impl Lambertian {
-    fn new(albedo: Box<dyn Texture>) -> Self {
-        Self { albedo }
+    #[cfg(not(feature = "window"))]
+    fn fly_through(&self, _scene: impl WorldScene + Sync) -> Result<(), Error> {
+        Err(Error::Invalid(
+            "--fly needs rayt built with --features window".into(),
+        ))
     }
-}
 
-impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
-        let target = hit.p + hit.n + Vec3::random_in_unit_sphere();
-        let albedo = self.albedo.value(hit.u, hit.v, hit.p);
-        Some(ScatterInfo::new(Ray::new(hit.p, target - hit.p), albedo))
+    #[cfg(feature = "window")]
+    fn look_dev(&self, scene: impl WorldScene + Sync) -> Result<(), Error> {
+        let materials = scene.world().materials();
+        render_look_dev(scene, &self.config.color, |ui| {
+            material_panel(ui, &materials)
+        })
     }
-}
-
-struct Metal {
-    albedo: Box<dyn Texture>,
-    fuzz: f64,
-}
 
-impl Metal {
-    fn new(albedo: Box<dyn Texture>, fuzz: f64) -> Self {
-        Self { albedo, fuzz }
+    #[cfg(not(feature = "window"))]
+    fn look_dev(&self, _scene: impl WorldScene + Sync) -> Result<(), Error> {
+        Err(Error::Invalid(
+            "--tweak needs rayt built with --features window".into(),
+        ))
     }
 }
 
-impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
-        let mut reflected = ray.direction.normalize().reflect(hit.n);
-        reflected = reflected + self.fuzz * Vec3::random_in_unit_sphere();
-        if reflected.dot(hit.n) > 0.0 {
-            let albedo = self.albedo.value(hit.u, hit.v, hit.p);
-            Some(ScatterInfo::new(Ray::new(hit.p, reflected), albedo))
-        } else {
-            None
+// 調整できる値を持つマテリアルごとにスライダーを並べる。値が変わったら true
+#[cfg(feature = "window")]
+fn material_panel(ui: &mut egui::Ui, materials: &[Arc<dyn Material>]) -> bool {
+    let mut changed = false;
+    for (i, material) in materials.iter().enumerate() {
+        let params = material.params();
+        if params.is_empty() {
+            continue;
         }
-    }
-}
-
-struct Dielectric {
-    ri: f64,
-}
-
-impl Dielectric {
-    const fn new(ri: f64) -> Self {
-        Self { ri }
-    }
-    // Schlick 近似
-    fn schlick(cosine: f64, ri: f64) -> f64 {
-        let r0 = ((1.0 - ri) / (1.0 + ri)).powi(2);
-        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
-    }
-}
-
-impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
-        let reflected = ray.direction.reflect(hit.n);
-        let (outward_normal, ni_over_nt, cosine) = {
-            let dot = ray.direction.dot(hit.n);
-            if dot > 0.0 {
-                (-hit.n, self.ri, self.ri * dot / ray.direction.length())
-            } else {
-                (hit.n, self.ri.recip(), -dot / ray.direction.length())
+        egui::CollapsingHeader::new(format!("#{} {}", i, material.name()))
+            .default_open(true)
+            .show(ui, |ui| {
+                for param in params {
+                    let mut value = param.get();
+                    let slider =
+                        egui::Slider::new(&mut value, param.min..=param.max).text(param.name);
+                    if ui.add(slider).changed() {
+                        param.set(value);
+                        changed = true;
+                    }
+                }
+            });
+    }
+    changed
+}
+
+const GALLERY_FILENAME: &str = "gallery.png";
+const GALLERY_THUMBNAIL_HEIGHT: u32 = 96;
+
+// SCENES のシーンをすべて小さく描いて 1 枚に並べる
+fn render_gallery(options: &Options) -> Result<(), Error> {
+    let thumbnails = SCENES
+        .iter()
+        .map(|entry| {
+            let thumbnail = options.run_scene(entry.kind, Thumbnail(GALLERY_THUMBNAIL_HEIGHT))?;
+            Ok((entry.name.to_string(), thumbnail))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    contact_sheet(&thumbnails).save(GALLERY_FILENAME)?;
+    println!("{} scenes -> {}", thumbnails.len(), GALLERY_FILENAME);
+    Ok(())
+}
+
+const BATCH_REPORT_FILENAME: &str = "render_batch.txt";
+
+// キューファイルは 1 行 1 ジョブで「出力ファイル名 [オプション...]」を並べる
+// 空行と # で始まる行は読み飛ばす
+fn render_batch(queue: &str) -> Result<(), Error> {
+    let jobs = std::fs::read_to_string(queue)
+        .map_err(|e| Error::File(format!("{}: {}", queue, e)))?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut report = String::new();
+    let mut failed = 0;
+    for (i, job) in jobs.iter().enumerate() {
+        let output = &job[0];
+        println!("[{}/{}] {}", i + 1, jobs.len(), job.join(" "));
+        let start = std::time::Instant::now();
+        // 1 つのジョブが失敗しても残りのジョブは続ける
+        let result = std::panic::catch_unwind(|| {
+            let mut options = Options::parse(&job[1..])?;
+            options.config.output = output.clone();
+            options.with_scene(RenderToFile)
+        });
+        let status = match result {
+            Ok(Ok(())) => "ok",
+            Ok(Err(e)) => {
+                eprintln!("{}: {}", output, e);
+                failed += 1;
+                "FAILED"
             }
-        };
-        if let Some(refracted) = (-ray.direction).refract(outward_normal, ni_over_nt) {
-            if Vec3::random_fill().x() > Self::schlick(cosine, self.ri) {
-                return Some(ScatterInfo::new(Ray::new(hit.p, refracted), Color::one()));
+            Err(_) => {
+                failed += 1;
+                "FAILED"
             }
-        }
-        Some(ScatterInfo::new(Ray::new(hit.p, reflected), Color::one()))
-    }
-}
-
-trait Texture: Sync + Send {
-    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
-}
-
-struct ColorTexture {
-    color: Color,
-}
-
-impl ColorTexture {
-    const fn new(color: Color) -> Self {
-        Self { color }
-    }
-}
-
-impl Texture for ColorTexture {
-    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
-        self.color
+        };
+        report += &format!(
+            "{:<8}{:>9.2}s  {}\n",
+            status,
+            start.elapsed().as_secs_f64(),
+            job.join(" ")
+        );
     }
+    report += &format!("{} jobs, {} failed\n", jobs.len(), failed);
+    print!("{}", report);
+    std::fs::write(BATCH_REPORT_FILENAME, report)?;
+    Ok(())
 }
 
-struct CheckerTexture {
-    odd: Box<dyn Texture>,
-    even: Box<dyn Texture>,
-    freq: f64,
+// 選んだシーンですること。シーンの型はそれぞれ違うので、組み立てた側から呼んでもらう
+trait SceneTask {
+    type Output;
+    fn run(self, options: &Options, scene: impl WorldScene + Sync) -> Result<Self::Output, Error>;
 }
 
-impl CheckerTexture {
-    fn new(odd: Box<dyn Texture>, even: Box<dyn Texture>, freq: f64) -> Self {
-        Self { odd, even, freq }
-    }
-}
+struct Render;
 
-impl Texture for CheckerTexture {
-    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
-        let sines = p.iter().fold(1.0, |acc, x| acc * (x * self.freq).sin());
-        if sines < 0.0 {
-            self.odd.value(u, v, p)
-        } else {
-            self.even.value(u, v, p)
-        }
+impl SceneTask for Render {
+    type Output = ();
+    fn run(self, options: &Options, scene: impl WorldScene + Sync) -> Result<(), Error> {
+        options.render(scene)
     }
 }
 
-struct ImageTexture {
-    pixels: Vec<Color>,
-    width: usize,
-    height: usize,
-}
-
-impl ImageTexture {
-    fn new(path: &str) -> Self {
-        let rgb_img = image::open(path).unwrap().to_rgb8();
-        let (w, h) = rgb_img.dimensions();
-        let mut image = vec![Color::zero(); (w * h) as usize];
-        for (i, (_, _, pixel)) in image.iter_mut().zip(rgb_img.enumerate_pixels()) {
-            *i = Color::from_rgb(pixel[0], pixel[1], pixel[2]);
-        }
-        Self {
-            pixels: image,
-            width: w as usize,
-            height: h as usize,
-        }
-    }
-
-    fn sample(&self, u: i64, v: i64) -> Color {
-        let tu = if u < 0 {
-            0
-        } else if u as usize >= self.width {
-            self.width - 1
-        } else {
-            u as usize
-        };
-        let tv = if v < 0 {
-            0
-        } else if v as usize >= self.height {
-            self.height - 1
-        } else {
-            v as usize
-        };
-        self.pixels[tu + self.width * tv]
-    }
-}
+// ウィンドウを開かず、config.output にだけ書き出す
+struct RenderToFile;
 
-impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
-        let x = (u * self.width as f64) as i64;
-        let y = ((1.0 - v) * self.height as f64) as i64;
-        self.sample(x, y)
+impl SceneTask for RenderToFile {
+    type Output = ();
+    fn run(self, options: &Options, scene: impl WorldScene + Sync) -> Result<(), Error> {
+        let config = options.metered(&scene);
+        render_aa_with_depth_to_file(scene, &config)
     }
 }
 
-struct DiffusedLight {
-    emit: Box<dyn Texture>,
-}
+struct Repro(u32, u32);
 
-impl DiffusedLight {
-    fn new(emit: Box<dyn Texture>) -> Self {
-        Self { emit }
+impl SceneTask for Repro {
+    type Output = ();
+    fn run(self, options: &Options, scene: impl WorldScene + Sync) -> Result<(), Error> {
+        repro_pixel(scene, self.0, self.1, options.seed);
+        Ok(())
     }
 }
 
-impl Material for DiffusedLight {
-    fn scatter(&self, _ray: &Ray, _hit: &HitInfo) -> Option<ScatterInfo> {
-        None
-    }
+// 高さを指定して縮めて描いた画像
+struct Thumbnail(u32);
 
-    fn emitted(&self, _ray: &Ray, hit: &HitInfo) -> Color {
-        self.emit.value(hit.u, hit.v, hit.p)
+impl SceneTask for Thumbnail {
+    type Output = RgbImage;
+    fn run(self, options: &Options, scene: impl WorldScene + Sync) -> Result<RgbImage, Error> {
+        Ok(render_thumbnail(&scene, self.0, &options.config.color))
     }
 }
 
-struct ShapeBuilder {
-    texture: Option<Box<dyn Texture>>,
-    material: Option<Arc<dyn Material>>,
-    shape: Option<Box<dyn Shape>>,
+#[derive(Debug, Clone, Copy)]
+enum SceneKind {
+    Cornell,
+    Simple,
+    Random,
+    Final,
 }
 
-impl ShapeBuilder {
-    fn new() -> Self {
-        Self {
-            texture: None,
-            material: None,
-            shape: None,
+impl Options {
+    fn run_scene<T: SceneTask>(&self, kind: SceneKind, task: T) -> Result<T::Output, Error> {
+        match kind {
+            SceneKind::Cornell => task.run(self, self.scene()?),
+            SceneKind::Simple => task.run(self, self.simple_scene()?),
+            SceneKind::Random => task.run(self, self.random_scene()?),
+            SceneKind::Final => task.run(self, self.final_scene()?),
         }
     }
 
-    // textures
-
-    fn color_texture(mut self, color: Color) -> Self {
-        self.texture = Some(Box::new(ColorTexture::new(color)));
-        self
-    }
-
-    fn checker_texture(mut self, odd_color: Color, even_color: Color, freq: f64) -> Self {
-        self.texture = Some(Box::new(CheckerTexture::new(
-            Box::new(ColorTexture::new(odd_color)),
-            Box::new(ColorTexture::new(even_color)),
-            freq,
-        )));
-        self
-    }
-
-    fn image_texture(mut self, path: &str) -> Self {
-        self.texture = Some(Box::new(ImageTexture::new(path)));
-        self
-    }
-
-    fn diffuse_light(mut self) -> Self {
-        self.material = Some(Arc::new(DiffusedLight::new(self.texture.unwrap())));
-        self.texture = None;
-        self
-    }
-
-    fn material(mut self, material: Arc<dyn Material>) -> Self {
-        self.material = Some(material);
-        self.texture = None;
-        self
-    }
-
-    // Material
-
-    fn lambertian(mut self) -> Self {
-        self.material = Some(Arc::new(Lambertian::new(self.texture.unwrap())));
-        self.texture = None;
-        self
-    }
-    fn metal(mut self, fuzz: f64) -> Self {
-        self.material = Some(Arc::new(Metal::new(self.texture.unwrap(), fuzz)));
-        self.texture = None;
-        self
-    }
-    fn dielectric(mut self, ri: f64) -> Self {
-        self.material = Some(Arc::new(Dielectric::new(ri)));
-        self
-    }
-
-    // shapes
-
-    fn sphere(mut self, center: Point3, radius: f64) -> Self {
-        self.shape = Some(Box::new(Sphere::new(
-            center,
-            radius,
-            self.material.unwrap(),
-        )));
-        self.material = None;
-        self
-    }
-
-    fn rect_xy(mut self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> Self {
-        self.shape = Some(Box::new(Rect::new(
-            x0,
-            x1,
-            y0,
-            y1,
-            k,
-            RectAxisType::XY,
-            self.material.unwrap(),
-        )));
-        self.material = None;
-        self
-    }
-
-    fn rect_xz(mut self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> Self {
-        self.shape = Some(Box::new(Rect::new(
-            x0,
-            x1,
-            y0,
-            y1,
-            k,
-            RectAxisType::XZ,
-            self.material.unwrap(),
-        )));
-        self.material = None;
-        self
-    }
-
-    fn rect_yz(mut self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> Self {
-        self.shape = Some(Box::new(Rect::new(
-            x0,
-            x1,
-            y0,
-            y1,
-            k,
-            RectAxisType::YZ,
-            self.material.unwrap(),
-        )));
-        self.material = None;
-        self
-    }
-
-    fn box3d(mut self, p0: Point3, p1: Point3) -> Self {
-        self.shape = Some(Box::new(Box3D::new(p0, p1, self.material.unwrap())));
-        self.material = None;
-        self
-    }
-
-    // decorators
-
-    fn flip_face(mut self) -> Self {
-        self.shape = Some(Box::new(FlipFace::new(self.shape.unwrap())));
-        self
-    }
-
-    fn translate(mut self, offset: Point3) -> Self {
-        self.shape = Some(Box::new(Translate::new(self.shape.unwrap(), offset)));
-        self
-    }
-
-    fn rotate(mut self, axis: Vec3, angle: f64) -> Self {
-        self.shape = Some(Box::new(Rotate::new(self.shape.unwrap(), axis, angle)));
-        self
-    }
-
-    // build
-
-    fn build(self) -> Box<dyn Shape> {
-        self.shape.unwrap()
-    }
-}
-
-struct SimpleScene {
-    world: ShapeList,
-}
-
-impl SimpleScene {
-    fn new() -> Self {
-        let mut world = ShapeList::new();
-        // world.push(
-        //     ShapeBuilder::new()
-        //         .image_texture("resources/Bricks082A_1K_Color.jpg")
-        //         .lambertian()
-        //         .sphere(Point3::new(0.6, 0.0, -1.0), 0.5)
-        //         .build(),
-        // );
-        // world.push(
-        //     ShapeBuilder::new()
-        //         .color_texture(Color::new(0.8, 0.8, 0.8))
-        //         .metal(0.4)
-        //         .sphere(Point3::new(-0.6, 0.0, -1.0), 0.5)
-        //         .build(),
-        // );
-        // world.push(
-        //     ShapeBuilder::new()
-        //         .checker_texture(Color::new(0.8, 0.8, 0.0), Color::new(0.8, 0.2, 0.0), 10.0)
-        //         .lambertian()
-        //         .sphere(Point3::new(0.0, -100.5, -1.0), 100.0)
-        //         .build(),
-        // );
-        // world.push(
-        //     ShapeBuilder::new()
-        //         .color_texture(Color::one())
-        //         .diffuse_light()
-        //         .sphere(Point3::new(0.0, 0.0, -1.0), 0.5)
-        //         .build(),
-        // );
-        // world.push(
-        //     ShapeBuilder::new()
-        //         .color_texture(Color::fill(0.8))
-        //         .lambertian()
-        //         .sphere(Point3::new(0.0, -100.5, -1.0), 100.0)
-        //         .build(),
-        // );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(Color::fill(0.5))
-                .lambertian()
-                .sphere(Point3::new(0.0, 2.0, 0.0), 2.0)
-                .build(),
-        );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(Color::fill(4.0))
-                .diffuse_light()
-                .rect_xy(3.0, 5.0, 1.0, 3.0, -2.0)
-                .build(),
-        );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(Color::fill(0.8))
-                .lambertian()
-                .sphere(Point3::new(0.0, -1000.0, 0.0), 1000.0)
-                .build(),
-        );
-        Self { world }
-    }
-    fn background(&self, _d: Vec3) -> Color {
-        // let t = 0.5 * (d.normalize().y() + 1.0);
-        // Color::one().lerp(Color::new(0.5, 0.7, 1.0), t)
-        Color::fill(0.1)
-    }
-}
-
-impl SceneWithDepth for SimpleScene {
-    fn camera(&self) -> Camera {
-        // Camera::new(
-        //     Vec3::new(4.0, 0.0, 0.0),
-        //     Vec3::new(0.0, 2.0, 0.0),
-        //     Vec3::new(-2.0, -1.0, -1.0),
-        // )
-        Camera::from_look_at(
-            Vec3::new(13.0, 2.0, 3.0),
-            Vec3::yaxis(),
-            Vec3::yaxis(),
-            30.0,
-            self.aspect(),
-        )
-    }
-    fn trace(&self, ray: Ray, depth: usize) -> Color {
-        let hit_info = self.world.hit(&ray, 0.001, f64::MAX);
-        if let Some(hit) = hit_info {
-            let emitted = hit.m.emitted(&ray, &hit);
-            let scatter_info = if depth > 0 {
-                hit.m.scatter(&ray, &hit)
-            } else {
-                None
-            };
-            if let Some(scatter) = scatter_info {
-                emitted + scatter.albedo * self.trace(scatter.ray, depth - 1)
-            } else {
-                emitted
-            }
-        } else {
-            self.background(ray.direction)
+    // --scene-file, --script, --scene の順に見て選んだシーンで task をする
+    // どれもなければ SCENES の先頭
+    fn with_scene<T: SceneTask>(&mut self, task: T) -> Result<T::Output, Error> {
+        if let Some(path) = self.scene_file.clone() {
+            let scene = self.file_scene(&path)?;
+            return task.run(self, scene);
         }
+        if let Some(path) = self.script.clone() {
+            let scene = self.script_scene(&path)?;
+            return task.run(self, scene);
+        }
+        let name = self.scene.as_deref().unwrap_or(SCENES[0].name);
+        self.run_scene(find_scene(name)?.kind, task)
+    }
+}
+
+// --scene で選べるシーン。先頭が既定
+struct SceneEntry {
+    name: &'static str,
+    description: &'static str,
+    kind: SceneKind,
+}
+
+const SCENES: &[SceneEntry] = &[
+    SceneEntry {
+        name: "cornell",
+        description: "Cornell box (--glass, --photons apply)",
+        kind: SceneKind::Cornell,
+    },
+    SceneEntry {
+        name: "simple",
+        description: "sphere on a ground sphere lit by a rectangle",
+        kind: SceneKind::Simple,
+    },
+    SceneEntry {
+        name: "random",
+        description: "book 1 final scene (--seed also fixes the sphere layout)",
+        kind: SceneKind::Random,
+    },
+    SceneEntry {
+        name: "final",
+        description: "book 2 final scene (boxes, smoke, motion blur, textures)",
+        kind: SceneKind::Final,
+    },
+];
+
+fn find_scene(name: &str) -> Result<&'static SceneEntry, Error> {
+    SCENES
+        .iter()
+        .find(|entry| entry.name == name)
+        .ok_or_else(|| {
+            let names = SCENES.iter().map(|entry| entry.name).collect::<Vec<_>>();
+            invalid(format!(
+                "unknown scene: {} (expected {})",
+                name,
+                names.join(", ")
+            ))
+        })
+}
+
+fn list_scenes() {
+    for entry in SCENES {
+        println!("{:<10}{}", entry.name, entry.description);
     }
 }
 
-// struct RandomScene {
-//     world: ShapeList,
-// }
-
-// impl RandomScene {
-//     fn new() -> Self {
-//         let mut world = ShapeList::new();
-//         world.push(
-//             ShapeBuilder::new()
-//                 .lambertian(Color::new(0.5, 0.5, 0.5))
-//                 .sphere(Point3::new(0.0, -1000.0, 0.0), 1000.0)
-//                 .build(),
-//         );
-//         // Small spheres
-//         for au in -11..11 {
-//             let a = au as f64;
-//             for bu in -11..11 {
-//                 let b = bu as f64;
-//                 let [rx, rz, material_choice] = Float3::random().to_array();
-//                 let center = Point3::new(a + 0.9 * rx, 0.2, b + 0.9 * rz);
-//                 if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-//                     world.push({
-//                         if material_choice < 0.8 {
-//                             let albedo = Color::random() * Color::random();
-//                             ShapeBuilder::new()
-//                                 .lambertian(albedo)
-//                                 .sphere(center, 0.2)
-//                                 .build()
-//                         } else if material_choice < 0.95 {
-//                             let albedo = Color::random_limit(0.5, 1.0);
-//                             let fuzz = Float3::random_fill().x();
-//                             ShapeBuilder::new()
-//                                 .metal(albedo, fuzz)
-//                                 .sphere(center, 0.2)
-//                                 .build()
-//                         } else {
-//                             ShapeBuilder::new()
-//                                 .dielectric(1.5)
-//                                 .sphere(center, 0.2)
-//                                 .build()
-//                         }
-//                     });
-//                 }
-//             }
-//         }
-
-//         // Big spheres
-//         world.push(
-//             ShapeBuilder::new()
-//                 .dielectric(1.5)
-//                 .sphere(Point3::new(0.0, 1.0, 0.0), 1.0)
-//                 .build(),
-//         );
-//         world.push(
-//             ShapeBuilder::new()
-//                 .lambertian(Color::new(0.4, 0.2, 0.1))
-//                 .sphere(Point3::new(-4.0, 1.0, 0.0), 1.0)
-//                 .build(),
-//         );
-//         world.push(
-//             ShapeBuilder::new()
-//                 .metal(Color::new(0.7, 0.6, 0.5), 0.0)
-//                 .sphere(Point3::new(4.0, 1.0, 0.0), 1.0)
-//                 .build(),
-//         );
-
-//         Self { world }
-//     }
-
-//     fn background(&self, d: Vec3) -> Color {
-//         let t = 0.5 * (d.normalize().y() + 1.0);
-//         Color::one().lerp(Color::new(0.5, 0.7, 1.0), t)
-//     }
-// }
-
-// impl SceneWithDepth for RandomScene {
-//     fn camera(&self) -> Camera {
-//         Camera::from_look_at(
-//             Point3::new(13.0, 2.0, 3.0),
-//             Point3::new(0.0, 0.0, 0.0),
-//             Vec3::yaxis(),
-//             20.0,
-//             self.aspect(),
-//         )
-//     }
-//     fn trace(&self, ray: Ray, depth: usize) -> Color {
-//         let hit_info = self.world.hit(&ray, 0.001, f64::MAX);
-//         if let Some(hit) = hit_info {
-//             let scatter_info = if depth > 0 {
-//                 hit.m.scatter(&ray, &hit)
-//             } else {
-//                 None
-//             };
-//             if let Some(scatter) = scatter_info {
-//                 scatter.albedo * self.trace(scatter.ray, depth - 1)
-//             } else {
-//                 Color::zero()
-//             }
-//         } else {
-//             self.background(ray.direction)
-//         }
-//     }
-// }
-
-struct CornelBoxScene {
-    world: ShapeList,
-}
-
-impl CornelBoxScene {
-    fn new() -> Self {
-        let mut world = ShapeList::new();
-
-        let red = Color::new(0.64, 0.05, 0.05);
-        let white = Color::fill(0.73);
-        let green = Color::new(0.12, 0.45, 0.15);
-
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(green)
-                .lambertian()
-                .rect_yz(0.0, 555.0, 0.0, 555.0, 555.0)
-                .flip_face()
-                .build(),
-        );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(red)
-                .lambertian()
-                .rect_yz(0.0, 555.0, 0.0, 555.0, 0.0)
-                .build(),
-        );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(Color::fill(15.0))
-                .diffuse_light()
-                .rect_xz(213.0, 343.0, 227.0, 332.0, 554.0)
-                .build(),
-        );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
-                .rect_xz(0.0, 555.0, 0.0, 555.0, 555.0)
-                .flip_face()
-                .build(),
-        );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
-                .rect_xz(0.0, 555.0, 0.0, 555.0, 0.0)
-                .build(),
-        );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
-                .rect_xy(0.0, 555.0, 0.0, 555.0, 555.0)
-                .flip_face()
-                .build(),
-        );
-
-        // world.push(
-        //     ShapeBuilder::new()
-        //         .color_texture(white)
-        //         .lambertian()
-        //         .box3d(
-        //             Point3::new(130.0, 0.0, 65.0),
-        //             Point3::new(295.0, 165.0, 230.0),
-        //         )
-        //         .build(),
-        // );
-        // world.push(
-        //     ShapeBuilder::new()
-        //         .color_texture(white)
-        //         .lambertian()
-        //         .box3d(
-        //             Point3::new(265.0, 0.0, 295.0),
-        //             Point3::new(430.0, 330.0, 460.0),
-        //         )
-        //         .build(),
-        // );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
-                .box3d(Point3::zero(), Point3::fill(165.0))
-                .rotate(Vec3::yaxis(), -18.0)
-                .translate(Point3::new(130.0, 0.0, 65.0))
-                .build(),
-        );
-        world.push(
-            ShapeBuilder::new()
-                .color_texture(white)
-                .lambertian()
-                .box3d(Point3::zero(), Point3::new(165.0, 330.0, 165.0))
-                .rotate(Vec3::yaxis(), 15.0)
-                .translate(Point3::new(265.0, 0.0, 295.0))
-                .build(),
-        );
-
-        Self { world }
-    }
-    fn background(&self, _d: Vec3) -> Color {
-        // let t = 0.5 * (d.normalize().y() + 1.0);
-        // Color::one().lerp(Color::new(0.5, 0.7, 1.0), t)
-        Color::fill(0.0)
-    }
-}
-
-impl SceneWithDepth for CornelBoxScene {
-    fn camera(&self) -> Camera {
-        Camera::from_look_at(
-            Vec3::new(278.0, 278.0, -800.0),
-            Vec3::new(278.0, 278.0, 0.0),
-            Vec3::yaxis(),
-            40.0,
-            self.aspect(),
-        )
-    }
-    fn trace(&self, ray: Ray, depth: usize) -> Color {
-        let hit_info = self.world.hit(&ray, 0.001, f64::MAX);
-        if let Some(hit) = hit_info {
-            let emitted = hit.m.emitted(&ray, &hit);
-            let scatter_info = if depth > 0 {
-                hit.m.scatter(&ray, &hit)
-            } else {
-                None
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let arg = |i: usize| args.get(i).map(String::as_str);
+    match arg(1) {
+        Some("repro") => {
+            let mut options = Options::parse(&args[2..])?;
+            let (x, y) = options
+                .pixel
+                .ok_or_else(|| invalid("repro requires --pixel x,y"))?;
+            options.with_scene(Repro(x, y))?;
+        }
+        Some("tweak") => {
+            let options = Options::parse(&args[2..])?;
+            options.look_dev(options.scene()?)?;
+        }
+        // turntable <frames> [options]  箱の中心のまわりをカメラが 1 周する
+        Some("turntable") => {
+            let frames = parse_option("turntable", arg(2))?.unwrap_or(36);
+            let path = CameraPath::orbit(
+                Point3::new(278.0, 278.0, -800.0),
+                Point3::new(278.0, 278.0, 278.0),
+                Vec3::yaxis(),
+                40.0,
+            );
+            let options = Options::parse(args.get(3..).unwrap_or_default())?;
+            render_camera_path(options.scene()?, &path, frames, &options.config)?;
+        }
+        Some("gallery") => render_gallery(&Options::parse(&args[2..])?)?,
+        Some("batch") => render_batch(required(arg(2), "batch requires a queue file")?)?,
+        // to-cubemap <panorama.png> <size> [--fisheye fov]
+        Some("to-cubemap") => {
+            let input = required(arg(2), "to-cubemap requires an image")?;
+            let size = parse_option("to-cubemap", arg(3))?.unwrap_or(512);
+            let img = image::open(input)?.to_rgb8();
+            let faces = match arg(4) {
+                Some("--fisheye") => {
+                    let fov = parse_option("--fisheye", arg(5))?;
+                    fisheye_to_cubemap(&img, fov.unwrap_or(180.0), size)
+                }
+                _ => equirect_to_cubemap(&img, size),
             };
-            if let Some(scatter) = scatter_info {
-                emitted + scatter.albedo * self.trace(scatter.ray, depth - 1)
-            } else {
-                emitted
+            save_cubemap(input, &faces)?;
+        }
+        // to-panorama <faces.png> <output.png> [width]  (faces_px.png などを読む)
+        Some("to-panorama") => {
+            let input = required(arg(2), "to-panorama requires a cubemap name")?;
+            let output = required(arg(3), "to-panorama requires an output image")?;
+            let width: u32 = parse_option("to-panorama", arg(4))?.unwrap_or(1024);
+            let faces = load_cubemap(input)?;
+            cubemap_to_equirect(&faces, width, width / 2).save(output)?;
+        }
+        _ => {
+            let mut options = Options::parse(&args[1..])?;
+            if options.list_scenes {
+                list_scenes();
+                return Ok(());
             }
-        } else {
-            self.background(ray.direction)
+            options.with_scene(Render)?;
         }
     }
-    fn width(&self) -> u32 {
-        200
-    }
-    fn height(&self) -> u32 {
-        200
-    }
-}
-
-fn main() {
-    render_aa_with_depth(CornelBoxScene::new());
+    Ok(())
 }
@@ -0,0 +1,10 @@
+// よく使うものだけをまとめたもの。use rayt::prelude::*; で読み込む
+pub use crate::rayt::{
+    render_aa_with_depth_to_file, render_with_callback, trace_scene, Background, Camera,
+    CameraBuilder, Color, Error, FileScene, Float3, Material, Point3, Ray, RenderConfig,
+    RenderEvent, Scene, SceneWithDepth, Shape, ShapeBuilder, ShapeEnum, ShapeList, Texture, Vec3,
+    WorldScene,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::rayt::render_aa_with_depth;
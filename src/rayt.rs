@@ -1,21 +1,172 @@
+mod error;
+pub use self::error::Error;
+
 mod float3;
-pub use self::float3::{Color, Float3, Point3, Vec3};
+mod lanes;
+pub use self::float3::{Color, Float3, Point3, Real, Vec3};
 
 mod quat;
 pub use self::quat::Quat;
 
+mod transform;
+pub use self::transform::{Mat4, Transform};
+
+mod onb;
+pub use self::onb::Onb;
+
+mod pdf;
+pub use self::pdf::{CosinePdf, MixturePdf, Pdf, ShapePdf};
+
 mod ray;
-pub use self::ray::Ray;
+pub use self::ray::{Ray, RayDifferential};
 
 mod camera;
-pub use self::camera::Camera;
+pub use self::camera::{
+    Aperture, ApertureMask, Camera, CameraBuilder, FisheyeCamera, FisheyeMapping, FlyCamera,
+    LensDistortion, LookAt, OrthographicCamera, PanoramicCamera, PerspectiveCamera, ReframedCamera,
+    Shutter,
+};
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+mod overlay;
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+pub use self::overlay::Overlay;
+
+mod camera_path;
+pub use self::camera_path::{CameraKey, CameraMotion, CameraPath};
 
+mod gallery;
+pub use self::gallery::{contact_sheet, draw_text};
+
+mod animation;
+pub use self::animation::{encode_gif, encode_video, AnimationFormat};
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
 mod window;
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
 pub use self::window::*;
 
 mod render;
 pub use self::render::*;
 
+mod aov;
+pub use self::aov::{Aov, AovBuffers, AovPass};
+
+mod denoise;
+pub use self::denoise::Denoiser;
+
+mod web;
+pub use self::web::ProgressiveCanvas;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod http_preview;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::http_preview::PreviewServer;
+
+mod distributed;
+pub use self::distributed::{serve_tiles, work_tiles, Job, Tile};
+
+mod path;
+pub use self::path::{trace_path, PathSegment, PathTracer};
+
+mod rng;
+pub use self::rng::{
+    pixel_seed, random_array, random_f64, reseed, start_sample, with_thread_sampler,
+};
+
+mod sampler;
+pub use self::sampler::{
+    BlueNoiseSampler, HaltonSampler, PcgSampler, RandomSampler, Sampler, SamplerKind, SobolSampler,
+};
+
+mod debug;
+pub use self::debug::*;
+
+mod perlin;
+pub use self::perlin::Perlin;
+
+mod worley;
+pub use self::worley::Worley;
+
+mod progress;
+pub use self::progress::Progress;
+
+mod stats;
+pub use self::stats::{PathEnd, PathStats};
+
+mod probe;
+pub use self::probe::*;
+
+mod hash_grid;
+pub use self::hash_grid::HashGrid;
+
+mod photon_map;
+pub use self::photon_map::{Photon, PhotonMap};
+
+mod color;
+pub use self::color::{ColorConfig, CubeLut, DisplayTransform, ToneMap, WorkingSpace};
+
+mod cubemap;
+pub use self::cubemap::*;
+
+mod param;
+pub use self::param::Param;
+
+mod polarization;
+pub use self::polarization::{Mueller, Stokes};
+
+mod shape;
+pub use self::shape::*;
+
+mod material;
+pub use self::material::*;
+
+mod texture;
+pub use self::texture::*;
+
+mod background;
+pub use self::background::*;
+
+mod fog;
+pub use self::fog::{fog_scattering, Fog};
+
+mod light;
+pub use self::light::{
+    direct_lighting, light_groups, portal_covers, scene_lights, unshadowed, DirectionalLight,
+    GroupRadiance, GroupedLight, Light, LightSample, PointLight, PortalLight, DEFAULT_LIGHT_GROUP,
+    MAX_LIGHT_GROUPS,
+};
+
+mod builder;
+pub use self::builder::ShapeBuilder;
+
+mod scene;
+pub use self::scene::*;
+
+mod scenes;
+pub use self::scenes::{CornelBoxScene, FinalScene, RandomScene, SimpleScene};
+
+mod obj;
+pub use self::obj::load_obj;
+
+mod volume;
+pub use self::volume::{load_voxel_grid, VoxelGrid};
+
+mod gltf_import;
+pub use self::gltf_import::{load_gltf, GltfCamera, GltfScene};
+
+mod scene_file;
+pub(crate) use self::scene_file::from_toml;
+pub use self::scene_file::{
+    export_scene, BackgroundSpec, CameraSection, FileScene, LightDesc, MaterialDesc, SceneWriter,
+    ShapeDesc, ShapeKind, TextureDesc, TextureSpec,
+};
+
+#[cfg(feature = "script")]
+mod script;
+#[cfg(feature = "script")]
+pub use self::script::load_script;
+
 pub use std::f64::consts::FRAC_1_PI;
 pub use std::f64::consts::PI;
 
@@ -0,0 +1,64 @@
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, ImageResult, RgbImage};
+use std::path::Path;
+use std::process::Command;
+use std::{fs, io};
+
+const GIF_SPEED: i32 = 10;
+
+// 連番の出力先の拡張子から、まとめて書き出す動画の形式を決める
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    // ffmpeg で符号化する
+    Video,
+}
+
+impl AnimationFormat {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path)
+            .extension()?
+            .to_string_lossy()
+            .to_lowercase();
+        match extension.as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "mp4" | "mov" | "webm" => Some(AnimationFormat::Video),
+            _ => None,
+        }
+    }
+}
+
+// 何度も繰り返す GIF にする
+pub fn encode_gif(frames: &[RgbImage], fps: f64, path: &str) -> ImageResult<()> {
+    let file = io::BufWriter::new(fs::File::create(path)?);
+    let mut encoder = GifEncoder::new_with_speed(file, GIF_SPEED);
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(1000, fps.round().max(1.0) as u32);
+    encoder.encode_frames(frames.iter().map(|frame| {
+        let rgba = DynamicImage::ImageRgb8(frame.clone()).to_rgba8();
+        Frame::from_parts(rgba, 0, 0, delay)
+    }))
+}
+
+// pattern は ffmpeg の連番の指定 (render_%04d.png)
+pub fn encode_video(pattern: &str, fps: f64, path: &str) -> io::Result<()> {
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-framerate"])
+        .arg(fps.to_string())
+        .arg("-i")
+        .arg(pattern)
+        // yuv420p は幅と高さが偶数でないといけない
+        .args([
+            "-vf",
+            "scale=trunc(iw/2)*2:trunc(ih/2)*2",
+            "-pix_fmt",
+            "yuv420p",
+        ])
+        .arg(path)
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("ffmpeg exited with {}", status)))
+    }
+}
@@ -0,0 +1,148 @@
+use crate::rayt::*;
+
+use image::{ImageResult, Rgb, Rgb32FImage, RgbImage};
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// 一次光線が最初に当たった点の情報
+#[derive(Debug, Clone, Copy)]
+pub struct Aov {
+    pub normal: Vec3,
+    // カメラからの距離
+    pub depth: f64,
+    pub albedo: Color,
+    // 物体 (マテリアル) ごとの番号
+    pub id: u32,
+    pub uv: (f64, f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum AovPass {
+    Normal,
+    Depth,
+    Albedo,
+    Id,
+}
+
+impl AovPass {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "normal" => Some(AovPass::Normal),
+            "depth" => Some(AovPass::Depth),
+            "albedo" => Some(AovPass::Albedo),
+            "id" => Some(AovPass::Id),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AovPass::Normal => "normal",
+            AovPass::Depth => "depth",
+            AovPass::Albedo => "albedo",
+            AovPass::Id => "id",
+        }
+    }
+
+    // 深度は 1 を超えるので float のまま書き出す
+    pub fn extension(self) -> &'static str {
+        match self {
+            AovPass::Depth => "exr",
+            _ => "png",
+        }
+    }
+}
+
+// 画素ごとに spp 本の一次光線で平均した AOV
+// 何にも当たらなかったサンプルは 0 として平均する。id だけは画素の中心で決める
+pub struct AovBuffers {
+    pub width: u32,
+    pub height: u32,
+    pub normal: Vec<Vec3>,
+    pub depth: Vec<f64>,
+    pub albedo: Vec<Color>,
+    pub id: Vec<Option<u32>>,
+}
+
+impl AovBuffers {
+    pub fn render(scene: &(impl SceneWithDepth + Sync)) -> Self {
+        let camera = scene.camera();
+        let (w, h, spp) = (scene.width(), scene.height(), scene.spp());
+        let pixels = (0..w * h)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = ((i % w) as f64, (i / w) as f64);
+                let (mut normal, mut depth, mut albedo) = (Vec3::zero(), 0.0, Color::zero());
+                for sample in 0..spp {
+                    let ray = primary_ray(scene, &*camera, i % w, i / w, sample);
+                    if let Some(aov) = scene.aov(&ray) {
+                        normal += aov.normal;
+                        depth += aov.depth;
+                        albedo += aov.albedo;
+                    }
+                }
+                let u = (x + 0.5) / (w - 1) as f64;
+                let v = ((h - 1) as f64 - y + 0.5) / (h - 1) as f64;
+                let id = scene.aov(&camera.center_ray(u, v)).map(|aov| aov.id);
+                let n = spp as f64;
+                (normal / n, depth / n, albedo / n, id)
+            })
+            .collect::<Vec<_>>();
+        let mut buffers = Self {
+            width: w,
+            height: h,
+            normal: Vec::with_capacity(pixels.len()),
+            depth: Vec::with_capacity(pixels.len()),
+            albedo: Vec::with_capacity(pixels.len()),
+            id: Vec::with_capacity(pixels.len()),
+        };
+        for (normal, depth, albedo, id) in pixels {
+            buffers.normal.push(normal);
+            buffers.depth.push(depth);
+            buffers.albedo.push(albedo);
+            buffers.id.push(id);
+        }
+        buffers
+    }
+
+    // 法線は [-1, 1] を [0, 1] に、id は番号ごとに適当な色にする
+    pub fn save(&self, pass: AovPass, path: &str) -> ImageResult<()> {
+        let (w, h) = (self.width, self.height);
+        match pass {
+            AovPass::Normal => {
+                to_rgb_image(w, h, self.normal.iter().map(|n| (*n + Vec3::one()) * 0.5))
+            }
+            AovPass::Albedo => to_rgb_image(w, h, self.albedo.iter().copied()),
+            AovPass::Id => to_rgb_image(w, h, self.id.iter().map(|id| id_color(*id))),
+            AovPass::Depth => {
+                let data = self.depth.iter().flat_map(|d| [*d as f32; 3]).collect();
+                return Rgb32FImage::from_raw(w, h, data).unwrap().save(path);
+            }
+        }
+        .save(path)
+    }
+}
+
+fn to_rgb_image(w: u32, h: u32, colors: impl Iterator<Item = Color>) -> RgbImage {
+    let mut img = RgbImage::new(w, h);
+    for (pixel, color) in img.pixels_mut().zip(colors) {
+        *pixel = Rgb(color.saturate().to_rgb());
+    }
+    img
+}
+
+fn id_color(id: Option<u32>) -> Color {
+    match id {
+        Some(id) => {
+            let [r, g, b, ..] = pixel_seed(id as u64, 0, 0).to_le_bytes();
+            Color::new(r as f64, g as f64, b as f64) / 255.0
+        }
+        None => Color::zero(),
+    }
+}
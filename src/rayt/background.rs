@@ -0,0 +1,261 @@
+use crate::rayt::*;
+
+use std::sync::Arc;
+
+// 光線がどこにも当たらなかったときの色 (trace_scene が呼ぶ)
+pub trait Background: Sync + Send {
+    fn value(&self, d: Vec3) -> Color;
+    // シーンファイルでの書き方。書けないものは None
+    fn describe(&self) -> Option<BackgroundSpec> {
+        None
+    }
+    // 背景の中で特に明るい部分 (太陽など)。影の光線で直接狙えるように光源として返す
+    // value にはその部分を含めない
+    fn light(&self) -> Option<&dyn Light> {
+        None
+    }
+}
+
+pub struct ColorBackground {
+    color: Color,
+}
+
+impl ColorBackground {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Background for ColorBackground {
+    fn value(&self, _d: Vec3) -> Color {
+        self.color
+    }
+
+    fn describe(&self) -> Option<BackgroundSpec> {
+        Some(BackgroundSpec::Color(self.color.to_array()))
+    }
+}
+
+// 真下の bottom から真上の top へ、方向の y で線形に補間する
+pub struct GradientBackground {
+    bottom: Color,
+    top: Color,
+}
+
+impl GradientBackground {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Background for GradientBackground {
+    fn value(&self, d: Vec3) -> Color {
+        let t = 0.5 * (d.normalize().y() + 1.0);
+        self.bottom.lerp(self.top, t)
+    }
+
+    fn describe(&self) -> Option<BackgroundSpec> {
+        Some(BackgroundSpec::Gradient {
+            bottom: self.bottom.to_array(),
+            top: self.top.to_array(),
+        })
+    }
+}
+
+// 正距円筒図法のパノラマ画像 (to-cubemap に渡すものと同じ向き)
+// 8bit の画像なので、明るさは intensity で補う
+pub struct EnvironmentMap {
+    path: String,
+    image: Arc<SharedImage>,
+    intensity: f64,
+}
+
+impl EnvironmentMap {
+    pub fn new(path: &str, intensity: f64) -> Result<Self, Error> {
+        Ok(Self {
+            path: path.to_string(),
+            image: TextureCache::global().get(path)?,
+            intensity,
+        })
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn value(&self, d: Vec3) -> Color {
+        let (u, v) = equirect_uv(d);
+        let color = self
+            .image
+            .get()
+            .filtered(u, 1.0 - v, WrapMode::Repeat, FilterMode::Bilinear);
+        color * self.intensity
+    }
+
+    fn describe(&self) -> Option<BackgroundSpec> {
+        Some(BackgroundSpec::Environment {
+            environment: self.path.clone(),
+            intensity: self.intensity,
+        })
+    }
+}
+
+// Preetham らの昼の空のモデル。sun は太陽へ向かう方向、turbidity は霞み具合 (2 で快晴、10 で霞)
+// 空の明るさは kcd/m² を SKY_SCALE 倍したもので、intensity で太陽と一緒に調整する
+// 太陽の円盤は value に含めず、light() の平行光源として影の光線で直接狙う
+pub struct PhysicalSky {
+    sun: Vec3,
+    turbidity: f64,
+    intensity: f64,
+    // 天頂の xyY と、太陽の位置で決まる Perez の式の正規化
+    // f32 でビルドしても Preetham の式は f64 で計算する
+    zenith: [f64; 3],
+    perez: [PerezCoefficients; 3],
+    sun_light: DirectionalLight,
+}
+
+const SKY_SCALE: f64 = 0.05;
+// 大気の外での太陽の放射照度 (SKY_SCALE に合わせた値)
+const SUN_IRRADIANCE: f64 = 6.0;
+// 太陽の見かけの半径 (度)
+const SUN_ANGULAR_RADIUS: f64 = 0.27;
+
+#[derive(Debug, Clone, Copy)]
+struct PerezCoefficients([f64; 5]);
+
+impl PerezCoefficients {
+    fn new(turbidity: f64, coefficients: [[f64; 2]; 5]) -> Self {
+        Self(coefficients.map(|[a, b]| a * turbidity + b))
+    }
+
+    // theta は天頂角、gamma は太陽との角度
+    fn eval(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+}
+
+impl PhysicalSky {
+    pub fn new(sun: Vec3, turbidity: f64, intensity: f64) -> Self {
+        let sun = sun.normalize();
+        let turbidity = turbidity.clamp(1.7, 10.0);
+        // 地平線より下の太陽は地平線に置く
+        let theta_s = sun.y().clamp(0.0, 1.0).acos();
+        let t = turbidity;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let theta = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let row = |r: [f64; 4]| r.iter().zip(theta).map(|(a, b)| a * b).sum::<f64>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_yc = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let perez = [
+            PerezCoefficients::new(
+                t,
+                [
+                    [-0.0193, -0.2592],
+                    [-0.0665, 0.0008],
+                    [-0.0004, 0.2125],
+                    [-0.0641, -0.8989],
+                    [-0.0033, 0.0452],
+                ],
+            ),
+            PerezCoefficients::new(
+                t,
+                [
+                    [-0.0167, -0.2608],
+                    [-0.0950, 0.0092],
+                    [-0.0079, 0.2102],
+                    [-0.0441, -1.6537],
+                    [-0.0109, 0.0529],
+                ],
+            ),
+            PerezCoefficients::new(
+                t,
+                [
+                    [0.1787, -1.4630],
+                    [-0.3554, 0.4275],
+                    [-0.0227, 5.3251],
+                    [0.1206, -2.5771],
+                    [-0.0670, 0.3703],
+                ],
+            ),
+        ];
+        // 天頂での値が zenith になるように割っておく
+        let zenith = [zenith_x, zenith_yc, zenith_y];
+        let zenith = std::array::from_fn(|i| zenith[i] / perez[i].eval(1.0, theta_s));
+
+        let irradiance = if sun.y() > 0.0 {
+            Self::sun_transmittance(theta_s, t) * SUN_IRRADIANCE * intensity
+        } else {
+            Color::zero()
+        };
+        let sun_light =
+            DirectionalLight::new(-sun, irradiance).with_angular_radius(SUN_ANGULAR_RADIUS);
+        Self {
+            sun,
+            turbidity,
+            intensity,
+            zenith,
+            perez,
+            sun_light,
+        }
+    }
+
+    // 大気を通った太陽光の透過率。赤、緑、青を 680, 550, 440 nm で代表させる
+    // レイリー散乱とエアロゾルの消散だけを考える (Preetham らの付録)
+    fn sun_transmittance(theta_s: f64, turbidity: f64) -> Color {
+        let degrees = theta_s.to_degrees();
+        let mass = (theta_s.cos() + 0.15 * (93.885 - degrees).max(1e-3).powf(-1.253)).recip();
+        let beta = 0.04608 * turbidity - 0.04586;
+        Color::from_iter([0.68, 0.55, 0.44].into_iter().map(|lambda: f64| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            rayleigh * aerosol
+        }))
+    }
+}
+
+impl Background for PhysicalSky {
+    fn value(&self, d: Vec3) -> Color {
+        // 地平線より下は地平線の色で埋める
+        let d = d.normalize();
+        let d = Vec3::new(d.x(), d.y().max(1e-3), d.z()).normalize();
+        let gamma = d.dot(self.sun).clamp(-1.0, 1.0).acos();
+        let [x, y, luminance] =
+            std::array::from_fn(|i| self.zenith[i] * self.perez[i].eval(d.y(), gamma));
+        if y <= 0.0 {
+            return Color::zero();
+        }
+        // xyY から XYZ、線形 sRGB へ
+        let (cx, cy, cz) = (x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        let rgb = [
+            3.2406 * cx - 1.5372 * cy - 0.4986 * cz,
+            -0.9689 * cx + 1.8758 * cy + 0.0415 * cz,
+            0.0557 * cx - 0.2040 * cy + 1.0570 * cz,
+        ];
+        Color::from_iter(rgb.map(|c| c.max(0.0))) * SKY_SCALE * self.intensity
+    }
+
+    fn describe(&self) -> Option<BackgroundSpec> {
+        Some(BackgroundSpec::Sky {
+            sun: self.sun.to_array(),
+            turbidity: self.turbidity,
+            intensity: self.intensity,
+        })
+    }
+
+    fn light(&self) -> Option<&dyn Light> {
+        Some(&self.sun_light)
+    }
+}
@@ -0,0 +1,485 @@
+use crate::rayt::*;
+
+use std::sync::Arc;
+
+pub struct ShapeBuilder {
+    pub texture: Option<Box<dyn Texture>>,
+    pub mask: Option<AlphaMask>,
+    pub material: Option<Arc<dyn Material>>,
+    pub shape: Option<ShapeEnum>,
+    pub seed: u64,
+    // 最初に起きたエラー。以降の呼び出しは何もせず、build で返す
+    error: Option<Error>,
+}
+
+impl ShapeBuilder {
+    pub fn new() -> Self {
+        Self {
+            texture: None,
+            mask: None,
+            material: None,
+            shape: None,
+            seed: 0,
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, error: Error) {
+        self.error.get_or_insert(error);
+    }
+
+    fn take_texture(&mut self, step: &str) -> Option<Box<dyn Texture>> {
+        let texture = self.texture.take();
+        if texture.is_none() {
+            self.fail(Error::Invalid(format!("{} needs a texture", step)));
+        }
+        texture
+    }
+
+    fn take_material(&mut self, step: &str) -> Option<Arc<dyn Material>> {
+        let material = self.material.take();
+        if material.is_none() {
+            self.fail(Error::Invalid(format!("{} needs a material", step)));
+        }
+        material
+    }
+
+    // 今の形状を f で包む
+    fn decorate<S: Shape + 'static>(
+        mut self,
+        step: &str,
+        f: impl FnOnce(&mut Self, Box<dyn Shape>) -> S,
+    ) -> Self {
+        match self.shape.take() {
+            Some(shape) => {
+                let shape = f(&mut self, shape.into_box());
+                self.shape = Some(ShapeEnum::boxed(shape));
+            }
+            None => self.fail(Error::Invalid(format!("{} needs a shape", step))),
+        }
+        self
+    }
+
+    // 今のマテリアルで形状を作る
+    fn with_material(mut self, step: &str, f: impl FnOnce(Arc<dyn Material>) -> ShapeEnum) -> Self {
+        if let Some(material) = self.take_material(step) {
+            self.shape = Some(f(material));
+        }
+        self
+    }
+
+    // textures
+
+    // 以降に作る手続き型テクスチャの seed
+    pub fn noise_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn color_texture(mut self, color: Color) -> Self {
+        self.texture = Some(Box::new(ColorTexture::new(color)));
+        self
+    }
+
+    // 色温度 (K) と輝度で光源色を指定する
+    pub fn blackbody_texture(mut self, kelvin: f64, intensity: f64) -> Self {
+        self.texture = Some(Box::new(ColorTexture::new(
+            Color::from_temperature(kelvin) * intensity,
+        )));
+        self
+    }
+
+    pub fn checker_texture(mut self, odd_color: Color, even_color: Color, freq: f64) -> Self {
+        self.texture = Some(Box::new(CheckerTexture::new(
+            Box::new(ColorTexture::new(odd_color)),
+            Box::new(ColorTexture::new(even_color)),
+            freq,
+        )));
+        self
+    }
+
+    pub fn noise_texture(mut self, scale: f64) -> Self {
+        self.texture = Some(Box::new(NoiseTexture::new(scale, self.seed)));
+        self
+    }
+
+    pub fn marble_texture(mut self, scale: f64, axis: Vec3) -> Self {
+        self.texture = Some(Box::new(MarbleTexture::new(scale, axis, self.seed)));
+        self
+    }
+
+    pub fn worley_texture(mut self, scale: f64) -> Self {
+        self.texture = Some(Box::new(WorleyTexture::new(scale, self.seed)));
+        self
+    }
+
+    pub fn brick_texture(
+        mut self,
+        brick_color: Color,
+        mortar_color: Color,
+        width: f64,
+        height: f64,
+        mortar_width: f64,
+        offset: f64,
+    ) -> Self {
+        self.texture = Some(Box::new(BrickTexture::new(
+            Box::new(ColorTexture::new(brick_color)),
+            Box::new(ColorTexture::new(mortar_color)),
+            width,
+            height,
+            mortar_width,
+            offset,
+        )));
+        self
+    }
+
+    pub fn image_texture(self, path: &str) -> Self {
+        self.image_texture_wrapped(path, WrapMode::Clamp)
+    }
+
+    pub fn image_texture_wrapped(self, path: &str, wrap: WrapMode) -> Self {
+        self.image_texture_filtered(path, wrap, FilterMode::Nearest)
+    }
+
+    pub fn image_texture_filtered(
+        mut self,
+        path: &str,
+        wrap: WrapMode,
+        filter: FilterMode,
+    ) -> Self {
+        match ImageTexture::with_sampling(path, wrap, filter) {
+            Ok(texture) => self.texture = Some(Box::new(texture)),
+            Err(e) => self.fail(e),
+        }
+        self
+    }
+
+    pub fn uv_scale(mut self, u: f64, v: f64) -> Self {
+        if let Some(texture) = self.take_texture("uv_scale") {
+            let mut transform = UvTransform::new(texture);
+            transform.scale = (u, v);
+            self.texture = Some(Box::new(transform));
+        }
+        self
+    }
+
+    pub fn uv_offset(mut self, u: f64, v: f64) -> Self {
+        if let Some(texture) = self.take_texture("uv_offset") {
+            let mut transform = UvTransform::new(texture);
+            transform.offset = (u, v);
+            self.texture = Some(Box::new(transform));
+        }
+        self
+    }
+
+    pub fn uv_rotate(mut self, angle: f64) -> Self {
+        if let Some(texture) = self.take_texture("uv_rotate") {
+            let mut transform = UvTransform::new(texture);
+            transform.rotation = angle.to_radians();
+            self.texture = Some(Box::new(transform));
+        }
+        self
+    }
+
+    pub fn triplanar(mut self, scale: f64, sharpness: f64) -> Self {
+        if let Some(texture) = self.take_texture("triplanar") {
+            self.texture = Some(Box::new(TriplanarTexture::new(texture, scale, sharpness)));
+        }
+        self
+    }
+
+    pub fn alpha_mask(mut self, threshold: f64) -> Self {
+        if let Some(texture) = self.take_texture("alpha_mask") {
+            self.mask = Some(AlphaMask::new(texture, threshold));
+        }
+        self
+    }
+
+    pub fn streamed_texture(mut self, path: &str, max_tiles: usize) -> Self {
+        match StreamedTexture::new(path, max_tiles) {
+            Ok(texture) => self.texture = Some(Box::new(texture)),
+            Err(e) => self.fail(e),
+        }
+        self
+    }
+
+    pub fn diffuse_light(mut self) -> Self {
+        if let Some(texture) = self.take_texture("diffuse_light") {
+            self.material = Some(Arc::new(DiffusedLight::new(texture)));
+        }
+        self
+    }
+
+    // color_texture(color).diffuse_light() と同じ
+    pub fn light(self, color: Color) -> Self {
+        self.color_texture(color).diffuse_light()
+    }
+
+    pub fn material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = Some(material);
+        self.texture = None;
+        self
+    }
+
+    // Material
+
+    pub fn lambertian(mut self) -> Self {
+        if let Some(texture) = self.take_texture("lambertian") {
+            self.material = Some(Arc::new(
+                Lambertian::new(texture).with_mask(self.mask.take()),
+            ));
+        }
+        self
+    }
+    pub fn metal(mut self, fuzz: f64) -> Self {
+        if let Some(texture) = self.take_texture("metal") {
+            self.material = Some(Arc::new(
+                Metal::new(texture, Box::new(ColorTexture::new(Color::fill(fuzz))))
+                    .with_mask(self.mask.take()),
+            ));
+        }
+        self
+    }
+    pub fn metal_textured(mut self, albedo: Box<dyn Texture>, fuzz: Box<dyn Texture>) -> Self {
+        // テクスチャを取らないので、前の手順の失敗はここで止める
+        if self.error.is_some() {
+            return self;
+        }
+        self.material = Some(Arc::new(
+            Metal::new(albedo, fuzz).with_mask(self.mask.take()),
+        ));
+        self.texture = None;
+        self
+    }
+    pub fn velvet(mut self, sheen: Color) -> Self {
+        if let Some(texture) = self.take_texture("velvet") {
+            self.material = Some(Arc::new(Velvet::new(
+                texture,
+                Box::new(ColorTexture::new(sheen)),
+            )));
+        }
+        self
+    }
+    pub fn dielectric(mut self, ri: f64) -> Self {
+        self.material = Some(Arc::new(Dielectric::new(ri)));
+        self
+    }
+    pub fn tinted_dielectric(mut self, ri: f64, tint: Color) -> Self {
+        self.material = Some(Arc::new(Dielectric::tinted(ri, tint)));
+        self
+    }
+    // 他の誘電体と重ねて置くもの (グラスの中の水と氷など)。重なりでは priority の高いほうが中身
+    pub fn nested_dielectric(mut self, ri: f64, tint: Color, priority: u32) -> Self {
+        self.material = Some(Arc::new(
+            Dielectric::tinted(ri, tint).with_priority(priority),
+        ));
+        self
+    }
+    pub fn emissive(mut self) -> Self {
+        if let (Some(material), Some(texture)) = (
+            self.take_material("emissive"),
+            self.take_texture("emissive"),
+        ) {
+            self.material = Some(Arc::new(Emissive::new(material, texture)));
+        }
+        self
+    }
+
+    // 作ったマテリアルを storage に入れて番号を返す
+    pub fn add_material(self, storage: &mut SceneStorage) -> Result<MaterialId, Error> {
+        Ok(storage.add_material(self.build_material()?))
+    }
+
+    // storage に入れてあるマテリアルを使う
+    pub fn material_id(self, storage: &SceneStorage, id: MaterialId) -> Self {
+        self.material(Arc::clone(storage.material(id)))
+    }
+
+    // 作ったマテリアルで storage に球を足す
+    pub fn add_sphere(
+        self,
+        storage: &mut SceneStorage,
+        center: Point3,
+        radius: f64,
+    ) -> Result<ShapeId, Error> {
+        let material = self.add_material(storage)?;
+        Ok(storage.add_sphere(center, radius, material))
+    }
+
+    // 作った形状を storage に足す
+    pub fn add_to(self, storage: &mut SceneStorage) -> Result<ShapeId, Error> {
+        Ok(storage.add_shape(self.build()?))
+    }
+
+    // shapes
+
+    pub fn sphere(self, center: Point3, radius: f64) -> Self {
+        self.with_material("sphere", |material| {
+            ShapeEnum::Sphere(Sphere::new(center, radius, material))
+        })
+    }
+
+    pub fn moving_sphere(
+        self,
+        center0: Point3,
+        center1: Point3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+    ) -> Self {
+        self.with_material("moving_sphere", |material| {
+            ShapeEnum::MovingSphere(MovingSphere::new(
+                Sphere::new(center0, radius, material),
+                center1,
+                time0,
+                time1,
+            ))
+        })
+    }
+
+    pub fn rect_xy(self, x0: f64, x1: f64, y0: f64, y1: f64, k: f64) -> Self {
+        self.with_material("rect_xy", |material| {
+            ShapeEnum::Rect(Rect::new(x0, x1, y0, y1, k, RectAxisType::XY, material))
+        })
+    }
+
+    pub fn rect_xz(self, x0: f64, x1: f64, z0: f64, z1: f64, k: f64) -> Self {
+        self.with_material("rect_xz", |material| {
+            ShapeEnum::Rect(Rect::new(x0, x1, z0, z1, k, RectAxisType::XZ, material))
+        })
+    }
+
+    pub fn rect_yz(self, y0: f64, y1: f64, z0: f64, z1: f64, k: f64) -> Self {
+        self.with_material("rect_yz", |material| {
+            ShapeEnum::Rect(Rect::new(y0, y1, z0, z1, k, RectAxisType::YZ, material))
+        })
+    }
+
+    pub fn box_shape(self, p0: Point3, p1: Point3) -> Self {
+        self.with_material("box_shape", |material| {
+            ShapeEnum::Box3D(Box3D::new(p0, p1, material))
+        })
+    }
+
+    // マテリアルは読み込むときに決めてあるので、ここでは使わない
+    pub fn mesh(mut self, mesh: Mesh) -> Self {
+        self.shape = Some(ShapeEnum::boxed(mesh));
+        self.material = None;
+        self
+    }
+
+    // マテリアルがあればすべての面に使い、なければ MTL のマテリアルを使う
+    pub fn obj(mut self, path: &str) -> Self {
+        match load_obj(path, self.material.take()) {
+            Ok(mesh) => self.shape = Some(ShapeEnum::boxed(mesh)),
+            Err(e) => self.fail(e),
+        }
+        self
+    }
+
+    // decorators
+
+    pub fn flip_face(self) -> Self {
+        self.decorate("flip_face", |_, shape| FlipFace::new(shape))
+    }
+
+    pub fn single_sided(self) -> Self {
+        self.decorate("single_sided", |_, shape| BackFace::new(shape, None))
+    }
+
+    pub fn back_material(self) -> Self {
+        self.decorate("back_material", |builder, shape| {
+            BackFace::new(shape, builder.material.take())
+        })
+    }
+
+    pub fn translate(self, offset: Point3) -> Self {
+        self.decorate("translate", |_, shape| Translate::new(shape, offset))
+    }
+
+    pub fn rotate(self, axis: Vec3, angle: f64) -> Self {
+        self.decorate("rotate", |_, shape| Rotate::new(shape, axis, angle))
+    }
+
+    pub fn rotate_y(self, angle: f64) -> Self {
+        self.rotate(Vec3::yaxis(), angle)
+    }
+
+    pub fn opacity(self, opacity: f64) -> Self {
+        self.decorate("opacity", |_, shape| StochasticAlpha::new(shape, opacity))
+    }
+
+    pub fn motion(self, velocity: Vec3) -> Self {
+        self.decorate("motion", |_, shape| Motion::new(shape, velocity))
+    }
+
+    // 形状の内側を texture の色の媒質で満たす
+    pub fn constant_medium(mut self, density: f64) -> Self {
+        let Some(texture) = self.texture.take() else {
+            self.fail(Error::Invalid(
+                "constant_medium needs a texture".to_string(),
+            ));
+            return self;
+        };
+        self.decorate("constant_medium", |_, shape| {
+            ConstantMedium::new(shape, density, Arc::new(Isotropic::new(texture)))
+        })
+    }
+
+    // 形状の内側を texture の色の雲で満たす。濃さは Perlin ノイズで決め、density が最大
+    pub fn noise_medium(self, density: f64, scale: f64) -> Self {
+        let field = NoiseTexture::new(scale, self.seed);
+        self.heterogeneous_medium("noise_medium", density, Box::new(field))
+    }
+
+    // 形状の内側を texture の色の煙で満たす。濃さは .vol ファイルの格子で決める
+    pub fn voxel_medium(mut self, density: f64, path: &str) -> Self {
+        match load_voxel_grid(path) {
+            Ok(grid) => self.heterogeneous_medium("voxel_medium", density, Box::new(grid)),
+            Err(e) => {
+                self.fail(e);
+                self
+            }
+        }
+    }
+
+    fn heterogeneous_medium(mut self, step: &str, density: f64, field: Box<dyn Texture>) -> Self {
+        let Some(texture) = self.take_texture(step) else {
+            return self;
+        };
+        self.decorate(step, |_, shape| {
+            HeterogeneousMedium::new(shape, density, field, Arc::new(Isotropic::new(texture)))
+        })
+    }
+
+    pub fn matte(self, name: &'static str) -> Self {
+        self.decorate("matte", |_, shape| Matte::new(shape, name))
+    }
+
+    // build
+
+    pub fn build(self) -> Result<ShapeEnum, Error> {
+        match (self.error, self.shape) {
+            (Some(e), _) => Err(e),
+            (None, Some(shape)) => Ok(shape),
+            (None, None) => Err(Error::Invalid("build needs a shape".to_string())),
+        }
+    }
+
+    // 形状を作らずにマテリアルだけを取り出す (モデルの読み込みなど)
+    pub fn build_material(self) -> Result<Arc<dyn Material>, Error> {
+        match (self.error, self.material) {
+            (Some(e), _) => Err(e),
+            (None, Some(material)) => Ok(material),
+            (None, None) => Err(Error::Invalid(
+                "build_material needs a material".to_string(),
+            )),
+        }
+    }
+}
+
+impl Default for ShapeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,20 +1,411 @@
 use crate::rayt::*;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// シャッターが開いている時間
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Shutter {
+    pub open: f64,
+    pub close: f64,
+}
+
+impl Shutter {
+    pub fn new(open: f64, close: f64) -> Self {
+        Self { open, close }
+    }
+
+    // シャッターが一瞬のときは乱数を消費しない
+    pub fn sample(&self) -> f64 {
+        if self.close > self.open {
+            self.open + random_f64() * (self.close - self.open)
+        } else {
+            self.open
+        }
+    }
+}
+
+// u, v はスクリーン座標 (左下が (0, 0)、右上が (1, 1))
+pub trait Camera: Send + Sync {
+    // lens はレンズ上の点のオフセット (ワールド座標)
+    fn ray_through(&self, u: f64, v: f64, lens: Vec3, time: f64) -> Ray;
+    // ray_through の逆で、p が写るスクリーン座標を返す。写らなければ None
+    fn project(&self, p: Point3) -> Option<(f64, f64)>;
+    // スクリーンの右向きの単位ベクトル (偏光フィルタの角度の基準)
+    fn right(&self) -> Vec3;
+    // ピンホールのときは乱数を消費しない
+    fn sample_lens(&self) -> Vec3 {
+        Vec3::zero()
+    }
+    fn shutter(&self) -> Shutter {
+        Shutter::default()
+    }
+    // 右に offset だけずらした目のカメラ。convergence の距離で左右の像が重なる
+    // 対応していないカメラは None
+    fn stereo_eye(&self, _offset: f64, _convergence: f64) -> Option<Box<dyn Camera>> {
+        None
+    }
+    // シーンを書き出すときに使う視点と画角。透視投影のカメラだけが返す
+    fn look_at(&self) -> Option<LookAt> {
+        None
+    }
+
+    fn ray(&self, u: f64, v: f64) -> Ray {
+        self.ray_through(u, v, self.sample_lens(), self.shutter().sample())
+    }
+
+    // du, dv は 1 画素分のスクリーン座標の幅
+    // 隣の光線もレンズ上の同じ点から同じ時刻に出す
+    fn ray_with_differential(&self, u: f64, v: f64, du: f64, dv: f64) -> Ray {
+        let lens = self.sample_lens();
+        let time = self.shutter().sample();
+        let rx = self.ray_through(u + du, v, lens, time);
+        let ry = self.ray_through(u, v + dv, lens, time);
+        self.ray_through(u, v, lens, time)
+            .with_differential(Some(RayDifferential {
+                rx_origin: rx.origin,
+                rx_direction: rx.direction,
+                ry_origin: ry.origin,
+                ry_direction: ry.direction,
+            }))
+    }
+
+    // レンズの中心からシャッターが開いた時刻に出す光線 (AOV 用)
+    fn center_ray(&self, u: f64, v: f64) -> Ray {
+        self.ray_through(u, v, Vec3::zero(), self.shutter().open)
+    }
+}
+
+impl<C: Camera + ?Sized> Camera for Arc<C> {
+    fn ray_through(&self, u: f64, v: f64, lens: Vec3, time: f64) -> Ray {
+        (**self).ray_through(u, v, lens, time)
+    }
+    fn project(&self, p: Point3) -> Option<(f64, f64)> {
+        (**self).project(p)
+    }
+    fn right(&self) -> Vec3 {
+        (**self).right()
+    }
+    fn sample_lens(&self) -> Vec3 {
+        (**self).sample_lens()
+    }
+    fn shutter(&self) -> Shutter {
+        (**self).shutter()
+    }
+    fn stereo_eye(&self, offset: f64, convergence: f64) -> Option<Box<dyn Camera>> {
+        (**self).stereo_eye(offset, convergence)
+    }
+    fn look_at(&self) -> Option<LookAt> {
+        (**self).look_at()
+    }
+}
+
+// from_look_at_with_lens に渡す値。look_at はピントの合う面の中心
+#[derive(Debug, Clone, Copy)]
+pub struct LookAt {
+    pub origin: Point3,
+    pub look_at: Point3,
+    pub up: Vec3,
+    pub fov: f64,
+    pub aperture: f64,
+}
+
+// プレビューで歩き回るためのカメラ
+// 向きは最初の up まわりの yaw と、up から測った仰角 pitch で持つので、上下が転がることはない
+#[derive(Debug, Clone, Copy)]
+pub struct FlyCamera {
+    origin: Point3,
+    up: Vec3,
+    // yaw = 0 の前向きと右向き (どちらも up に垂直)
+    front: Vec3,
+    right: Vec3,
+    yaw: f64,
+    pitch: f64,
+    // 最初のピントまでの距離
+    distance: f64,
+    // 1 歩で進む距離
+    step: f64,
+    fov: f64,
+    aperture: f64,
+}
+
+const FLY_STEP: f64 = 0.05;
+const FLY_MAX_PITCH: f64 = 1.55;
+
+impl FlyCamera {
+    pub fn new(look: LookAt) -> Self {
+        let up = look.up.normalize();
+        let forward = look.look_at - look.origin;
+        let distance = forward.length();
+        let forward = forward / distance;
+        let flat = forward - forward.dot(up) * up;
+        // 真上か真下を向いていたら、適当な水平方向を yaw = 0 にする
+        let front = if flat.near_zero() {
+            Onb::from_w(up).u()
+        } else {
+            flat.normalize()
+        };
+        Self {
+            origin: look.origin,
+            up,
+            front,
+            right: front.cross(up),
+            yaw: 0.0,
+            pitch: forward.dot(up).clamp(-1.0, 1.0).asin(),
+            distance,
+            // ピントの距離が既定の 1 のままのカメラも多いので、原点までの距離も見て決める
+            step: FLY_STEP * distance.max(look.origin.length()),
+            fov: look.fov,
+            aperture: look.aperture,
+        }
+    }
+
+    fn direction(&self) -> Vec3 {
+        let (sy, cy) = self.yaw.sin_cos();
+        let (sp, cp) = self.pitch.sin_cos();
+        cp * (cy * self.front + sy * self.right) + sp * self.up
+    }
+
+    // right, up, forward は 1 で 1 歩、yaw と pitch はラジアン (右、上が正)
+    pub fn fly(&mut self, right: f64, up: f64, forward: f64, yaw: f64, pitch: f64) {
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).clamp(-FLY_MAX_PITCH, FLY_MAX_PITCH);
+        let direction = self.direction();
+        let side = direction.cross(self.up).normalize();
+        self.origin += self.step * (right * side + up * self.up + forward * direction);
+    }
+
+    pub fn look_at(&self) -> LookAt {
+        LookAt {
+            origin: self.origin,
+            look_at: self.origin + self.distance * self.direction(),
+            up: self.up,
+            fov: self.fov,
+            aperture: self.aperture,
+        }
+    }
+
+    pub fn camera(&self, aspect: f64) -> PerspectiveCamera {
+        let look = self.look_at();
+        PerspectiveCamera::from_look_at_with_lens(
+            look.origin,
+            look.look_at,
+            look.up,
+            look.fov,
+            aspect,
+            look.aperture,
+            self.distance,
+        )
+    }
+}
+
+// 縦の範囲はそのままで、横だけを scale 倍に広げたカメラ
+// 別の縦横比の画像に描くときに使い、はみ出した u は元のカメラの画面外を写す
+pub struct ReframedCamera {
+    inner: Arc<dyn Camera>,
+    scale: f64,
+}
+
+impl ReframedCamera {
+    pub fn new(inner: Arc<dyn Camera>, scale: f64) -> Self {
+        Self { inner, scale }
+    }
+}
+
+impl Camera for ReframedCamera {
+    fn ray_through(&self, u: f64, v: f64, lens: Vec3, time: f64) -> Ray {
+        let u = 0.5 + (u - 0.5) * self.scale;
+        self.inner.ray_through(u, v, lens, time)
+    }
+    fn project(&self, p: Point3) -> Option<(f64, f64)> {
+        let (u, v) = self.inner.project(p)?;
+        Some((0.5 + (u - 0.5) / self.scale, v))
+    }
+    fn right(&self) -> Vec3 {
+        self.inner.right()
+    }
+    fn sample_lens(&self) -> Vec3 {
+        self.inner.sample_lens()
+    }
+    fn shutter(&self) -> Shutter {
+        self.inner.shutter()
+    }
+    fn stereo_eye(&self, offset: f64, convergence: f64) -> Option<Box<dyn Camera>> {
+        let eye = self.inner.stereo_eye(offset, convergence)?;
+        Some(Box::new(Self::new(Arc::from(eye), self.scale)))
+    }
+}
+
+// Brown-Conrady モデル。k1, k2 は放射方向、p1, p2 は接線方向の歪み
+// 係数は焦点距離で正規化した座標に対するもので、OpenCV のキャリブレーション結果をそのまま使える
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LensDistortion {
+    pub k1: f64,
+    pub k2: f64,
+    pub p1: f64,
+    pub p2: f64,
+}
+
+impl LensDistortion {
+    pub fn new(k1: f64, k2: f64, p1: f64, p2: f64) -> Self {
+        Self { k1, k2, p1, p2 }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // 理想的な (歪みのない) 座標から、実際に写る座標へ
+    pub fn distort(&self, x: f64, y: f64) -> (f64, f64) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+        (
+            x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+            y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y,
+        )
+    }
+
+    // distort の逆を不動点反復で求める
+    pub fn undistort(&self, xd: f64, yd: f64) -> (f64, f64) {
+        let (mut x, mut y) = (xd, yd);
+        for _ in 0..UNDISTORT_ITERATIONS {
+            let r2 = x * x + y * y;
+            let radial = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+            let dx = 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x);
+            let dy = self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y;
+            x = (xd - dx) / radial;
+            y = (yd - dy) / radial;
+        }
+        (x, y)
+    }
+}
+
+const UNDISTORT_ITERATIONS: usize = 20;
+
+// 絞りの形。ピントの外れた明るい点 (ボケ) がこの形に写る
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Aperture {
+    #[default]
+    Circle,
+    // 羽根の枚数の正多角形。rotation (度) で回す
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    Mask(Arc<ApertureMask>),
+}
+
+impl Aperture {
+    // 絞りの上で一様に選んだ点。円と多角形は半径 1 の円に収まり、画像は -1..1 の正方形に広げる
+    pub fn sample(&self) -> (f64, f64) {
+        match self {
+            Aperture::Circle => {
+                let [x, y, _] = Vec3::random_in_unit_disk().to_array();
+                (x, y)
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // 中心と隣り合う 2 つの頂点の三角形を選び、その中で一様に選ぶ
+                let blades = (*blades).max(3);
+                let [i, a, b] = random_array();
+                let i = ((i * blades as f64) as u32).min(blades - 1);
+                let angle = |k: u32| rotation.to_radians() + 2.0 * PI * k as f64 / blades as f64;
+                let (a, b) = if a + b > 1.0 {
+                    (1.0 - a, 1.0 - b)
+                } else {
+                    (a, b)
+                };
+                let (t0, t1) = (angle(i), angle(i + 1));
+                (a * t0.cos() + b * t1.cos(), a * t0.sin() + b * t1.sin())
+            }
+            Aperture::Mask(mask) => mask.sample(),
+        }
+    }
+}
+
+// 白いところほど光を通す絞りの画像。白い円を描いた画像なら Circle と同じ大きさのボケになる
 #[derive(Debug)]
-pub struct Camera {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    // 画素の明るさの累積和 (最後が 1)
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let error = |e: &dyn std::fmt::Display| Error::File(format!("{}: {}", path, e));
+        let image = image::open(path).map_err(|e| error(&e))?.to_luma32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut total = 0.0;
+        let mut cdf = image
+            .pixels()
+            .map(|pixel| {
+                total += pixel[0].max(0.0) as f64;
+                total
+            })
+            .collect::<Vec<_>>();
+        if total <= 0.0 {
+            return Err(error(&"the aperture mask is completely black"));
+        }
+        cdf.iter_mut().for_each(|c| *c /= total);
+        Ok(Self { width, height, cdf })
+    }
+
+    // 明るさに比例して画素を選び、画素の中で一様にずらす
+    pub fn sample(&self) -> (f64, f64) {
+        let [r, jx, jy] = random_array();
+        let i = self.cdf.partition_point(|&c| c < r).min(self.cdf.len() - 1);
+        let x = ((i % self.width) as f64 + jx) / self.width as f64;
+        let y = ((i / self.width) as f64 + jy) / self.height as f64;
+        // 画像は下向きが y なので上下を返す
+        (2.0 * x - 1.0, 1.0 - 2.0 * y)
+    }
+}
+
+// 右, 上, 後ろ向きの正規直交基底
+fn look_at_basis(origin: Point3, look_at: Point3, view_up: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (origin - look_at).normalize();
+    let u = view_up.cross(w).normalize();
+    let v = w.cross(u);
+    (u, v, w)
+}
+
+// スクリーンは lower_left から horizontal, vertical に張られた矩形
+// lens_radius が 0 ならピンホール、そうでなければ薄レンズ
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PerspectiveCamera {
     pub origin: Point3,
-    pub u: Vec3,
-    pub v: Vec3,
-    pub w: Vec3,
+    pub lower_left: Point3,
+    pub horizontal: Vec3,
+    pub vertical: Vec3,
+    pub lens_radius: f64,
+    // レンズ面の単位ベクトル
+    pub lens_u: Vec3,
+    pub lens_v: Vec3,
+    pub aperture: Aperture,
+    pub shutter: Shutter,
+    pub distortion: LensDistortion,
 }
 
-impl Camera {
-    pub fn new(u: Vec3, v: Vec3, w: Vec3) -> Self {
+impl PerspectiveCamera {
+    pub fn new(horizontal: Vec3, vertical: Vec3, lower_left: Point3) -> Self {
         Self {
             origin: Point3::zero(),
-            u,
-            v,
-            w,
+            lower_left,
+            horizontal,
+            vertical,
+            lens_radius: 0.0,
+            lens_u: Vec3::xaxis(),
+            lens_v: Vec3::yaxis(),
+            aperture: Aperture::Circle,
+            shutter: Shutter::default(),
+            distortion: LensDistortion::default(),
         }
     }
 
@@ -24,26 +415,501 @@ impl Camera {
         view_up: Vec3,
         view_fov: f64,
         aspect: f64,
+    ) -> Self {
+        Self::from_look_at_with_lens(origin, look_at, view_up, view_fov, aspect, 0.0, 1.0)
+    }
+
+    // focus_dist の距離にある面にピントが合う
+    pub fn from_look_at_with_lens(
+        origin: Vec3,
+        look_at: Vec3,
+        view_up: Vec3,
+        view_fov: f64,
+        aspect: f64,
+        aperture: f64,
+        focus_dist: f64,
     ) -> Self {
         let half_h = (view_fov.to_radians() * 0.5).tan();
         let half_w = aspect * half_h;
-        let w = (origin - look_at).normalize();
-        let u = view_up.cross(w).normalize();
-        let v = w.cross(u);
-        let uw = half_w * u;
-        let vh = half_h * v;
+        let (u, v, w) = look_at_basis(origin, look_at, view_up);
+        let uw = focus_dist * half_w * u;
+        let vh = focus_dist * half_h * v;
         Self {
             origin,
-            u: 2.0 * uw,
-            v: 2.0 * vh,
-            w: origin - uw - vh - w,
+            lower_left: origin - uw - vh - focus_dist * w,
+            horizontal: 2.0 * uw,
+            vertical: 2.0 * vh,
+            lens_radius: aperture * 0.5,
+            lens_u: u,
+            lens_v: v,
+            aperture: Aperture::Circle,
+            shutter: Shutter::default(),
+            distortion: LensDistortion::default(),
+        }
+    }
+
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter: Shutter::new(open, close),
+            ..self
+        }
+    }
+
+    pub fn with_distortion(self, distortion: LensDistortion) -> Self {
+        Self { distortion, ..self }
+    }
+
+    pub fn with_aperture(self, aperture: Aperture) -> Self {
+        Self { aperture, ..self }
+    }
+
+    // スクリーン座標と、焦点距離で正規化した座標の変換係数
+    fn normalized_scale(&self) -> (f64, f64) {
+        let center = self.lower_left + 0.5 * (self.horizontal + self.vertical);
+        let focal = (center - self.origin).length();
+        (
+            self.horizontal.length() / focal,
+            self.vertical.length() / focal,
+        )
+    }
+
+    // 画像上の (u, v) に写る、歪みのないスクリーン座標
+    fn undistorted_uv(&self, u: f64, v: f64) -> (f64, f64) {
+        if self.distortion.is_identity() {
+            return (u, v);
+        }
+        let (sx, sy) = self.normalized_scale();
+        let (x, y) = self.distortion.undistort((u - 0.5) * sx, (v - 0.5) * sy);
+        (x / sx + 0.5, y / sy + 0.5)
+    }
+
+    fn distorted_uv(&self, u: f64, v: f64) -> (f64, f64) {
+        if self.distortion.is_identity() {
+            return (u, v);
+        }
+        let (sx, sy) = self.normalized_scale();
+        let (x, y) = self.distortion.distort((u - 0.5) * sx, (v - 0.5) * sy);
+        (x / sx + 0.5, y / sy + 0.5)
+    }
+}
+
+// PerspectiveCamera を名前付きの値で組み立てる。指定しなかった値は既定値のまま
+// build で、向きが決まらない (look_from と look_at が同じ、up が視線と平行) などの
+// 取り違えをエラーにする
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CameraBuilder {
+    look_from: Point3,
+    look_at: Point3,
+    up: Vec3,
+    fov: f64,
+    aspect: f64,
+    aperture: f64,
+    aperture_shape: Aperture,
+    // None なら look_at までの距離
+    focus_distance: Option<f64>,
+    shutter: (f64, f64),
+    distortion: LensDistortion,
+}
+
+impl CameraBuilder {
+    pub fn new() -> Self {
+        Self {
+            look_from: Point3::zero(),
+            look_at: -Vec3::zaxis(),
+            up: Vec3::yaxis(),
+            fov: 90.0,
+            aspect: 16.0 / 9.0,
+            aperture: 0.0,
+            aperture_shape: Aperture::Circle,
+            focus_distance: None,
+            shutter: (0.0, 0.0),
+            distortion: LensDistortion::default(),
         }
     }
 
-    pub fn ray(&self, u: f64, v: f64) -> Ray {
-        Ray {
+    pub fn look_from(self, look_from: Point3) -> Self {
+        Self { look_from, ..self }
+    }
+
+    pub fn look_at(self, look_at: Point3) -> Self {
+        Self { look_at, ..self }
+    }
+
+    pub fn up(self, up: Vec3) -> Self {
+        Self { up, ..self }
+    }
+
+    // 縦の画角 (度)
+    pub fn fov(self, fov: f64) -> Self {
+        Self { fov, ..self }
+    }
+
+    // 幅 / 高さ
+    pub fn aspect(self, aspect: f64) -> Self {
+        Self { aspect, ..self }
+    }
+
+    // レンズの直径。0 ならピンホール
+    pub fn aperture(self, aperture: f64) -> Self {
+        Self { aperture, ..self }
+    }
+
+    pub fn aperture_shape(self, aperture_shape: Aperture) -> Self {
+        Self {
+            aperture_shape,
+            ..self
+        }
+    }
+
+    pub fn focus_distance(self, focus_distance: f64) -> Self {
+        Self {
+            focus_distance: Some(focus_distance),
+            ..self
+        }
+    }
+
+    pub fn shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter: (open, close),
+            ..self
+        }
+    }
+
+    pub fn distortion(self, distortion: LensDistortion) -> Self {
+        Self { distortion, ..self }
+    }
+
+    pub fn build(self) -> Result<PerspectiveCamera, Error> {
+        let invalid = |message: &str| Err(Error::Invalid(format!("camera: {}", message)));
+        let direction = self.look_at - self.look_from;
+        if direction.near_zero() {
+            return invalid("look_from and look_at are the same point");
+        }
+        if self.up.near_zero() || self.up.normalize().cross(direction.normalize()).near_zero() {
+            return invalid("up must not be zero or parallel to the view direction");
+        }
+        if !(self.fov > 0.0 && self.fov < 180.0) {
+            return invalid(&format!(
+                "fov must be between 0 and 180 degrees, not {}",
+                self.fov
+            ));
+        }
+        if !(self.aspect > 0.0 && self.aspect.is_finite()) {
+            return invalid(&format!("aspect must be positive, not {}", self.aspect));
+        }
+        if self.aperture.is_nan() || self.aperture < 0.0 {
+            return invalid(&format!(
+                "aperture must not be negative, not {}",
+                self.aperture
+            ));
+        }
+        let focus_distance = self.focus_distance.unwrap_or_else(|| direction.length());
+        if focus_distance.is_nan() || focus_distance <= 0.0 {
+            return invalid(&format!(
+                "focus_distance must be positive, not {}",
+                focus_distance
+            ));
+        }
+        let (open, close) = self.shutter;
+        if close < open {
+            return invalid("shutter closes before it opens");
+        }
+        Ok(PerspectiveCamera::from_look_at_with_lens(
+            self.look_from,
+            self.look_at,
+            self.up,
+            self.fov,
+            self.aspect,
+            self.aperture,
+            focus_distance,
+        )
+        .with_shutter(open, close)
+        .with_distortion(self.distortion)
+        .with_aperture(self.aperture_shape))
+    }
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn ray_through(&self, u: f64, v: f64, lens: Vec3, time: f64) -> Ray {
+        let (u, v) = self.undistorted_uv(u, v);
+        let origin = self.origin + lens;
+        let target = self.lower_left + self.horizontal * u + self.vertical * v;
+        Ray::new(origin, target - origin).with_time(time)
+    }
+
+    fn project(&self, p: Point3) -> Option<(f64, f64)> {
+        let n = self.horizontal.cross(self.vertical);
+        let d = p - self.origin;
+        let t = (self.lower_left - self.origin).dot(n) / d.dot(n);
+        if t.is_nan() || t <= 0.0 {
+            return None;
+        }
+        let q = self.origin + t * d - self.lower_left;
+        Some(self.distorted_uv(
+            q.dot(self.horizontal) / self.horizontal.length_squared(),
+            q.dot(self.vertical) / self.vertical.length_squared(),
+        ))
+    }
+
+    fn right(&self) -> Vec3 {
+        self.horizontal.normalize()
+    }
+
+    fn sample_lens(&self) -> Vec3 {
+        if self.lens_radius > 0.0 {
+            let (x, y) = self.aperture.sample();
+            let (x, y) = (self.lens_radius * x, self.lens_radius * y);
+            self.lens_u * x + self.lens_v * y
+        } else {
+            Vec3::zero()
+        }
+    }
+
+    fn shutter(&self) -> Shutter {
+        self.shutter
+    }
+
+    fn look_at(&self) -> Option<LookAt> {
+        let center = self.lower_left + 0.5 * (self.horizontal + self.vertical);
+        let focus_dist = (center - self.origin).length();
+        Some(LookAt {
             origin: self.origin,
-            direction: self.w + self.u * u + self.v * v - self.origin,
+            look_at: center,
+            up: self.vertical.normalize(),
+            fov: 2.0
+                * (0.5 * self.vertical.length() / focus_dist)
+                    .atan()
+                    .to_degrees(),
+            aperture: 2.0 * self.lens_radius,
+        })
+    }
+
+    // 両目でスクリーンを共有する (軸をずらした視錐台にする)
+    fn stereo_eye(&self, offset: f64, convergence: f64) -> Option<Box<dyn Camera>> {
+        let center = self.lower_left + 0.5 * (self.horizontal + self.vertical);
+        let scale = convergence / (center - self.origin).length();
+        let horizontal = self.horizontal * scale;
+        let vertical = self.vertical * scale;
+        let center = self.origin + (center - self.origin) * scale;
+        Some(Box::new(Self {
+            origin: self.origin + self.horizontal.normalize() * offset,
+            lower_left: center - 0.5 * (horizontal + vertical),
+            horizontal,
+            vertical,
+            ..self.clone()
+        }))
+    }
+}
+
+// 視線方向に平行な光線をビューの矩形から飛ばす
+#[derive(Debug, Clone)]
+pub struct OrthographicCamera {
+    pub lower_left: Point3,
+    pub horizontal: Vec3,
+    pub vertical: Vec3,
+    pub direction: Vec3,
+    pub shutter: Shutter,
+}
+
+impl OrthographicCamera {
+    // view_height はビューの矩形の高さ (ワールド座標)
+    pub fn from_look_at(
+        origin: Vec3,
+        look_at: Vec3,
+        view_up: Vec3,
+        view_height: f64,
+        aspect: f64,
+    ) -> Self {
+        let (u, v, w) = look_at_basis(origin, look_at, view_up);
+        let uw = 0.5 * aspect * view_height * u;
+        let vh = 0.5 * view_height * v;
+        Self {
+            lower_left: origin - uw - vh,
+            horizontal: 2.0 * uw,
+            vertical: 2.0 * vh,
+            direction: -w,
+            shutter: Shutter::default(),
         }
     }
+
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter: Shutter::new(open, close),
+            ..self
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn ray_through(&self, u: f64, v: f64, _lens: Vec3, time: f64) -> Ray {
+        let origin = self.lower_left + self.horizontal * u + self.vertical * v;
+        Ray::new(origin, self.direction).with_time(time)
+    }
+
+    fn project(&self, p: Point3) -> Option<(f64, f64)> {
+        let q = p - self.lower_left;
+        Some((
+            q.dot(self.horizontal) / self.horizontal.length_squared(),
+            q.dot(self.vertical) / self.vertical.length_squared(),
+        ))
+    }
+
+    fn right(&self) -> Vec3 {
+        self.horizontal.normalize()
+    }
+
+    fn shutter(&self) -> Shutter {
+        self.shutter
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    // 像高が入射角に比例する
+    Equidistant,
+    // 像高が立体角に比例する (面積が保たれる)
+    Equisolid,
+}
+
+// 画像の高さに内接する円が fov (度) に対応する
+#[derive(Debug, Clone)]
+pub struct FisheyeCamera {
+    pub origin: Point3,
+    pub right: Vec3,
+    pub up: Vec3,
+    pub forward: Vec3,
+    pub mapping: FisheyeMapping,
+    pub fov: f64,
+    pub aspect: f64,
+    pub shutter: Shutter,
+}
+
+impl FisheyeCamera {
+    // fov は 180 度を超えてもよい (360 度まで)
+    pub fn from_look_at(
+        origin: Vec3,
+        look_at: Vec3,
+        view_up: Vec3,
+        fov: f64,
+        aspect: f64,
+        mapping: FisheyeMapping,
+    ) -> Self {
+        let (u, v, w) = look_at_basis(origin, look_at, view_up);
+        Self {
+            origin,
+            right: u,
+            up: v,
+            forward: -w,
+            mapping,
+            fov: fov.clamp(1.0, 360.0),
+            aspect,
+            shutter: Shutter::default(),
+        }
+    }
+
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter: Shutter::new(open, close),
+            ..self
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn ray_through(&self, u: f64, v: f64, _lens: Vec3, time: f64) -> Ray {
+        let x = (2.0 * u - 1.0) * self.aspect;
+        let y = 2.0 * v - 1.0;
+        let r = (x * x + y * y).sqrt();
+        let half_fov = (self.fov * 0.5).to_radians();
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * half_fov,
+            FisheyeMapping::Equisolid => 2.0 * (r * (half_fov * 0.5).sin()).clamp(-1.0, 1.0).asin(),
+        }
+        .min(PI);
+        let phi = y.atan2(x);
+        let direction = theta.sin() * (phi.cos() * self.right + phi.sin() * self.up)
+            + theta.cos() * self.forward;
+        Ray::new(self.origin, direction).with_time(time)
+    }
+
+    fn project(&self, p: Point3) -> Option<(f64, f64)> {
+        let d = (p - self.origin).normalize();
+        let theta = d.dot(self.forward).clamp(-1.0, 1.0).acos();
+        let phi = d.dot(self.up).atan2(d.dot(self.right));
+        let half_fov = (self.fov * 0.5).to_radians();
+        let r = match self.mapping {
+            FisheyeMapping::Equidistant => theta / half_fov,
+            FisheyeMapping::Equisolid => (theta * 0.5).sin() / (half_fov * 0.5).sin(),
+        };
+        let (x, y) = (r * phi.cos(), r * phi.sin());
+        Some((0.5 * (x / self.aspect + 1.0), 0.5 * (y + 1.0)))
+    }
+
+    fn right(&self) -> Vec3 {
+        self.right
+    }
+
+    fn shutter(&self) -> Shutter {
+        self.shutter
+    }
+}
+
+// 全方位を正距円筒図法で写す。画像の中心が視線方向
+#[derive(Debug, Clone)]
+pub struct PanoramicCamera {
+    pub origin: Point3,
+    pub right: Vec3,
+    pub up: Vec3,
+    pub forward: Vec3,
+    pub shutter: Shutter,
+}
+
+impl PanoramicCamera {
+    pub fn from_look_at(origin: Vec3, look_at: Vec3, view_up: Vec3) -> Self {
+        let (u, v, w) = look_at_basis(origin, look_at, view_up);
+        Self {
+            origin,
+            right: u,
+            up: v,
+            forward: -w,
+            shutter: Shutter::default(),
+        }
+    }
+
+    pub fn with_shutter(self, open: f64, close: f64) -> Self {
+        Self {
+            shutter: Shutter::new(open, close),
+            ..self
+        }
+    }
+}
+
+impl Camera for PanoramicCamera {
+    // equirect_direction は u = 0.5 が +X、右に進むと -Z なのでカメラの向きに合わせる
+    fn ray_through(&self, u: f64, v: f64, _lens: Vec3, time: f64) -> Ray {
+        let [x, y, z] = equirect_direction(u, v).to_array();
+        let direction = x * self.forward + y * self.up - z * self.right;
+        Ray::new(self.origin, direction).with_time(time)
+    }
+
+    fn project(&self, p: Point3) -> Option<(f64, f64)> {
+        let d = p - self.origin;
+        let local = Vec3::new(d.dot(self.forward), d.dot(self.up), -d.dot(self.right));
+        Some(equirect_uv(local))
+    }
+
+    fn right(&self) -> Vec3 {
+        self.right
+    }
+
+    fn shutter(&self) -> Shutter {
+        self.shutter
+    }
 }
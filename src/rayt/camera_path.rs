@@ -0,0 +1,111 @@
+use crate::rayt::*;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CameraKey {
+    pub time: f64,
+    pub origin: Point3,
+    pub look_at: Point3,
+}
+
+impl CameraKey {
+    pub fn new(time: f64, origin: Point3, look_at: Point3) -> Self {
+        Self {
+            time,
+            origin,
+            look_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CameraMotion {
+    // キーフレームの間を Catmull-Rom で補間する
+    Keyframes(Vec<CameraKey>),
+    // look_at を中心に view_up 軸まわりに 1 周する
+    Orbit { origin: Point3, look_at: Point3 },
+}
+
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    pub motion: CameraMotion,
+    pub view_up: Vec3,
+    pub view_fov: f64,
+}
+
+impl CameraPath {
+    pub fn keyframes(mut keys: Vec<CameraKey>, view_up: Vec3, view_fov: f64) -> Self {
+        assert!(!keys.is_empty(), "camera path needs at least one keyframe");
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self {
+            motion: CameraMotion::Keyframes(keys),
+            view_up,
+            view_fov,
+        }
+    }
+
+    pub fn orbit(origin: Point3, look_at: Point3, view_up: Vec3, view_fov: f64) -> Self {
+        Self {
+            motion: CameraMotion::Orbit { origin, look_at },
+            view_up,
+            view_fov,
+        }
+    }
+
+    // frames 枚に分けたときの frame 枚目の時刻 (0..=1)
+    // 周回は最後のフレームが最初と重ならないように 1 を含めない
+    pub fn frame_time(&self, frame: usize, frames: usize) -> f64 {
+        match self.motion {
+            CameraMotion::Orbit { .. } => frame as f64 / frames.max(1) as f64,
+            CameraMotion::Keyframes(_) => frame as f64 / (frames.max(2) - 1) as f64,
+        }
+    }
+
+    // t は 0..=1 で、キーフレームでは最初から最後のキーの時刻に対応させる
+    pub fn eval(&self, t: f64) -> (Point3, Point3) {
+        match &self.motion {
+            CameraMotion::Orbit { origin, look_at } => {
+                let q = Quat::from_rot(self.view_up.normalize(), PI2 * t);
+                (*look_at + q.rotate(*origin - *look_at), *look_at)
+            }
+            CameraMotion::Keyframes(keys) => {
+                let (first, last) = (keys[0].time, keys[keys.len() - 1].time);
+                let time = first + t.clamp(0.0, 1.0) * (last - first);
+                let i = keys
+                    .windows(2)
+                    .position(|w| time <= w[1].time)
+                    .unwrap_or(0)
+                    .min(keys.len().saturating_sub(2));
+                let key = |j: i64| keys[j.clamp(0, keys.len() as i64 - 1) as usize];
+                let (k0, k1, k2, k3) = (
+                    key(i as i64 - 1),
+                    key(i as i64),
+                    key(i as i64 + 1),
+                    key(i as i64 + 2),
+                );
+                let span = k2.time - k1.time;
+                let s = if span > 0.0 {
+                    (time - k1.time) / span
+                } else {
+                    0.0
+                };
+                (
+                    catmull_rom(k0.origin, k1.origin, k2.origin, k3.origin, s),
+                    catmull_rom(k0.look_at, k1.look_at, k2.look_at, k3.look_at, s),
+                )
+            }
+        }
+    }
+
+    pub fn camera(&self, t: f64, aspect: f64) -> PerspectiveCamera {
+        let (origin, look_at) = self.eval(t);
+        PerspectiveCamera::from_look_at(origin, look_at, self.view_up, self.view_fov, aspect)
+    }
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, s: f64) -> Vec3 {
+    let (s2, s3) = (s * s, s * s * s);
+    0.5 * (2.0 * p1
+        + s * (p2 - p0)
+        + s2 * (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3)
+        + s3 * (3.0 * p1 - p0 - 3.0 * p2 + p3))
+}
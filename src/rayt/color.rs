@@ -0,0 +1,261 @@
+use crate::rayt::*;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::{fs, io, path::Path};
+
+// レンダリングに使う色空間 (出力時に Rec.709 の原色へ変換する)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum WorkingSpace {
+    #[cfg_attr(feature = "serde", serde(rename = "srgb"))]
+    LinearSrgb,
+    #[cfg_attr(feature = "serde", serde(rename = "acescg"))]
+    AcesCg,
+    #[cfg_attr(feature = "serde", serde(rename = "aces2065"))]
+    Aces2065,
+}
+
+impl WorkingSpace {
+    // Bradford 変換で白色点も D60 から D65 へ合わせた行列
+    fn to_rec709(self) -> Option<[[f64; 3]; 3]> {
+        match self {
+            WorkingSpace::LinearSrgb => None,
+            WorkingSpace::AcesCg => Some([
+                [1.70505, -0.62179, -0.08326],
+                [-0.13026, 1.14080, -0.01055],
+                [-0.02400, -0.12897, 1.15297],
+            ]),
+            WorkingSpace::Aces2065 => Some([
+                [2.52169, -1.13413, -0.38756],
+                [-0.27648, 1.37272, -0.09624],
+                [-0.01538, -0.15298, 1.16835],
+            ]),
+        }
+    }
+
+    pub fn to_linear_srgb(self, c: Color) -> Color {
+        match self.to_rec709() {
+            Some(m) => Color::from_iter(
+                m.iter()
+                    .map(|row| Color::new(row[0], row[1], row[2]).dot(c)),
+            ),
+            None => c,
+        }
+    }
+}
+
+// 設定ファイルでは "srgb" か { gamma = 2.2 }
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DisplayTransform {
+    Gamma(f64),
+    // sRGB の区分的な伝達関数
+    Srgb,
+}
+
+impl DisplayTransform {
+    pub fn encode(self, c: Color) -> Color {
+        match self {
+            DisplayTransform::Gamma(factor) => c.saturate().gamma(factor),
+            DisplayTransform::Srgb => Color::from_iter(c.saturate().iter().map(|x| {
+                if x <= 0.0031308 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1.0 / 2.4) - 0.055
+                }
+            })),
+        }
+    }
+}
+
+// 表示用に符号化する前に、1 を超える明るさを 0..1 に収める
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ToneMap {
+    // 露出をかけるだけで 1 を超えた分は白に飛ぶ
+    #[default]
+    Linear,
+    // 輝度 L を L / (1 + L) にして色相を保つ
+    Reinhard,
+    // Narkowicz 2015, "ACES Filmic Tone Mapping Curve"
+    Aces,
+}
+
+impl ToneMap {
+    pub fn apply(self, c: Color) -> Color {
+        match self {
+            ToneMap::Linear => c,
+            ToneMap::Reinhard => {
+                let l = c.luminance();
+                if l <= 0.0 {
+                    c
+                } else {
+                    c * (1.0 / (1.0 + l))
+                }
+            }
+            ToneMap::Aces => Color::from_iter(c.iter().map(|x| {
+                let x = x.max(0.0);
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            })),
+        }
+    }
+}
+
+// Adobe/Resolve 形式 (.cube) の 3D LUT
+#[derive(Debug)]
+pub struct CubeLut {
+    size: usize,
+    // 赤が最も速く変わる順
+    table: Vec<Color>,
+    domain_min: Color,
+    domain_max: Color,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_color(fields: &[&str]) -> io::Result<Color> {
+    if fields.len() != 3 {
+        return Err(invalid_data(format!("expected 3 values: {:?}", fields)));
+    }
+    let mut values = [0.0; 3];
+    for (value, field) in values.iter_mut().zip(fields) {
+        *value = field
+            .parse()
+            .map_err(|_| invalid_data(format!("not a number: {}", field)))?;
+    }
+    Ok(Color::new(values[0], values[1], values[2]))
+}
+
+impl CubeLut {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut size = None;
+        let mut table = Vec::new();
+        let mut domain_min = Color::zero();
+        let mut domain_max = Color::one();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields[0] {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    let n = fields.get(1).and_then(|n| n.parse::<usize>().ok());
+                    size = Some(n.ok_or_else(|| invalid_data(line.to_string()))?);
+                }
+                "LUT_1D_SIZE" => {
+                    return Err(invalid_data("1D LUTs are not supported".to_string()));
+                }
+                "DOMAIN_MIN" => domain_min = parse_color(&fields[1..])?,
+                "DOMAIN_MAX" => domain_max = parse_color(&fields[1..])?,
+                _ => table.push(parse_color(&fields)?),
+            }
+        }
+        let size = size.ok_or_else(|| invalid_data("missing LUT_3D_SIZE".to_string()))?;
+        if size < 2 || table.len() != size * size * size {
+            return Err(invalid_data(format!(
+                "expected {} entries, found {}",
+                size * size * size,
+                table.len()
+            )));
+        }
+        Ok(Self {
+            size,
+            table,
+            domain_min,
+            domain_max,
+        })
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> Color {
+        self.table[r + self.size * (g + self.size * b)]
+    }
+
+    // 格子点の間は三重線形補間する
+    pub fn apply(&self, c: Color) -> Color {
+        let n = (self.size - 1) as f64;
+        let (c, min, max) = (
+            c.to_array(),
+            self.domain_min.to_array(),
+            self.domain_max.to_array(),
+        );
+        let [r, g, b] =
+            [0, 1, 2].map(|i| ((c[i] - min[i]) / (max[i] - min[i])).clamp(0.0, 1.0) * n);
+        let (r0, g0, b0) = (r.floor() as usize, g.floor() as usize, b.floor() as usize);
+        let (r1, g1, b1) = (
+            (r0 + 1).min(self.size - 1),
+            (g0 + 1).min(self.size - 1),
+            (b0 + 1).min(self.size - 1),
+        );
+        let (fr, fg, fb) = (r - r0 as f64, g - g0 as f64, b - b0 as f64);
+        let lerp_r = |g, b| self.entry(r0, g, b).lerp(self.entry(r1, g, b), fr);
+        let lerp_g = |b| lerp_r(g0, b).lerp(lerp_r(g1, b), fg);
+        lerp_g(b0).lerp(lerp_g(b1), fb)
+    }
+}
+
+// 出力時の色変換
+// 作業色空間 -> Rec.709 の原色 -> 露出 -> トーンマップ -> 表示用の符号化
+// -> ビュー LUT (表示側の 0..1 を受け取る)
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct ColorConfig {
+    pub working_space: WorkingSpace,
+    // 自動露出に足す EV
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+    pub display: DisplayTransform,
+    // 読み込んだ LUT は書き出せないので、設定ファイルでは扱わない (--lut で渡す)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub view: Option<Arc<CubeLut>>,
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        Self {
+            working_space: WorkingSpace::LinearSrgb,
+            exposure: 0.0,
+            tone_map: ToneMap::Linear,
+            display: DisplayTransform::Srgb,
+            view: None,
+        }
+    }
+}
+
+impl ColorConfig {
+    pub fn with_view_lut<P: AsRef<Path>>(self, path: P) -> io::Result<Self> {
+        Ok(Self {
+            view: Some(Arc::new(CubeLut::load(path)?)),
+            ..self
+        })
+    }
+
+    pub fn to_display(&self, c: Color) -> Color {
+        let c = self.working_space.to_linear_srgb(c) * self.exposure.exp2();
+        let c = self.display.encode(self.tone_map.apply(c));
+        match &self.view {
+            Some(lut) => lut.apply(c),
+            None => c,
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::rayt::*;
+
+use image::{Rgb, RgbImage};
+use std::path::Path;
+
+// OpenGL と同じ並び (+X, -X, +Y, -Y, +Z, -Z)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CubeFace {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PosX,
+        CubeFace::NegX,
+        CubeFace::PosY,
+        CubeFace::NegY,
+        CubeFace::PosZ,
+        CubeFace::NegZ,
+    ];
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            CubeFace::PosX => "px",
+            CubeFace::NegX => "nx",
+            CubeFace::PosY => "py",
+            CubeFace::NegY => "ny",
+            CubeFace::PosZ => "pz",
+            CubeFace::NegZ => "nz",
+        }
+    }
+
+    // a, b は面上の [-1, 1] の座標 (b は下向き)
+    pub fn direction(self, a: f64, b: f64) -> Vec3 {
+        match self {
+            CubeFace::PosX => Vec3::new(1.0, -b, -a),
+            CubeFace::NegX => Vec3::new(-1.0, -b, a),
+            CubeFace::PosY => Vec3::new(a, 1.0, b),
+            CubeFace::NegY => Vec3::new(a, -1.0, -b),
+            CubeFace::PosZ => Vec3::new(a, -b, 1.0),
+            CubeFace::NegZ => Vec3::new(-a, -b, -1.0),
+        }
+        .normalize()
+    }
+
+    pub fn from_direction(d: Vec3) -> (Self, f64, f64) {
+        let [x, y, z] = d.to_array();
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+        if ax >= ay && ax >= az {
+            if x > 0.0 {
+                (CubeFace::PosX, -z / ax, -y / ax)
+            } else {
+                (CubeFace::NegX, z / ax, -y / ax)
+            }
+        } else if ay >= az {
+            if y > 0.0 {
+                (CubeFace::PosY, x / ay, z / ay)
+            } else {
+                (CubeFace::NegY, x / ay, -z / ay)
+            }
+        } else if z > 0.0 {
+            (CubeFace::PosZ, x / az, -y / az)
+        } else {
+            (CubeFace::NegZ, -x / az, -y / az)
+        }
+    }
+}
+
+// 球の UV と同じ対応 (u は経度, v は緯度で上が 1)
+pub fn equirect_uv(d: Vec3) -> (f64, f64) {
+    let d = d.normalize();
+    let phi = d.z().atan2(d.x());
+    let theta = d.y().clamp(-1.0, 1.0).asin();
+    (1.0 - (phi + PI) / PI2, (theta + PI / 2.0) / PI)
+}
+
+pub fn equirect_direction(u: f64, v: f64) -> Vec3 {
+    let phi = (1.0 - u) * PI2 - PI;
+    let theta = v * PI - PI / 2.0;
+    Vec3::new(
+        theta.cos() * phi.cos(),
+        theta.sin(),
+        theta.cos() * phi.sin(),
+    )
+}
+
+fn pixel(img: &RgbImage, x: i64, y: i64, wrap_x: bool) -> Color {
+    let (w, h) = (img.width() as i64, img.height() as i64);
+    let x = if wrap_x {
+        x.rem_euclid(w)
+    } else {
+        x.clamp(0, w - 1)
+    };
+    let p = img.get_pixel(x as u32, y.clamp(0, h - 1) as u32);
+    Color::from_rgb(p[0], p[1], p[2])
+}
+
+// x, y は画素単位 (画素の中心が整数 + 0.5)
+fn sample_bilinear(img: &RgbImage, x: f64, y: f64, wrap_x: bool) -> Color {
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let top = pixel(img, x0, y0, wrap_x).lerp(pixel(img, x0 + 1, y0, wrap_x), fx);
+    let bottom = pixel(img, x0, y0 + 1, wrap_x).lerp(pixel(img, x0 + 1, y0 + 1, wrap_x), fx);
+    top.lerp(bottom, fy)
+}
+
+fn make_face<F: Fn(Vec3) -> Color>(face: CubeFace, size: u32, sample: F) -> RgbImage {
+    RgbImage::from_fn(size, size, |x, y| {
+        let a = 2.0 * (x as f64 + 0.5) / size as f64 - 1.0;
+        let b = 2.0 * (y as f64 + 0.5) / size as f64 - 1.0;
+        Rgb(sample(face.direction(a, b)).to_rgb())
+    })
+}
+
+pub fn sample_equirect(img: &RgbImage, d: Vec3) -> Color {
+    let (u, v) = equirect_uv(d);
+    let x = u * img.width() as f64;
+    let y = (1.0 - v) * img.height() as f64;
+    sample_bilinear(img, x, y, true)
+}
+
+// 正距円筒図法 (等距離射影) の魚眼、+Z が正面で +Y が上
+// 画角の外は黒になる
+pub fn sample_fisheye(img: &RgbImage, fov: f64, d: Vec3) -> Color {
+    let d = d.normalize();
+    let r = d.z().clamp(-1.0, 1.0).acos() / (fov.to_radians() * 0.5);
+    if r > 1.0 {
+        return Color::zero();
+    }
+    let phi = d.y().atan2(d.x());
+    let radius = img.width().min(img.height()) as f64 * 0.5;
+    let x = img.width() as f64 * 0.5 + r * radius * phi.cos();
+    let y = img.height() as f64 * 0.5 - r * radius * phi.sin();
+    sample_bilinear(img, x, y, false)
+}
+
+pub fn sample_cubemap(faces: &[RgbImage; 6], d: Vec3) -> Color {
+    let (face, a, b) = CubeFace::from_direction(d);
+    let img = &faces[face as usize];
+    let x = (a + 1.0) * 0.5 * img.width() as f64;
+    let y = (b + 1.0) * 0.5 * img.height() as f64;
+    sample_bilinear(img, x, y, false)
+}
+
+pub fn equirect_to_cubemap(img: &RgbImage, size: u32) -> [RgbImage; 6] {
+    CubeFace::ALL.map(|face| make_face(face, size, |d| sample_equirect(img, d)))
+}
+
+pub fn fisheye_to_cubemap(img: &RgbImage, fov: f64, size: u32) -> [RgbImage; 6] {
+    CubeFace::ALL.map(|face| make_face(face, size, |d| sample_fisheye(img, fov, d)))
+}
+
+pub fn cubemap_to_equirect(faces: &[RgbImage; 6], width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| {
+        let u = (x as f64 + 0.5) / width as f64;
+        let v = 1.0 - (y as f64 + 0.5) / height as f64;
+        Rgb(sample_cubemap(faces, equirect_direction(u, v)).to_rgb())
+    })
+}
+
+// path が "sky.png" なら "sky_px.png" などに書き出す
+pub fn face_filename(path: &str, face: CubeFace) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let ext = path
+        .extension()
+        .map_or("png".into(), |e| e.to_string_lossy());
+    let name = format!("{}_{}.{}", stem, face.suffix(), ext);
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+pub fn save_cubemap(path: &str, faces: &[RgbImage; 6]) -> image::ImageResult<()> {
+    for (face, img) in CubeFace::ALL.iter().zip(faces) {
+        img.save(face_filename(path, *face))?;
+    }
+    Ok(())
+}
+
+pub fn load_cubemap(path: &str) -> image::ImageResult<[RgbImage; 6]> {
+    let mut faces = Vec::with_capacity(6);
+    for face in CubeFace::ALL {
+        faces.push(image::open(face_filename(path, face))?.to_rgb8());
+    }
+    Ok(faces.try_into().unwrap())
+}
@@ -0,0 +1,193 @@
+use crate::rayt::*;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+#[derive(Debug, Clone)]
+pub struct BounceRecord {
+    pub depth: usize,
+    pub ray: Ray,
+    pub hit: Option<(Point3, Vec3)>,
+    pub material: &'static str,
+    pub emitted: Color,
+    pub albedo: Option<Color>,
+}
+
+thread_local! {
+    static TRACE_LOG: RefCell<Option<Vec<BounceRecord>>> = const { RefCell::new(None) };
+}
+
+pub fn start_trace_log() {
+    TRACE_LOG.with(|log| *log.borrow_mut() = Some(Vec::new()));
+}
+
+pub fn take_trace_log() -> Vec<BounceRecord> {
+    TRACE_LOG.with(|log| log.borrow_mut().take().unwrap_or_default())
+}
+
+// ログ採取中でなければ record を組み立てるコストもかけない
+pub fn log_bounce<F: FnOnce() -> BounceRecord>(record: F) {
+    TRACE_LOG.with(|log| {
+        if let Some(log) = log.borrow_mut().as_mut() {
+            log.push(record());
+        }
+    });
+}
+
+fn format_color(c: Color) -> String {
+    format!("({:.4}, {:.4}, {:.4})", c.x(), c.y(), c.z())
+}
+
+pub fn repro_pixel(scene: impl SceneWithDepth, x: u32, y: u32, seed: Option<u64>) {
+    // 本描画と同じく、サンプルごとに 1 pass として乱数を選び直す
+    let seed = seed.or(scene.seed());
+    let sum = (0..scene.spp()).fold(Color::zero(), |acc, sample| {
+        acc + trace_pixel_sample(&scene, x, y, sample, seed)
+    });
+    println!(
+        "pixel ({}, {}) mean radiance {}",
+        x,
+        y,
+        format_color(sum / scene.spp() as f64)
+    );
+}
+
+// 描画中のウィンドウで画素をクリックしたときに、1 サンプルの経路だけを表示する
+// 本描画の sample 番目の pass と同じ乱数を使う
+pub fn inspect_pixel(scene: &impl SceneWithDepth, x: u32, y: u32, sample: usize) -> Color {
+    println!("inspect pixel ({}, {})", x, y);
+    trace_pixel_sample(scene, x, y, sample % scene.spp().max(1), scene.seed())
+}
+
+fn trace_pixel_sample(
+    scene: &impl SceneWithDepth,
+    x: u32,
+    y: u32,
+    sample: usize,
+    seed: Option<u64>,
+) -> Color {
+    let pass = sample as u64;
+    with_pixel_sampler(scene.sampler(), seed, x as i64, y as i64, pass, || {
+        repro_sample(scene, x, y, sample)
+    })
+}
+
+fn repro_sample(scene: &impl SceneWithDepth, x: u32, y: u32, sample: usize) -> Color {
+    let camera = scene.camera();
+    let w = scene.width();
+    let h = scene.height();
+    start_sample(x as i64, y as i64, sample as u64);
+    let (rx, ry) = pixel_offset(sample, scene.strata());
+    let u = (x as f64 + rx) / (w - 1) as f64;
+    let v = ((h - y - 1) as f64 + ry) / (h - 1) as f64;
+    start_trace_log();
+    let ray = camera.ray_with_differential(u, v, 1.0 / (w - 1) as f64, 1.0 / (h - 1) as f64);
+    let radiance = GroupRadiance::new(None, scene.trace(ray, scene.max_depth()));
+    let radiance = scene.radiance_clamp().sample(radiance).total;
+    println!("sample {} (u, v) = ({:.4}, {:.4})", sample, u, v);
+    let mut throughput = Color::one();
+    for record in take_trace_log() {
+        match record.hit {
+            Some((p, n)) => println!(
+                "  depth {:>2} hit {} p={} n={} emitted={} albedo={} throughput={}",
+                record.depth,
+                record.material,
+                format_color(p),
+                format_color(n),
+                format_color(record.emitted),
+                record.albedo.map_or("absorbed".to_string(), format_color),
+                format_color(throughput),
+            ),
+            None => println!(
+                "  depth {:>2} miss d={} background={} throughput={}",
+                record.depth,
+                format_color(record.ray.direction),
+                format_color(record.emitted),
+                format_color(throughput),
+            ),
+        }
+        if let Some(albedo) = record.albedo {
+            throughput *= albedo;
+        }
+    }
+    println!("  radiance {}", format_color(radiance));
+    radiance
+}
+
+// --mode で選ぶ、経路を追う代わりの 1 サンプルだけの可視化
+// 法線の向きや UV の崩れを、本描画を待たずに確かめる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DebugMode {
+    // 法線の [-1, 1] を [0, 1] にして RGB に
+    Normal,
+    // UV を 0..1 に折り返して赤と緑に
+    Uv,
+    // カメラからの距離。いちばん遠い点を白にする
+    Depth,
+    // 経路が面に当たった回数。青 (0 回) から赤 (max_depth 回) へ
+    Bounces,
+}
+
+impl DebugMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "normal" => Some(DebugMode::Normal),
+            "uv" => Some(DebugMode::Uv),
+            "depth" => Some(DebugMode::Depth),
+            "bounces" => Some(DebugMode::Bounces),
+            _ => None,
+        }
+    }
+
+    pub fn trace(self, scene: &impl SceneWithDepth, ray: Ray, depth: usize) -> Color {
+        match self {
+            DebugMode::Normal => scene
+                .aov(&ray)
+                .map_or(Color::zero(), |aov| (aov.normal + Vec3::one()) * 0.5),
+            DebugMode::Uv => scene.aov(&ray).map_or(Color::zero(), |aov| {
+                let (u, v) = aov.uv;
+                Color::new(u.rem_euclid(1.0), v.rem_euclid(1.0), 0.0)
+            }),
+            DebugMode::Depth => scene
+                .aov(&ray)
+                .map_or(Color::zero(), |aov| Color::fill(aov.depth)),
+            DebugMode::Bounces => {
+                start_trace_log();
+                scene.trace(ray, depth);
+                let bounces = take_trace_log()
+                    .iter()
+                    .filter(|record| record.hit.is_some())
+                    .count();
+                heatmap(bounces as f64 / depth.max(1) as f64)
+            }
+        }
+    }
+
+    // 描き終えた値を表示できる範囲にそろえる
+    pub fn normalize(self, buffer: Vec<Color>) -> Vec<Color> {
+        if self != DebugMode::Depth {
+            return buffer;
+        }
+        let far = buffer.iter().map(|c| c.x()).fold(0.0, f64::max);
+        if far <= 0.0 {
+            return buffer;
+        }
+        buffer.into_iter().map(|c| c / far).collect()
+    }
+}
+
+// 0 を青、0.5 を緑、1 を赤にする
+fn heatmap(t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5 {
+        Color::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        Color::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    }
+}
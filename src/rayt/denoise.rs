@@ -0,0 +1,107 @@
+use crate::rayt::*;
+
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// AOV を手がかりにした joint bilateral filter
+// 放射輝度をアルベドで割った照明成分をぼかし、最後にアルベドを掛け戻して模様を保つ
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct Denoiser {
+    pub radius: u32,
+    pub sigma_spatial: f64,
+    // 照明成分の差 (対数輝度) の許容量
+    pub sigma_color: f64,
+    pub sigma_normal: f64,
+    pub sigma_albedo: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            radius: 6,
+            sigma_spatial: 3.0,
+            sigma_color: 0.6,
+            sigma_normal: 0.2,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+const MIN_ALBEDO: f64 = 0.01;
+
+fn demodulate(color: Color, albedo: Color) -> Color {
+    color / albedo_or_one(albedo)
+}
+
+// 暗すぎる成分は割ると発散するので 1 にしておく
+fn albedo_or_one(albedo: Color) -> Color {
+    Color::from_iter(albedo.iter().map(|a| if a > MIN_ALBEDO { a } else { 1.0 }))
+}
+
+fn remodulate(irradiance: Color, albedo: Color) -> Color {
+    irradiance * albedo_or_one(albedo)
+}
+
+impl Denoiser {
+    // 1 回目は法線とアルベドだけでぼかし、その輝度を 2 回目で色の差を測る手がかりにする
+    // ノイズの大きい画素そのものを手がかりにすると、飛び抜けて明るい画素が残ってしまう
+    pub fn denoise(&self, buffer: &[Color], aovs: &AovBuffers) -> Vec<Color> {
+        let irradiance = buffer
+            .iter()
+            .zip(&aovs.albedo)
+            .map(|(c, a)| demodulate(*c, *a))
+            .collect::<Vec<_>>();
+        let guide = self
+            .filter(&irradiance, aovs, None)
+            .iter()
+            .map(|c| (1.0 + c.luminance().max(0.0)).ln())
+            .collect::<Vec<_>>();
+        self.filter(&irradiance, aovs, Some(&guide))
+            .into_iter()
+            .zip(&aovs.albedo)
+            .map(|(e, a)| remodulate(e, *a))
+            .collect()
+    }
+
+    fn filter(&self, irradiance: &[Color], aovs: &AovBuffers, guide: Option<&[f64]>) -> Vec<Color> {
+        let (w, h) = (aovs.width as i64, aovs.height as i64);
+        let r = self.radius as i64;
+        let inv_spatial = 0.5 / (self.sigma_spatial * self.sigma_spatial);
+        let inv_color = 0.5 / (self.sigma_color * self.sigma_color);
+        let inv_normal = 0.5 / (self.sigma_normal * self.sigma_normal);
+        let inv_albedo = 0.5 / (self.sigma_albedo * self.sigma_albedo);
+        (0..w * h)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % w, i / w);
+                let center = i as usize;
+                let (n0, a0) = (aovs.normal[center], aovs.albedo[center]);
+                let mut sum = Color::zero();
+                let mut weight_sum = 0.0;
+                for sy in (y - r).max(0)..=(y + r).min(h - 1) {
+                    for sx in (x - r).max(0)..=(x + r).min(w - 1) {
+                        let j = (sy * w + sx) as usize;
+                        let (dx, dy) = ((sx - x) as f64, (sy - y) as f64);
+                        let dn = aovs.normal[j] - n0;
+                        let da = aovs.albedo[j] - a0;
+                        let dl = guide.map_or(0.0, |g| g[j] - g[center]);
+                        let weight = (-(dx * dx + dy * dy) * inv_spatial
+                            - dl * dl * inv_color
+                            - dn.dot(dn) * inv_normal
+                            - da.dot(da) * inv_albedo)
+                            .exp();
+                        sum += irradiance[j] * weight;
+                        weight_sum += weight;
+                    }
+                }
+                sum / weight_sum
+            })
+            .collect()
+    }
+}
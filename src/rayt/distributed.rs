@@ -0,0 +1,165 @@
+use crate::rayt::*;
+
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// 画素の範囲 (x0, y0, x1, y1)
+pub type Tile = (u32, u32, u32, u32);
+
+// ワーカーが最初に送る描画の条件 (幅, 高さ, overscan, spp)
+// 食い違うワーカーには仕事を渡さない
+pub type Job = [u32; 4];
+
+// これ以上タイルがないことを表す x0
+const END_OF_TILES: u32 = u32::MAX;
+const IDLE_POLL: Duration = Duration::from_millis(50);
+
+fn write_u32s(stream: &mut impl Write, values: &[u32]) -> io::Result<()> {
+    for value in values {
+        stream.write_all(&value.to_le_bytes())?;
+    }
+    stream.flush()
+}
+
+fn read_u32s<const N: usize>(stream: &mut impl Read) -> io::Result<[u32; N]> {
+    let mut values = [0; N];
+    let mut bytes = [0; 4];
+    for value in &mut values {
+        stream.read_exact(&mut bytes)?;
+        *value = u32::from_le_bytes(bytes);
+    }
+    Ok(values)
+}
+
+fn write_colors(stream: &mut impl Write, colors: &[Color]) -> io::Result<()> {
+    for color in colors {
+        for c in color.iter() {
+            stream.write_all(&c.to_le_bytes())?;
+        }
+    }
+    stream.flush()
+}
+
+fn read_colors(stream: &mut impl Read, len: usize) -> io::Result<Vec<Color>> {
+    let mut bytes = [0; 8];
+    let mut component = || -> io::Result<f64> {
+        stream.read_exact(&mut bytes)?;
+        Ok(f64::from_le_bytes(bytes))
+    };
+    (0..len)
+        .map(|_| Ok(Color::new(component()?, component()?, component()?)))
+        .collect()
+}
+
+fn tile_len((x0, y0, x1, y1): Tile) -> usize {
+    ((x1 - x0) * (y1 - y0)) as usize
+}
+
+// addr で待ち受け、つないできたワーカーにタイルを 1 枚ずつ渡す
+// 描き終わったタイルを受け取るたびに on_tile を呼び、false が返ったらそこでやめる
+// 途中で切れたワーカーのタイルは別のワーカーに渡し直す
+pub fn serve_tiles(
+    addr: &str,
+    job: Job,
+    tiles: Vec<Tile>,
+    mut on_tile: impl FnMut(Tile, Vec<Color>) -> bool,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("waiting for workers on {}", listener.local_addr()?);
+    let total = tiles.len();
+    let queue = Arc::new(Mutex::new(tiles.into_iter().collect::<VecDeque<_>>()));
+    let finished = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    {
+        let (queue, finished) = (queue.clone(), finished.clone());
+        // 描き終わった後も accept で止まったままになるが、プロセスの終了とともに消える
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (queue, finished, sender) = (queue.clone(), finished.clone(), sender.clone());
+                thread::spawn(move || {
+                    let peer = stream
+                        .peer_addr()
+                        .map(|a| a.to_string())
+                        .unwrap_or_default();
+                    if let Err(e) = serve_worker(stream, job, &queue, &finished, &sender) {
+                        eprintln!("worker {}: {}", peer, e);
+                    }
+                });
+            }
+        });
+    }
+    for _ in 0..total {
+        let (tile, colors) = receiver.recv().unwrap();
+        if !on_tile(tile, colors) {
+            break;
+        }
+    }
+    finished.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+fn serve_worker(
+    stream: TcpStream,
+    job: Job,
+    queue: &Mutex<VecDeque<Tile>>,
+    finished: &AtomicBool,
+    sender: &mpsc::Sender<(Tile, Vec<Color>)>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let worker_job = read_u32s::<4>(&mut reader)?;
+    if worker_job != job {
+        let message = format!("expected {:?}, worker has {:?}", job, worker_job);
+        write_u32s(&mut writer, &[END_OF_TILES; 4])?;
+        return Err(io::Error::other(message));
+    }
+    loop {
+        let tile = queue.lock().unwrap().pop_front();
+        let Some(tile) = tile else {
+            // 他のワーカーが落ちてタイルが戻ってくるかもしれないので、全部そろうまで待つ
+            if finished.load(Ordering::Relaxed) {
+                return write_u32s(&mut writer, &[END_OF_TILES; 4]);
+            }
+            thread::sleep(IDLE_POLL);
+            continue;
+        };
+        let (x0, y0, x1, y1) = tile;
+        let colors = write_u32s(&mut writer, &[x0, y0, x1, y1])
+            .and_then(|_| read_colors(&mut reader, tile_len(tile)));
+        match colors {
+            Ok(colors) => {
+                // 受け取る側がもういなければ、残りは要らない
+                if sender.send((tile, colors)).is_err() {
+                    return write_u32s(&mut writer, &[END_OF_TILES; 4]);
+                }
+            }
+            Err(e) => {
+                queue.lock().unwrap().push_back(tile);
+                return Err(e);
+            }
+        }
+    }
+}
+
+// addr の配り手につなぎ、渡されたタイルを render で描いて返す
+// 描いたタイルの数を返す
+pub fn work_tiles(addr: &str, job: Job, render: impl Fn(Tile) -> Vec<Color>) -> io::Result<usize> {
+    let stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    write_u32s(&mut writer, &job)?;
+    let mut count = 0;
+    loop {
+        let [x0, y0, x1, y1] = read_u32s::<4>(&mut reader)?;
+        if x0 == END_OF_TILES {
+            return Ok(count);
+        }
+        write_colors(&mut writer, &render((x0, y0, x1, y1)))?;
+        count += 1;
+    }
+}
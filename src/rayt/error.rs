@@ -0,0 +1,54 @@
+use std::{fmt, io};
+
+// ライブラリの関数が返すエラー
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Image(image::ImageError),
+    // ウィンドウを開けない、または更新できない
+    Window(String),
+    // シーンファイル、スクリプト、モデル、テクスチャなどを読み書きできない ("path: 理由" の形)
+    File(String),
+    // 引数や呼び出しの順番がおかしい (テクスチャなしで lambertian を呼んだなど)
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Image(e) => write!(f, "{}", e),
+            Self::Window(message) => write!(f, "window: {}", message),
+            Self::File(message) | Self::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Self::Image(e)
+    }
+}
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+impl From<minifb::Error> for Error {
+    fn from(e: minifb::Error) -> Self {
+        Self::Window(e.to_string())
+    }
+}
@@ -1,9 +1,27 @@
-use rand::random;
+// Real が f64 のときは f64 への変換が素通りになる
+#![allow(clippy::unnecessary_cast)]
 
+use crate::rayt::lanes::Lanes;
 use crate::rayt::*;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// 成分を持つ型。f32 feature を有効にすると半分の大きさになる
+// 外からは常に f64 で読み書きし、精度を比べるときは feature を切り替えるだけで済むようにする
+#[cfg(not(feature = "f32"))]
+pub type Real = f64;
+#[cfg(feature = "f32")]
+pub type Real = f32;
+
+// serde feature ではシーンファイルと同じ [x, y, z] の配列で読み書きする
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct Float3([f64; 3]);
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(from = "[f64; 3]", into = "[f64; 3]")
+)]
+pub struct Float3(Lanes);
 
 pub type Color = Float3;
 pub type Vec3 = Float3;
@@ -11,13 +29,13 @@ pub type Point3 = Float3;
 
 impl Float3 {
     pub fn x(&self) -> f64 {
-        self.0[0]
+        self.0.to_array()[0] as f64
     }
     pub fn y(&self) -> f64 {
-        self.0[1]
+        self.0.to_array()[1] as f64
     }
     pub fn z(&self) -> f64 {
-        self.0[2]
+        self.0.to_array()[2] as f64
     }
     pub const fn xaxis() -> Self {
         Self::new(1.0, 0.0, 0.0)
@@ -32,62 +50,75 @@ impl Float3 {
 
 impl Float3 {
     pub const fn new(x: f64, y: f64, z: f64) -> Self {
-        Self([x, y, z])
+        Self(Lanes::new(x as Real, y as Real, z as Real))
     }
     pub const fn zero() -> Self {
-        Self([0.0; 3])
+        Self(Lanes::splat(0.0))
     }
     pub const fn one() -> Self {
-        Self([1.0; 3])
+        Self(Lanes::splat(1.0))
     }
     pub const fn fill(value: f64) -> Self {
-        Self([value; 3])
+        Self(Lanes::splat(value as Real))
     }
 }
 
 impl Float3 {
     pub fn sqrt(&self) -> Self {
-        Self::from_iter(self.0.iter().map(|x| x.sqrt()))
+        Self(self.0.sqrt())
     }
     pub fn near_zero(&self) -> bool {
-        self.0.iter().all(|x| x.abs() < EPS)
+        self.iter().all(|x| x.abs() < EPS)
+    }
+    pub fn mean(&self) -> f64 {
+        self.iter().sum::<f64>() / 3.0
     }
     pub fn saturate(&self) -> Self {
-        Self::from_iter(self.0.iter().map(|x| x.min(1.0).max(0.0)))
+        self.clamp(0.0, 1.0)
+    }
+    pub fn abs(&self) -> Self {
+        Self(self.0.map(|x| x.abs()))
+    }
+    // 成分ごとの小さいほう、大きいほう
+    pub fn min(&self, rhs: Self) -> Self {
+        Self(self.0.min(rhs.0))
+    }
+    pub fn max(&self, rhs: Self) -> Self {
+        Self(self.0.max(rhs.0))
+    }
+    pub fn clamp(&self, min: f64, max: f64) -> Self {
+        Self(self.0.map(|x| x.clamp(min as Real, max as Real)))
     }
 }
 
 impl Float3 {
     pub fn to_array(self) -> [f64; 3] {
-        self.0
+        self.0.to_array().map(|x| x as f64)
     }
-    pub fn iter(&self) -> std::slice::Iter<'_, f64> {
-        self.0.iter()
+    pub fn iter(&self) -> std::array::IntoIter<f64, 3> {
+        self.to_array().into_iter()
     }
-    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, f64> {
-        self.0.iter_mut()
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Real> {
+        self.0.as_mut_slice().iter_mut()
     }
 }
 
 impl Float3 {
     pub fn dot(&self, rhs: Self) -> f64 {
-        self.0
-            .iter()
-            .zip(rhs.0.iter())
-            .fold(0.0, |acc, (l, r)| acc + l * r)
+        self.0.dot(rhs.0) as f64
     }
     pub fn cross(&self, rhs: Self) -> Self {
-        Self([
-            self.y() * rhs.z() - self.z() * rhs.y(),
-            self.z() * rhs.x() - self.x() * rhs.z(),
-            self.x() * rhs.y() - self.y() * rhs.x(),
-        ])
+        let ([x, y, z], [rx, ry, rz]) = (self.0.to_array(), rhs.0.to_array());
+        Self(
+            Lanes::new(y, z, x) * Lanes::new(rz, rx, ry)
+                - Lanes::new(z, x, y) * Lanes::new(ry, rz, rx),
+        )
     }
     pub fn length(&self) -> f64 {
         self.length_squared().sqrt()
     }
     pub fn length_squared(&self) -> f64 {
-        self.0.iter().fold(0.0, |acc, x| acc + x * x)
+        self.dot(*self)
     }
     pub fn normalize(&self) -> Self {
         *self / self.length()
@@ -97,73 +128,85 @@ impl Float3 {
     }
 }
 
+impl From<[f64; 3]> for Float3 {
+    fn from([x, y, z]: [f64; 3]) -> Self {
+        Self::new(x, y, z)
+    }
+}
+
+impl From<Float3> for [f64; 3] {
+    fn from(v: Float3) -> Self {
+        v.to_array()
+    }
+}
+
 impl FromIterator<f64> for Float3 {
     fn from_iter<T: IntoIterator<Item = f64>>(iter: T) -> Self {
         let mut inner_itr = iter.into_iter();
-        Self([
+        Self::new(
             inner_itr.next().unwrap(),
             inner_itr.next().unwrap(),
             inner_itr.next().unwrap(),
-        ])
+        )
     }
 }
 
 impl std::ops::Neg for Float3 {
     type Output = Self;
     fn neg(self) -> Self {
-        Self::from_iter(self.0.iter().map(|x| -x))
+        Self(-self.0)
     }
 }
 
 impl std::ops::AddAssign<Float3> for Float3 {
     fn add_assign(&mut self, rhs: Float3) {
-        for i in 0..3 {
-            self.0[i] += rhs.0[i];
-        }
+        self.0 = self.0 + rhs.0;
     }
 }
 
 impl std::ops::Add<Float3> for Float3 {
     type Output = Self;
     fn add(self, rhs: Float3) -> Self {
-        Self::from_iter(self.0.iter().zip(rhs.0.iter()).map(|(l, r)| l + r))
+        Self(self.0 + rhs.0)
     }
 }
 
 impl std::ops::SubAssign<Float3> for Float3 {
     fn sub_assign(&mut self, rhs: Float3) {
-        for i in 0..3 {
-            self.0[i] -= rhs.0[i];
-        }
+        self.0 = self.0 - rhs.0;
     }
 }
 
 impl std::ops::Sub<Float3> for Float3 {
     type Output = Self;
     fn sub(self, rhs: Float3) -> Self {
-        Self::from_iter(self.0.iter().zip(rhs.0.iter()).map(|(l, r)| l - r))
+        Self(self.0 - rhs.0)
     }
 }
 
 impl std::ops::Mul<Float3> for Float3 {
     type Output = Self;
     fn mul(self, rhs: Float3) -> Self {
-        Self::from_iter(self.0.iter().zip(rhs.0.iter()).map(|(l, r)| l * r))
+        Self(self.0 * rhs.0)
+    }
+}
+
+impl std::ops::MulAssign<Float3> for Float3 {
+    fn mul_assign(&mut self, rhs: Float3) {
+        self.0 = self.0 * rhs.0;
     }
 }
 
 impl std::ops::MulAssign<f64> for Float3 {
     fn mul_assign(&mut self, rhs: f64) {
-        for i in 0..3 {
-            self.0[i] *= rhs;
-        }
+        self.0 = self.0 * Lanes::splat(rhs as Real);
     }
 }
 
 impl std::ops::Mul<f64> for Float3 {
     type Output = Self;
     fn mul(self, rhs: f64) -> Self {
-        Self::from_iter(self.0.iter().map(|x| x * rhs))
+        Self(self.0 * Lanes::splat(rhs as Real))
     }
 }
 
@@ -174,60 +217,123 @@ impl std::ops::Mul<Float3> for f64 {
     }
 }
 
+impl std::ops::Div<Float3> for Float3 {
+    type Output = Self;
+    fn div(self, rhs: Float3) -> Self {
+        Self(self.0 / rhs.0)
+    }
+}
+
+impl std::ops::DivAssign<Float3> for Float3 {
+    fn div_assign(&mut self, rhs: Float3) {
+        self.0 = self.0 / rhs.0;
+    }
+}
+
 impl std::ops::DivAssign<f64> for Float3 {
     fn div_assign(&mut self, rhs: f64) {
-        for i in 0..3 {
-            self.0[i] /= rhs;
-        }
+        self.0 = self.0 / Lanes::splat(rhs as Real);
     }
 }
 
 impl std::ops::Div<f64> for Float3 {
     type Output = Self;
     fn div(self, rhs: f64) -> Self {
-        Self::from_iter(self.0.iter().map(|x| x / rhs))
+        Self(self.0 / Lanes::splat(rhs as Real))
+    }
+}
+
+// 成分は Real のまま返すので、f32 feature では f32 になる
+impl std::ops::Index<usize> for Float3 {
+    type Output = Real;
+    fn index(&self, index: usize) -> &Real {
+        &self.0.as_slice()[index]
+    }
+}
+
+impl std::ops::IndexMut<usize> for Float3 {
+    fn index_mut(&mut self, index: usize) -> &mut Real {
+        &mut self.0.as_mut_slice()[index]
+    }
+}
+
+impl std::iter::Sum for Float3 {
+    fn sum<I: Iterator<Item = Float3>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, v| acc + v)
+    }
+}
+
+impl<'a> std::iter::Sum<&'a Float3> for Float3 {
+    fn sum<I: Iterator<Item = &'a Float3>>(iter: I) -> Self {
+        iter.copied().sum()
     }
 }
 
 impl Float3 {
-    pub fn from_hex(hex: &[u8; 6]) -> Self {
-        if let Ok(hex_str) = std::str::from_utf8(hex) {
-            let r = u8::from_str_radix(&hex_str[0..2], 16).unwrap();
-            let g = u8::from_str_radix(&hex_str[2..4], 16).unwrap();
-            let b = u8::from_str_radix(&hex_str[4..6], 16).unwrap();
-            Self::from_rgb(r, g, b)
-        } else {
-            panic!();
-        }
+    pub fn from_hex(hex: &[u8; 6]) -> Result<Self, Error> {
+        let invalid =
+            || Error::Invalid(format!("not a hex color: {}", String::from_utf8_lossy(hex)));
+        let hex_str = std::str::from_utf8(hex).map_err(|_| invalid())?;
+        let channel = |i: usize| u8::from_str_radix(&hex_str[i..i + 2], 16).map_err(|_| invalid());
+        Ok(Self::from_rgb(channel(0)?, channel(2)?, channel(4)?))
     }
     pub fn from_rgb(r: u8, g: u8, b: u8) -> Self {
         Self::new(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0)
     }
 
+    // 黒体放射の色 (線形 sRGB, 輝度 1)
+    // プランク軌跡は Kim et al. の 3 次近似で求める (1667K - 25000K)
+    pub fn from_temperature(kelvin: f64) -> Self {
+        let t = kelvin.clamp(1667.0, 25000.0);
+        let (t2, t3) = (t * t, t * t * t);
+        let x = if t < 4000.0 {
+            -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+        } else {
+            -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+        };
+        let (x2, x3) = (x * x, x * x * x);
+        let y = if t < 2222.0 {
+            -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+        } else if t < 4000.0 {
+            -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+        } else {
+            3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+        };
+        let (cx, cy, cz) = (x / y, 1.0, (1.0 - x - y) / y);
+        let rgb = Self::new(
+            3.2406 * cx - 1.5372 * cy - 0.4986 * cz,
+            -0.9689 * cx + 1.8758 * cy + 0.0415 * cz,
+            0.0557 * cx - 0.2040 * cy + 1.0570 * cz,
+        );
+        // 低温側は sRGB の色域外になるので切り詰めてから輝度を合わせ直す
+        let rgb = Self::from_iter(rgb.iter().map(|c| c.max(0.0)));
+        rgb / rgb.luminance()
+    }
+
     pub fn to_rgb(self) -> [u8; 3] {
         [self.r(), self.g(), self.b()]
     }
 
     pub fn r(&self) -> u8 {
-        (255.99 * self.0[0].min(1.0).max(0.0)) as u8
+        (255.99 * self.x().clamp(0.0, 1.0)) as u8
     }
     pub fn g(&self) -> u8 {
-        (255.99 * self.0[1].min(1.0).max(0.0)) as u8
+        (255.99 * self.y().clamp(0.0, 1.0)) as u8
     }
     pub fn b(&self) -> u8 {
-        (255.99 * self.0[2].min(1.0).max(0.0)) as u8
+        (255.99 * self.z().clamp(0.0, 1.0)) as u8
     }
 }
 
 impl Float3 {
     pub fn random() -> Self {
-        Self::new(random::<f64>(), random::<f64>(), random::<f64>())
+        Self::from(random_array::<3>())
     }
     pub fn random_fill() -> Self {
-        Self::fill(random::<f64>())
+        Self::fill(random_f64())
     }
     pub fn random_limit(min: f64, max: f64) -> Self {
-        Self::from_iter(Self::random().0.iter().map(|x| min + x * (max - min)))
+        Self::from_iter(Self::random().iter().map(|x| min + x * (max - min)))
     }
     pub fn random_in_unit_sphere() -> Self {
         loop {
@@ -237,15 +343,62 @@ impl Float3 {
             }
         }
     }
+    pub fn random_in_unit_disk() -> Self {
+        loop {
+            let [x, y] = random_array();
+            let point = Self::new(x * 2.0 - 1.0, y * 2.0 - 1.0, 0.0);
+            if point.length_squared() < 1.0 {
+                return point;
+            }
+        }
+    }
+    // z 軸まわりの半球上で cosθ/π に比例する方向
+    pub fn random_cosine_direction() -> Self {
+        let [r1, r2] = random_array();
+        let phi = PI2 * r1;
+        let r = r2.sqrt();
+        Self::new(phi.cos() * r, phi.sin() * r, (1.0 - r2).sqrt())
+    }
+    // 単位球面上の一様な方向
+    pub fn random_unit_vector() -> Self {
+        let [r1, r2] = random_array();
+        let z = 1.0 - 2.0 * r1;
+        let phi = PI2 * r2;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Self::new(phi.cos() * r, phi.sin() * r, z)
+    }
+    // normal 側の半球上の一様な方向
+    pub fn random_in_hemisphere(normal: Self) -> Self {
+        let v = Self::random_unit_vector();
+        if v.dot(normal) > 0.0 {
+            v
+        } else {
+            -v
+        }
+    }
+    // 中心まで距離の 2 乗が dist_sq の位置から、半径 radius の球が見える円錐の中の一様な方向
+    // 円錐の軸は z 軸なので、Onb で中心の向きに直して使う
+    pub fn random_to_sphere(radius: f64, dist_sq: f64) -> Self {
+        let [r1, r2] = random_array();
+        let cos_max = (1.0 - radius * radius / dist_sq).max(0.0).sqrt();
+        let z = 1.0 + r2 * (cos_max - 1.0);
+        let phi = PI2 * r1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Self::new(phi.cos() * r, phi.sin() * r, z)
+    }
 }
 
 impl Float3 {
     pub fn gamma(&self, factor: f64) -> Self {
         let recip = factor.recip();
-        Self::from_iter(self.0.iter().map(|x| x.powf(recip)))
+        Self::from_iter(self.iter().map(|x| x.powf(recip)))
     }
     pub fn degamma(&self, factor: f64) -> Self {
-        Self::from_iter(self.0.iter().map(|x| x.powf(factor)))
+        Self::from_iter(self.iter().map(|x| x.powf(factor)))
+    }
+    // Rec. 709 の輝度
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x() + 0.7152 * self.y() + 0.0722 * self.z()
     }
 }
 
@@ -267,3 +420,85 @@ impl Float3 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2 のべき乗で割り切れる値だけを使い、f32 feature でも丸め誤差が出ないようにする
+    fn a() -> Float3 {
+        Float3::new(1.0, -2.0, 4.0)
+    }
+
+    fn b() -> Float3 {
+        Float3::new(2.0, 0.5, -4.0)
+    }
+
+    #[test]
+    fn arithmetic_is_component_wise() {
+        assert_eq!(a() + b(), Float3::new(3.0, -1.5, 0.0));
+        assert_eq!(a() - b(), Float3::new(-1.0, -2.5, 8.0));
+        assert_eq!(a() * b(), Float3::new(2.0, -1.0, -16.0));
+        assert_eq!(a() / b(), Float3::new(0.5, -4.0, -1.0));
+        assert_eq!(-a(), Float3::new(-1.0, 2.0, -4.0));
+        assert_eq!(a() * 2.0, Float3::new(2.0, -4.0, 8.0));
+        assert_eq!(2.0 * a(), a() * 2.0);
+        assert_eq!(a() / 2.0, Float3::new(0.5, -1.0, 2.0));
+    }
+
+    #[test]
+    fn assign_operators_match_binary_operators() {
+        let mut v = a();
+        v += b();
+        assert_eq!(v, a() + b());
+        let mut v = a();
+        v -= b();
+        assert_eq!(v, a() - b());
+        let mut v = a();
+        v *= b();
+        assert_eq!(v, a() * b());
+        let mut v = a();
+        v *= 0.5;
+        assert_eq!(v, a() * 0.5);
+        let mut v = a();
+        v /= b();
+        assert_eq!(v, a() / b());
+        let mut v = a();
+        v /= 4.0;
+        assert_eq!(v, a() / 4.0);
+    }
+
+    #[test]
+    fn min_max_abs_and_clamp_are_component_wise() {
+        assert_eq!(a().min(b()), Float3::new(1.0, -2.0, -4.0));
+        assert_eq!(a().max(b()), Float3::new(2.0, 0.5, 4.0));
+        assert_eq!(a().abs(), Float3::new(1.0, 2.0, 4.0));
+        assert_eq!(a().clamp(-1.0, 2.0), Float3::new(1.0, -1.0, 2.0));
+        assert_eq!(b().saturate(), Float3::new(1.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn index_and_conversions() {
+        let mut v = a();
+        assert_eq!((v[0], v[1], v[2]), (1.0, -2.0, 4.0));
+        v[1] = 3.0;
+        assert_eq!(v, Float3::new(1.0, 3.0, 4.0));
+        assert_eq!(Float3::from([1.0, -2.0, 4.0]), a());
+        assert_eq!(<[f64; 3]>::from(a()), [1.0, -2.0, 4.0]);
+        assert_eq!(a().iter().collect::<Float3>(), a());
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_out_of_range_panics() {
+        let _ = a()[3];
+    }
+
+    #[test]
+    fn sum_accumulates_owned_and_borrowed() {
+        let vs = [a(), b(), Float3::one()];
+        assert_eq!(vs.iter().sum::<Float3>(), Float3::new(4.0, -0.5, 1.0));
+        assert_eq!(vs.into_iter().sum::<Float3>(), Float3::new(4.0, -0.5, 1.0));
+        assert_eq!(std::iter::empty::<Float3>().sum::<Float3>(), Float3::zero());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::rayt::*;
+
+// シーン全体にかかる霧。形状を置かずに、遠くのものほど color に溶け込ませる
+// 経路の各区間で、区間の長さに応じて弱めた光に霧の色を足す (霧の中の散乱は color で近似する)
+// 光源から影の光線で届く光は弱めない
+#[derive(Debug, Clone, Copy)]
+pub struct Fog {
+    pub color: Color,
+    // 単位長さあたりの消散係数
+    pub density: f64,
+    // Some((base, falloff)) なら高さ base で density になり、上へ exp(-falloff * h) で薄くなる
+    pub height: Option<(f64, f64)>,
+    // 光源からの光を霧が散乱する割合 (アルベド)。0 より大きければ光源のまわりに光の筋が出る
+    pub scattering: f64,
+}
+
+impl Fog {
+    // どこでも同じ濃さの霧 (距離に対して指数的に減衰する)
+    pub fn new(color: Color, density: f64) -> Self {
+        Self {
+            color,
+            density,
+            height: None,
+            scattering: 0.0,
+        }
+    }
+
+    // 地面付近に溜まる霧
+    pub fn with_height(self, base: f64, falloff: f64) -> Self {
+        Self {
+            height: Some((base, falloff)),
+            ..self
+        }
+    }
+
+    pub fn with_scattering(self, scattering: f64) -> Self {
+        Self { scattering, ..self }
+    }
+
+    pub fn density_at(&self, p: Point3) -> f64 {
+        match self.height {
+            None => self.density,
+            Some((base, falloff)) => self.density * (-falloff * (p.y() - base)).exp(),
+        }
+    }
+
+    // ray.at(0) から ray.at(t) までを通り抜ける割合。t が f64::MAX なら空へ抜ける光線
+    pub fn transmittance(&self, ray: &Ray, t: f64) -> f64 {
+        if self.density <= 0.0 {
+            return 1.0;
+        }
+        let length = ray.direction.length();
+        let distance = t * length;
+        let optical_depth = match self.height {
+            None => self.density * distance,
+            Some((base, falloff)) => {
+                // 密度を光線に沿って積分する。高さの変わらない光線は一様な霧と同じ
+                let start = self.density * (-falloff * (ray.origin.y() - base)).exp();
+                let k = falloff * ray.direction.y() / length;
+                if start == 0.0 {
+                    0.0
+                } else if k.abs() < 1e-9 {
+                    start * distance
+                } else {
+                    start * (1.0 - (-k * distance).exp()) / k
+                }
+            }
+        };
+        (-optical_depth).exp()
+    }
+
+    // 区間の先から届いた radiance に霧をかける
+    pub fn apply(&self, ray: &Ray, t: f64, radiance: Color) -> Color {
+        let transmittance = self.transmittance(ray, t);
+        radiance * transmittance + self.color * (1.0 - transmittance)
+    }
+}
+
+// ray.at(0) から ray.at(t) までの間で、霧が光源の光を 1 回だけ散乱してこちらへ向ける光
+// 光源ごとに光線上の 1 点を選ぶ。位置のある光源は光源に近いところほど多く選ぶ (equiangular)
+// 無限遠の光源は霧を通り抜ける割合に比例して選ぶ
+// 光源ごとにそのグループへ入れる
+pub fn fog_scattering(scene: &impl WorldScene, ray: &Ray, t: f64) -> GroupRadiance {
+    let Some(fog) = scene
+        .fog()
+        .filter(|fog| fog.scattering > 0.0 && fog.density > 0.0)
+    else {
+        return GroupRadiance::zero();
+    };
+    let length = ray.direction.length();
+    let ray = Ray::new(ray.origin, ray.direction / length).with_time(ray.time);
+    let t_max = t * length;
+    scene_lights(scene)
+        .filter_map(|light| {
+            let (t, pdf) = match light.position() {
+                Some(position) => equiangular(&ray, t_max, position),
+                None => exponential(fog.density, t_max),
+            }?;
+            let p = ray.at(t);
+            let (_, irradiance) = unshadowed(scene, light, p, ray.time)?;
+            // 等方的に散乱する
+            let phase = 0.25 * FRAC_1_PI;
+            let density = fog.scattering * fog.density_at(p);
+            let color = irradiance * (density * phase * fog.transmittance(&ray, t) / pdf);
+            Some(GroupRadiance::new(
+                scene.light_group_index(light.group()),
+                color,
+            ))
+        })
+        .sum()
+}
+
+// 光線から見た光源の角度が一様になるように選んだ距離と、その確率密度
+fn equiangular(ray: &Ray, t_max: f64, position: Point3) -> Option<(f64, f64)> {
+    let delta = (position - ray.origin).dot(ray.direction);
+    let d = (position - ray.at(delta)).length().max(1e-4);
+    let theta_a = (-delta).atan2(d);
+    let theta_b = (t_max - delta).atan2(d);
+    if theta_b <= theta_a {
+        return None;
+    }
+    let theta = theta_a + random_f64() * (theta_b - theta_a);
+    let offset = d * theta.tan();
+    let t = delta + offset;
+    let pdf = d / ((theta_b - theta_a) * (d * d + offset * offset));
+    (t > 0.0 && t < t_max).then_some((t, pdf))
+}
+
+// 一様な霧を通り抜ける割合に比例して、t_max までで選んだ距離と、その確率密度
+fn exponential(density: f64, t_max: f64) -> Option<(f64, f64)> {
+    let reach = 1.0 - (-density * t_max).exp();
+    let t = -(1.0 - random_f64() * reach).ln() / density;
+    let pdf = density * (-density * t).exp() / reach;
+    (t < t_max && pdf > 0.0).then_some((t, pdf))
+}
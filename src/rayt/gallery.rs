@@ -0,0 +1,115 @@
+use image::{Rgb, RgbImage};
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+const SHEET_PADDING: u32 = 6;
+const LABEL_HEIGHT: u32 = GLYPH_HEIGHT + 6;
+const SHEET_BACKGROUND: Rgb<u8> = Rgb([32, 32, 32]);
+const LABEL_COLOR: Rgb<u8> = Rgb([230, 230, 230]);
+
+// 5x7 のビットマップフォント (小文字は大文字で描く)
+#[rustfmt::skip]
+const GLYPHS: [(char, [u8; 7]); 42] = [
+    ('A', [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('B', [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110]),
+    ('C', [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110]),
+    ('D', [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110]),
+    ('E', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111]),
+    ('F', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('G', [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111]),
+    ('H', [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('I', [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('J', [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100]),
+    ('K', [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001]),
+    ('L', [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111]),
+    ('M', [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001]),
+    ('N', [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001]),
+    ('O', [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('P', [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('Q', [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101]),
+    ('R', [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001]),
+    ('S', [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110]),
+    ('T', [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100]),
+    ('U', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('V', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100]),
+    ('W', [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010]),
+    ('X', [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001]),
+    ('Y', [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100]),
+    ('Z', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111]),
+    ('0', [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110]),
+    ('1', [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('2', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111]),
+    ('3', [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110]),
+    ('4', [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010]),
+    ('5', [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110]),
+    ('6', [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110]),
+    ('7', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000]),
+    ('8', [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110]),
+    ('9', [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100]),
+    ('-', [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000]),
+    ('_', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111]),
+    ('.', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100]),
+    ('(', [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010]),
+    (')', [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000]),
+    ('?', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100]),
+];
+
+fn glyph(c: char) -> Option<[u8; 7]> {
+    if c == ' ' {
+        return None;
+    }
+    let c = c.to_ascii_uppercase();
+    GLYPHS
+        .iter()
+        .find(|(g, _)| *g == c)
+        .or_else(|| GLYPHS.iter().find(|(g, _)| *g == '?'))
+        .map(|(_, rows)| *rows)
+}
+
+// (x, y) を左上にして text を描く。はみ出した部分は切り捨てる
+pub fn draw_text(img: &mut RgbImage, x: u32, y: u32, text: &str, color: Rgb<u8>) {
+    for (i, c) in text.chars().enumerate() {
+        let Some(rows) = glyph(c) else {
+            continue;
+        };
+        let x0 = x + i as u32 * (GLYPH_WIDTH + 1);
+        for (dy, row) in rows.iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                let (px, py) = (x0 + dx, y + dy as u32);
+                let inside = px < img.width() && py < img.height();
+                if inside && row & (1 << (GLYPH_WIDTH - 1 - dx)) != 0 {
+                    img.put_pixel(px, py, color);
+                }
+            }
+        }
+    }
+}
+
+// サムネイルを格子状に並べ、それぞれの下に名前を書く
+pub fn contact_sheet(entries: &[(String, RgbImage)]) -> RgbImage {
+    let columns = (entries.len() as f64).sqrt().ceil().max(1.0) as u32;
+    let rows = (entries.len() as u32).div_ceil(columns);
+    let cell_w = entries
+        .iter()
+        .map(|(_, img)| img.width())
+        .max()
+        .unwrap_or(0);
+    let cell_h = entries
+        .iter()
+        .map(|(_, img)| img.height())
+        .max()
+        .unwrap_or(0)
+        + LABEL_HEIGHT;
+    let mut sheet = RgbImage::from_pixel(
+        SHEET_PADDING + columns * (cell_w + SHEET_PADDING),
+        SHEET_PADDING + rows * (cell_h + SHEET_PADDING),
+        SHEET_BACKGROUND,
+    );
+    for (i, (name, img)) in entries.iter().enumerate() {
+        let x = SHEET_PADDING + i as u32 % columns * (cell_w + SHEET_PADDING);
+        let y = SHEET_PADDING + i as u32 / columns * (cell_h + SHEET_PADDING);
+        image::imageops::replace(&mut sheet, img, x as i64, y as i64);
+        draw_text(&mut sheet, x, y + img.height() + 3, name, LABEL_COLOR);
+    }
+    sheet
+}
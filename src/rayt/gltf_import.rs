@@ -0,0 +1,223 @@
+use crate::rayt::*;
+
+use base64::Engine;
+use gltf::camera::Projection;
+use gltf::mesh::Mode;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0] as f64, v[1] as f64, v[2] as f64)
+}
+
+// glTF のカメラの位置と向き。fov は縦の画角 (度)
+pub struct GltfCamera {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub up: Vec3,
+    pub fov: f64,
+}
+
+pub struct GltfScene {
+    pub mesh: Mesh,
+    // ノードの順で最初に見つかった透視投影のカメラ
+    pub camera: Option<GltfCamera>,
+}
+
+fn load_buffers(path: &Path, gltf: &gltf::Gltf) -> Result<Vec<Vec<u8>>, String> {
+    gltf.buffers()
+        .map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Bin => {
+                gltf.blob.clone().ok_or("missing binary chunk".to_string())
+            }
+            gltf::buffer::Source::Uri(uri) => match uri.split_once(";base64,") {
+                Some((_, data)) if uri.starts_with("data:") => {
+                    base64::engine::general_purpose::STANDARD
+                        .decode(data)
+                        .map_err(|e| e.to_string())
+                }
+                _ => {
+                    let file = path.parent().unwrap_or(Path::new("")).join(uri);
+                    std::fs::read(&file).map_err(|e| format!("{}: {}", file.display(), e))
+                }
+            },
+        })
+        .collect()
+}
+
+// 基本色のテクスチャはファイルを参照しているものだけ使う
+// glb に埋め込まれた画像は読めないので、基本色の係数で代用する
+fn base_color_texture(path: &Path, material: &gltf::Material) -> Option<String> {
+    let info = material.pbr_metallic_roughness().base_color_texture()?;
+    match info.texture().source().source() {
+        gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => Some(
+            path.parent()
+                .unwrap_or(Path::new(""))
+                .join(uri)
+                .to_string_lossy()
+                .into_owned(),
+        ),
+        _ => None,
+    }
+}
+
+// 発光があれば光源、metallic が 0.5 以上なら金属 (roughness を fuzz に)、それ以外は Lambertian にする
+fn build_material(path: &Path, material: &gltf::Material) -> Result<Arc<dyn Material>, Error> {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let base_color = Color::new(r as f64, g as f64, b as f64);
+    let [er, eg, eb] = material.emissive_factor();
+    let emissive = Color::new(er as f64, eg as f64, eb as f64);
+    let builder = ShapeBuilder::new();
+    let builder = if emissive.mean() > 0.0 {
+        builder.color_texture(emissive).diffuse_light()
+    } else {
+        let builder = match base_color_texture(path, material) {
+            Some(texture) => builder.image_texture(&texture),
+            None => builder.color_texture(base_color),
+        };
+        if pbr.metallic_factor() >= 0.5 {
+            builder.metal(pbr.roughness_factor() as f64)
+        } else {
+            builder.lambertian()
+        }
+    };
+    builder.build_material()
+}
+
+struct Loader<'a> {
+    path: &'a Path,
+    buffers: Vec<Vec<u8>>,
+    // 上書きするマテリアル
+    material: Option<Arc<dyn Material>>,
+    materials: HashMap<Option<usize>, Arc<dyn Material>>,
+    triangles: Vec<Triangle>,
+    camera: Option<GltfCamera>,
+}
+
+impl Loader<'_> {
+    fn material(&mut self, material: &gltf::Material) -> Result<Arc<dyn Material>, String> {
+        if let Some(material) = &self.material {
+            return Ok(Arc::clone(material));
+        }
+        if let Some(built) = self.materials.get(&material.index()) {
+            return Ok(Arc::clone(built));
+        }
+        let built = build_material(self.path, material).map_err(|e| e.to_string())?;
+        self.materials.insert(material.index(), Arc::clone(&built));
+        Ok(built)
+    }
+
+    fn node(&mut self, node: gltf::Node, parent: &Transform) -> Result<(), String> {
+        let local = Mat4::from_cols(node.transform().matrix().map(|col| col.map(|v| v as f64)));
+        // 拡大率 0 で隠してあるノードは、子も含めて何も写らない
+        let Some(world) = Transform::from_matrix(*parent.matrix() * local) else {
+            return Ok(());
+        };
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.primitive(&primitive, &world)?;
+            }
+        }
+        if let (None, Some(camera)) = (&self.camera, node.camera()) {
+            if let Projection::Perspective(perspective) = camera.projection() {
+                // カメラは -Z を向き、+Y が上
+                let look_from = world.point(Point3::zero());
+                self.camera = Some(GltfCamera {
+                    look_from,
+                    look_at: look_from - world.vector(Vec3::zaxis()),
+                    up: world.vector(Vec3::yaxis()),
+                    fov: (perspective.yfov() as f64).to_degrees(),
+                });
+            }
+        }
+        for child in node.children() {
+            self.node(child, &world)?;
+        }
+        Ok(())
+    }
+
+    fn primitive(&mut self, primitive: &gltf::Primitive, world: &Transform) -> Result<(), String> {
+        if primitive.mode() != Mode::Triangles {
+            eprintln!(
+                "{}: skipping {:?} primitive",
+                self.path.display(),
+                primitive.mode()
+            );
+            return Ok(());
+        }
+        let material = self.material(&primitive.material())?;
+        let buffers = &self.buffers;
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let positions = reader
+            .read_positions()
+            .ok_or("primitive without POSITION")?
+            .map(|p| world.point(vec3(p)))
+            .collect::<Vec<_>>();
+        let normals = reader.read_normals().map(|normals| {
+            normals
+                .map(|n| world.normal(vec3(n)).normalize())
+                .collect::<Vec<_>>()
+        });
+        // glTF の v は下向きなので、OBJ と同じく上向きにそろえる
+        let uvs = reader.read_tex_coords(0).map(|uvs| {
+            uvs.into_f32()
+                .map(|[u, v]| (u as f64, 1.0 - v as f64))
+                .collect::<Vec<_>>()
+        });
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+            None => (0..positions.len()).collect::<Vec<_>>(),
+        };
+        // 裏返る変換では頂点の順番を入れ替えて、面の向きを保つ
+        let flip = world.flips_handedness();
+        for corners in indices.chunks_exact(3) {
+            let mut corners = [corners[0], corners[1], corners[2]];
+            if flip {
+                corners.swap(1, 2);
+            }
+            if corners.iter().any(|&i| i >= positions.len()) {
+                return Err("index out of range".to_string());
+            }
+            self.triangles.push(Triangle::new(
+                corners.map(|i| positions[i]),
+                normals.as_ref().map(|normals| corners.map(|i| normals[i])),
+                uvs.as_ref().map(|uvs| corners.map(|i| uvs[i])),
+                Arc::clone(&material),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// .gltf か .glb を読み、既定のシーン (なければ最初のシーン) のノードをたどって
+// 変換を焼き込んだ三角形を 1 つの Mesh にまとめる
+// material を渡すと glTF のマテリアルは使わず、すべての面をそのマテリアルにする
+pub fn load_gltf(path: &str, material: Option<Arc<dyn Material>>) -> Result<GltfScene, Error> {
+    let path = Path::new(path);
+    let error = |e: String| Error::File(format!("{}: {}", path.display(), e));
+    let gltf = gltf::Gltf::open(path).map_err(|e| error(e.to_string()))?;
+    let mut loader = Loader {
+        path,
+        buffers: load_buffers(path, &gltf).map_err(error)?,
+        material,
+        materials: HashMap::new(),
+        triangles: Vec::new(),
+        camera: None,
+    };
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or_else(|| error("no scenes".to_string()))?;
+    for node in scene.nodes() {
+        loader.node(node, &Transform::identity()).map_err(error)?;
+    }
+    if loader.triangles.is_empty() {
+        return Err(error("no triangles".to_string()));
+    }
+    Ok(GltfScene {
+        mesh: Mesh::new(loader.triangles),
+        camera: loader.camera,
+    })
+}
@@ -0,0 +1,171 @@
+use crate::rayt::*;
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::RwLock;
+
+pub struct HashGrid<T> {
+    cell_size: f64,
+    buckets: Vec<RwLock<Vec<(Point3, T)>>>,
+    // 点の入ったセルを囲む範囲。探すセルはこの中に限る
+    min_cell: [AtomicI64; 3],
+    max_cell: [AtomicI64; 3],
+}
+
+impl<T> HashGrid<T> {
+    pub fn new(cell_size: f64, bucket_count: usize) -> Self {
+        Self {
+            cell_size,
+            buckets: (0..bucket_count.max(1))
+                .map(|_| RwLock::new(Vec::new()))
+                .collect(),
+            min_cell: std::array::from_fn(|_| AtomicI64::new(i64::MAX)),
+            max_cell: std::array::from_fn(|_| AtomicI64::new(i64::MIN)),
+        }
+    }
+
+    fn cell(&self, p: Point3) -> [i64; 3] {
+        let [x, y, z] = (p / self.cell_size).to_array();
+        [x.floor() as i64, y.floor() as i64, z.floor() as i64]
+    }
+
+    // Teschner et al. の空間ハッシュ
+    fn bucket(&self, cell: [i64; 3]) -> usize {
+        let h = (cell[0].wrapping_mul(73856093))
+            ^ (cell[1].wrapping_mul(19349663))
+            ^ (cell[2].wrapping_mul(83492791));
+        h.rem_euclid(self.buckets.len() as i64) as usize
+    }
+
+    pub fn insert(&self, p: Point3, payload: T) {
+        let cell = self.cell(p);
+        for ((min, max), c) in self.min_cell.iter().zip(&self.max_cell).zip(cell) {
+            min.fetch_min(c, Ordering::Relaxed);
+            max.fetch_max(c, Ordering::Relaxed);
+        }
+        let bucket = self.bucket(cell);
+        self.buckets[bucket].write().unwrap().push((p, payload));
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|b| b.read().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        for bucket in &self.buckets {
+            bucket.write().unwrap().clear();
+        }
+        for (min, max) in self.min_cell.iter().zip(&self.max_cell) {
+            min.store(i64::MAX, Ordering::Relaxed);
+            max.store(i64::MIN, Ordering::Relaxed);
+        }
+    }
+
+    pub fn for_each_in_radius<F>(&self, p: Point3, radius: f64, mut f: F)
+    where
+        F: FnMut(Point3, &T),
+    {
+        // 半径が大きくても、点の入ったセルの範囲より外は探さない
+        let (lo, hi) = (
+            self.cell(p - Vec3::fill(radius)),
+            self.cell(p + Vec3::fill(radius)),
+        );
+        let [x0, y0, z0] =
+            std::array::from_fn(|axis| lo[axis].max(self.min_cell[axis].load(Ordering::Relaxed)));
+        let [x1, y1, z1] =
+            std::array::from_fn(|axis| hi[axis].min(self.max_cell[axis].load(Ordering::Relaxed)));
+        if x0 > x1 || y0 > y1 || z0 > z1 {
+            return;
+        }
+        // セルがバケットより多ければ、すべてのバケットを一度ずつ見るほうが速い
+        let cells = [(x0, x1), (y0, y1), (z0, z1)]
+            .iter()
+            .map(|&(a, b)| (b - a + 1) as u128)
+            .product::<u128>();
+        let mut buckets = Vec::new();
+        if cells >= self.buckets.len() as u128 {
+            buckets.extend(0..self.buckets.len());
+        } else {
+            // 別のセルが同じバケットに入ることがあるので重複を除いてから走査する
+            for x in x0..=x1 {
+                for y in y0..=y1 {
+                    for z in z0..=z1 {
+                        buckets.push(self.bucket([x, y, z]));
+                    }
+                }
+            }
+        }
+        buckets.sort_unstable();
+        buckets.dedup();
+        let radius_squared = radius * radius;
+        for bucket in buckets {
+            for (q, payload) in self.buckets[bucket].read().unwrap().iter() {
+                if (*q - p).length_squared() <= radius_squared {
+                    f(*q, payload);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Clone> HashGrid<T> {
+    pub fn query_radius(&self, p: Point3, radius: f64) -> Vec<(Point3, T)> {
+        let mut found = Vec::new();
+        self.for_each_in_radius(p, radius, |q, payload| found.push((q, payload.clone())));
+        found
+    }
+
+    pub fn k_nearest(&self, p: Point3, k: usize, max_radius: f64) -> Vec<(Point3, T)> {
+        let mut found = self.query_radius(p, max_radius);
+        found.sort_by(|(a, _), (b, _)| {
+            (*a - p)
+                .length_squared()
+                .total_cmp(&(*b - p).length_squared())
+        });
+        found.truncate(k);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radius_query_finds_points_within_radius() {
+        let grid = HashGrid::new(1.0, 64);
+        grid.insert(Point3::new(0.0, 0.0, 0.0), 0);
+        grid.insert(Point3::new(1.5, 0.0, 0.0), 1);
+        grid.insert(Point3::new(5.0, 5.0, 5.0), 2);
+        let mut found = grid
+            .query_radius(Point3::new(0.5, 0.0, 0.0), 1.1)
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, vec![0, 1]);
+    }
+
+    #[test]
+    fn huge_radius_is_clamped_to_occupied_cells() {
+        let grid = HashGrid::new(1e-3, 16);
+        for i in 0..10 {
+            grid.insert(Point3::new(i as f64, 0.0, 0.0), i);
+        }
+        // 範囲を絞らなければ (2e9)^3 個のセルを回ることになる
+        assert_eq!(grid.query_radius(Point3::zero(), 1e6).len(), 10);
+        assert_eq!(grid.k_nearest(Point3::zero(), 3, 1e6).len(), 3);
+    }
+
+    #[test]
+    fn empty_grid_finds_nothing() {
+        let grid = HashGrid::<u32>::new(1.0, 16);
+        assert!(grid.query_radius(Point3::zero(), 10.0).is_empty());
+        grid.insert(Point3::zero(), 1);
+        grid.clear();
+        assert!(grid.query_radius(Point3::zero(), 10.0).is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use image::{ImageOutputFormat, RgbImage};
+use std::io::{self, BufRead, BufReader, Cursor, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+const JPEG_QUALITY: u8 = 90;
+const BOUNDARY: &str = "frame";
+
+const INDEX_HTML: &str = "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>rayt</title></head>
+<body style=\"background: #222; color: #ccc; font-family: sans-serif\">
+<img src=\"/stream.mjpg\"><br>
+<a href=\"/render.png\" style=\"color: #ccc\">render.png</a>
+</body>
+</html>
+";
+
+// 最新の途中経過。number は update のたびに 1 つ増える (0 ならまだ何もない)
+#[derive(Default)]
+struct Frame {
+    number: u64,
+    png: Arc<Vec<u8>>,
+    jpeg: Arc<Vec<u8>>,
+    finished: bool,
+}
+
+type Shared = Arc<(Mutex<Frame>, Condvar)>;
+
+// 描画の途中経過を HTTP で配る (リモートのマシンでの長い描画をブラウザで見る用)
+//   /            ストリームを表示するページ
+//   /stream.mjpg pass を終えるたびに JPEG を送り続ける (multipart/x-mixed-replace)
+//   /render.png  その時点の画像
+pub struct PreviewServer {
+    addr: String,
+    shared: Shared,
+}
+
+impl PreviewServer {
+    pub fn start(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?.to_string();
+        println!("preview on http://{}/", addr);
+        let shared = Shared::default();
+        {
+            let shared = shared.clone();
+            // 描き終わっても accept で止まったままになるが、プロセスの終了とともに消える
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let shared = shared.clone();
+                    thread::spawn(move || {
+                        // ブラウザが途中で閉じたときのエラーは気にしない
+                        let _ = respond(stream, &shared);
+                    });
+                }
+            });
+        }
+        Ok(Self { addr, shared })
+    }
+
+    // 配る画像を img に差し替える
+    pub fn update(&self, img: &RgbImage) {
+        let encode = |format| {
+            let mut bytes = Vec::new();
+            img.write_to(&mut Cursor::new(&mut bytes), format).unwrap();
+            Arc::new(bytes)
+        };
+        let (png, jpeg) = (
+            encode(ImageOutputFormat::Png),
+            encode(ImageOutputFormat::Jpeg(JPEG_QUALITY)),
+        );
+        let (frame, changed) = &*self.shared;
+        let mut frame = frame.lock().unwrap();
+        frame.number += 1;
+        frame.png = png;
+        frame.jpeg = jpeg;
+        changed.notify_all();
+    }
+
+    // 最後の画像を送ったらストリームを閉じる。その後も /render.png は返し続ける
+    // (プロセスが終わるまで。画面のないサーバでは wait で待つ)
+    pub fn finish(&self) {
+        let (frame, changed) = &*self.shared;
+        frame.lock().unwrap().finished = true;
+        changed.notify_all();
+    }
+
+    // 描き終えた結果を Ctrl-C で止めるまで配り続ける
+    pub fn wait(&self) {
+        println!("finished; serving http://{}/ until interrupted", self.addr);
+        loop {
+            thread::park();
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // ヘッダは読み捨てる
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    match path {
+        "/" => send(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            INDEX_HTML.as_bytes(),
+        ),
+        "/render.png" => {
+            let png = shared.0.lock().unwrap().png.clone();
+            if png.is_empty() {
+                send(
+                    &mut stream,
+                    "503 Service Unavailable",
+                    "text/plain",
+                    b"no image yet\n",
+                )
+            } else {
+                send(&mut stream, "200 OK", "image/png", &png)
+            }
+        }
+        "/stream.mjpg" => stream_frames(&mut stream, shared),
+        _ => send(&mut stream, "404 Not Found", "text/plain", b"not found\n"),
+    }
+}
+
+fn send(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+// 新しい画像が来るたびに 1 枚ずつ送る。描き終えて最後の 1 枚を送ったら閉じる
+fn stream_frames(stream: &mut TcpStream, shared: &Shared) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        BOUNDARY
+    )?;
+    let (frame, changed) = &**shared;
+    let mut sent = 0;
+    loop {
+        let (number, jpeg, finished) = {
+            let frame = changed
+                .wait_while(frame.lock().unwrap(), |f| f.number == sent && !f.finished)
+                .unwrap();
+            (frame.number, frame.jpeg.clone(), frame.finished)
+        };
+        if number != sent {
+            write!(
+                stream,
+                "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                BOUNDARY,
+                jpeg.len()
+            )?;
+            stream.write_all(&jpeg)?;
+            stream.write_all(b"\r\n")?;
+            stream.flush()?;
+            sent = number;
+        }
+        if finished {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::rayt::Real;
+
+// Float3 の中身。simd feature のときは 4 レーンのベクトルで持ち、4 つ目は使わない
+// どちらも成分ごとに同じ順で計算するので、結果は feature によらず一致する
+#[cfg(not(feature = "simd"))]
+#[derive(Clone, Copy)]
+pub struct Lanes([Real; 3]);
+
+#[cfg(all(feature = "simd", not(feature = "f32")))]
+type Wide = wide::f64x4;
+#[cfg(all(feature = "simd", feature = "f32"))]
+type Wide = wide::f32x4;
+
+#[cfg(feature = "simd")]
+#[derive(Clone, Copy)]
+pub struct Lanes(Wide);
+
+#[cfg(not(feature = "simd"))]
+impl Lanes {
+    pub const fn new(x: Real, y: Real, z: Real) -> Self {
+        Self([x, y, z])
+    }
+    pub fn to_array(self) -> [Real; 3] {
+        self.0
+    }
+    pub fn as_slice(&self) -> &[Real] {
+        &self.0
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [Real] {
+        &mut self.0
+    }
+    pub fn sqrt(self) -> Self {
+        Self(self.0.map(|x| x.sqrt()))
+    }
+    fn zip(self, rhs: Self, f: impl Fn(Real, Real) -> Real) -> Self {
+        let ([x, y, z], [rx, ry, rz]) = (self.0, rhs.0);
+        Self([f(x, rx), f(y, ry), f(z, rz)])
+    }
+}
+
+#[cfg(feature = "simd")]
+impl Lanes {
+    pub const fn new(x: Real, y: Real, z: Real) -> Self {
+        Self(Wide::new([x, y, z, 0.0]))
+    }
+    pub fn to_array(self) -> [Real; 3] {
+        let [x, y, z, _] = self.0.to_array();
+        [x, y, z]
+    }
+    pub fn as_slice(&self) -> &[Real] {
+        &self.0.as_array_ref()[..3]
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [Real] {
+        &mut self.0.as_array_mut()[..3]
+    }
+    pub fn sqrt(self) -> Self {
+        Self(self.0.sqrt())
+    }
+    fn zip(self, rhs: Self, f: impl Fn(Wide, Wide) -> Wide) -> Self {
+        Self(f(self.0, rhs.0))
+    }
+}
+
+impl Lanes {
+    pub const fn splat(value: Real) -> Self {
+        Self::new(value, value, value)
+    }
+    pub fn map(self, f: impl Fn(Real) -> Real) -> Self {
+        let [x, y, z] = self.to_array();
+        Self::new(f(x), f(y), f(z))
+    }
+    pub fn min(self, rhs: Self) -> Self {
+        self.zip(rhs, |l, r| l.min(r))
+    }
+    pub fn max(self, rhs: Self) -> Self {
+        self.zip(rhs, |l, r| l.max(r))
+    }
+    // 足す順番をスカラー版とそろえるため、水平加算は使わない
+    pub fn dot(self, rhs: Self) -> Real {
+        let [x, y, z] = (self * rhs).to_array();
+        x + y + z
+    }
+}
+
+impl std::fmt::Debug for Lanes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_array().fmt(f)
+    }
+}
+
+// 4 つ目のレーンは 0 除算で NaN になりうるので比べない
+impl PartialEq for Lanes {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_array() == other.to_array()
+    }
+}
+
+impl std::ops::Neg for Lanes {
+    type Output = Self;
+    fn neg(self) -> Self {
+        self.map(|x| -x)
+    }
+}
+
+impl std::ops::Add for Lanes {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        self.zip(rhs, |l, r| l + r)
+    }
+}
+
+impl std::ops::Sub for Lanes {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self.zip(rhs, |l, r| l - r)
+    }
+}
+
+impl std::ops::Mul for Lanes {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        self.zip(rhs, |l, r| l * r)
+    }
+}
+
+impl std::ops::Div for Lanes {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        self.zip(rhs, |l, r| l / r)
+    }
+}
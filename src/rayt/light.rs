@@ -0,0 +1,429 @@
+use crate::rayt::*;
+
+// 形状を持たず、光線では当たらない光源
+// 拡散面に当たるたびに影の光線を飛ばして、遮られていなければ直接照らす
+pub trait Light: Sync + Send {
+    // p から光源を見た方向と、p に届く光 (影は考えない)。届かなければ None
+    fn sample(&self, p: Point3) -> Option<LightSample>;
+    // シーンファイルでの書き方。書けないものは None
+    fn describe(&self) -> Option<LightDesc> {
+        None
+    }
+    // 光源グループの名前。None なら DEFAULT_LIGHT_GROUP に入る
+    fn group(&self) -> Option<&str> {
+        None
+    }
+    // origin から direction を選ぶ確率密度 (立体角あたり)。方向の決まっている光源は 0
+    // 背景を狙う光源 (ポータル) の向きに抜けた光線で、背景を重ねて数えないために使う
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3) -> f64 {
+        0.0
+    }
+    // 霧の中の光の筋を求めるときに、光線のどのあたりを調べるかの目安にする位置
+    // 広がりのある光源は乱数で 1 点選ぶ。無限遠の光源は None
+    fn position(&self) -> Option<Point3> {
+        None
+    }
+}
+
+// グループのない光源と、光る物体や背景はこのグループにまとめる
+// すべてのグループの画像を足すと本描画と同じになる
+pub const DEFAULT_LIGHT_GROUP: &str = "default";
+
+// 光源にグループの名前を付ける
+pub struct GroupedLight {
+    light: Box<dyn Light>,
+    group: String,
+}
+
+impl GroupedLight {
+    pub fn new(light: Box<dyn Light>, group: &str) -> Self {
+        Self {
+            light,
+            group: group.to_string(),
+        }
+    }
+}
+
+impl Light for GroupedLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        self.light.sample(p)
+    }
+
+    fn describe(&self) -> Option<LightDesc> {
+        self.light.describe()
+    }
+
+    fn group(&self) -> Option<&str> {
+        Some(&self.group)
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.light.pdf_value(origin, direction)
+    }
+
+    fn position(&self) -> Option<Point3> {
+        self.light.position()
+    }
+}
+
+// 名前の付いたグループと DEFAULT_LIGHT_GROUP。どの光源にも名前がなければ空
+pub fn light_groups(lights: &[Box<dyn Light>]) -> Vec<String> {
+    let mut groups = lights
+        .iter()
+        .filter_map(|light| light.group())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if groups.is_empty() {
+        return groups;
+    }
+    groups.push(DEFAULT_LIGHT_GROUP.to_string());
+    groups.sort();
+    groups.dedup();
+    groups
+}
+
+// 1 回の描画で分けられる光源グループの数 (DEFAULT_LIGHT_GROUP を含む)
+pub const MAX_LIGHT_GROUPS: usize = 8;
+
+// 経路で集めた光と、その光源グループごとの内訳。i 番目は light_groups の i 番目
+// total は内訳を足したものと同じ光だが、グループを分けないときと同じ順に足して丸めもそろえる
+#[derive(Debug, Clone, Copy)]
+pub struct GroupRadiance {
+    pub total: Color,
+    groups: [Color; MAX_LIGHT_GROUPS],
+}
+
+impl GroupRadiance {
+    pub fn zero() -> Self {
+        Self {
+            total: Color::zero(),
+            groups: [Color::zero(); MAX_LIGHT_GROUPS],
+        }
+    }
+
+    // group に入る光。None ならどのグループにも入れない
+    pub fn new(group: Option<usize>, color: Color) -> Self {
+        let mut radiance = Self::zero();
+        radiance.total = color;
+        if let Some(group) = group {
+            radiance.groups[group] = color;
+        }
+        radiance
+    }
+
+    pub fn group(&self, group: usize) -> Color {
+        self.groups[group]
+    }
+}
+
+impl Default for GroupRadiance {
+    fn default() -> Self {
+        Self::zero()
+    }
+}
+
+impl std::ops::Add for GroupRadiance {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            total: self.total + rhs.total,
+            groups: std::array::from_fn(|i| self.groups[i] + rhs.groups[i]),
+        }
+    }
+}
+
+impl std::ops::AddAssign for GroupRadiance {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl std::ops::Mul<Color> for GroupRadiance {
+    type Output = Self;
+    fn mul(self, rhs: Color) -> Self {
+        Self {
+            total: self.total * rhs,
+            groups: self.groups.map(|c| c * rhs),
+        }
+    }
+}
+
+impl std::ops::Mul<f64> for GroupRadiance {
+    type Output = Self;
+    fn mul(self, rhs: f64) -> Self {
+        Self {
+            total: self.total * rhs,
+            groups: self.groups.map(|c| c * rhs),
+        }
+    }
+}
+
+impl std::ops::Div<f64> for GroupRadiance {
+    type Output = Self;
+    fn div(self, rhs: f64) -> Self {
+        Self {
+            total: self.total / rhs,
+            groups: self.groups.map(|c| c / rhs),
+        }
+    }
+}
+
+impl std::iter::Sum for GroupRadiance {
+    fn sum<I: Iterator<Item = GroupRadiance>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |acc, v| acc + v)
+    }
+}
+
+pub struct LightSample {
+    // 光源へ向かう単位ベクトル
+    pub direction: Vec3,
+    // 影の光線をここまで調べる
+    pub distance: f64,
+    // direction に垂直な面での放射照度
+    pub irradiance: Color,
+    // true なら irradiance は選んだ向きの確率密度の逆数で、その向きの背景の放射輝度を掛けて使う
+    pub environment: bool,
+}
+
+// 1 点から全方向に同じ強さで光る。intensity は放射強度 (W/sr)
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight {
+    pub position: Point3,
+    pub intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            irradiance: self.intensity / distance_squared,
+            environment: false,
+        })
+    }
+
+    fn describe(&self) -> Option<LightDesc> {
+        Some(LightDesc::Point {
+            position: self.position.to_array(),
+            intensity: self.intensity.to_array(),
+        })
+    }
+
+    fn position(&self) -> Option<Point3> {
+        Some(self.position)
+    }
+}
+
+// 太陽のように十分遠くから平行に届く光。direction は光の進む向き
+// angular_radius (度) が 0 より大きければ、その広がりの円盤として影の縁をぼかす
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub irradiance: Color,
+    pub angular_radius: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self {
+            direction,
+            irradiance,
+            angular_radius: 0.0,
+        }
+    }
+
+    // 太陽の見かけの半径はおよそ 0.27 度
+    pub fn with_angular_radius(self, angular_radius: f64) -> Self {
+        Self {
+            angular_radius,
+            ..self
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Point3) -> Option<LightSample> {
+        let to_light = -self.direction.normalize();
+        let direction = if self.angular_radius > 0.0 {
+            let sin_max = self.angular_radius.to_radians().sin();
+            Onb::from_w(to_light).local_vec(Vec3::random_to_sphere(sin_max, 1.0))
+        } else {
+            to_light
+        };
+        Some(LightSample {
+            direction,
+            distance: f64::MAX,
+            irradiance: self.irradiance,
+            environment: false,
+        })
+    }
+
+    fn describe(&self) -> Option<LightDesc> {
+        Some(LightDesc::Directional {
+            direction: self.direction.to_array(),
+            irradiance: self.irradiance.to_array(),
+            angular_radius: self.angular_radius,
+        })
+    }
+}
+
+// 窓や戸口などの開口部に置く平行四辺形。光らず、光線も遮らない
+// 拡散面から開口部の上の点を選び、その向きの背景 (空や環境マップ) を直接見に行く
+// 室内を外の光だけで照らすときに、開口部を偶然抜ける経路を待たずに済む
+#[derive(Debug, Clone, Copy)]
+pub struct PortalLight {
+    pub corner: Point3,
+    pub edge_u: Vec3,
+    pub edge_v: Vec3,
+}
+
+impl PortalLight {
+    pub fn new(corner: Point3, edge_u: Vec3, edge_v: Vec3) -> Self {
+        Self {
+            corner,
+            edge_u,
+            edge_v,
+        }
+    }
+
+    // 開口部を通るなら、そこまでの距離 (direction の長さ単位) と開口部の法線
+    fn hit(&self, origin: Point3, direction: Vec3) -> Option<(f64, Vec3)> {
+        let n = self.edge_u.cross(self.edge_v);
+        let denom = direction.dot(n);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.corner - origin).dot(n) / denom;
+        if t <= 0.001 {
+            return None;
+        }
+        // 面内の位置を edge_u, edge_v の係数に直す
+        let q = origin + direction * t - self.corner;
+        let w = n / n.length_squared();
+        let a = q.cross(self.edge_v).dot(w);
+        let b = self.edge_u.cross(q).dot(w);
+        ((0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b)).then_some((t, n))
+    }
+}
+
+impl Light for PortalLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let [a, b] = random_array();
+        let to_light = self.corner + a * self.edge_u + b * self.edge_v - p;
+        let direction = to_light.normalize();
+        let pdf = self.pdf_value(p, direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance: f64::MAX,
+            irradiance: Color::fill(pdf.recip()),
+            environment: true,
+        })
+    }
+
+    fn describe(&self) -> Option<LightDesc> {
+        Some(LightDesc::Portal {
+            corner: self.corner.to_array(),
+            edge_u: self.edge_u.to_array(),
+            edge_v: self.edge_v.to_array(),
+        })
+    }
+
+    // 開口部の上で一様に選んだ点の密度を立体角あたりに直す
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let Some((t, n)) = self.hit(origin, direction) else {
+            return 0.0;
+        };
+        let area = n.length();
+        let dist_sq = t * t * direction.length_squared();
+        let cosine = (direction.dot(n) / (direction.length() * area)).abs();
+        dist_sq / (cosine * area)
+    }
+
+    fn position(&self) -> Option<Point3> {
+        let [a, b] = random_array();
+        Some(self.corner + a * self.edge_u + b * self.edge_v)
+    }
+}
+
+// この光線を出した拡散面で、ポータルが背景を狙い済みか
+// そうなら、ポータルを抜けた先の背景は足さない
+pub fn portal_covers(scene: &impl WorldScene, ray: &Ray) -> bool {
+    scene
+        .lights()
+        .iter()
+        .any(|light| light.pdf_value(ray.origin, ray.direction) > 0.0)
+}
+
+// 形状のない光源と背景の太陽
+// 背景の太陽は背景と同じく DEFAULT_LIGHT_GROUP に入れる
+pub fn scene_lights(scene: &impl WorldScene) -> impl Iterator<Item = &dyn Light> {
+    scene
+        .lights()
+        .iter()
+        .map(|light| &**light)
+        .chain(scene.background().light())
+}
+
+// light から p に届く光の向きと放射照度。遮られていれば None
+pub fn unshadowed(
+    scene: &impl WorldScene,
+    light: &dyn Light,
+    p: Point3,
+    time: f64,
+) -> Option<(Ray, Color)> {
+    let sample = light.sample(p)?;
+    let shadow = Ray::new(p, sample.direction).with_time(time);
+    let transmittance =
+        scene
+            .world()
+            .shadow_transmittance(&shadow, sample.distance, scene.colored_shadows());
+    if transmittance.near_zero() {
+        return None;
+    }
+    let irradiance = if sample.environment {
+        sample.irradiance * scene.background().value(sample.direction)
+    } else {
+        sample.irradiance
+    } * transmittance;
+    Some((shadow, irradiance))
+}
+
+// 形状のない光源と背景の太陽からの直接光。scatter が拡散 (pdf を持つ) ときだけ照らす
+// 光源ごとにそのグループへ入れる
+pub fn direct_lighting(
+    scene: &impl WorldScene,
+    ray: &Ray,
+    hit: &HitInfo,
+    scatter: &ScatterInfo,
+) -> GroupRadiance {
+    if scatter.pdf.is_none() {
+        return GroupRadiance::zero();
+    }
+    scene_lights(scene)
+        .filter_map(|light| {
+            let (shadow, irradiance) = unshadowed(scene, light, hit.p, ray.time)?;
+            // albedo * scattering_pdf が BRDF と cos の積になる (attenuation と同じ)
+            let pdf = hit.m.scattering_pdf(ray, hit, &shadow);
+            let group = scene.light_group_index(light.group());
+            Some(GroupRadiance::new(group, scatter.albedo * pdf * irradiance))
+        })
+        .sum()
+}
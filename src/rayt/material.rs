@@ -0,0 +1,495 @@
+use crate::rayt::*;
+
+use std::sync::Arc;
+
+pub trait Material: Sync + Send {
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo>;
+    fn emitted(&self, _ray: &Ray, _hit: &HitInfo) -> Color {
+        Color::zero()
+    }
+    fn is_cutout(&self, _hit: &HitInfo) -> bool {
+        false
+    }
+    fn name(&self) -> &'static str {
+        "material"
+    }
+    fn transmittance(&self, _hit: &HitInfo) -> Option<Color> {
+        None
+    }
+    fn is_specular(&self) -> bool {
+        false
+    }
+    // 偏光の変化 (強度は scatter の albedo が受け持つので m00 = 1)
+    fn mueller(&self, _ray: &Ray, _hit: &HitInfo, _scattered: &Ray) -> Mueller {
+        if self.is_specular() {
+            Mueller::identity()
+        } else {
+            Mueller::depolarizer()
+        }
+    }
+    // 調整パネルから書き換えられる値
+    fn params(&self) -> Vec<&Param> {
+        Vec::new()
+    }
+    // scattered の方向へ散乱する確率密度 (立体角あたり)
+    fn scattering_pdf(&self, _ray: &Ray, _hit: &HitInfo, _scattered: &Ray) -> f64 {
+        0.0
+    }
+    // シーンファイルでの書き方。書けないものは None
+    fn describe(&self) -> Option<MaterialDesc> {
+        None
+    }
+    // 入れ子にできる透明な物体の屈折率と優先度。重なった部分では優先度の高いほうの中身になる
+    fn interior(&self) -> Option<(f64, u32)> {
+        None
+    }
+    // 光線の来た側の屈折率 from と、面の向こう側の屈折率 to を与えて散乱する
+    // 入れ子の誘電体で使う。interior を持たないマテリアルは scatter と同じ
+    fn scatter_between(
+        &self,
+        ray: &Ray,
+        hit: &HitInfo,
+        _from: f64,
+        _to: f64,
+    ) -> Option<ScatterInfo> {
+        self.scatter(ray, hit)
+    }
+}
+
+pub struct ScatterInfo {
+    pub ray: Ray,
+    pub albedo: Color,
+    // 散乱方向を選んだ分布。None は鏡面反射・屈折のように方向が決まっている場合
+    pub pdf: Option<Box<dyn Pdf>>,
+}
+
+impl ScatterInfo {
+    pub fn new(ray: Ray, albedo: Color) -> Self {
+        Self {
+            ray,
+            albedo,
+            pdf: None,
+        }
+    }
+
+    pub fn with_pdf(self, pdf: impl Pdf + 'static) -> Self {
+        Self {
+            pdf: Some(Box::new(pdf)),
+            ..self
+        }
+    }
+
+    // 散乱した光線が運んでくる光に掛ける重み
+    pub fn attenuation(&self, ray: &Ray, hit: &HitInfo) -> Color {
+        let Some(pdf) = &self.pdf else {
+            return self.albedo;
+        };
+        match pdf.value(self.ray.direction) {
+            value if value > 0.0 => self.albedo * hit.m.scattering_pdf(ray, hit, &self.ray) / value,
+            _ => Color::zero(),
+        }
+    }
+}
+
+pub struct AlphaMask {
+    texture: Box<dyn Texture>,
+    threshold: f64,
+}
+
+impl AlphaMask {
+    pub fn new(texture: Box<dyn Texture>, threshold: f64) -> Self {
+        Self { texture, threshold }
+    }
+
+    pub fn is_cutout(&self, hit: &HitInfo) -> bool {
+        self.texture.value_at(hit).mean() < self.threshold
+    }
+}
+
+pub struct Lambertian {
+    albedo: Box<dyn Texture>,
+    mask: Option<AlphaMask>,
+    albedo_scale: Param,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Box<dyn Texture>) -> Self {
+        Self {
+            albedo,
+            mask: None,
+            albedo_scale: Param::new("albedo", 1.0, 0.0, 1.0),
+        }
+    }
+
+    pub fn with_mask(self, mask: Option<AlphaMask>) -> Self {
+        Self { mask, ..self }
+    }
+}
+
+impl Material for Lambertian {
+    fn name(&self) -> &'static str {
+        "Lambertian"
+    }
+
+    fn scatter(&self, _ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+        let pdf = CosinePdf::new(hit.n);
+        let albedo = self.albedo.value_at(hit) * self.albedo_scale.get();
+        Some(ScatterInfo::new(Ray::new(hit.p, pdf.generate()), albedo).with_pdf(pdf))
+    }
+
+    fn scattering_pdf(&self, _ray: &Ray, hit: &HitInfo, scattered: &Ray) -> f64 {
+        let cosine = hit.n.normalize().dot(scattered.direction.normalize());
+        cosine.max(0.0) * FRAC_1_PI
+    }
+
+    fn is_cutout(&self, hit: &HitInfo) -> bool {
+        self.mask.as_ref().is_some_and(|mask| mask.is_cutout(hit))
+    }
+
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.albedo_scale]
+    }
+
+    fn describe(&self) -> Option<MaterialDesc> {
+        Some(MaterialDesc::Lambertian {
+            texture: self.albedo.describe()?,
+        })
+    }
+}
+
+pub struct Metal {
+    albedo: Box<dyn Texture>,
+    fuzz: Box<dyn Texture>,
+    mask: Option<AlphaMask>,
+    albedo_scale: Param,
+    // fuzz テクスチャの値に足し込む
+    roughness_offset: Param,
+}
+
+impl Metal {
+    pub fn new(albedo: Box<dyn Texture>, fuzz: Box<dyn Texture>) -> Self {
+        Self {
+            albedo,
+            fuzz,
+            mask: None,
+            albedo_scale: Param::new("albedo", 1.0, 0.0, 1.0),
+            roughness_offset: Param::new("roughness", 0.0, -1.0, 1.0),
+        }
+    }
+
+    pub fn with_mask(self, mask: Option<AlphaMask>) -> Self {
+        Self { mask, ..self }
+    }
+}
+
+impl Material for Metal {
+    fn name(&self) -> &'static str {
+        "Metal"
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+        let mut reflected = ray.direction.normalize().reflect(hit.n);
+        let differential = ray.scattered(hit.p, hit.dpdx, hit.dpdy, |d| Some(d.reflect(hit.n)));
+        let fuzz = (self.fuzz.value_at(hit).mean() + self.roughness_offset.get()).max(0.0);
+        reflected += fuzz * Vec3::random_in_unit_sphere();
+        if reflected.dot(hit.n) > 0.0 {
+            let albedo = self.albedo.value_at(hit) * self.albedo_scale.get();
+            Some(ScatterInfo::new(
+                Ray::new(hit.p, reflected).with_differential(differential),
+                albedo,
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn is_cutout(&self, hit: &HitInfo) -> bool {
+        self.mask.as_ref().is_some_and(|mask| mask.is_cutout(hit))
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.albedo_scale, &self.roughness_offset]
+    }
+
+    // fuzz は一様なものだけ書ける
+    fn describe(&self) -> Option<MaterialDesc> {
+        let TextureSpec::Color([fuzz, ..]) = self.fuzz.describe()? else {
+            return None;
+        };
+        Some(MaterialDesc::Metal {
+            texture: self.albedo.describe()?,
+            fuzz,
+        })
+    }
+}
+
+pub struct Velvet {
+    albedo: Box<dyn Texture>,
+    sheen: Box<dyn Texture>,
+}
+
+impl Velvet {
+    pub fn new(albedo: Box<dyn Texture>, sheen: Box<dyn Texture>) -> Self {
+        Self { albedo, sheen }
+    }
+}
+
+impl Material for Velvet {
+    fn name(&self) -> &'static str {
+        "Velvet"
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+        let target = hit.p + hit.n + Vec3::random_in_unit_sphere();
+        let direction = target - hit.p;
+        // 入射と出射の中間ベクトルに対する Schlick 重みで、すれすれの角度ほど光沢が強くなる
+        let half = (direction.normalize() - ray.direction.normalize()).normalize();
+        let cos_d = direction.normalize().dot(half).max(0.0);
+        let weight = (1.0 - cos_d).powi(5);
+        let albedo = self.albedo.value_at(hit) + weight * self.sheen.value_at(hit);
+        Some(ScatterInfo::new(Ray::new(hit.p, direction), albedo))
+    }
+
+    fn describe(&self) -> Option<MaterialDesc> {
+        let TextureSpec::Color(sheen) = self.sheen.describe()? else {
+            return None;
+        };
+        Some(MaterialDesc::Velvet {
+            texture: self.albedo.describe()?,
+            sheen,
+        })
+    }
+}
+
+// 媒質の中で全方向に等しく散乱する位相関数
+pub struct Isotropic {
+    albedo: Box<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Box<dyn Texture>) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn name(&self) -> &'static str {
+        "Isotropic"
+    }
+
+    fn scatter(&self, _ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+        let direction = Vec3::random_unit_vector();
+        Some(ScatterInfo::new(
+            Ray::new(hit.p, direction),
+            self.albedo.value_at(hit),
+        ))
+    }
+}
+
+pub struct Dielectric {
+    ri: Param,
+    tint: Color,
+    priority: u32,
+}
+
+impl Dielectric {
+    pub fn new(ri: f64) -> Self {
+        Self::tinted(ri, Color::one())
+    }
+    pub fn tinted(ri: f64, tint: Color) -> Self {
+        Self {
+            ri: Param::new("ior", ri, 1.0, 3.0),
+            tint,
+            priority: 0,
+        }
+    }
+    // グラスの中の水のように重なる物体では、中身にしたいほうの優先度を高くする
+    pub fn with_priority(self, priority: u32) -> Self {
+        Self { priority, ..self }
+    }
+    // Schlick 近似
+    pub fn schlick(cosine: f64, ri: f64) -> f64 {
+        let r0 = ((1.0 - ri) / (1.0 + ri)).powi(2);
+        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+    }
+}
+
+impl Material for Dielectric {
+    fn name(&self) -> &'static str {
+        "Dielectric"
+    }
+
+    // 外側は空気とみなす
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+        let ri = self.ri.get();
+        if ray.direction.dot(hit.n) > 0.0 {
+            self.scatter_between(ray, hit, ri, 1.0)
+        } else {
+            self.scatter_between(ray, hit, 1.0, ri)
+        }
+    }
+
+    fn scatter_between(&self, ray: &Ray, hit: &HitInfo, from: f64, to: f64) -> Option<ScatterInfo> {
+        let reflected = ray.direction.reflect(hit.n);
+        let ni_over_nt = from / to;
+        let (outward_normal, relative, cosine) = {
+            let dot = ray.direction.dot(hit.n);
+            if dot > 0.0 {
+                (
+                    -hit.n,
+                    ni_over_nt,
+                    ni_over_nt * dot / ray.direction.length(),
+                )
+            } else {
+                (hit.n, to / from, -dot / ray.direction.length())
+            }
+        };
+        if let Some(refracted) = (-ray.direction).refract(outward_normal, ni_over_nt) {
+            if Vec3::random_fill().x() > Self::schlick(cosine, relative) {
+                let differential = ray.scattered(hit.p, hit.dpdx, hit.dpdy, |d| {
+                    (-d).refract(outward_normal, ni_over_nt)
+                });
+                return Some(ScatterInfo::new(
+                    Ray::new(hit.p, refracted).with_differential(differential),
+                    self.tint,
+                ));
+            }
+        }
+        let differential = ray.scattered(hit.p, hit.dpdx, hit.dpdy, |d| Some(d.reflect(hit.n)));
+        Some(ScatterInfo::new(
+            Ray::new(hit.p, reflected).with_differential(differential),
+            Color::one(),
+        ))
+    }
+
+    fn transmittance(&self, _hit: &HitInfo) -> Option<Color> {
+        Some(self.tint)
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn mueller(&self, ray: &Ray, hit: &HitInfo, scattered: &Ray) -> Mueller {
+        let dot = ray.direction.dot(hit.n);
+        let (outward_normal, eta) = if dot > 0.0 {
+            (-hit.n, self.ri.get().recip())
+        } else {
+            (hit.n, self.ri.get())
+        };
+        let cosine = dot.abs() / ray.direction.length();
+        if scattered.direction.dot(outward_normal) > 0.0 {
+            Mueller::fresnel_reflection(cosine, eta)
+        } else {
+            Mueller::fresnel_transmission(cosine, eta)
+        }
+    }
+
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.ri]
+    }
+
+    fn describe(&self) -> Option<MaterialDesc> {
+        Some(MaterialDesc::Dielectric {
+            ri: self.ri.get(),
+            tint: (self.tint != Color::one()).then_some(self.tint.to_array()),
+            priority: self.priority,
+        })
+    }
+
+    fn interior(&self) -> Option<(f64, u32)> {
+        Some((self.ri.get(), self.priority))
+    }
+}
+
+pub struct DiffusedLight {
+    emit: Box<dyn Texture>,
+    intensity: Param,
+}
+
+impl DiffusedLight {
+    pub fn new(emit: Box<dyn Texture>) -> Self {
+        Self {
+            emit,
+            intensity: Param::new("intensity", 1.0, 0.0, 4.0),
+        }
+    }
+}
+
+impl Material for DiffusedLight {
+    fn name(&self) -> &'static str {
+        "DiffusedLight"
+    }
+
+    fn scatter(&self, _ray: &Ray, _hit: &HitInfo) -> Option<ScatterInfo> {
+        None
+    }
+
+    fn emitted(&self, _ray: &Ray, hit: &HitInfo) -> Color {
+        self.emit.value_at(hit) * self.intensity.get()
+    }
+
+    fn params(&self) -> Vec<&Param> {
+        vec![&self.intensity]
+    }
+
+    fn describe(&self) -> Option<MaterialDesc> {
+        Some(MaterialDesc::DiffuseLight {
+            texture: self.emit.describe()?,
+        })
+    }
+}
+
+pub struct Emissive {
+    material: Arc<dyn Material>,
+    emit: Box<dyn Texture>,
+}
+
+impl Emissive {
+    pub fn new(material: Arc<dyn Material>, emit: Box<dyn Texture>) -> Self {
+        Self { material, emit }
+    }
+}
+
+impl Material for Emissive {
+    fn name(&self) -> &'static str {
+        "Emissive"
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<ScatterInfo> {
+        self.material.scatter(ray, hit)
+    }
+
+    // 散乱とは別に返すので、中身が光を吸収しても、反射を追わないときも光る
+    fn emitted(&self, ray: &Ray, hit: &HitInfo) -> Color {
+        self.material.emitted(ray, hit) + self.emit.value_at(hit)
+    }
+
+    fn is_cutout(&self, hit: &HitInfo) -> bool {
+        self.material.is_cutout(hit)
+    }
+
+    fn transmittance(&self, hit: &HitInfo) -> Option<Color> {
+        self.material.transmittance(hit)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.material.is_specular()
+    }
+
+    fn mueller(&self, ray: &Ray, hit: &HitInfo, scattered: &Ray) -> Mueller {
+        self.material.mueller(ray, hit, scattered)
+    }
+
+    fn params(&self) -> Vec<&Param> {
+        self.material.params()
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit: &HitInfo, scattered: &Ray) -> f64 {
+        self.material.scattering_pdf(ray, hit, scattered)
+    }
+}
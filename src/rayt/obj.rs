@@ -0,0 +1,234 @@
+use crate::rayt::*;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// MTL の 1 マテリアル分。使うのは Kd, Ks, Ns, Ke, map_Kd だけ
+struct MtlMaterial {
+    kd: Color,
+    ks: Color,
+    ns: f64,
+    ke: Color,
+    map_kd: Option<String>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            kd: Color::fill(0.8),
+            ks: Color::zero(),
+            ns: 0.0,
+            ke: Color::zero(),
+            map_kd: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    // Ke があれば光源、Ks が Kd より強ければ金属、それ以外は Lambertian にする
+    // 金属の fuzz は Ns (Phong の指数) から粗さ sqrt(2 / (Ns + 2)) に直す
+    fn build(&self) -> Result<Arc<dyn Material>, Error> {
+        let builder = ShapeBuilder::new();
+        let builder = if self.ke.mean() > 0.0 {
+            builder.color_texture(self.ke).diffuse_light()
+        } else if self.ks.mean() > self.kd.mean() {
+            let fuzz = (2.0 / (self.ns + 2.0)).sqrt();
+            builder.color_texture(self.ks).metal(fuzz)
+        } else {
+            match &self.map_kd {
+                Some(path) => builder.image_texture(path),
+                None => builder.color_texture(self.kd),
+            }
+            .lambertian()
+        };
+        builder.build_material()
+    }
+}
+
+fn error(path: &Path, line: usize, message: impl std::fmt::Display) -> Error {
+    Error::File(format!("{}:{}: {}", path.display(), line + 1, message))
+}
+
+fn floats<'a>(values: impl Iterator<Item = &'a str>) -> Result<Vec<f64>, String> {
+    values
+        .map(|value| {
+            value
+                .parse::<f64>()
+                .map_err(|e| format!("{}: {}", value, e))
+        })
+        .collect()
+}
+
+// Kd 0.5 のように 1 つだけ書いてあれば 3 成分とも同じ値にする
+fn color<'a>(values: impl Iterator<Item = &'a str>) -> Result<Color, String> {
+    match floats(values)?[..] {
+        [r, g, b, ..] => Ok(Color::new(r, g, b)),
+        [v] => Ok(Color::fill(v)),
+        _ => Err("expects r g b".to_string()),
+    }
+}
+
+// テクスチャなどのパスはファイルのあるディレクトリからの相対パス
+fn relative_to(path: &Path, name: &str) -> String {
+    path.parent()
+        .unwrap_or(Path::new(""))
+        .join(name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn load_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>, Error> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| Error::File(format!("{}: {}", path.display(), e)))?;
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            if let Some((name, material)) = current.replace((name, MtlMaterial::default())) {
+                materials.insert(name, material);
+            }
+            continue;
+        }
+        let Some((_, material)) = current.as_mut() else {
+            continue;
+        };
+        match keyword {
+            "Kd" => material.kd = color(tokens).map_err(|e| error(path, i, e))?,
+            "Ks" => material.ks = color(tokens).map_err(|e| error(path, i, e))?,
+            "Ke" => material.ke = color(tokens).map_err(|e| error(path, i, e))?,
+            "Ns" => {
+                material.ns = floats(tokens)
+                    .map_err(|e| error(path, i, e))?
+                    .first()
+                    .copied()
+                    .unwrap_or(0.0)
+            }
+            // -s 1 1 1 などのオプションは読み飛ばし、最後をファイル名とする
+            "map_Kd" => material.map_kd = tokens.last().map(|name| relative_to(path, name)),
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
+
+// 1 始まりの番号か、末尾から数える負の番号
+fn index(value: &str, len: usize) -> Result<usize, String> {
+    let n = value
+        .parse::<i64>()
+        .map_err(|e| format!("{}: {}", value, e))?;
+    let i = if n < 0 { len as i64 + n } else { n - 1 };
+    if 0 <= i && (i as usize) < len {
+        Ok(i as usize)
+    } else {
+        Err(format!("index {} out of range", n))
+    }
+}
+
+// OBJ を読み、mtllib のマテリアルを割り当てた Mesh にする
+// material を渡すと MTL は使わず、すべての面をそのマテリアルにする
+pub fn load_obj(path: &str, material: Option<Arc<dyn Material>>) -> Result<Mesh, Error> {
+    let path = Path::new(path);
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| Error::File(format!("{}: {}", path.display(), e)))?;
+    let use_mtl = material.is_none();
+    let default = match material {
+        Some(material) => material,
+        None => MtlMaterial::default().build()?,
+    };
+    let mut library = HashMap::new();
+    let mut built = HashMap::<String, Arc<dyn Material>>::new();
+    let mut current = Arc::clone(&default);
+    let (mut positions, mut texcoords, mut normals) = (Vec::new(), Vec::new(), Vec::new());
+    let mut triangles = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        match keyword {
+            "v" | "vn" => {
+                let v = match floats(tokens).map_err(|e| error(path, i, e))?[..] {
+                    [x, y, z, ..] => Vec3::new(x, y, z),
+                    _ => return Err(error(path, i, "expects x y z")),
+                };
+                if keyword == "v" {
+                    positions.push(v);
+                } else {
+                    normals.push(v);
+                }
+            }
+            "vt" => match floats(tokens).map_err(|e| error(path, i, e))?[..] {
+                [u, v, ..] => texcoords.push((u, v)),
+                [u] => texcoords.push((u, 0.0)),
+                _ => return Err(error(path, i, "expects u v")),
+            },
+            "mtllib" if use_mtl => {
+                for name in tokens {
+                    library.extend(load_mtl(Path::new(&relative_to(path, name)))?);
+                }
+            }
+            "usemtl" if use_mtl => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                current = match (built.get(&name), library.get(&name)) {
+                    (Some(material), _) => Arc::clone(material),
+                    (None, Some(mtl)) => {
+                        let material = mtl.build().map_err(|e| error(path, i, e))?;
+                        built.insert(name, Arc::clone(&material));
+                        material
+                    }
+                    (None, None) => Arc::clone(&default),
+                };
+            }
+            "f" => {
+                // v, v/vt, v//vn, v/vt/vn
+                let vertices = tokens
+                    .map(|token| {
+                        let mut parts = token.split('/');
+                        let v = index(parts.next().unwrap_or(""), positions.len())?;
+                        let vt = match parts.next() {
+                            Some("") | None => None,
+                            Some(vt) => Some(index(vt, texcoords.len())?),
+                        };
+                        let vn = match parts.next() {
+                            Some("") | None => None,
+                            Some(vn) => Some(index(vn, normals.len())?),
+                        };
+                        Ok((v, vt, vn))
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+                    .map_err(|e| error(path, i, e))?;
+                if vertices.len() < 3 {
+                    return Err(error(path, i, "a face needs at least 3 vertices"));
+                }
+                // 多角形は最初の頂点を中心に扇形に分ける
+                for k in 1..vertices.len() - 1 {
+                    let corners = [vertices[0], vertices[k], vertices[k + 1]];
+                    let p = corners.map(|(v, _, _)| positions[v]);
+                    let uv = match corners.map(|(_, vt, _)| vt) {
+                        [Some(a), Some(b), Some(c)] => Some([a, b, c].map(|t| texcoords[t])),
+                        _ => None,
+                    };
+                    let n = match corners.map(|(_, _, vn)| vn) {
+                        [Some(a), Some(b), Some(c)] => Some([a, b, c].map(|n| normals[n])),
+                        _ => None,
+                    };
+                    triangles.push(Triangle::new(p, n, uv, Arc::clone(&current)));
+                }
+            }
+            _ => {}
+        }
+    }
+    if triangles.is_empty() {
+        return Err(Error::File(format!("{}: no faces", path.display())));
+    }
+    Ok(Mesh::new(triangles))
+}
@@ -0,0 +1,40 @@
+use crate::rayt::*;
+
+// w を法線とする正規直交基底
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn from_w(n: Vec3) -> Self {
+        let w = n.normalize();
+        let a = if w.x().abs() > 0.9 {
+            Vec3::yaxis()
+        } else {
+            Vec3::xaxis()
+        };
+        let v = w.cross(a).normalize();
+        let u = w.cross(v);
+        Self { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+
+    // 基底の座標 (a, b, c) をワールド座標に直す
+    pub fn local(&self, a: f64, b: f64, c: f64) -> Vec3 {
+        a * self.u() + b * self.v() + c * self.w()
+    }
+
+    pub fn local_vec(&self, a: Vec3) -> Vec3 {
+        self.local(a.x(), a.y(), a.z())
+    }
+}
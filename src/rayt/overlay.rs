@@ -0,0 +1,184 @@
+use egui::epaint::{Color32, Primitive, Vertex};
+use egui::{Context, Event, ImageData, Mesh, Pos2, RawInput, Rect, TextureId, TexturesDelta};
+use minifb::{MouseButton, MouseMode, Window};
+use std::collections::HashMap;
+
+struct OverlayTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color32>,
+}
+
+impl OverlayTexture {
+    fn sample(&self, uv: Pos2) -> Color32 {
+        let x = ((uv.x * self.width as f32) as usize).min(self.width - 1);
+        let y = ((uv.y * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[x + y * self.width]
+    }
+}
+
+// egui の出力をソフトウェアで minifb のバッファに描く
+pub struct Overlay {
+    ctx: Context,
+    textures: HashMap<TextureId, OverlayTexture>,
+    pressed: bool,
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        Self {
+            ctx: Context::default(),
+            textures: HashMap::new(),
+            pressed: false,
+        }
+    }
+
+    // minifb のマウスの状態を egui の入力イベントに直す
+    fn input(&mut self, window: &Window, width: usize, height: usize) -> RawInput {
+        let mut events = Vec::new();
+        match window.get_mouse_pos(MouseMode::Discard) {
+            Some((x, y)) => {
+                let pos = Pos2::new(x, y);
+                events.push(Event::PointerMoved(pos));
+                let pressed = window.get_mouse_down(MouseButton::Left);
+                if pressed != self.pressed {
+                    events.push(Event::PointerButton {
+                        pos,
+                        button: egui::PointerButton::Primary,
+                        pressed,
+                        modifiers: Default::default(),
+                    });
+                    self.pressed = pressed;
+                }
+            }
+            None => events.push(Event::PointerGone),
+        }
+        RawInput {
+            screen_rect: Some(Rect::from_min_size(
+                Pos2::ZERO,
+                egui::vec2(width as f32, height as f32),
+            )),
+            events,
+            ..Default::default()
+        }
+    }
+
+    pub fn run(
+        &mut self,
+        window: &Window,
+        buffer: &mut [u32],
+        width: usize,
+        height: usize,
+        ui: impl FnOnce(&Context),
+    ) {
+        let input = self.input(window, width, height);
+        let output = self.ctx.run(input, ui);
+        self.update_textures(&output.textures_delta);
+        for primitive in self.ctx.tessellate(output.shapes, output.pixels_per_point) {
+            if let Primitive::Mesh(mesh) = &primitive.primitive {
+                self.draw_mesh(buffer, width, height, primitive.clip_rect, mesh);
+            }
+        }
+        for id in &output.textures_delta.free {
+            self.textures.remove(id);
+        }
+    }
+
+    fn update_textures(&mut self, delta: &TexturesDelta) {
+        for (id, image_delta) in &delta.set {
+            let [w, h] = image_delta.image.size();
+            let pixels = match &image_delta.image {
+                ImageData::Color(image) => image.pixels.clone(),
+                ImageData::Font(image) => image.srgba_pixels(None).collect(),
+            };
+            match (image_delta.pos, self.textures.get_mut(id)) {
+                // 部分更新はすでにあるテクスチャに書き込む
+                (Some([x0, y0]), Some(texture)) => {
+                    for y in 0..h {
+                        let dst = x0 + (y0 + y) * texture.width;
+                        texture.pixels[dst..dst + w].copy_from_slice(&pixels[y * w..(y + 1) * w]);
+                    }
+                }
+                _ => {
+                    self.textures.insert(
+                        *id,
+                        OverlayTexture {
+                            width: w,
+                            height: h,
+                            pixels,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    fn draw_mesh(&self, buffer: &mut [u32], width: usize, height: usize, clip: Rect, mesh: &Mesh) {
+        let Some(texture) = self.textures.get(&mesh.texture_id) else {
+            return;
+        };
+        let clip = clip.intersect(Rect::from_min_size(
+            Pos2::ZERO,
+            egui::vec2(width as f32, height as f32),
+        ));
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| &mesh.vertices[triangle[i] as usize]);
+            draw_triangle(buffer, width, clip, texture, a, b, c);
+        }
+    }
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn edge(a: Pos2, b: Pos2, p: Pos2) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+// 頂点色とテクスチャを重心座標で補間し、乗算済みアルファで合成する
+fn draw_triangle(
+    buffer: &mut [u32],
+    width: usize,
+    clip: Rect,
+    texture: &OverlayTexture,
+    a: &Vertex,
+    b: &Vertex,
+    c: &Vertex,
+) {
+    let area = edge(a.pos, b.pos, c.pos);
+    if area.abs() < 1e-6 {
+        return;
+    }
+    let x0 = a.pos.x.min(b.pos.x).min(c.pos.x).max(clip.min.x).floor() as usize;
+    let y0 = a.pos.y.min(b.pos.y).min(c.pos.y).max(clip.min.y).floor() as usize;
+    let x1 = a.pos.x.max(b.pos.x).max(c.pos.x).min(clip.max.x).ceil() as usize;
+    let y1 = a.pos.y.max(b.pos.y).max(c.pos.y).min(clip.max.y).ceil() as usize;
+    let rgba = |color: Color32| color.to_array().map(|x| x as f32);
+    let (ca, cb, cc) = (rgba(a.color), rgba(b.color), rgba(c.color));
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let wa = edge(b.pos, c.pos, p) / area;
+            let wb = edge(c.pos, a.pos, p) / area;
+            let wc = 1.0 - wa - wb;
+            if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                continue;
+            }
+            let uv = Pos2::new(
+                wa * a.uv.x + wb * b.uv.x + wc * c.uv.x,
+                wa * a.uv.y + wb * b.uv.y + wc * c.uv.y,
+            );
+            let texel = rgba(texture.sample(uv));
+            let src: [f32; 4] =
+                std::array::from_fn(|i| (wa * ca[i] + wb * cb[i] + wc * cc[i]) * texel[i] / 255.0);
+            let pixel = &mut buffer[x + y * width];
+            let [_, r, g, b] = pixel.to_be_bytes();
+            let keep = 1.0 - src[3] / 255.0;
+            let blend = |s: f32, d: u8| (s + d as f32 * keep).clamp(0.0, 255.0) as u8;
+            *pixel = u32::from_be_bytes([0, blend(src[0], r), blend(src[1], g), blend(src[2], b)]);
+        }
+    }
+}
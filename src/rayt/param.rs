@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// 描画中にパネルから書き換えられる数値 (f64 のビット列をアトミックに持つ)
+pub struct Param {
+    pub name: &'static str,
+    pub min: f64,
+    pub max: f64,
+    bits: AtomicU64,
+}
+
+impl Param {
+    pub fn new(name: &'static str, value: f64, min: f64, max: f64) -> Self {
+        Self {
+            name,
+            min,
+            max,
+            bits: AtomicU64::new(value.to_bits()),
+        }
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f64) {
+        let value = value.clamp(self.min, self.max);
+        self.bits.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
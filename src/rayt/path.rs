@@ -0,0 +1,73 @@
+use crate::rayt::*;
+
+// 反射・屈折のあとに追う光線
+pub struct PathSegment<S> {
+    pub ray: Ray,
+    // この光線が運んでくる光に掛ける重み
+    pub weight: Color,
+    pub state: S,
+}
+
+pub trait PathTracer {
+    // 経路に沿って引き継ぐ情報 (コースティクスかどうか など)
+    type State: Copy + Default;
+    // ray の先で出ている光 (当たらなければ背景) を光源グループごとに返し、続けて追う光線を next に積む
+    // next は空で渡される。depth は残りの反射回数
+    fn bounce(
+        &self,
+        ray: &Ray,
+        depth: usize,
+        state: Self::State,
+        next: &mut Vec<PathSegment<Self::State>>,
+    ) -> GroupRadiance;
+    fn radiance_clamp(&self) -> RadianceClamp {
+        RadianceClamp::Off
+    }
+}
+
+struct PathVertex<S> {
+    ray: Ray,
+    depth: usize,
+    // 最初に当たった点での重みと、そこから先の重みの積
+    primary: Color,
+    throughput: Color,
+    state: S,
+}
+
+// 再帰せずに経路を追う。分岐した光線はスタックに積み、積んだ順に追う
+pub fn trace_path<T: PathTracer + ?Sized>(tracer: &T, ray: Ray, depth: usize) -> GroupRadiance {
+    let clamp = tracer.radiance_clamp();
+    let mut radiance = GroupRadiance::zero();
+    let mut stack = vec![PathVertex {
+        ray,
+        depth,
+        primary: Color::one(),
+        throughput: Color::one(),
+        state: T::State::default(),
+    }];
+    let mut next = Vec::new();
+    while let Some(vertex) = stack.pop() {
+        let emitted = tracer.bounce(&vertex.ray, vertex.depth, vertex.state, &mut next);
+        let is_primary = vertex.depth == depth;
+        radiance += if is_primary {
+            emitted
+        } else {
+            clamp.indirect(emitted * vertex.throughput) * vertex.primary
+        };
+        for segment in next.drain(..).rev() {
+            let (primary, throughput) = if is_primary {
+                (segment.weight, Color::one())
+            } else {
+                (vertex.primary, vertex.throughput * segment.weight)
+            };
+            stack.push(PathVertex {
+                ray: segment.ray,
+                depth: vertex.depth - 1,
+                primary,
+                throughput,
+                state: segment.state,
+            });
+        }
+    }
+    radiance
+}
@@ -0,0 +1,102 @@
+use crate::rayt::*;
+
+// 方向の確率密度 (立体角あたり)
+pub trait Pdf {
+    fn value(&self, direction: Vec3) -> f64;
+    fn generate(&self) -> Vec3;
+}
+
+impl<P: Pdf + ?Sized> Pdf for &P {
+    fn value(&self, direction: Vec3) -> f64 {
+        (**self).value(direction)
+    }
+    fn generate(&self) -> Vec3 {
+        (**self).generate()
+    }
+}
+
+impl<P: Pdf + ?Sized> Pdf for Box<P> {
+    fn value(&self, direction: Vec3) -> f64 {
+        (**self).value(direction)
+    }
+    fn generate(&self) -> Vec3 {
+        (**self).generate()
+    }
+}
+
+// w を中心とする半球上で cosθ/π
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(w: Vec3) -> Self {
+        Self {
+            uvw: Onb::from_w(w),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let cosine = direction.normalize().dot(self.uvw.w());
+        cosine.max(0.0) * FRAC_1_PI
+    }
+    fn generate(&self) -> Vec3 {
+        self.uvw.local_vec(Vec3::random_cosine_direction())
+    }
+}
+
+// origin から shape (光源など) に向かう方向。Shape::pdf_value と Shape::random を使う
+pub struct ShapePdf<'a, S: ?Sized> {
+    shape: &'a S,
+    origin: Point3,
+}
+
+impl<'a, S: Shape + ?Sized> ShapePdf<'a, S> {
+    pub fn new(shape: &'a S, origin: Point3) -> Self {
+        Self { shape, origin }
+    }
+}
+
+impl<S: Shape + ?Sized> Pdf for ShapePdf<'_, S> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.shape.pdf_value(self.origin, direction)
+    }
+    fn generate(&self) -> Vec3 {
+        self.shape.random(self.origin)
+    }
+}
+
+// weight の割合で a を、残りで b を選ぶ
+pub struct MixturePdf<A, B> {
+    a: A,
+    b: B,
+    weight: f64,
+}
+
+impl<A: Pdf, B: Pdf> MixturePdf<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b, weight: 0.5 }
+    }
+
+    pub fn with_weight(self, weight: f64) -> Self {
+        Self {
+            weight: weight.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+}
+
+impl<A: Pdf, B: Pdf> Pdf for MixturePdf<A, B> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.weight * self.a.value(direction) + (1.0 - self.weight) * self.b.value(direction)
+    }
+    fn generate(&self) -> Vec3 {
+        if random_f64() < self.weight {
+            self.a.generate()
+        } else {
+            self.b.generate()
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::rayt::*;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+const POINT_COUNT: usize = 256;
+
+pub struct Perlin {
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    // 同じ seed なら描画スレッドに関係なく同じ模様になる
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Self {
+            ranvec: (0..POINT_COUNT)
+                .map(|_| Vec3::from_iter((0..3).map(|_| rng.gen_range(-1.0..1.0))).normalize())
+                .collect(),
+            perm_x: Self::generate_perm(&mut rng),
+            perm_y: Self::generate_perm(&mut rng),
+            perm_z: Self::generate_perm(&mut rng),
+        }
+    }
+
+    fn generate_perm(rng: &mut StdRng) -> Vec<usize> {
+        let mut perm = (0..POINT_COUNT).collect::<Vec<_>>();
+        perm.shuffle(rng);
+        perm
+    }
+
+    pub fn noise(&self, p: Point3) -> f64 {
+        let [px, py, pz] = p.to_array();
+        let (u, v, w) = (px - px.floor(), py - py.floor(), pz - pz.floor());
+        let (i, j, k) = (px.floor() as i64, py.floor() as i64, pz.floor() as i64);
+        let mut c = [[[Vec3::zero(); 2]; 2]; 2];
+        for (di, ci) in c.iter_mut().enumerate() {
+            for (dj, cj) in ci.iter_mut().enumerate() {
+                for (dk, ck) in cj.iter_mut().enumerate() {
+                    let x = self.perm_x[((i + di as i64) & 255) as usize];
+                    let y = self.perm_y[((j + dj as i64) & 255) as usize];
+                    let z = self.perm_z[((k + dk as i64) & 255) as usize];
+                    *ck = self.ranvec[x ^ y ^ z];
+                }
+            }
+        }
+        Self::trilinear_interp(&c, u, v, w)
+    }
+
+    // エルミート補間でマッハバンドを抑える
+    fn trilinear_interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+        let mut accum = 0.0;
+        for (i, ci) in c.iter().enumerate() {
+            for (j, cj) in ci.iter().enumerate() {
+                for (k, ck) in cj.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * ck.dot(weight);
+                }
+            }
+        }
+        accum
+    }
+
+    pub fn turb(&self, p: Point3, depth: usize) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accum += weight * self.noise(temp_p);
+            weight *= 0.5;
+            temp_p *= 2.0;
+        }
+        accum.abs()
+    }
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
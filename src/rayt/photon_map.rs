@@ -0,0 +1,246 @@
+use crate::rayt::*;
+
+use std::collections::BinaryHeap;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub position: Point3,
+    // 進んできた向き
+    pub direction: Vec3,
+    pub power: Color,
+}
+
+// フォトンを中央値で分割した kd 木 (Jensen 2001)
+// 範囲 [lo, hi) の部分木は中央 (lo + hi) / 2 に節点を置き、左右を再帰的に並べる
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+
+fn axis_value(p: Point3, axis: usize) -> f64 {
+    p.to_array()[axis]
+}
+
+// 距離の 2 乗は非負なのでビット列の大小がそのまま値の大小になる
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Candidate(u64, usize);
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        Self::build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [u8]) {
+        if photons.len() <= 1 {
+            return;
+        }
+        let (min, max) = photons.iter().fold(
+            (Vec3::fill(f64::MAX), Vec3::fill(f64::MIN)),
+            |(min, max), photon| (min.min(photon.position), max.max(photon.position)),
+        );
+        let extent = (max - min).to_array();
+        let axis = (0..3)
+            .max_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+            .unwrap();
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| {
+            axis_value(a.position, axis).total_cmp(&axis_value(b.position, axis))
+        });
+        axes[mid] = axis as u8;
+        let (left, right) = photons.split_at_mut(mid);
+        let (left_axes, right_axes) = axes.split_at_mut(mid);
+        Self::build(left, left_axes);
+        Self::build(&mut right[1..], &mut right_axes[1..]);
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    // p に近い順に最大 k 個、距離 max_radius 以内のフォトンと距離の 2 乗
+    pub fn nearest(&self, p: Point3, k: usize, max_radius: f64) -> Vec<(f64, &Photon)> {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        if k > 0 {
+            self.search(
+                0,
+                self.photons.len(),
+                p,
+                k,
+                max_radius * max_radius,
+                &mut heap,
+            );
+        }
+        let mut found = heap
+            .into_iter()
+            .map(|Candidate(d2, i)| (f64::from_bits(d2), &self.photons[i]))
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| a.0.total_cmp(&b.0));
+        found
+    }
+
+    fn search(
+        &self,
+        lo: usize,
+        hi: usize,
+        p: Point3,
+        k: usize,
+        max_d2: f64,
+        heap: &mut BinaryHeap<Candidate>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let node = &self.photons[mid];
+        let axis = self.axes[mid] as usize;
+        let d = axis_value(p, axis) - axis_value(node.position, axis);
+        let (near, far) = if d < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(near.0, near.1, p, k, max_d2, heap);
+        let d2 = (node.position - p).length_squared();
+        if d2 <= max_d2 {
+            heap.push(Candidate(d2.to_bits(), mid));
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+        let bound = match heap.peek() {
+            Some(Candidate(worst, _)) if heap.len() == k => f64::from_bits(*worst).min(max_d2),
+            _ => max_d2,
+        };
+        if d * d <= bound {
+            self.search(far.0, far.1, p, k, max_d2, heap);
+        }
+    }
+
+    // 法線 n の面の表側に届いた近傍 k 個のフォトンから放射照度を推定する
+    pub fn irradiance(&self, p: Point3, n: Vec3, k: usize, max_radius: f64) -> Color {
+        let found = self.nearest(p, k, max_radius);
+        let Some(&(farthest, _)) = found.last() else {
+            return Color::zero();
+        };
+        // k 個集まらなければ探した範囲全体の面積で割る
+        let r2 = if found.len() < k && max_radius.is_finite() {
+            max_radius * max_radius
+        } else {
+            farthest
+        };
+        if r2 <= 0.0 {
+            return Color::zero();
+        }
+        let flux = found
+            .iter()
+            .filter(|(_, photon)| photon.direction.dot(n) < 0.0)
+            .map(|(_, photon)| photon.power)
+            .sum::<Color>();
+        flux / (PI * r2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn photon(position: Point3, direction: Vec3) -> Photon {
+        Photon {
+            position,
+            direction,
+            power: Color::one(),
+        }
+    }
+
+    // 一辺 1 の立方体に散らばったフォトン。同じ位置のものも混ぜる
+    fn random_photons(n: usize) -> Vec<Photon> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut photons = (0..n)
+            .map(|_| {
+                let p = Point3::new(rng.gen(), rng.gen(), rng.gen());
+                photon(p, -Vec3::yaxis())
+            })
+            .collect::<Vec<_>>();
+        photons.extend(photons[..n / 10].to_vec());
+        photons
+    }
+
+    // 全部を調べて近い順に並べた距離の 2 乗
+    fn brute_force(photons: &[Photon], p: Point3, k: usize, max_radius: f64) -> Vec<f64> {
+        let mut d2 = photons
+            .iter()
+            .map(|photon| (photon.position - p).length_squared())
+            .filter(|&d2| d2 <= max_radius * max_radius)
+            .collect::<Vec<_>>();
+        d2.sort_by(f64::total_cmp);
+        d2.truncate(k);
+        d2
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let photons = random_photons(500);
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(map.len(), photons.len());
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..20 {
+            // 立方体の少し外側も探す
+            let p = Point3::new(rng.gen(), rng.gen(), rng.gen()) * 1.2 - Vec3::fill(0.1);
+            for k in [1, 8, 50, photons.len() + 10] {
+                for max_radius in [0.05, 0.2, f64::INFINITY] {
+                    let found = map
+                        .nearest(p, k, max_radius)
+                        .iter()
+                        .map(|&(d2, _)| d2)
+                        .collect::<Vec<_>>();
+                    assert_eq!(found, brute_force(&photons, p, k, max_radius));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_returns_the_photons_at_those_distances() {
+        let map = PhotonMap::new(random_photons(200));
+        let p = Point3::fill(0.5);
+        for (d2, photon) in map.nearest(p, 16, f64::INFINITY) {
+            assert_eq!(d2, (photon.position - p).length_squared());
+        }
+    }
+
+    #[test]
+    fn nearest_finds_nothing_in_an_empty_map_or_for_zero_k() {
+        let empty = PhotonMap::new(Vec::new());
+        assert!(empty.is_empty());
+        assert!(empty.nearest(Point3::zero(), 4, f64::INFINITY).is_empty());
+        let map = PhotonMap::new(random_photons(10));
+        assert!(map.nearest(Point3::zero(), 0, f64::INFINITY).is_empty());
+    }
+
+    #[test]
+    fn irradiance_ignores_photons_from_behind() {
+        // y = 0 の面の上に、表 (+y) から届いたものと裏から届いたものを 2 つずつ置く
+        let down = -Vec3::yaxis();
+        let photons = vec![
+            photon(Point3::new(0.1, 0.0, 0.1), down),
+            photon(Point3::new(-0.1, 0.0, -0.1), down),
+            photon(Point3::new(0.1, 0.0, -0.1), -down),
+            photon(Point3::new(-0.1, 0.0, 0.1), -down),
+        ];
+        let map = PhotonMap::new(photons);
+        // 4 つとも半径 √0.02 の円の上にあり、そのうち表側の 2 つだけを数える
+        let expected = 2.0 / (PI * 0.02);
+        for n in [Vec3::yaxis(), -Vec3::yaxis()] {
+            let e = map.irradiance(Point3::zero(), n, 4, f64::INFINITY);
+            assert!((e.x() - expected).abs() < 1e-6 * expected, "{:?}", e);
+            assert_eq!(e.x(), e.y());
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::rayt::*;
+
+use std::ops::Mul;
+
+// ストークスベクトル (各成分を RGB ごとに持つ)
+// 基準軸 x 方向の直線偏光で q が正になる
+#[derive(Debug, Clone, Copy)]
+pub struct Stokes {
+    pub i: Color,
+    pub q: Color,
+    pub u: Color,
+    pub v: Color,
+}
+
+impl Stokes {
+    pub fn unpolarized(i: Color) -> Self {
+        Self {
+            i,
+            q: Color::zero(),
+            u: Color::zero(),
+            v: Color::zero(),
+        }
+    }
+
+    pub fn scale(&self, albedo: Color) -> Self {
+        Self {
+            i: self.i * albedo,
+            q: self.q * albedo,
+            u: self.u * albedo,
+            v: self.v * albedo,
+        }
+    }
+
+    // 進行方向 d のまわりで基準軸を from から to へ取り替える
+    pub fn reframe(&self, d: Vec3, from: Vec3, to: Vec3) -> Self {
+        let phi = from.cross(to).dot(d.normalize()).atan2(from.dot(to));
+        Mueller::rotator(phi) * *self
+    }
+
+    pub fn degree_of_polarization(&self) -> Color {
+        let [i, q, u, v] = [self.i, self.q, self.u, self.v].map(|c| c.to_array());
+        Color::from_iter((0..3).map(|c| {
+            if i[c] > EPS {
+                (q[c] * q[c] + u[c] * u[c] + v[c] * v[c]).sqrt() / i[c]
+            } else {
+                0.0
+            }
+        }))
+    }
+}
+
+impl std::ops::Add for Stokes {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            i: self.i + rhs.i,
+            q: self.q + rhs.q,
+            u: self.u + rhs.u,
+            v: self.v + rhs.v,
+        }
+    }
+}
+
+// ミュラー行列
+#[derive(Debug, Clone, Copy)]
+pub struct Mueller(pub [[f64; 4]; 4]);
+
+impl Mueller {
+    pub const fn identity() -> Self {
+        Self([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // 拡散面などで偏光が失われる
+    pub const fn depolarizer() -> Self {
+        Self([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0],
+        ])
+    }
+
+    pub fn rotator(phi: f64) -> Self {
+        let (s, c) = (2.0 * phi).sin_cos();
+        Self([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, c, s, 0.0],
+            [0.0, -s, c, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // 基準軸から theta 傾いた直線偏光子
+    pub fn linear_polarizer(theta: f64) -> Self {
+        let (s, c) = (2.0 * theta).sin_cos();
+        Self([
+            [0.5, 0.5 * c, 0.5 * s, 0.0],
+            [0.5 * c, 0.5 * c * c, 0.5 * c * s, 0.0],
+            [0.5 * s, 0.5 * c * s, 0.5 * s * s, 0.0],
+            [0.0, 0.0, 0.0, 0.0],
+        ])
+    }
+
+    // s, p の振幅係数から作る (基準軸は s 方向)
+    // 強度は散乱の確率と albedo で扱うので m00 = 1 に正規化する
+    fn from_amplitudes(s: f64, p: f64) -> Self {
+        let a = 0.5 * (s * s + p * p);
+        if a < EPS {
+            return Self::depolarizer();
+        }
+        let b = 0.5 * (s * s - p * p) / a;
+        let c = s * p / a;
+        Self([
+            [1.0, b, 0.0, 0.0],
+            [b, 1.0, 0.0, 0.0],
+            [0.0, 0.0, c, 0.0],
+            [0.0, 0.0, 0.0, c],
+        ])
+    }
+
+    // eta は入射側に対する透過側の屈折率の比
+    pub fn fresnel_reflection(cos_i: f64, eta: f64) -> Self {
+        match Self::cos_transmitted(cos_i, eta) {
+            Some(cos_t) => Self::from_amplitudes(
+                (cos_i - eta * cos_t) / (cos_i + eta * cos_t),
+                (eta * cos_i - cos_t) / (eta * cos_i + cos_t),
+            ),
+            // 全反射では位相差しか生じないので無視する
+            None => Self::identity(),
+        }
+    }
+
+    pub fn fresnel_transmission(cos_i: f64, eta: f64) -> Self {
+        match Self::cos_transmitted(cos_i, eta) {
+            Some(cos_t) => Self::from_amplitudes(
+                2.0 * cos_i / (cos_i + eta * cos_t),
+                2.0 * cos_i / (eta * cos_i + cos_t),
+            ),
+            None => Self::depolarizer(),
+        }
+    }
+
+    fn cos_transmitted(cos_i: f64, eta: f64) -> Option<f64> {
+        let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+        if sin2_t < 1.0 {
+            Some((1.0 - sin2_t).sqrt())
+        } else {
+            None
+        }
+    }
+}
+
+impl Mul for Mueller {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.0[i][k] * rhs.0[k][j]).sum();
+            }
+        }
+        Self(m)
+    }
+}
+
+impl Mul<Stokes> for Mueller {
+    type Output = Stokes;
+    fn mul(self, rhs: Stokes) -> Stokes {
+        let s = [rhs.i, rhs.q, rhs.u, rhs.v];
+        let row = |r: [f64; 4]| s[0] * r[0] + s[1] * r[1] + s[2] * r[2] + s[3] * r[3];
+        Stokes {
+            i: row(self.0[0]),
+            q: row(self.0[1]),
+            u: row(self.0[2]),
+            v: row(self.0[3]),
+        }
+    }
+}
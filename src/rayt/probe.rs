@@ -0,0 +1,182 @@
+use crate::rayt::*;
+
+use rayon::prelude::*;
+use std::{fs, io, path::Path};
+
+#[derive(Debug, Clone, Copy)]
+pub struct IrradianceProbe {
+    pub position: Point3,
+    pub sh: [Color; 9],
+}
+
+impl IrradianceProbe {
+    // 実数球面調和関数 (l <= 2)
+    pub fn basis(d: Vec3) -> [f64; 9] {
+        let [x, y, z] = d.to_array();
+        let c0 = 0.5 * FRAC_1_PI.sqrt();
+        let c1 = (0.75 * FRAC_1_PI).sqrt();
+        let c2 = 0.5 * (15.0 * FRAC_1_PI).sqrt();
+        let c3 = 0.25 * (5.0 * FRAC_1_PI).sqrt();
+        let c4 = 0.25 * (15.0 * FRAC_1_PI).sqrt();
+        [
+            c0,
+            c1 * y,
+            c1 * z,
+            c1 * x,
+            c2 * x * y,
+            c2 * y * z,
+            c3 * (3.0 * z * z - 1.0),
+            c2 * x * z,
+            c4 * (x * x - y * y),
+        ]
+    }
+
+    pub fn radiance(&self, d: Vec3) -> Color {
+        Self::basis(d.normalize())
+            .iter()
+            .zip(self.sh.iter())
+            .fold(Color::zero(), |acc, (y, c)| acc + *c * *y)
+    }
+
+    // コサインローブとの畳み込み (Ramamoorthi & Hanrahan)
+    pub fn irradiance(&self, n: Vec3) -> Color {
+        let band = [PI, PI2 / 3.0, PI * 0.25];
+        Self::basis(n.normalize())
+            .iter()
+            .zip(self.sh.iter())
+            .enumerate()
+            .fold(Color::zero(), |acc, (i, (y, c))| {
+                let l = if i == 0 {
+                    0
+                } else if i < 4 {
+                    1
+                } else {
+                    2
+                };
+                acc + *c * (band[l] * y)
+            })
+    }
+}
+
+// 反射を追う深さは scene.max_depth() (RenderConfig::apply した scene なら --depth の値)
+pub fn render_irradiance_probe(
+    scene: &(impl SceneWithDepth + Sync),
+    position: Point3,
+    samples: usize,
+) -> IrradianceProbe {
+    let depth = scene.max_depth();
+    let sum = (0..samples)
+        .into_par_iter()
+        .map(|_| {
+            let [r1, r2, _] = Float3::random().to_array();
+            let z = 1.0 - 2.0 * r1;
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let (s, c) = (PI2 * r2).sin_cos();
+            let d = Vec3::new(r * c, r * s, z);
+            let radiance = scene.trace(Ray::new(position, d), depth);
+            IrradianceProbe::basis(d).map(|y| radiance * y)
+        })
+        .reduce(
+            || [Color::zero(); 9],
+            |mut acc, sh| {
+                for (a, c) in acc.iter_mut().zip(sh.iter()) {
+                    *a += *c;
+                }
+                acc
+            },
+        );
+    // 一様球面サンプリングの pdf は 1 / 4π
+    let weight = 2.0 * PI2 / samples as f64;
+    IrradianceProbe {
+        position,
+        sh: sum.map(|c| c * weight),
+    }
+}
+
+pub fn render_irradiance_probes(
+    scene: &(impl SceneWithDepth + Sync),
+    positions: &[Point3],
+    samples: usize,
+) -> Vec<IrradianceProbe> {
+    positions
+        .iter()
+        .map(|p| render_irradiance_probe(scene, *p, samples))
+        .collect()
+}
+
+pub fn write_irradiance_probes<P: AsRef<Path>>(
+    path: P,
+    probes: &[IrradianceProbe],
+) -> io::Result<()> {
+    let mut text = String::new();
+    for probe in probes {
+        let [x, y, z] = probe.position.to_array();
+        text += &format!("probe {} {} {}\n", x, y, z);
+        for c in probe.sh.iter() {
+            text += &format!("{} {} {}\n", c.x(), c.y(), c.z());
+        }
+    }
+    fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // どの向きからも同じ放射輝度が届く。trace に渡された深さを覚えておく
+    struct Sky {
+        radiance: Color,
+        depth: AtomicUsize,
+    }
+
+    impl Sky {
+        fn new(radiance: Color) -> Self {
+            Self {
+                radiance,
+                depth: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl SceneWithDepth for Sky {
+        fn camera(&self) -> Box<dyn Camera> {
+            Box::new(PerspectiveCamera::new(
+                Vec3::new(4.0, 0.0, 0.0),
+                Vec3::new(0.0, 2.0, 0.0),
+                Point3::new(-2.0, -1.0, -1.0),
+            ))
+        }
+        fn trace(&self, _ray: Ray, depth: usize) -> Color {
+            self.depth.store(depth, Ordering::Relaxed);
+            self.radiance
+        }
+    }
+
+    #[test]
+    fn constant_radiance_projects_onto_l0_only() {
+        let radiance = Color::new(1.0, 0.5, 0.25);
+        let probe = render_irradiance_probe(&Sky::new(radiance), Point3::zero(), 100_000);
+        // ∫ L Y00 dω = L · 4π / (2√π) = L √(4π)
+        let l0 = radiance * (4.0 * PI).sqrt();
+        assert!((probe.sh[0] - l0).length() < 1e-3, "{:?}", probe.sh[0]);
+        for c in &probe.sh[1..] {
+            assert!(c.length() < 0.1, "{:?}", c);
+        }
+        // L0 だけなら、どの向きの面にも πL が届く
+        let e = probe.irradiance(Vec3::xaxis());
+        assert!((e - radiance * PI).length() < 0.1 * PI, "{:?}", e);
+    }
+
+    #[test]
+    fn probe_traces_to_the_configured_depth() {
+        let sky = Sky::new(Color::one());
+        let config = RenderConfig {
+            max_depth: Some(7),
+            ..RenderConfig::default()
+        };
+        render_irradiance_probe(&config.apply(&sky), Point3::zero(), 16);
+        assert_eq!(sky.depth.load(Ordering::Relaxed), 7);
+    }
+}
@@ -0,0 +1,69 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+// 終わった仕事の数から進み具合と残り時間を見積もって標準エラーに出す
+// 複数のスレッドから tick してよい
+pub struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    printed: AtomicUsize,
+    start: Instant,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        Self {
+            total: total.max(1),
+            done: AtomicUsize::new(0),
+            printed: AtomicUsize::new(usize::MAX),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn tick(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        // 同じ割合を何度も出さない
+        let percent = 100 * done.min(self.total) / self.total;
+        if self.printed.swap(percent, Ordering::Relaxed) != percent {
+            self.print(done, percent);
+        }
+    }
+
+    pub fn finish(&self) {
+        eprintln!();
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn print(&self, done: usize, percent: usize) {
+        let elapsed = self.elapsed().as_secs_f64();
+        let eta = elapsed * (self.total.saturating_sub(done)) as f64 / done as f64;
+        let filled = PROGRESS_BAR_WIDTH * percent / 100;
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {:>3}%  elapsed {}  eta {}",
+            "#".repeat(filled),
+            "-".repeat(PROGRESS_BAR_WIDTH - filled),
+            percent,
+            format_duration(elapsed),
+            format_duration(eta),
+        );
+        let _ = stderr.flush();
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
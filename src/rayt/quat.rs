@@ -1,4 +1,15 @@
 use crate::rayt::*;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// serde feature では [x, y, z, w] の配列で読み書きする
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(from = "[f64; 4]", into = "[f64; 4]")
+)]
 pub struct Quat(Vec3, f64);
 
 impl Quat {
@@ -56,13 +67,89 @@ impl Quat {
         let x = (w1 * x2 + y1 * z2) - (z1 * y2);
         let y = (w1 * y2 + z1 * x2) - (x1 * z2);
         let z = (w1 * z2 + x1 * y2) - (y1 * x2);
-        let w = (x1 * x2 + y1 * y2) - (z1 * z2);
+        let w = (x1 * x2 + y1 * y2) + (z1 * z2);
         Vec3::new(
             ((w * x1 + x * w1) - y * z1) + z * y1,
             ((w * y1 + y * w1) - z * x1) + x * z1,
             ((w * z1 + z * w1) - x * y1) + y * x1,
         )
     }
+
+    pub fn rotate_ray(&self, ray: &Ray) -> Ray {
+        ray.transform(|p| self.rotate(p), |d| self.rotate(d))
+    }
+}
+
+impl Quat {
+    // X 軸、Y 軸、Z 軸の順に回す (ラジアン)
+    pub fn from_euler(x: f64, y: f64, z: f64) -> Self {
+        Quat::from_rot_z(z) * Quat::from_rot_y(y) * Quat::from_rot_x(x)
+    }
+
+    // 単位四元数の回転軸とラジアンの角度。回っていなければ軸は x 軸にする
+    pub fn axis_angle(&self) -> (Vec3, f64) {
+        let q = self.normalize();
+        let angle = 2.0 * q.1.clamp(-1.0, 1.0).acos();
+        let s = (angle * 0.5).sin();
+        if s < EPS {
+            (Vec3::xaxis(), 0.0)
+        } else {
+            (q.0 / s, angle)
+        }
+    }
+
+    // 行優先の回転行列。m[i] が i 行目で、rotate(p) と m * p は一致する
+    pub fn to_matrix(&self) -> [[f64; 3]; 3] {
+        let [x, y, z, w] = self.normalize().to_array();
+        [
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ]
+    }
+
+    // 球面線形補間。近い向きどうしでは割り算が不安定なので線形補間で済ませる
+    pub fn slerp(&self, rhs: Self, t: f64) -> Self {
+        let (a, mut b) = (self.normalize(), rhs.normalize());
+        let mut cos = a.dot(b);
+        // q と -q は同じ回転なので、近い回り方のほうを選ぶ
+        if cos < 0.0 {
+            b = Quat(-b.0, -b.1);
+            cos = -cos;
+        }
+        let (wa, wb) = if cos > 1.0 - EPS {
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Quat(a.0 * wa + b.0 * wb, a.1 * wa + b.1 * wb).normalize()
+    }
+}
+
+impl From<[f64; 4]> for Quat {
+    fn from([x, y, z, w]: [f64; 4]) -> Self {
+        Quat::new(x, y, z, w)
+    }
+}
+
+impl From<Quat> for [f64; 4] {
+    fn from(q: Quat) -> Self {
+        q.to_array()
+    }
 }
 
 impl std::ops::Mul<Quat> for Quat {
@@ -72,9 +159,119 @@ impl std::ops::Mul<Quat> for Quat {
         let [x2, y2, z2, w2] = rhs.to_array();
         Quat::new(
             w1 * x2 + x1 * w2 + y1 * z2 - z1 * y2,
-            w1 * y2 + y2 * w2 + z1 * x2 - x1 * z2,
+            w1 * y2 + y1 * w2 + z1 * x2 - x1 * z2,
             w1 * z2 + z1 * w2 + x1 * y2 - y1 * x2,
             w1 * w2 - x1 * x2 - y1 * y2 - z1 * z2,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn rotate_turns_counterclockwise_about_the_axis() {
+        let q = Quat::from_rot_z(FRAC_PI_2);
+        assert_near(q.rotate(Vec3::xaxis()), Vec3::yaxis());
+        assert_near(q.rotate(Vec3::yaxis()), -Vec3::xaxis());
+        assert_near(q.rotate(Vec3::zaxis()), Vec3::zaxis());
+        let q = Quat::from_rot(Vec3::xaxis(), FRAC_PI_2);
+        assert_near(q.rotate(Vec3::yaxis()), Vec3::zaxis());
+        assert_near(q.conj().rotate(q.rotate(Vec3::one())), Vec3::one());
+    }
+
+    #[test]
+    fn rotate_matches_the_matrix_form() {
+        let q = Quat::from_euler(0.3, -0.7, 1.1);
+        let p = Vec3::new(0.5, 1.0, -2.0);
+        let m = q.to_matrix();
+        let rotated = Vec3::new(
+            Vec3::from(m[0]).dot(p),
+            Vec3::from(m[1]).dot(p),
+            Vec3::from(m[2]).dot(p),
+        );
+        assert_near(q.rotate(p), rotated);
+    }
+
+    #[test]
+    fn product_applies_the_right_hand_side_first() {
+        let (a, b) = (Quat::from_rot_z(FRAC_PI_2), Quat::from_rot_x(FRAC_PI_2));
+        let p = Vec3::yaxis();
+        assert_near((a * b).rotate(p), a.rotate(b.rotate(p)));
+        // y -> z (x 軸まわり) -> z (z 軸まわり)
+        assert_near((a * b).rotate(p), Vec3::zaxis());
+        // y -> -x (z 軸まわり) -> -x (x 軸まわり)
+        assert_near((b * a).rotate(p), -Vec3::xaxis());
+        let q = Quat::from_euler(0.3, -0.7, 1.1);
+        assert_eq!((Quat::unit() * q).to_array(), q.to_array());
+        assert_eq!((q * Quat::unit()).to_array(), q.to_array());
+    }
+
+    #[test]
+    fn euler_angles_rotate_about_x_then_y_then_z() {
+        let q = Quat::from_euler(FRAC_PI_2, 0.0, FRAC_PI_2);
+        assert_near(q.rotate(Vec3::xaxis()), Vec3::yaxis());
+        assert_near(q.rotate(Vec3::yaxis()), Vec3::zaxis());
+    }
+
+    fn assert_same_quat(a: Quat, b: Quat) {
+        let (a, b) = (a.to_array(), b.to_array());
+        assert!(
+            a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-5),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn slerp_reaches_both_endpoints() {
+        let (a, b) = (Quat::from_rot_z(0.2), Quat::from_rot_z(1.4));
+        assert_same_quat(a.slerp(b, 0.0), a);
+        assert_same_quat(a.slerp(b, 1.0), b);
+        // 同じ軸まわりなら角度が t に比例して変わる
+        assert_same_quat(a.slerp(b, 0.25), Quat::from_rot_z(0.5));
+    }
+
+    #[test]
+    fn slerp_takes_the_shorter_arc() {
+        let a = Quat::from_rot_z(0.2);
+        // -q は q と同じ回転なので、符号を反転しても同じ向きに補間する
+        let [x, y, z, w] = Quat::from_rot_z(0.6).to_array();
+        let negated = Quat::new(-x, -y, -z, -w);
+        assert_same_quat(a.slerp(negated, 0.5), Quat::from_rot_z(0.4));
+    }
+
+    #[test]
+    fn slerp_between_nearly_equal_rotations_stays_finite() {
+        let a = Quat::from_rot_z(0.3);
+        // 同じ向きどうしでは sin が 0 になるので、線形補間に切り替わる
+        assert_same_quat(a.slerp(a, 0.5), a);
+        let b = Quat::from_rot_z(0.3 + 1e-4);
+        assert_same_quat(a.slerp(b, 0.5), Quat::from_rot_z(0.3 + 0.5e-4));
+    }
+
+    #[test]
+    fn axis_angle_recovers_the_rotation() {
+        let axis = Vec3::new(1.0, 2.0, 2.0) / 3.0;
+        let (a, angle) = Quat::from_rot(axis, 1.2).axis_angle();
+        assert_near(a, axis);
+        assert!((angle - 1.2).abs() < 1e-5);
+        // 回っていなければ軸は x 軸で 0 rad
+        assert_eq!(Quat::unit().axis_angle(), (Vec3::xaxis(), 0.0));
+    }
+
+    #[test]
+    fn rotate_ray_rotates_origin_and_direction() {
+        let ray = Ray::new(Point3::new(1.0, 0.0, 3.0), Vec3::xaxis());
+        let rotated = Quat::from_rot_z(FRAC_PI_2).rotate_ray(&ray);
+        assert_near(rotated.origin, Point3::new(0.0, 1.0, 3.0));
+        assert_near(rotated.direction, Vec3::yaxis());
+    }
+}
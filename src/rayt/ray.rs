@@ -1,16 +1,96 @@
 use crate::rayt::*;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// 隣の画素を通る光線 (x 方向と y 方向)
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RayDifferential {
+    pub rx_origin: Point3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Point3,
+    pub ry_direction: Vec3,
+}
+
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
+    pub differential: Option<RayDifferential>,
+    // シャッターが開いている間のどの時刻の光線か
+    pub time: f64,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            differential: None,
+            time: 0.0,
+        }
+    }
+    pub fn with_time(self, time: f64) -> Self {
+        Self { time, ..self }
+    }
+    pub fn with_differential(self, differential: Option<RayDifferential>) -> Self {
+        Self {
+            differential,
+            ..self
+        }
     }
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + t * self.direction
     }
+
+    // 点と方向を別々に変換する (平行移動は方向に効かない)
+    pub fn transform<P, D>(&self, point: P, direction: D) -> Self
+    where
+        P: Fn(Point3) -> Point3,
+        D: Fn(Vec3) -> Vec3,
+    {
+        Self {
+            origin: point(self.origin),
+            direction: direction(self.direction),
+            differential: self.differential.map(|d| RayDifferential {
+                rx_origin: point(d.rx_origin),
+                rx_direction: direction(d.rx_direction),
+                ry_origin: point(d.ry_origin),
+                ry_direction: direction(d.ry_direction),
+            }),
+            time: self.time,
+        }
+    }
+
+    // 隣の光線と p を通る接平面との交点から、画素一つ分の p の変化を求める
+    pub fn footprint(&self, p: Point3, n: Vec3) -> Option<(Vec3, Vec3)> {
+        let d = self.differential?;
+        let offset = |origin: Point3, direction: Vec3| {
+            let denom = n.dot(direction);
+            if denom.abs() < EPS {
+                return None;
+            }
+            Some(origin + n.dot(p - origin) / denom * direction - p)
+        };
+        Some((
+            offset(d.rx_origin, d.rx_direction)?,
+            offset(d.ry_origin, d.ry_direction)?,
+        ))
+    }
+
+    // 散乱後の光線の微分 (法線の変化は無視する)
+    pub fn scattered<F>(&self, p: Point3, dpdx: Vec3, dpdy: Vec3, f: F) -> Option<RayDifferential>
+    where
+        F: Fn(Vec3) -> Option<Vec3>,
+    {
+        let d = self.differential?;
+        Some(RayDifferential {
+            rx_origin: p + dpdx,
+            rx_direction: f(d.rx_direction)?,
+            ry_origin: p + dpdy,
+            ry_direction: f(d.ry_direction)?,
+        })
+    }
 }
@@ -1,27 +1,584 @@
 use crate::rayt::*;
 
-use image::{Rgb, RgbImage};
+use image::codecs::hdr::HdrEncoder;
+use image::{GrayImage, ImageFormat, ImageResult, Luma, Rgb, Rgb32FImage, RgbImage};
 use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
 use std::{fs, path::Path};
 
 const IMAGE_WIDTH: u32 = 200;
 const IMAGE_HEIGHT: u32 = 100;
-const OUTPUT_FILENAME: &str = "render.png";
-const BACKUP_FILENAME: &str = "render_back.png";
+pub const OUTPUT_FILENAME: &str = "render.png";
 const SAMPLES_PER_PIXEL: usize = 8;
-const GAMMA_FACTOR: f64 = 2.2;
-const MAX_RAY_BOUNCE_DEPTH: usize = 50;
+const PATH_STATS_FILENAME: &str = "render_paths.txt";
+pub const MAX_RAY_BOUNCE_DEPTH: usize = 50;
+const METERING_DOWNSCALE: u32 = 4;
+const METERING_SAMPLES_PER_PIXEL: usize = 4;
+const METERING_MIDDLE_GRAY: f64 = 0.18;
+const THUMBNAIL_SAMPLES_PER_PIXEL: usize = 16;
+const TILE_SIZE: u32 = 32;
+// pass の途中でタイルの様子を見せる間隔
+const TILE_BOARD_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
 
-fn backup() {
-    let output_path = Path::new(OUTPUT_FILENAME);
-    if output_path.exists() {
-        println!("backup {:?} -> {:?}", OUTPUT_FILENAME, BACKUP_FILENAME);
-        fs::rename(OUTPUT_FILENAME, BACKUP_FILENAME).unwrap();
+// 書き出す前に前回の出力をどう残すか
+// 設定ファイルではコマンドラインと同じ back, timestamp, none
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum Backup {
+    // render_back.png に移す (前回の分は消える)
+    #[default]
+    #[cfg_attr(feature = "serde", serde(rename = "back"))]
+    Previous,
+    // 前回の出力の更新日時を付けた名前に移す (render_20240101_120000.png)
+    #[cfg_attr(feature = "serde", serde(rename = "timestamp"))]
+    Timestamped,
+    // 残さずに上書きする
+    #[cfg_attr(feature = "serde", serde(rename = "none"))]
+    Off,
+}
+
+// 描画の設定。None の項目はシーンの値を使う
+// TOML から読むときは、書かなかった項目が既定値になる (RenderConfig::load)
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct RenderConfig {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    // シーンファイルの [render] と同じく samples と書く
+    #[cfg_attr(feature = "serde", serde(rename = "samples"))]
+    pub spp: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub output: String,
+    pub threads: Option<usize>,
+    // output と並べて線形の 32 bit float で書き出すファイル (.exr か .hdr)
+    pub hdr: Option<String>,
+    pub backup: Backup,
+    // 線形の蓄積バッファを表示用に変換する設定
+    pub color: ColorConfig,
+    // render_normal.png などとして書き出す AOV
+    pub aovs: Vec<AovPass>,
+    // 書き出す前にかけるノイズ除去
+    pub denoise: Option<Denoiser>,
+    // 動画として書き出すときのフレームレート
+    pub fps: f64,
+    // false ならウィンドウを開かずにファイルだけ書き出す (画面のないサーバ用)
+    pub window: bool,
+    // 自分では描かず、このアドレスで待ち受けてワーカーにタイルを配る
+    pub serve: Option<String>,
+    // 途中経過をこのアドレスの HTTP で配る (PreviewServer)
+    pub http: Option<String>,
+    // この範囲 (x0, y0, x1, y1) だけを描き、外側は前回の出力か黒にする
+    pub crop: Option<Tile>,
+    // 経路を追う代わりに法線や UV を 1 サンプルで描く
+    pub mode: Option<DebugMode>,
+    // 下見描画から露出を決める測光。None ならシーンの値 (既定では測らない)
+    pub metering: Option<Metering>,
+    // 測り終えた自動露出 (EV)。None なら描く前に測る
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub metered_exposure: Option<f64>,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            width: None,
+            height: None,
+            spp: None,
+            max_depth: None,
+            output: OUTPUT_FILENAME.to_string(),
+            threads: None,
+            hdr: None,
+            backup: Backup::Previous,
+            color: ColorConfig::default(),
+            aovs: Vec::new(),
+            denoise: None,
+            fps: 24.0,
+            window: true,
+            serve: None,
+            http: None,
+            crop: None,
+            mode: None,
+            metering: None,
+            metered_exposure: None,
+        }
+    }
+}
+
+impl RenderConfig {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let error = |e: &dyn std::fmt::Display| Error::File(format!("{}: {}", path, e));
+        let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+        let config: Self = from_toml(&text).map_err(|e| error(&e))?;
+        check_output_format(&config.output).map_err(|e| error(&e))?;
+        Ok(config)
+    }
+
+    pub fn with_size(self, width: u32, height: u32) -> Self {
+        Self {
+            width: Some(width),
+            height: Some(height),
+            ..self
+        }
+    }
+    pub fn with_spp(self, spp: usize) -> Self {
+        Self {
+            spp: Some(spp),
+            ..self
+        }
+    }
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        Self {
+            max_depth: Some(max_depth),
+            ..self
+        }
+    }
+    pub fn with_output(self, output: &str) -> Self {
+        Self {
+            output: output.to_string(),
+            ..self
+        }
+    }
+    pub fn with_threads(self, threads: usize) -> Self {
+        Self {
+            threads: Some(threads),
+            ..self
+        }
+    }
+    pub fn with_color(self, color: ColorConfig) -> Self {
+        Self { color, ..self }
+    }
+    pub fn with_window(self, window: bool) -> Self {
+        Self { window, ..self }
+    }
+
+    // 出力と同じ場所に、名前に suffix を付けて置くファイル (render.png -> render_back.png)
+    pub fn sibling(&self, suffix: &str, extension: &str) -> String {
+        let path = Path::new(&self.output);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}{}.{}", stem, suffix, extension))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // 幅か高さの片方だけなら縦横比を保ち、両方なら横の画角を広げて合わせる
+    pub fn apply<'a, S: SceneWithDepth>(&self, scene: &'a S) -> impl SceneWithDepth + 'a {
+        let mut applied = SceneOverride::new(scene);
+        let aspect = scene.aspect();
+        let size = match (self.width, self.height) {
+            (Some(w), Some(h)) => Some((w, h)),
+            (Some(w), None) => Some((w, ((w as f64 / aspect).round() as u32).max(2))),
+            (None, Some(h)) => Some((((h as f64 * aspect).round() as u32).max(2), h)),
+            (None, None) => None,
+        };
+        if let Some((w, h)) = size {
+            applied = applied.with_size(w, h);
+            if self.width.is_some() && self.height.is_some() {
+                let scale = w as f64 / h as f64 / aspect;
+                applied = applied.with_camera(Arc::new(ReframedCamera::new(
+                    Arc::from(scene.camera()),
+                    scale,
+                )));
+            }
+        }
+        if let Some(mode) = self.mode {
+            applied = applied.with_mode(mode).with_spp(1);
+        }
+        if let Some(spp) = self.spp {
+            applied = applied.with_spp(spp);
+        }
+        if let Some(depth) = self.max_depth {
+            applied = applied.with_max_depth(depth);
+        }
+        if let Some(crop) = self.crop {
+            applied = applied.with_crop(crop);
+        }
+        if let Some(metering) = self.metering {
+            applied = applied.with_metering(metering);
+        }
+        applied
+    }
+
+    // threads が指定されていればその数のスレッドで f を動かす
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        match self.threads {
+            Some(threads) => rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(f),
+            None => f(),
+        }
+    }
+
+    // 測り終えていればその値、まだなら scene の下見描画から露出を決める
+    fn exposure(&self, scene: &(impl SceneWithDepth + Sync)) -> f64 {
+        self.metered_exposure
+            .unwrap_or_else(|| self.install(|| auto_exposure(scene)))
+    }
+
+    fn extension(&self) -> String {
+        let extension = Path::new(&self.output).extension().unwrap_or_default();
+        extension.to_string_lossy().into_owned()
+    }
+
+    // output の拡張子が exr か hdr なら img の代わりに buffer をそのまま書き出す
+    fn save(&self, img: &RgbImage, buffer: &[Color]) -> Result<(), Error> {
+        let (w, h) = img.dimensions();
+        if is_linear_format(&self.output) {
+            save_linear(buffer, w, h, &self.output)?;
+        } else {
+            img.save(&self.output)?;
+        }
+        if let Some(hdr) = &self.hdr {
+            save_linear(buffer, w, h, hdr)?;
+        }
+        Ok(())
+    }
+
+    // crop の外側を previous (前回の出力) からそのまま写す
+    // 大きさが違ったり線形の形式だったりするときは黒のまま
+    fn fill_outside_crop(&self, img: &mut RgbImage, previous: &str) {
+        let Some((x0, y0, x1, y1)) = self.crop else {
+            return;
+        };
+        if is_linear_format(previous) {
+            return;
+        }
+        let Ok(previous) = image::open(previous).map(|img| img.to_rgb8()) else {
+            return;
+        };
+        if previous.dimensions() != img.dimensions() {
+            return;
+        }
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            if !(x0..x1).contains(&x) || !(y0..y1).contains(&y) {
+                *pixel = *previous.get_pixel(x, y);
+            }
+        }
+    }
+
+    fn show(&self, backup: Option<&str>, img: RgbImage) -> Result<(), Error> {
+        if self.window {
+            #[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+            draw_in_window(backup, img)?;
+            // wasm にはウィンドウがない。ブラウザでは web.rs の側で canvas に描く
+            #[cfg(not(all(feature = "window", not(target_arch = "wasm32"))))]
+            drop((backup, img));
+        }
+        Ok(())
+    }
+
+    // 前回の出力を退避して、ウィンドウで比べる画像のファイル名を返す
+    fn backup(&self) -> Result<Option<String>, Error> {
+        let exists = Path::new(&self.output).exists();
+        let backup = match self.backup {
+            Backup::Previous => self.sibling("_back", &self.extension()),
+            Backup::Timestamped if exists => {
+                let modified = fs::metadata(&self.output).and_then(|m| m.modified())?;
+                self.sibling(&format!("_{}", timestamp(modified)), &self.extension())
+            }
+            Backup::Timestamped | Backup::Off => return Ok(None),
+        };
+        if exists {
+            println!("backup {:?} -> {:?}", self.output, backup);
+            fs::rename(&self.output, &backup)?;
+        }
+        Ok(Some(backup))
+    }
+}
+
+// 書き出せる形式か拡張子から確かめる (png, jpg, ppm, bmp や線形の exr, hdr など)
+pub fn check_output_format(path: &str) -> Result<(), String> {
+    if is_linear_format(path) || AnimationFormat::from_path(path).is_some() {
+        return Ok(());
+    }
+    match ImageFormat::from_path(path) {
+        Ok(format) if format.can_write() => Ok(()),
+        Ok(format) => Err(format!("writing {:?} is not supported", format)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// UTC の年月日と時刻 (20240101_120000)
+fn timestamp(time: std::time::SystemTime) -> String {
+    let seconds = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    // Howard Hinnant の civil_from_days
+    let z = (seconds / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = seconds % 86400;
+    format!(
+        "{:04}{:02}{:02}_{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+fn morton(x: u32, y: u32) -> u64 {
+    fn spread(v: u32) -> u64 {
+        let mut v = v as u64;
+        v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+        v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        v = (v | (v << 1)) & 0x5555_5555_5555_5555;
+        v
+    }
+    spread(x) | (spread(y) << 1)
+}
+
+// 近い画素が連続して処理されるように Z 曲線順に並べる
+fn morton_ordered<T>(pixels: impl Iterator<Item = (u32, u32, T)>) -> Vec<(u32, u32, T)> {
+    let mut pixels = pixels.collect::<Vec<_>>();
+    pixels.sort_unstable_by_key(|(x, y, _)| morton(*x, *y));
+    pixels
+}
+
+// overscan 込みのバッファで描くタイル。scene.crop() があればその範囲に切り詰める
+// 縮小した下見 (測光など) では全体を描く
+fn tiles_to_render(scene: &impl SceneWithDepth, w: u32, h: u32, o: u32) -> Vec<Tile> {
+    let tiles = tiles(w + 2 * o, h + 2 * o);
+    let crop = scene
+        .crop()
+        .filter(|_| (w, h) == (scene.width(), scene.height()));
+    let Some((cx0, cy0, cx1, cy1)) = crop else {
+        return tiles;
+    };
+    let (cx0, cy0) = (cx0.min(w) + o, cy0.min(h) + o);
+    let (cx1, cy1) = (cx1.min(w) + o, cy1.min(h) + o);
+    tiles
+        .into_iter()
+        .map(|(x0, y0, x1, y1)| (x0.max(cx0), y0.max(cy0), x1.min(cx1), y1.min(cy1)))
+        .filter(|(x0, y0, x1, y1)| x0 < x1 && y0 < y1)
+        .collect()
+}
+
+// w x h を TILE_SIZE 四方のタイルに分ける
+pub(crate) fn tiles(w: u32, h: u32) -> Vec<Tile> {
+    (0..h)
+        .step_by(TILE_SIZE as usize)
+        .flat_map(|y0| {
+            (0..w)
+                .step_by(TILE_SIZE as usize)
+                .map(move |x0| (x0, y0, (x0 + TILE_SIZE).min(w), (y0 + TILE_SIZE).min(h)))
+        })
+        .collect()
+}
+
+fn bracket_filename(config: &RenderConfig, exposure: f64) -> String {
+    config.sibling(&format!("_ev{:+}", exposure), "png")
+}
+
+fn frame_filename(config: &RenderConfig, frame: usize) -> String {
+    config.sibling(&format!("_{:04}", frame), "png")
+}
+
+fn motion_filename(config: &RenderConfig, frame: usize) -> String {
+    config.sibling(&format!("_{:04}_motion", frame), "exr")
+}
+
+fn matte_filename(config: &RenderConfig, name: &str) -> String {
+    config.sibling(&format!("_matte_{}", name), "png")
+}
+
+fn aov_filename(config: &RenderConfig, pass: AovPass) -> String {
+    config.sibling(&format!("_{}", pass.name()), pass.extension())
+}
+
+// 後から足し合わせて配分を変えられるように、露出をかけない線形の値で書く
+fn light_group_filename(config: &RenderConfig, group: &str) -> String {
+    config.sibling(&format!("_light_{}", group), "exr")
+}
+
+pub fn is_linear_format(path: &str) -> bool {
+    let extension = Path::new(path).extension().unwrap_or_default();
+    extension.eq_ignore_ascii_case("exr") || extension.eq_ignore_ascii_case("hdr")
+}
+
+// 露出も表示変換もかけない作業色空間の放射輝度を書き出す
+pub fn save_linear(buffer: &[Color], w: u32, h: u32, path: &str) -> ImageResult<()> {
+    let pixels = buffer
+        .iter()
+        .map(|c| Rgb([c.x() as f32, c.y() as f32, c.z() as f32]))
+        .collect::<Vec<_>>();
+    if Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"))
+    {
+        let file = std::io::BufWriter::new(fs::File::create(path)?);
+        HdrEncoder::new(file).encode(&pixels, w as usize, h as usize)
+    } else {
+        let data = pixels.iter().flat_map(|p| p.0).collect();
+        Rgb32FImage::from_raw(w, h, data).unwrap().save(path)
+    }
+}
+
+fn to_image(buffer: &[Color], w: u32, h: u32, exposure: f64, config: &ColorConfig) -> RgbImage {
+    let scale = exposure.exp2();
+    let mut img = RgbImage::new(w, h);
+    for (pixel, color) in img.pixels_mut().zip(buffer.iter()) {
+        *pixel = Rgb(config.to_display(*color * scale).to_rgb());
+    }
+    img
+}
+
+// 設定ファイルではコマンドラインと同じ off, median, matrix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Metering {
+    Off,
+    Median,
+    Matrix,
+}
+
+impl Metering {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Metering::Off),
+            "median" => Some(Metering::Median),
+            "matrix" => Some(Metering::Matrix),
+            _ => None,
+        }
+    }
+
+    // 中央値または分割測光の輝度を 18% グレーに合わせる露出 (EV) を返す
+    // 下見は spp が少ないので、真っ黒な画素は測光から外す
+    fn exposure(self, buffer: &[Color], w: u32, h: u32) -> f64 {
+        let key = match self {
+            Metering::Off => return 0.0,
+            Metering::Median => {
+                let mut lums = buffer
+                    .iter()
+                    .map(|c| c.luminance())
+                    .filter(|l| *l > EPS)
+                    .collect::<Vec<_>>();
+                if lums.is_empty() {
+                    return 0.0;
+                }
+                lums.sort_unstable_by(|a, b| a.total_cmp(b));
+                lums[lums.len() / 2]
+            }
+            Metering::Matrix => {
+                // 3x3 の区画ごとに対数平均を取り、中央の区画を重く見る
+                let mut zones = [(0.0, 0usize); 9];
+                for (i, color) in buffer.iter().enumerate() {
+                    let lum = color.luminance();
+                    if lum <= EPS {
+                        continue;
+                    }
+                    let (x, y) = (i as u32 % w, i as u32 / w);
+                    let zone = (3 * y / h * 3 + 3 * x / w) as usize;
+                    zones[zone].0 += lum.ln();
+                    zones[zone].1 += 1;
+                }
+                let (sum, weights) = zones.iter().enumerate().filter(|(_, (_, n))| *n > 0).fold(
+                    (0.0, 0.0),
+                    |(sum, weights), (i, (log_sum, n))| {
+                        let weight = if i == 4 { 4.0 } else { 1.0 };
+                        (sum + weight * log_sum / *n as f64, weights + weight)
+                    },
+                );
+                if weights == 0.0 {
+                    return 0.0;
+                }
+                (sum / weights).exp()
+            }
+        };
+        if key > EPS {
+            (METERING_MIDDLE_GRAY / key).log2()
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CausticSettings {
+    pub enabled: bool,
+    pub sample_multiplier: usize,
+    // フォトンマップがあるとき、放射照度の推定に使う近傍フォトンの数と探す範囲
+    pub photon_neighbors: usize,
+    pub photon_radius: f64,
+}
+
+impl Default for CausticSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_multiplier: 1,
+            photon_neighbors: 64,
+            photon_radius: f64::INFINITY,
+        }
+    }
+}
+
+// 1 サンプルの放射輝度の上限。まれな経路から来る極端に明るい画素 (ファイアフライ) を抑える
+// 色の成分の最大値で測り、色相は変えずに縮める
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RadianceClamp {
+    #[default]
+    Off,
+    // 直接光も含めて制限する
+    All(f64),
+    // 最初に当たった点へ届く間接光だけを制限する
+    Indirect(f64),
+}
+
+impl RadianceClamp {
+    // 光源グループの内訳も合計と同じ割合で弱める
+    fn limit(c: GroupRadiance, max: f64) -> GroupRadiance {
+        let peak = c.total.x().max(c.total.y()).max(c.total.z());
+        if peak > max {
+            c * (max / peak)
+        } else {
+            c
+        }
+    }
+
+    pub fn sample(self, c: GroupRadiance) -> GroupRadiance {
+        match self {
+            RadianceClamp::All(max) => Self::limit(c, max),
+            _ => c,
+        }
+    }
+
+    pub fn indirect(self, c: GroupRadiance) -> GroupRadiance {
+        match self {
+            RadianceClamp::Indirect(max) => Self::limit(c, max),
+            _ => c,
+        }
     }
 }
 
 pub trait Scene {
-    fn camera(&self) -> Camera;
+    fn camera(&self) -> Box<dyn Camera>;
     fn trace(&self, ray: Ray) -> Color;
     fn width(&self) -> u32 {
         IMAGE_WIDTH
@@ -38,7 +595,7 @@ pub trait Scene {
 }
 
 pub trait SceneWithDepth {
-    fn camera(&self) -> Camera;
+    fn camera(&self) -> Box<dyn Camera>;
     fn trace(&self, ray: Ray, depth: usize) -> Color;
     fn width(&self) -> u32 {
         IMAGE_WIDTH
@@ -52,83 +609,1641 @@ pub trait SceneWithDepth {
     fn aspect(&self) -> f64 {
         self.width() as f64 / self.height() as f64
     }
+    fn overscan(&self) -> u32 {
+        0
+    }
+    // 画素を strata x strata の小区画に分け、サンプルを順に各区画へ振り分ける
+    fn strata(&self) -> u32 {
+        1
+    }
+    // 光線を追う最大の反射回数
+    fn max_depth(&self) -> usize {
+        MAX_RAY_BOUNCE_DEPTH
+    }
+    // 描く範囲 (x0, y0, x1, y1)。None なら全体
+    fn crop(&self) -> Option<Tile> {
+        None
+    }
+    // サンプルの位置や反射方向に使う乱数源
+    fn sampler(&self) -> SamplerKind {
+        SamplerKind::Random
+    }
+    fn seed(&self) -> Option<u64> {
+        None
+    }
+    // カメラ前の直線偏光フィルタの角度 (度)
+    fn polarizer(&self) -> Option<f64> {
+        None
+    }
+    // カメラから見えている物体のマット名
+    fn matte(&self, _ray: &Ray) -> Option<&'static str> {
+        None
+    }
+    // カメラから見えている点の位置
+    fn position(&self, _ray: &Ray) -> Option<Point3> {
+        None
+    }
+    // カメラから見えている点の法線、距離、アルベドなど
+    fn aov(&self, _ray: &Ray) -> Option<Aov> {
+        None
+    }
+    // render_light_<group>.exr を書き出す光源グループ
+    fn light_groups(&self) -> Vec<String> {
+        Vec::new()
+    }
+    // trace と同じ光を、light_groups ごとの内訳と一緒に返す
+    fn trace_groups(&self, ray: Ray, depth: usize) -> GroupRadiance {
+        GroupRadiance::new(None, self.trace(ray, depth))
+    }
+    // 露出を自動で決めたいシーンだけが測光を選ぶ
+    fn metering(&self) -> Metering {
+        Metering::Off
+    }
+    fn caustics(&self) -> CausticSettings {
+        CausticSettings::default()
+    }
+    fn radiance_clamp(&self) -> RadianceClamp {
+        RadianceClamp::Off
+    }
+    fn path_stats(&self) -> Option<&PathStats> {
+        None
+    }
+    fn record_path(&self, depth: usize, end: PathEnd) {
+        if let Some(stats) = self.path_stats() {
+            stats.record(self.max_depth().saturating_sub(depth), end);
+        }
+    }
 }
 
-pub fn render(scene: impl Scene + Sync) {
-    backup();
+pub fn render(scene: impl Scene + Sync, config: &RenderConfig) -> Result<(), Error> {
+    let backup = config.backup()?;
 
     let camera = scene.camera();
     let w = scene.width();
     let h = scene.height();
     let mut img = RgbImage::new(w, h);
-    img.enumerate_pixels_mut()
-        .collect::<Vec<(u32, u32, &mut Rgb<u8>)>>()
-        .par_iter_mut()
-        .for_each(|(x, y, pixel)| {
-            let u = *x as f64 / (w - 1) as f64;
-            let v = (h - *y - 1) as f64 / (h - 1) as f64;
-            let ray = camera.ray(u, v);
-            let rgb = scene.trace(ray).to_rgb();
-            pixel[0] = rgb[0];
-            pixel[1] = rgb[1];
-            pixel[2] = rgb[2];
-        });
-    img.save(OUTPUT_FILENAME).unwrap();
-    draw_in_window(BACKUP_FILENAME, img).unwrap();
+    config.install(|| {
+        morton_ordered(img.enumerate_pixels_mut())
+            .par_iter_mut()
+            .for_each(|(x, y, pixel)| {
+                let u = *x as f64 / (w - 1) as f64;
+                let v = (h - *y - 1) as f64 / (h - 1) as f64;
+                let ray = camera.ray(u, v);
+                **pixel = Rgb(config.color.to_display(scene.trace(ray)).to_rgb());
+            })
+    });
+    img.save(&config.output)?;
+    config.show(backup.as_deref(), img)
 }
 
-pub fn render_aa(scene: impl Scene + Sync) {
-    backup();
+pub fn render_aa(scene: impl Scene + Sync, config: &RenderConfig) -> Result<(), Error> {
+    let backup = config.backup()?;
 
     let camera = scene.camera();
     let w = scene.width();
     let h = scene.height();
+    let spp = config.spp.unwrap_or(scene.spp());
     let mut img = RgbImage::new(w, h);
-    img.enumerate_pixels_mut()
-        .collect::<Vec<(u32, u32, &mut Rgb<u8>)>>()
-        .par_iter_mut()
-        .for_each(|(x, y, pixel)| {
-            let mut pixel_color = (0..scene.spp()).into_iter().fold(Color::zero(), |acc, _| {
-                let [rx, ry, _] = Float3::random().to_array();
-                let u = (*x as f64 + rx) / (w - 1) as f64;
-                let v = ((h - *y - 1) as f64 + ry) / (h - 1) as f64;
-                let ray = camera.ray(u, v);
-                acc + scene.trace(ray)
-            });
-            pixel_color /= scene.spp() as f64;
-            let rgb = pixel_color.gamma(GAMMA_FACTOR).to_rgb();
-            pixel[0] = rgb[0];
-            pixel[1] = rgb[1];
-            pixel[2] = rgb[2];
+    config.install(|| {
+        morton_ordered(img.enumerate_pixels_mut())
+            .par_iter_mut()
+            .for_each(|(x, y, pixel)| {
+                let mut pixel_color = (0..spp).fold(Color::zero(), |acc, _| {
+                    let [rx, ry, _] = Float3::random().to_array();
+                    let u = (*x as f64 + rx) / (w - 1) as f64;
+                    let v = ((h - *y - 1) as f64 + ry) / (h - 1) as f64;
+                    let ray = camera.ray(u, v);
+                    acc + scene.trace(ray)
+                });
+                pixel_color /= spp as f64;
+                **pixel = Rgb(config.color.to_display(pixel_color).to_rgb());
+            })
+    });
+    img.save(&config.output)?;
+    config.show(backup.as_deref(), img)
+}
+
+// sample 番目のサンプルの画素内の位置 ([0, 1) x [0, 1))
+// strata が 1 のときはこれまでどおり一様乱数のみ
+pub fn pixel_offset(sample: usize, strata: u32) -> (f64, f64) {
+    let [rx, ry, _] = Float3::random().to_array();
+    if strata <= 1 {
+        return (rx, ry);
+    }
+    let n = strata as usize;
+    let cell = sample % (n * n);
+    (
+        ((cell % n) as f64 + rx) / n as f64,
+        ((cell / n) as f64 + ry) / n as f64,
+    )
+}
+
+// 画素 (x, y) を描く間 f から使う乱数源を用意する
+// 擬似乱数は pass ごとに別の系列にし、準乱数は同じ系列の続きを使う
+pub fn with_pixel_sampler<R>(
+    kind: SamplerKind,
+    seed: Option<u64>,
+    x: i64,
+    y: i64,
+    pass: u64,
+    f: impl FnOnce() -> R,
+) -> R {
+    match kind {
+        SamplerKind::Random => {
+            if let Some(seed) = seed {
+                reseed(pixel_seed(seed.wrapping_add(pass), x, y));
+            }
+            f()
+        }
+        kind => with_thread_sampler(kind.create(seed.unwrap_or(0)), f),
+    }
+}
+
+// 本描画の sample 番目と同じ乱数で作る画素 (x, y) の一次光線
+// マットや AOV をこれで作れば、シードを決めたときに本描画と同じ位置を通る
+pub fn primary_ray(
+    scene: &impl SceneWithDepth,
+    camera: &dyn Camera,
+    x: u32,
+    y: u32,
+    sample: usize,
+) -> Ray {
+    let (w, h) = (scene.width(), scene.height());
+    let (ix, iy) = (x as i64, y as i64);
+    with_pixel_sampler(scene.sampler(), scene.seed(), ix, iy, sample as u64, || {
+        start_sample(ix, iy, sample as u64);
+        let (rx, ry) = pixel_offset(sample, scene.strata());
+        let u = (x as f64 + rx) / (w - 1) as f64;
+        let v = ((h - 1 - y) as f64 + ry) / (h - 1) as f64;
+        camera.ray(u, v)
+    })
+}
+
+fn crop(buffer: &[Color], stride: u32, x0: u32, y0: u32, w: u32, h: u32) -> Vec<Color> {
+    (y0..y0 + h)
+        .flat_map(|y| {
+            let start = (y * stride + x0) as usize;
+            buffer[start..start + w as usize].iter().copied()
+        })
+        .collect()
+}
+
+// 周囲に overscan 分だけ余分に描いたバッファを返す
+fn render_buffer(scene: &(impl SceneWithDepth + Sync)) -> Result<Vec<Color>, Error> {
+    let (buffer, _) =
+        render_buffer_progressive(scene, &RenderConfig::default(), 0, None, false, |_, _| {
+            PassControl::Continue
+        })?;
+    Ok(buffer)
+}
+
+// TileDone に何 pass 目かを添えたもの
+type TileCallback<'a> = dyn Fn(u64, Tile, &[Color]) + Sync + 'a;
+
+// 途中経過を受け取った側からの指示
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PassControl {
+    Continue,
+    Stop,
+    // 蓄積を捨てて 1 pass 目から描き直す (ウィンドウの R)
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    Restart,
+}
+
+impl From<bool> for PassControl {
+    fn from(running: bool) -> Self {
+        match running {
+            true => Self::Continue,
+            false => Self::Stop,
+        }
+    }
+}
+
+// pass の途中の様子。座標は overscan を含めたバッファ上
+#[derive(Debug, Clone)]
+struct TileBoard {
+    active: Vec<Tile>,
+    done: Vec<Tile>,
+    // 描き終えたタイルだけ、この pass まで足した平均にしたもの
+    mean: Vec<Color>,
+}
+
+impl TileBoard {
+    fn update(&mut self, tile: Tile, event: TileEvent, sum: &[Color], passes: u64, stride: u32) {
+        match event {
+            TileEvent::Started => self.active.push(tile),
+            TileEvent::Done(colors) => {
+                self.active.retain(|&active| active != tile);
+                self.done.push(tile);
+                let (x0, y0, x1, y1) = tile;
+                let tile_w = (x1 - x0) as usize;
+                for (row, y) in colors.chunks(tile_w).zip(y0..y1) {
+                    let start = (y * stride + x0) as usize;
+                    for (i, color) in row.iter().enumerate() {
+                        self.mean[start + i] = (sum[start + i] + *color) / passes as f64;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 1 spp ずつ蓄積し、pass ごとにそこまでの平均を on_pass に渡す
+// on_pass が Stop を返したらその時点の平均を返す
+// show_tiles なら pass を別のスレッドで描き、その間も途中の様子を TileBoard と一緒に渡す
+// (途中で返った Stop や Restart は、その pass を描き終えてから従う)
+// 光源グループの先頭 groups 個の光も同じ経路から別のバッファに集め、pass の平均と一緒に返す
+fn render_buffer_progressive(
+    scene: &(impl SceneWithDepth + Sync),
+    config: &RenderConfig,
+    groups: usize,
+    on_tile: Option<&TileCallback>,
+    show_tiles: bool,
+    mut on_pass: impl FnMut(&[Color], Option<&TileBoard>) -> PassControl,
+) -> Result<(Vec<Color>, Vec<Vec<Color>>), Error> {
+    let (w, h, o, spp) = (scene.width(), scene.height(), scene.overscan(), scene.spp());
+    let stride = w + 2 * o;
+    let mut sum = vec![Color::zero(); (stride * (h + 2 * o)) as usize];
+    let mut mean = sum.clone();
+    let mut group_sums = vec![sum.clone(); groups];
+    let total = spp * tiles_to_render(scene, w, h, o).len();
+    let mut progress = Progress::new(total);
+    let mut passes = 0;
+    while passes < spp {
+        let pass = passes as u64;
+        let board = show_tiles.then(|| {
+            Mutex::new(TileBoard {
+                active: Vec::new(),
+                done: Vec::new(),
+                mean: mean.clone(),
+            })
         });
-    img.save(OUTPUT_FILENAME).unwrap();
-    draw_in_window(BACKUP_FILENAME, img).unwrap();
+        let tick = |tile: Tile, event: TileEvent| {
+            if let Some(board) = &board {
+                let mut board = board.lock().unwrap();
+                board.update(tile, event, &sum, pass + 1, stride);
+            }
+            if let TileEvent::Done(colors) = event {
+                progress.tick();
+                if let Some(on_tile) = on_tile {
+                    on_tile(pass, tile, colors);
+                }
+            }
+        };
+        let render = || {
+            config.install(|| render_buffer_groups(scene, w, h, o, 1, pass, Some(&tick), groups))
+        };
+        let mut control = PassControl::Continue;
+        let (buffer, group_buffers) = match &board {
+            Some(board) => std::thread::scope(|s| {
+                let (finished, wait) = mpsc::channel();
+                let handle = s.spawn(move || {
+                    let buffer = render();
+                    let _ = finished.send(());
+                    buffer
+                });
+                // すぐに描き終える pass では何もしない
+                while let Err(RecvTimeoutError::Timeout) = wait.recv_timeout(TILE_BOARD_INTERVAL) {
+                    let snapshot = board.lock().unwrap().clone();
+                    match on_pass(&snapshot.mean, Some(&snapshot)) {
+                        PassControl::Continue => {}
+                        requested => control = requested,
+                    }
+                }
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            }),
+            None => render(),
+        };
+        passes += 1;
+        for ((sum, mean), color) in sum.iter_mut().zip(mean.iter_mut()).zip(buffer) {
+            *sum += color;
+            *mean = *sum / passes as f64;
+        }
+        for (group_sum, group) in group_sums.iter_mut().zip(group_buffers) {
+            for (sum, color) in group_sum.iter_mut().zip(group) {
+                *sum += color;
+            }
+        }
+        let control = match on_pass(&mean, None) {
+            PassControl::Continue => control,
+            requested => requested,
+        };
+        match control {
+            PassControl::Continue => {}
+            PassControl::Stop => break,
+            PassControl::Restart => {
+                progress.finish();
+                println!("restarted after {} passes", passes);
+                sum.fill(Color::zero());
+                for group_sum in &mut group_sums {
+                    group_sum.fill(Color::zero());
+                }
+                passes = 0;
+                progress = Progress::new(total);
+            }
+        }
+    }
+    progress.finish();
+    if passes < spp {
+        println!("stopped after {} of {} passes", passes, spp);
+    }
+    if let Some(stats) = scene.path_stats() {
+        print!("{}", stats.report());
+        stats.write(PATH_STATS_FILENAME)?;
+    }
+    let group_means = group_sums
+        .into_iter()
+        .map(|sum| sum.into_iter().map(|c| c / passes.max(1) as f64).collect())
+        .collect();
+    Ok((mean, group_means))
 }
 
-pub fn render_aa_with_depth(scene: impl SceneWithDepth + Sync) {
-    backup();
+// タイルを描き始めたときと描き終えたときに、描いたスレッドから知らせる
+#[derive(Clone, Copy)]
+enum TileEvent<'a> {
+    Started,
+    Done(&'a [Color]),
+}
+
+type TileDone<'a> = dyn Fn(Tile, TileEvent) + Sync + 'a;
 
+fn render_buffer_sized(
+    scene: &(impl SceneWithDepth + Sync),
+    w: u32,
+    h: u32,
+    o: u32,
+    spp: usize,
+    pass: u64,
+    on_tile: Option<&TileDone>,
+) -> Vec<Color> {
+    render_buffer_groups(scene, w, h, o, spp, pass, on_tile, 0).0
+}
+
+// render_buffer_sized と、光源グループの先頭 groups 個ごとのバッファ
+#[allow(clippy::too_many_arguments)]
+fn render_buffer_groups(
+    scene: &(impl SceneWithDepth + Sync),
+    w: u32,
+    h: u32,
+    o: u32,
+    spp: usize,
+    pass: u64,
+    on_tile: Option<&TileDone>,
+    groups: usize,
+) -> (Vec<Color>, Vec<Vec<Color>>) {
+    let (full_w, full_h) = (w + 2 * o, h + 2 * o);
+    let tiles = tiles_to_render(scene, w, h, o);
+    let rendered = tiles
+        .par_iter()
+        .map(|&tile| {
+            if let Some(on_tile) = on_tile {
+                on_tile(tile, TileEvent::Started);
+            }
+            let (colors, group_colors) =
+                render_tile_groups(scene, w, h, o, spp, pass, tile, groups);
+            if let Some(on_tile) = on_tile {
+                on_tile(tile, TileEvent::Done(&colors));
+            }
+            (colors, group_colors)
+        })
+        .collect::<Vec<_>>();
+    let mut buffer = vec![Color::zero(); (full_w * full_h) as usize];
+    let mut group_buffers = vec![buffer.clone(); groups];
+    for (tile, (colors, group_colors)) in tiles.into_iter().zip(rendered) {
+        put_tile(&mut buffer, full_w, tile, &colors);
+        for (group_buffer, colors) in group_buffers.iter_mut().zip(group_colors) {
+            put_tile(group_buffer, full_w, tile, &colors);
+        }
+    }
+    (buffer, group_buffers)
+}
+
+// overscan を含めたバッファ上の tile を行ごとに並べて返す
+pub(crate) fn render_tile(
+    scene: &impl SceneWithDepth,
+    w: u32,
+    h: u32,
+    o: u32,
+    spp: usize,
+    pass: u64,
+    tile: Tile,
+) -> Vec<Color> {
+    render_tile_groups(scene, w, h, o, spp, pass, tile, 0).0
+}
+
+// render_tile と、光源グループの先頭 groups 個ごとに同じ画素を並べたもの
+// グループは本描画と同じ経路から分けるので、すべてのグループを足すと本描画になる
+#[allow(clippy::too_many_arguments)]
+fn render_tile_groups(
+    scene: &impl SceneWithDepth,
+    w: u32,
+    h: u32,
+    o: u32,
+    spp: usize,
+    pass: u64,
+    (x0, y0, x1, y1): Tile,
+    groups: usize,
+) -> (Vec<Color>, Vec<Vec<Color>>) {
     let camera = scene.camera();
-    let w = scene.width();
-    let h = scene.height();
-    let mut img = RgbImage::new(w, h);
+    let (strata, sampler) = (scene.strata(), scene.sampler());
+    let (clamp, depth) = (scene.radiance_clamp(), scene.max_depth());
+    let (du, dv) = (1.0 / (w - 1) as f64, 1.0 / (h - 1) as f64);
+    let render_pixel = |x: u32, y: u32| {
+        // 画面外の画素ではカメラの u, v が [0, 1] をはみ出す
+        let (ix, iy) = (x as i64 - o as i64, y as i64 - o as i64);
+        let (x, y) = (ix as f64, iy as f64);
+        let pixel_color = with_pixel_sampler(sampler, scene.seed(), ix, iy, pass, || {
+            (0..spp).fold(GroupRadiance::zero(), |acc, sample| {
+                start_sample(ix, iy, pass * spp as u64 + sample as u64);
+                let (rx, ry) = pixel_offset(pass as usize * spp + sample, strata);
+                let u = (x + rx) / (w - 1) as f64;
+                let v = ((h - 1) as f64 - y + ry) / (h - 1) as f64;
+                let ray = camera.ray_with_differential(u, v, du, dv);
+                let radiance = if groups > 0 {
+                    scene.trace_groups(ray, depth)
+                } else {
+                    GroupRadiance::new(None, scene.trace(ray, depth))
+                };
+                acc + clamp.sample(radiance)
+            })
+        });
+        pixel_color / spp as f64
+    };
+    let pixels = (y0..y1)
+        .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+        .map(|(x, y)| render_pixel(x, y))
+        .collect::<Vec<_>>();
+    let group_colors = (0..groups)
+        .map(|group| pixels.iter().map(|p| p.group(group)).collect())
+        .collect();
+    (pixels.iter().map(|p| p.total).collect(), group_colors)
+}
+
+fn put_tile(buffer: &mut [Color], stride: u32, (x0, y0, x1, y1): Tile, colors: &[Color]) {
+    let tile_w = (x1 - x0) as usize;
+    for (row, y) in colors.chunks(tile_w).zip(y0..y1) {
+        let start = (y * stride + x0) as usize;
+        buffer[start..start + tile_w].copy_from_slice(row);
+    }
+}
+
+// render_buffer_progressive で全 pass を重ねたのと同じ値になるように、
+// 1 spp の pass を順に足してから割る
+fn render_tile_passes(scene: &(impl SceneWithDepth + Sync), tile: Tile) -> Vec<Color> {
+    let (w, h, o, spp) = (scene.width(), scene.height(), scene.overscan(), scene.spp());
+    let passes = (0..spp as u64)
+        .into_par_iter()
+        .map(|pass| render_tile(scene, w, h, o, 1, pass, tile))
+        .collect::<Vec<_>>();
+    let mut sum = vec![Color::zero(); passes[0].len()];
+    for pass in passes {
+        for (sum, color) in sum.iter_mut().zip(pass) {
+            *sum += color;
+        }
+    }
+    sum.into_iter().map(|c| c / spp as f64).collect()
+}
+
+fn job(scene: &impl SceneWithDepth) -> Job {
+    let (w, h, o, spp) = (scene.width(), scene.height(), scene.overscan(), scene.spp());
+    [w, h, o, spp as u32]
+}
+
+// render_buffer_progressive の代わりに、タイルをワーカーに描かせて集める
+// ワーカーが同じシードで描けば、手元で描いたのと同じ画像になる
+fn render_buffer_served(
+    scene: &impl SceneWithDepth,
+    addr: &str,
+    mut on_tile: impl FnMut(&[Color]) -> PassControl,
+) -> Result<Vec<Color>, Error> {
+    let (w, h, o) = (scene.width(), scene.height(), scene.overscan());
+    let (full_w, full_h) = (w + 2 * o, h + 2 * o);
+    let mut buffer = vec![Color::zero(); (full_w * full_h) as usize];
+    let tiles = tiles_to_render(scene, w, h, o);
+    let progress = Progress::new(tiles.len());
+    serve_tiles(addr, job(scene), tiles, |tile, colors| {
+        put_tile(&mut buffer, full_w, tile, &colors);
+        progress.tick();
+        on_tile(&buffer) != PassControl::Stop
+    })?;
+    progress.finish();
+    Ok(buffer)
+}
+
+// addr で待つ配り手からタイルを受け取って描く
+// 配り手と同じシーンと設定 (サイズ, spp, シードなど) で呼ぶ
+pub fn render_worker(
+    scene: impl SceneWithDepth + Sync,
+    config: &RenderConfig,
+    addr: &str,
+) -> Result<(), Error> {
+    let scene = config.apply(&scene);
+    let count = work_tiles(addr, job(&scene), |tile| {
+        config.install(|| render_tile_passes(&scene, tile))
+    })?;
+    println!("rendered {} tiles for {}", count, addr);
+    Ok(())
+}
+
+// 名前ごとに、一次光線がその物体に当たった割合を画素の値にする
+fn render_mattes(scene: &(impl SceneWithDepth + Sync)) -> HashMap<&'static str, GrayImage> {
+    let camera = scene.camera();
+    let (w, h, spp) = (scene.width(), scene.height(), scene.spp());
+    let coverage = (0..w * h)
+        .into_par_iter()
+        .map(|i| {
+            let mut hits = Vec::<(&'static str, usize)>::new();
+            for sample in 0..spp {
+                let ray = primary_ray(scene, &*camera, i % w, i / w, sample);
+                if let Some(name) = scene.matte(&ray) {
+                    match hits.iter_mut().find(|(n, _)| *n == name) {
+                        Some((_, count)) => *count += 1,
+                        None => hits.push((name, 1)),
+                    }
+                }
+            }
+            hits
+        })
+        .collect::<Vec<_>>();
+    let mut mattes = HashMap::new();
+    for (i, hits) in coverage.into_iter().enumerate() {
+        for (name, count) in hits {
+            let matte = mattes.entry(name).or_insert_with(|| GrayImage::new(w, h));
+            let value = (255.99 * count as f64 / spp as f64) as u8;
+            matte.put_pixel(i as u32 % w, i as u32 / w, Luma([value]));
+        }
+    }
+    mattes
+}
+
+// 画素の中心から見えている点を前のフレームのカメラに投影し、
+// 今の位置との差 (画素単位、右と下が正) を R と G に入れる
+// 何にも当たらなければ十分遠くの点を使う
+fn render_motion_vectors(
+    scene: &(impl SceneWithDepth + Sync),
+    previous: &dyn Camera,
+) -> Rgb32FImage {
+    let camera = scene.camera();
+    let (w, h) = (scene.width(), scene.height());
+    let mut img = Rgb32FImage::new(w, h);
     img.enumerate_pixels_mut()
-        .collect::<Vec<(u32, u32, &mut Rgb<u8>)>>()
-        .par_iter_mut()
+        .par_bridge()
         .for_each(|(x, y, pixel)| {
-            let mut pixel_color = (0..scene.spp()).into_iter().fold(Color::zero(), |acc, _| {
-                let [rx, ry, _] = Float3::random().to_array();
-                let u = (*x as f64 + rx) / (w - 1) as f64;
-                let v = ((h - *y - 1) as f64 + ry) / (h - 1) as f64;
-                let ray = camera.ray(u, v);
-                acc + scene.trace(ray, MAX_RAY_BOUNCE_DEPTH)
-            });
-            pixel_color /= scene.spp() as f64;
-            let rgb = pixel_color.gamma(GAMMA_FACTOR).to_rgb();
-            pixel[0] = rgb[0];
-            pixel[1] = rgb[1];
-            pixel[2] = rgb[2];
+            let u = (x as f64 + 0.5) / (w - 1) as f64;
+            let v = ((h - 1 - y) as f64 + 0.5) / (h - 1) as f64;
+            let ray = camera.center_ray(u, v);
+            let p = scene
+                .position(&ray)
+                .unwrap_or_else(|| ray.at(1e6 / ray.direction.length()));
+            if let Some((pu, pv)) = previous.project(p) {
+                let dx = (u - pu) * (w - 1) as f64;
+                let dy = (pv - v) * (h - 1) as f64;
+                *pixel = Rgb([dx as f32, dy as f32, 0.0]);
+            }
         });
-    img.save(OUTPUT_FILENAME).unwrap();
-    draw_in_window(BACKUP_FILENAME, img).unwrap();
+    img
+}
+
+// 縮小した低 spp の下見描画から露出を決める。測光が Off なら 0
+pub fn auto_exposure(scene: &(impl SceneWithDepth + Sync)) -> f64 {
+    let metering = scene.metering();
+    if metering == Metering::Off {
+        return 0.0;
+    }
+    let w = (scene.width() / METERING_DOWNSCALE).max(2);
+    let h = (scene.height() / METERING_DOWNSCALE).max(2);
+    let buffer = render_buffer_sized(scene, w, h, 0, METERING_SAMPLES_PER_PIXEL, 0, None);
+    let exposure = metering.exposure(&buffer, w, h);
+    if let Some(stats) = scene.path_stats() {
+        stats.clear();
+    }
+    exposure
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn render_aa_with_depth(
+    scene: impl SceneWithDepth + Sync,
+    config: &RenderConfig,
+) -> Result<(), Error> {
+    render_aa_with_depth_bracketed(scene, config, &[])
+}
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+pub fn render_aa_with_depth_bracketed(
+    scene: impl SceneWithDepth + Sync,
+    config: &RenderConfig,
+    exposures: &[f64],
+) -> Result<(), Error> {
+    let backup = config.backup()?;
+
+    // 途中経過を表示しながら蓄積し、打ち切ったらそこまでの結果を保存する
+    let scene = config.apply(&scene);
+    let mut window = match config.window {
+        true => Some(PreviewWindow::new(scene.width(), scene.height())?),
+        false => None,
+    };
+    let server = match &config.http {
+        Some(addr) => Some(PreviewServer::start(addr)?),
+        None => None,
+    };
+    // ウィンドウを更新できなくなったら、そこで打ち切ってエラーを返す
+    let mut window_error = None;
+    let mut aborted = false;
+    // クリックするたびに次のサンプルを調べる
+    let mut inspected = 0;
+    let mut inspect = |x: u32, y: u32| {
+        inspect_pixel(&scene, x, y, inspected);
+        inspected += 1;
+    };
+    let mut update = |img: &RgbImage, tiles: Option<(&[Tile], &[Tile])>| {
+        if let Some(server) = &server {
+            server.update(img);
+        }
+        let update = |window: &mut PreviewWindow| match tiles {
+            Some((active, done)) => window.update_with_tiles(img, active, done),
+            None => window.update(img),
+        };
+        match window.as_mut().map(update) {
+            Some(Ok(PreviewAction::Continue)) | None => PassControl::Continue,
+            Some(Ok(PreviewAction::Stop)) => PassControl::Stop,
+            Some(Ok(PreviewAction::Abort)) => {
+                aborted = true;
+                PassControl::Stop
+            }
+            Some(Ok(PreviewAction::Restart)) => PassControl::Restart,
+            Some(Ok(PreviewAction::Save)) => {
+                save_snapshot(config, img);
+                PassControl::Continue
+            }
+            Some(Ok(PreviewAction::Inspect(x, y))) => {
+                inspect(x, y);
+                PassControl::Continue
+            }
+            Some(Err(e)) => {
+                window_error = Some(e);
+                PassControl::Stop
+            }
+        }
+    };
+    let callbacks = RenderCallbacks {
+        image: (config.window || server.is_some()).then_some(&mut update as &mut PreviewCallback),
+        tile: None,
+        show_tiles: config.window,
+    };
+    let (mut img, buffer) = render_image(&scene, config, exposures, callbacks)?;
+    if let Some(e) = window_error {
+        return Err(e.into());
+    }
+    if let Some(backup) = &backup {
+        config.fill_outside_crop(&mut img, backup);
+    }
+    config.save(&img, &buffer)?;
+    if let Some(server) = &server {
+        server.update(&img);
+        server.finish();
+    }
+    match (window, server) {
+        // ESC で打ち切ったら、書き出すだけで閉じる
+        (Some(_), _) if aborted => {}
+        (Some(window), _) => window.wait(backup.as_deref(), inspect)?,
+        // ウィンドウがなければ、結果を見られるように配り続ける
+        (None, Some(server)) => server.wait(),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+// window なしでビルドしたときは、途中経過を --http でだけ見られる
+#[cfg(all(not(feature = "window"), not(target_arch = "wasm32")))]
+pub fn render_aa_with_depth_bracketed(
+    scene: impl SceneWithDepth + Sync,
+    config: &RenderConfig,
+    exposures: &[f64],
+) -> Result<(), Error> {
+    let backup = config.backup()?;
+
+    let scene = config.apply(&scene);
+    let server = match &config.http {
+        Some(addr) => Some(PreviewServer::start(addr)?),
+        None => None,
+    };
+    let mut update = |img: &RgbImage, _: Option<(&[Tile], &[Tile])>| {
+        if let Some(server) = &server {
+            server.update(img);
+        }
+        PassControl::Continue
+    };
+    let callbacks = RenderCallbacks {
+        image: server
+            .is_some()
+            .then_some(&mut update as &mut PreviewCallback),
+        tile: None,
+        show_tiles: false,
+    };
+    let (mut img, buffer) = render_image(&scene, config, exposures, callbacks)?;
+    if let Some(backup) = &backup {
+        config.fill_outside_crop(&mut img, backup);
+    }
+    config.save(&img, &buffer)?;
+    if let Some(server) = &server {
+        server.update(&img);
+        server.finish();
+        server.wait();
+    }
+    Ok(())
+}
+
+// 描画中の途中経過を output の隣に時刻付きで保存する
+// 描画は続けるので、失敗しても知らせるだけにする
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+fn save_snapshot(config: &RenderConfig, img: &RgbImage) {
+    let now = std::time::SystemTime::now();
+    let filename = config.sibling(&format!("_{}", timestamp(now)), "png");
+    match img.save(&filename) {
+        Ok(()) => println!("saved {:?}", filename),
+        Err(e) => eprintln!("{}: {}", filename, e),
+    }
+}
+
+// ウィンドウを出さずに config.output へ書き出す (バッチ描画用)
+pub fn render_aa_with_depth_to_file(
+    scene: impl SceneWithDepth + Sync,
+    config: &RenderConfig,
+) -> Result<(), Error> {
+    let callbacks = RenderCallbacks {
+        image: None,
+        tile: None,
+        show_tiles: false,
+    };
+    let (img, buffer) = render_image(&config.apply(&scene), config, &[], callbacks)?;
+    config.save(&img, &buffer)
+}
+
+// render_with_callback で知らせること
+pub enum RenderEvent<'a> {
+    // pass 回目のあるタイルを描き終えた。pixels はその pass の 1 spp 分を行ごとに並べたもの
+    // tile は overscan を含めたバッファ上の位置。描いたスレッドから呼ばれる (--serve では来ない)
+    Tile {
+        pass: usize,
+        tile: Tile,
+        pixels: &'a [Color],
+    },
+    // pass を 1 つ終えるごと (とノイズ除去の後) の表示用の画像
+    Image(&'a RgbImage),
+    // 最後に書き出すはずの画像とその線形の値
+    Finished {
+        image: &'a RgbImage,
+        buffer: &'a [Color],
+    },
+}
+
+// ウィンドウの代わりに途中経過を callback に渡しながら描き、結果を返す
+// callback が false を返したら、その pass で打ち切る
+// config.output には書き出さない (マットや AOV など、頼まれた別ファイルは書き出す)
+pub fn render_with_callback<F>(
+    scene: impl SceneWithDepth + Sync,
+    config: &RenderConfig,
+    callback: F,
+) -> Result<(RgbImage, Vec<Color>), Error>
+where
+    F: FnMut(RenderEvent) -> bool + Send,
+{
+    let scene = config.apply(&scene);
+    let callback = Mutex::new(callback);
+    let stopped = AtomicBool::new(false);
+    let on_tile = |pass: u64, tile: Tile, pixels: &[Color]| {
+        let pass = pass as usize;
+        if !(callback.lock().unwrap())(RenderEvent::Tile { pass, tile, pixels }) {
+            stopped.store(true, Ordering::Relaxed);
+        }
+    };
+    let mut on_image = |img: &RgbImage, _: Option<(&[Tile], &[Tile])>| {
+        let running = (callback.lock().unwrap())(RenderEvent::Image(img));
+        PassControl::from(running && !stopped.load(Ordering::Relaxed))
+    };
+    let callbacks = RenderCallbacks {
+        image: Some(&mut on_image),
+        tile: Some(&on_tile),
+        show_tiles: false,
+    };
+    let (img, buffer) = render_image(&scene, config, &[], callbacks)?;
+    let mut callback = callback.into_inner().unwrap();
+    callback(RenderEvent::Finished {
+        image: &img,
+        buffer: &buffer,
+    });
+    Ok((img, buffer))
+}
+
+// 表示用の画像と、pass の途中なら描いているタイルと描き終えたタイル (画像上の座標)
+type PreviewCallback<'a> = dyn FnMut(&RgbImage, Option<(&[Tile], &[Tile])>) -> PassControl + 'a;
+
+// render_image の途中経過の受け取り先
+struct RenderCallbacks<'a> {
+    // pass ごとの表示用の画像。Stop が返ったら打ち切る
+    image: Option<&'a mut PreviewCallback<'a>>,
+    tile: Option<&'a TileCallback<'a>>,
+    // pass の途中でもタイルの様子と一緒に image に渡す
+    show_tiles: bool,
+}
+
+fn render_image(
+    scene: &(impl SceneWithDepth + Sync),
+    output: &RenderConfig,
+    exposures: &[f64],
+    callbacks: RenderCallbacks,
+) -> Result<(RgbImage, Vec<Color>), Error> {
+    let (w, h, o) = (scene.width(), scene.height(), scene.overscan());
+    // 可視化の値はトーンマップもガンマもかけずにそのまま画素にする
+    let raw = ColorConfig {
+        display: DisplayTransform::Gamma(1.0),
+        ..ColorConfig::default()
+    };
+    let config = match output.mode {
+        Some(_) => &raw,
+        None => &output.color,
+    };
+    let base = output.exposure(scene);
+    let RenderCallbacks {
+        image: mut preview,
+        tile: on_tile,
+        show_tiles,
+    } = callbacks;
+    // overscan の分をずらして画像からはみ出した部分を切る
+    let to_image_tile = |&(x0, y0, x1, y1): &Tile| {
+        let clamp = |v: u32, max: u32| v.saturating_sub(o).min(max);
+        (clamp(x0, w), clamp(y0, h), clamp(x1, w), clamp(y1, h))
+    };
+    let mut on_update = |mean: &[Color], board: Option<&TileBoard>| {
+        let Some(update) = preview.as_deref_mut() else {
+            return PassControl::Continue;
+        };
+        let img = to_image(&crop(mean, w + 2 * o, o, o, w, h), w, h, base, config);
+        match board {
+            Some(board) => {
+                let active = board.active.iter().map(to_image_tile).collect::<Vec<_>>();
+                let done = board.done.iter().map(to_image_tile).collect::<Vec<_>>();
+                update(&img, Some((&active, &done)))
+            }
+            None => update(&img, None),
+        }
+    };
+    // 光源グループは本描画の経路から分けて集める
+    // ワーカーはグループを返さず、偏光フィルタを通した光はグループに分けない
+    let mut groups = scene.light_groups();
+    if !groups.is_empty() && (output.serve.is_some() || scene.polarizer().is_some()) {
+        eprintln!("light groups are not written with --serve or --polarizer");
+        groups.clear();
+    }
+    let (buffer, light) = match &output.serve {
+        Some(addr) => (
+            render_buffer_served(scene, addr, |mean| on_update(mean, None))?,
+            Vec::new(),
+        ),
+        None => {
+            render_buffer_progressive(scene, output, groups.len(), on_tile, show_tiles, on_update)?
+        }
+    };
+    let buffer = match output.mode {
+        Some(mode) => mode.normalize(buffer),
+        None => buffer,
+    };
+    let buffer = if o > 0 {
+        to_image(&buffer, w + 2 * o, h + 2 * o, base, config)
+            .save(output.sibling("_overscan", "png"))?;
+        crop(&buffer, w + 2 * o, o, o, w, h)
+    } else {
+        buffer
+    };
+    for (name, matte) in output.install(|| render_mattes(scene)) {
+        matte.save(matte_filename(output, name))?;
+    }
+    for (group, light) in groups.iter().zip(light) {
+        let light = crop(&light, w + 2 * o, o, o, w, h);
+        save_linear(&light, w, h, &light_group_filename(output, group))?;
+    }
+    let buffer = if !output.aovs.is_empty() || output.denoise.is_some() {
+        let aovs = output.install(|| AovBuffers::render(scene));
+        for pass in &output.aovs {
+            aovs.save(*pass, &aov_filename(output, *pass))?;
+        }
+        match output.denoise {
+            Some(denoiser) => {
+                let denoised = output.install(|| denoiser.denoise(&buffer, &aovs));
+                if let Some(update) = preview {
+                    update(&to_image(&denoised, w, h, base, config), None);
+                }
+                denoised
+            }
+            None => buffer,
+        }
+    } else {
+        buffer
+    };
+    // 同じ蓄積バッファから露出だけを変えて書き出す
+    for exposure in exposures {
+        to_image(&buffer, w, h, base + *exposure, config)
+            .save(bracket_filename(output, *exposure))?;
+    }
+    Ok((to_image(&buffer, w, h, base, config), buffer))
+}
+
+// 1 spp ずつ蓄積しながら表示し、パネルで値が変わったら蓄積をやり直す
+// パネルの上には描画の設定を並べ、その下に panel (マテリアルの調整など) を出す
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+pub fn render_look_dev<P>(
+    scene: impl WorldScene + Sync,
+    color: &ColorConfig,
+    mut panel: P,
+) -> Result<(), Error>
+where
+    P: FnMut(&mut egui::Ui) -> bool,
+{
+    let (w, h) = (scene.width(), scene.height());
+    let exposure = auto_exposure(&scene);
+    // 描く側とパネルの両方から触るので Cell に入れる
+    let settings = Cell::new(LookDevSettings {
+        spp: scene.spp(),
+        max_depth: scene.max_depth(),
+        exposure: color.exposure,
+        gamma: None,
+        background: LookDevBackground::Scene,
+    });
+    let mut sum = vec![Color::zero(); (w * h) as usize];
+    let mut passes = 0;
+    draw_look_dev(
+        w,
+        h,
+        |restart| {
+            let current = settings.get();
+            if restart {
+                sum.fill(Color::zero());
+                passes = 0;
+            }
+            // 目標の spp に届いたら、描かずに表示だけを作り直す
+            if passes < current.spp {
+                let view = BackgroundOverride {
+                    scene: &scene,
+                    background: current.background.build(),
+                };
+                let view = SceneOverride::new(&view).with_max_depth(current.max_depth);
+                let buffer = render_buffer_sized(&view, w, h, 0, 1, passes as u64, None);
+                for (sum, color) in sum.iter_mut().zip(buffer) {
+                    *sum += color;
+                }
+                passes += 1;
+            }
+            let color = ColorConfig {
+                exposure: current.exposure,
+                display: current.gamma.map_or(color.display, DisplayTransform::Gamma),
+                ..color.clone()
+            };
+            let mean = sum.iter().map(|c| *c / passes as f64).collect::<Vec<_>>();
+            to_image(&mean, w, h, exposure, &color)
+        },
+        |ui| {
+            let mut current = settings.get();
+            let restart = current.ui(ui);
+            settings.set(current);
+            panel(ui) || restart
+        },
+    )?;
+    Ok(())
+}
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LookDevSettings {
+    spp: usize,
+    max_depth: usize,
+    // 自動露出に足す EV
+    exposure: f64,
+    // None なら color の表示変換のまま
+    gamma: Option<f64>,
+    background: LookDevBackground,
+}
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+impl LookDevSettings {
+    // 蓄積をやり直す必要がある値が変わったら true
+    // 露出とガンマは表示を作り直すだけなので false のまま
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let before = *self;
+        egui::CollapsingHeader::new("render")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.add(
+                    egui::Slider::new(&mut self.spp, 1..=4096)
+                        .logarithmic(true)
+                        .text("samples"),
+                );
+                ui.add(egui::Slider::new(&mut self.max_depth, 1..=100).text("max depth"));
+                ui.add(egui::Slider::new(&mut self.exposure, -5.0..=5.0).text("exposure"));
+                let mut gamma = self.gamma.is_some();
+                ui.checkbox(&mut gamma, "gamma");
+                self.gamma = match (gamma, self.gamma) {
+                    (true, current) => {
+                        let mut value = current.unwrap_or(2.2);
+                        ui.add(egui::Slider::new(&mut value, 1.0..=3.0).text("gamma"));
+                        Some(value)
+                    }
+                    (false, _) => None,
+                };
+                egui::ComboBox::from_label("background")
+                    .selected_text(self.background.label())
+                    .show_ui(ui, |ui| {
+                        for choice in LookDevBackground::ALL {
+                            ui.selectable_value(&mut self.background, choice, choice.label());
+                        }
+                    });
+            });
+        self.max_depth != before.max_depth || self.background != before.background
+    }
+}
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LookDevBackground {
+    // シーンに設定してある背景
+    Scene,
+    Black,
+    White,
+    Sky,
+    // 斜め上の太陽と物理ベースの空
+    Sun,
+}
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+impl LookDevBackground {
+    const ALL: [Self; 5] = [Self::Scene, Self::Black, Self::White, Self::Sky, Self::Sun];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Scene => "scene",
+            Self::Black => "black",
+            Self::White => "white",
+            Self::Sky => "sky",
+            Self::Sun => "sun and sky",
+        }
+    }
+
+    fn build(self) -> Option<Box<dyn Background>> {
+        match self {
+            Self::Scene => None,
+            Self::Black => Some(Box::new(ColorBackground::new(Color::zero()))),
+            Self::White => Some(Box::new(ColorBackground::new(Color::one()))),
+            Self::Sky => Some(Box::new(GradientBackground::new(
+                Color::one(),
+                Color::new(0.5, 0.7, 1.0),
+            ))),
+            Self::Sun => Some(Box::new(PhysicalSky::new(
+                Vec3::new(1.0, 1.0, 0.5),
+                3.0,
+                1.0,
+            ))),
+        }
+    }
+}
+
+// 背景だけを差し替えた scene。background が None ならシーンのまま
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+struct BackgroundOverride<'a, S> {
+    scene: &'a S,
+    background: Option<Box<dyn Background>>,
+}
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+impl<S: WorldScene> WorldScene for BackgroundOverride<'_, S> {
+    fn world(&self) -> &ShapeList {
+        self.scene.world()
+    }
+    fn background(&self) -> &dyn Background {
+        match &self.background {
+            Some(background) => &**background,
+            None => self.scene.background(),
+        }
+    }
+    fn photon_map(&self) -> Option<&PhotonMap> {
+        self.scene.photon_map()
+    }
+    fn lights(&self) -> &[Box<dyn Light>] {
+        self.scene.lights()
+    }
+    fn fog(&self) -> Option<&Fog> {
+        self.scene.fog()
+    }
+    fn colored_shadows(&self) -> bool {
+        self.scene.colored_shadows()
+    }
+}
+
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+impl<S: WorldScene> SceneWithDepth for BackgroundOverride<'_, S> {
+    fn camera(&self) -> Box<dyn Camera> {
+        self.scene.camera()
+    }
+    fn trace(&self, ray: Ray, depth: usize) -> Color {
+        trace_scene(self, ray, depth)
+    }
+    fn width(&self) -> u32 {
+        self.scene.width()
+    }
+    fn height(&self) -> u32 {
+        self.scene.height()
+    }
+    fn spp(&self) -> usize {
+        self.scene.spp()
+    }
+    fn aspect(&self) -> f64 {
+        self.scene.aspect()
+    }
+    fn overscan(&self) -> u32 {
+        self.scene.overscan()
+    }
+    fn strata(&self) -> u32 {
+        self.scene.strata()
+    }
+    fn max_depth(&self) -> usize {
+        self.scene.max_depth()
+    }
+    fn crop(&self) -> Option<Tile> {
+        self.scene.crop()
+    }
+    fn sampler(&self) -> SamplerKind {
+        self.scene.sampler()
+    }
+    fn seed(&self) -> Option<u64> {
+        self.scene.seed()
+    }
+    fn polarizer(&self) -> Option<f64> {
+        self.scene.polarizer()
+    }
+    fn matte(&self, ray: &Ray) -> Option<&'static str> {
+        self.scene.matte(ray)
+    }
+    fn position(&self, ray: &Ray) -> Option<Point3> {
+        self.scene.position(ray)
+    }
+    fn aov(&self, ray: &Ray) -> Option<Aov> {
+        self.scene.aov(ray)
+    }
+    fn metering(&self) -> Metering {
+        self.scene.metering()
+    }
+    fn caustics(&self) -> CausticSettings {
+        self.scene.caustics()
+    }
+    fn radiance_clamp(&self) -> RadianceClamp {
+        self.scene.radiance_clamp()
+    }
+    fn path_stats(&self) -> Option<&PathStats> {
+        self.scene.path_stats()
+    }
+    fn record_path(&self, depth: usize, end: PathEnd) {
+        self.scene.record_path(depth, end);
+    }
+}
+
+// 1 spp ずつ蓄積しながら表示し、カメラを動かしたら蓄積をやり直す
+// 閉じるとそのときのカメラをシーンファイルの形で表示し、ENTER で閉じたときはそのカメラで本描画する
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+pub fn render_fly_through(
+    scene: impl SceneWithDepth + Sync,
+    config: &RenderConfig,
+) -> Result<(), Error> {
+    let look = scene
+        .camera()
+        .look_at()
+        .ok_or_else(|| Error::Invalid("fly-through needs a perspective camera".to_string()))?;
+    let (w, h) = (scene.width(), scene.height());
+    let aspect = scene.aspect();
+    let exposure = auto_exposure(&scene);
+    let mut fly = FlyCamera::new(look);
+    let mut sum = vec![Color::zero(); (w * h) as usize];
+    let mut passes = 0;
+    let accepted = draw_fly_through(w, h, |input| {
+        if !input.is_idle() {
+            fly.fly(
+                input.right,
+                input.up,
+                input.forward,
+                input.drag_x * FLY_LOOK_SPEED,
+                -input.drag_y * FLY_LOOK_SPEED,
+            );
+            sum.fill(Color::zero());
+            passes = 0;
+        }
+        let framed = SceneOverride::new(&scene).with_camera(Arc::new(fly.camera(aspect)));
+        let buffer = render_buffer_sized(&framed, w, h, 0, 1, passes, None);
+        for (sum, color) in sum.iter_mut().zip(buffer) {
+            *sum += color;
+        }
+        passes += 1;
+        let mean = sum.iter().map(|c| *c / passes as f64).collect::<Vec<_>>();
+        to_image(&mean, w, h, exposure, &config.color)
+    })?;
+    let section = CameraSection::from_look_at(&fly.look_at());
+    let text = super::scene_file::to_toml(&section).map_err(Error::Invalid)?;
+    println!("[camera]\n{}", text);
+    if !accepted {
+        return Ok(());
+    }
+    let framed = SceneOverride::new(&scene).with_camera(Arc::new(fly.camera(aspect)));
+    render_aa_with_depth(framed, config)
+}
+
+// ドラッグした 1 画素あたりに向きを変える角度 (ラジアン)
+#[cfg(all(feature = "window", not(target_arch = "wasm32")))]
+const FLY_LOOK_SPEED: f64 = 0.005;
+
+// 一部の設定だけを差し替えた scene
+// 大きさを変えてもカメラは元の scene の縦横比のまま
+struct SceneOverride<'a, S> {
+    scene: &'a S,
+    camera: Option<Arc<dyn Camera>>,
+    size: Option<(u32, u32)>,
+    spp: Option<usize>,
+    max_depth: Option<usize>,
+    crop: Option<Tile>,
+    mode: Option<DebugMode>,
+    metering: Option<Metering>,
+}
+
+impl<'a, S: SceneWithDepth> SceneOverride<'a, S> {
+    fn new(scene: &'a S) -> Self {
+        Self {
+            scene,
+            camera: None,
+            size: None,
+            spp: None,
+            max_depth: None,
+            crop: None,
+            mode: None,
+            metering: None,
+        }
+    }
+
+    fn with_camera(self, camera: Arc<dyn Camera>) -> Self {
+        Self {
+            camera: Some(camera),
+            ..self
+        }
+    }
+
+    fn with_size(self, width: u32, height: u32) -> Self {
+        Self {
+            size: Some((width, height)),
+            ..self
+        }
+    }
+
+    fn with_spp(self, spp: usize) -> Self {
+        Self {
+            spp: Some(spp),
+            ..self
+        }
+    }
+
+    fn with_max_depth(self, max_depth: usize) -> Self {
+        Self {
+            max_depth: Some(max_depth),
+            ..self
+        }
+    }
+
+    fn with_crop(self, crop: Tile) -> Self {
+        Self {
+            crop: Some(crop),
+            ..self
+        }
+    }
+
+    fn with_mode(self, mode: DebugMode) -> Self {
+        Self {
+            mode: Some(mode),
+            ..self
+        }
+    }
+
+    fn with_metering(self, metering: Metering) -> Self {
+        Self {
+            metering: Some(metering),
+            ..self
+        }
+    }
+}
+
+impl<S: SceneWithDepth> SceneWithDepth for SceneOverride<'_, S> {
+    fn camera(&self) -> Box<dyn Camera> {
+        match &self.camera {
+            Some(camera) => Box::new(Arc::clone(camera)),
+            None => self.scene.camera(),
+        }
+    }
+    fn trace(&self, ray: Ray, depth: usize) -> Color {
+        match self.mode {
+            Some(mode) => mode.trace(self.scene, ray, depth),
+            None => self.scene.trace(ray, depth),
+        }
+    }
+    fn width(&self) -> u32 {
+        self.size.map_or(self.scene.width(), |(w, _)| w)
+    }
+    fn height(&self) -> u32 {
+        self.size.map_or(self.scene.height(), |(_, h)| h)
+    }
+    fn spp(&self) -> usize {
+        self.spp.unwrap_or(self.scene.spp())
+    }
+    fn aspect(&self) -> f64 {
+        self.scene.aspect()
+    }
+    fn strata(&self) -> u32 {
+        self.scene.strata()
+    }
+    fn sampler(&self) -> SamplerKind {
+        self.scene.sampler()
+    }
+    fn max_depth(&self) -> usize {
+        self.max_depth.unwrap_or(self.scene.max_depth())
+    }
+    fn crop(&self) -> Option<Tile> {
+        self.crop.or(self.scene.crop())
+    }
+    // 大きさを変えても縦の画角は変わらないので、縁の画素数は高さに比例させる
+    fn overscan(&self) -> u32 {
+        match self.size {
+            Some((_, h)) => {
+                let scale = h as f64 / self.scene.height() as f64;
+                (self.scene.overscan() as f64 * scale).ceil() as u32
+            }
+            None => self.scene.overscan(),
+        }
+    }
+    fn seed(&self) -> Option<u64> {
+        self.scene.seed()
+    }
+    fn polarizer(&self) -> Option<f64> {
+        self.scene.polarizer()
+    }
+    fn matte(&self, ray: &Ray) -> Option<&'static str> {
+        self.scene.matte(ray)
+    }
+    fn position(&self, ray: &Ray) -> Option<Point3> {
+        self.scene.position(ray)
+    }
+    fn aov(&self, ray: &Ray) -> Option<Aov> {
+        self.scene.aov(ray)
+    }
+    // 可視化では光源グループごとの画像は書き出さない
+    fn light_groups(&self) -> Vec<String> {
+        match self.mode {
+            Some(_) => Vec::new(),
+            None => self.scene.light_groups(),
+        }
+    }
+    fn trace_groups(&self, ray: Ray, depth: usize) -> GroupRadiance {
+        match self.mode {
+            Some(_) => GroupRadiance::new(None, self.trace(ray, depth)),
+            None => self.scene.trace_groups(ray, depth),
+        }
+    }
+    fn metering(&self) -> Metering {
+        match self.mode {
+            Some(_) => Metering::Off,
+            None => self.metering.unwrap_or_else(|| self.scene.metering()),
+        }
+    }
+    fn caustics(&self) -> CausticSettings {
+        self.scene.caustics()
+    }
+    fn radiance_clamp(&self) -> RadianceClamp {
+        self.scene.radiance_clamp()
+    }
+    fn path_stats(&self) -> Option<&PathStats> {
+        match self.mode {
+            Some(_) => None,
+            None => self.scene.path_stats(),
+        }
+    }
+}
+
+// path に沿って frames 枚を render_0000.png から順に書き出す
+// 前のフレームからの動きベクトルを render_0000_motion.exr に書き出す
+// 露出は最初のフレームで決めて固定し、フレーム間でちらつかないようにする
+// 出力先が .gif や .mp4 なら、最後に連番をまとめて動画にする
+pub fn render_camera_path(
+    scene: impl SceneWithDepth + Sync,
+    path: &CameraPath,
+    frames: usize,
+    output: &RenderConfig,
+) -> Result<(), Error> {
+    let scene = output.apply(&scene);
+    let (w, h, o) = (scene.width(), scene.height(), scene.overscan());
+    let config = &output.color;
+    let aspect = scene.aspect();
+    let exposure =
+        auto_exposure(&SceneOverride::new(&scene).with_camera(Arc::new(path.camera(0.0, aspect))));
+    let animation = AnimationFormat::from_path(&output.output);
+    let mut images = Vec::new();
+    for frame in 0..frames {
+        let framed = SceneOverride::new(&scene).with_camera(Arc::new(
+            path.camera(path.frame_time(frame, frames), aspect),
+        ));
+        let buffer = output.install(|| render_buffer(&framed))?;
+        let buffer = if o > 0 {
+            crop(&buffer, w + 2 * o, o, o, w, h)
+        } else {
+            buffer
+        };
+        let filename = frame_filename(output, frame);
+        let img = to_image(&buffer, w, h, exposure, config);
+        img.save(&filename)?;
+        if animation == Some(AnimationFormat::Gif) {
+            images.push(img);
+        }
+        // 最初のフレームは前がないので動きなしとする
+        let previous = match frame {
+            0 => framed.camera(),
+            _ => Box::new(path.camera(path.frame_time(frame - 1, frames), aspect)),
+        };
+        render_motion_vectors(&framed, &*previous).save(motion_filename(output, frame))?;
+        println!("frame {}/{} -> {}", frame + 1, frames, filename);
+    }
+    match animation {
+        Some(AnimationFormat::Gif) => encode_gif(&images, output.fps, &output.output)?,
+        Some(AnimationFormat::Video) => {
+            let pattern = output.sibling("_%04d", "png");
+            encode_video(&pattern, output.fps, &output.output)
+                .map_err(|e| Error::File(format!("{}: ffmpeg: {}", output.output, e)))?
+        }
+        None => return Ok(()),
+    }
+    println!("{} frames -> {}", frames, output.output);
+    Ok(())
+}
+
+// 高さ height に縮めて描く (一覧用なので spp も抑える)
+pub fn render_thumbnail(
+    scene: &(impl SceneWithDepth + Sync),
+    height: u32,
+    color: &ColorConfig,
+) -> RgbImage {
+    let width = ((height as f64 * scene.aspect()).round() as u32).max(2);
+    let thumbnail = SceneOverride::new(scene)
+        .with_size(width, height.max(2))
+        .with_spp(scene.spp().min(THUMBNAIL_SAMPLES_PER_PIXEL));
+    let exposure = auto_exposure(&thumbnail);
+    let buffer = render_buffer_sized(
+        &thumbnail,
+        width,
+        height.max(2),
+        0,
+        thumbnail.spp(),
+        0,
+        None,
+    );
+    to_image(&buffer, width, height.max(2), exposure, color)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StereoLayout {
+    // 左目を左半分、右目を右半分に並べた 1 枚
+    SideBySide,
+    // 左右を別のファイルに書き出す
+    Separate,
+}
+
+// ipd は両目の間隔、convergence は左右の像が重なる距離 (どちらもワールド座標)
+#[derive(Debug, Clone, Copy)]
+pub struct Stereo {
+    pub ipd: f64,
+    pub convergence: f64,
+    pub layout: StereoLayout,
+}
+
+// 露出は中央のカメラで決めて両目で揃える
+pub fn render_stereo(
+    scene: impl SceneWithDepth + Sync,
+    stereo: Stereo,
+    output: &RenderConfig,
+) -> Result<(), Error> {
+    let scene = output.apply(&scene);
+    let (w, h, o) = (scene.width(), scene.height(), scene.overscan());
+    let config = &output.color;
+    let exposure = output.exposure(&scene);
+    let camera = scene.camera();
+    let [left, right] = [-0.5, 0.5].map(|side| {
+        let eye = camera
+            .stereo_eye(side * stereo.ipd, stereo.convergence)
+            .ok_or_else(|| Error::Invalid("stereo rendering needs a perspective camera".into()))?;
+        let eye_scene = SceneOverride::new(&scene).with_camera(Arc::from(eye));
+        let buffer = output.install(|| render_buffer(&eye_scene))?;
+        let buffer = if o > 0 {
+            crop(&buffer, w + 2 * o, o, o, w, h)
+        } else {
+            buffer
+        };
+        Ok::<_, Error>(to_image(&buffer, w, h, exposure, config))
+    });
+    let (left, right) = (left?, right?);
+    match stereo.layout {
+        StereoLayout::SideBySide => {
+            let backup = output.backup()?;
+            let mut img = RgbImage::new(2 * w, h);
+            image::imageops::replace(&mut img, &left, 0, 0);
+            image::imageops::replace(&mut img, &right, w as i64, 0);
+            img.save(&output.output)?;
+            output.show(backup.as_deref(), img)
+        }
+        StereoLayout::Separate => {
+            left.save(output.sibling("_left", "png"))?;
+            right.save(output.sibling("_right", "png"))?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 200 x 100 に 8 画素の縁を付けて描く scene
+    struct Framed;
+
+    impl SceneWithDepth for Framed {
+        fn camera(&self) -> Box<dyn Camera> {
+            Box::new(PerspectiveCamera::new(
+                Vec3::new(4.0, 0.0, 0.0),
+                Vec3::new(0.0, 2.0, 0.0),
+                Point3::new(-2.0, -1.0, -1.0),
+            ))
+        }
+        fn trace(&self, _ray: Ray, _depth: usize) -> Color {
+            Color::zero()
+        }
+        fn width(&self) -> u32 {
+            200
+        }
+        fn height(&self) -> u32 {
+            100
+        }
+        fn overscan(&self) -> u32 {
+            8
+        }
+    }
+
+    fn resized(width: Option<u32>, height: Option<u32>) -> u32 {
+        let config = RenderConfig {
+            width,
+            height,
+            ..RenderConfig::default()
+        };
+        config.apply(&Framed).overscan()
+    }
+
+    #[test]
+    fn overscan_scales_with_the_overridden_size() {
+        assert_eq!(resized(None, None), 8);
+        assert_eq!(resized(Some(400), None), 16);
+        assert_eq!(resized(None, Some(50)), 4);
+        // 横だけ広げても縦の画角は同じなので縁も同じ
+        assert_eq!(resized(Some(300), Some(100)), 8);
+        // 小さくしても縁は残す
+        assert_eq!(resized(None, Some(10)), 1);
+    }
+
+    #[test]
+    fn metering_is_off_unless_requested() {
+        assert_eq!(Framed.metering(), Metering::Off);
+        assert_eq!(
+            RenderConfig::default().apply(&Framed).metering(),
+            Metering::Off
+        );
+        let config = RenderConfig {
+            metering: Some(Metering::Median),
+            ..RenderConfig::default()
+        };
+        assert_eq!(config.apply(&Framed).metering(), Metering::Median);
+        // デバッグ表示では測らない
+        let config = RenderConfig {
+            metering: Some(Metering::Matrix),
+            mode: Some(DebugMode::Normal),
+            ..RenderConfig::default()
+        };
+        assert_eq!(config.apply(&Framed).metering(), Metering::Off);
+    }
+
+    #[test]
+    fn metered_exposure_is_used_without_metering_again() {
+        let config = RenderConfig {
+            metering: Some(Metering::Median),
+            metered_exposure: Some(1.5),
+            ..RenderConfig::default()
+        };
+        assert_eq!(config.exposure(&config.apply(&Framed)), 1.5);
+        assert_eq!(auto_exposure(&Framed), 0.0);
+    }
 }
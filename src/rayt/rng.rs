@@ -0,0 +1,45 @@
+use crate::rayt::{RandomSampler, Sampler};
+use std::cell::RefCell;
+
+thread_local! {
+    static SAMPLER: RefCell<Box<dyn Sampler>> = RefCell::new(Box::new(RandomSampler::from_entropy()));
+}
+
+pub fn random_f64() -> f64 {
+    SAMPLER.with(|sampler| sampler.borrow_mut().next_f64())
+}
+
+// N 個をまとめて引く。random_f64 を N 回呼ぶのと同じ値になるが、thread_local を引くのは 1 回で済む
+pub fn random_array<const N: usize>() -> [f64; N] {
+    SAMPLER.with(|sampler| {
+        let mut sampler = sampler.borrow_mut();
+        std::array::from_fn(|_| sampler.next_f64())
+    })
+}
+
+pub fn reseed(seed: u64) {
+    SAMPLER.with(|sampler| *sampler.borrow_mut() = Box::new(RandomSampler::new(seed)));
+}
+
+// 画素 (x, y) の index 番目のサンプルを始める
+pub fn start_sample(x: i64, y: i64, index: u64) {
+    SAMPLER.with(|sampler| sampler.borrow_mut().start_sample(x, y, index));
+}
+
+// f を呼ぶ間だけ sampler を使い、終わったら元に戻す
+pub fn with_thread_sampler<R>(sampler: Box<dyn Sampler>, f: impl FnOnce() -> R) -> R {
+    let previous = SAMPLER.with(|s| s.replace(sampler));
+    let result = f();
+    SAMPLER.with(|s| *s.borrow_mut() = previous);
+    result
+}
+
+// splitmix64 で画素ごとに独立した系列を作る
+pub fn pixel_seed(seed: u64, x: i64, y: i64) -> u64 {
+    let mut z = seed
+        ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,518 @@
+use crate::rayt::*;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::OnceLock;
+
+// random_f64 が呼ばれるたびに次の次元の値を返す
+// 準モンテカルロ法では画素とサンプル番号ごとに start_sample で系列の位置を決める
+pub trait Sampler {
+    fn start_sample(&mut self, x: i64, y: i64, index: u64);
+    fn next_f64(&mut self) -> f64;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplerKind {
+    #[default]
+    Random,
+    Pcg,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub fn create(self, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Random => Box::new(RandomSampler::new(seed)),
+            SamplerKind::Pcg => Box::new(PcgSampler::new(seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
+
+// 2^-32 倍して [0, 1) にする
+fn to_unit(x: u32) -> f64 {
+    x as f64 * (1.0 / 4294967296.0)
+}
+
+// start_sample を無視して系列を流し続ける (reseed で系列を選ぶ)
+pub struct RandomSampler {
+    rng: StdRng,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn from_entropy() -> Self {
+        Self {
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn start_sample(&mut self, _x: i64, _y: i64, _index: u64) {}
+
+    fn next_f64(&mut self) -> f64 {
+        self.rng.gen()
+    }
+}
+
+// PCG32 (O'Neill 2014)。サンプルごとに独立した系列を使う
+pub struct PcgSampler {
+    seed: u64,
+    state: u64,
+    inc: u64,
+}
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+impl PcgSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: 0,
+            inc: 1,
+        }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+}
+
+impl Sampler for PcgSampler {
+    fn start_sample(&mut self, x: i64, y: i64, index: u64) {
+        let stream = pixel_seed(self.seed, x, y);
+        self.inc = (stream << 1) | 1;
+        self.state = 0;
+        self.next_u32();
+        self.state = self.state.wrapping_add(pixel_seed(stream, index as i64, 0));
+        self.next_u32();
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        // 上位 53 ビットを使う
+        let hi = (self.next_u32() as u64) << 21;
+        let lo = (self.next_u32() >> 11) as u64;
+        (hi | lo) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+}
+
+const HALTON_PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+fn radical_inverse(base: u32, mut index: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let (mut result, mut scale) = (0.0, inv_base);
+    while index > 0 {
+        result += (index % base as u64) as f64 * scale;
+        index /= base as u64;
+        scale *= inv_base;
+    }
+    result
+}
+
+// 画素ごとに次元ごとの乱数だけずらす (Cranley-Patterson 回転)
+// 用意した素数を使い切った次元はハッシュ値を返す
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: i64, y: i64, index: u64) {
+        self.pixel = pixel_seed(self.seed, x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        let d = self.dimension;
+        self.dimension += 1;
+        let hash = pixel_seed(self.pixel, d as i64, 0);
+        match HALTON_PRIMES.get(d) {
+            Some(&base) => {
+                let shift = to_unit((hash >> 32) as u32);
+                (radical_inverse(base, self.index) + shift).fract()
+            }
+            None => to_unit((pixel_seed(hash, self.index as i64, 1) >> 32) as u32),
+        }
+    }
+}
+
+const SOBOL_DIMENSIONS: usize = 16;
+
+// new-joe-kuo-6.21201 の 2 次元目以降 (s, a, m_1..m_s)
+const SOBOL_POLYNOMIALS: [(u32, u32, &[u32]); SOBOL_DIMENSIONS - 1] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+];
+
+fn sobol_directions() -> &'static [[u32; 32]; SOBOL_DIMENSIONS] {
+    static DIRECTIONS: OnceLock<[[u32; 32]; SOBOL_DIMENSIONS]> = OnceLock::new();
+    DIRECTIONS.get_or_init(|| {
+        let mut v = [[0; 32]; SOBOL_DIMENSIONS];
+        for (k, x) in v[0].iter_mut().enumerate() {
+            *x = 1 << (31 - k);
+        }
+        for (d, (s, a, m)) in SOBOL_POLYNOMIALS.iter().enumerate() {
+            let (s, v) = (*s as usize, &mut v[d + 1]);
+            for k in 0..32 {
+                v[k] = if k < s {
+                    m[k] << (31 - k)
+                } else {
+                    let mut x = v[k - s] ^ (v[k - s] >> s);
+                    for j in 1..s {
+                        if (a >> (s - 1 - j)) & 1 == 1 {
+                            x ^= v[k - j];
+                        }
+                    }
+                    x
+                };
+            }
+        }
+        v
+    })
+}
+
+fn sobol(index: u32, dimension: usize) -> u32 {
+    let v = &sobol_directions()[dimension];
+    let mut x = 0;
+    let mut i = index;
+    let mut k = 0;
+    while i > 0 {
+        if i & 1 == 1 {
+            x ^= v[k];
+        }
+        i >>= 1;
+        k += 1;
+    }
+    x
+}
+
+// Burley 2020, "Practical Hash-based Owen Scrambling"
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Owen スクランブルした Sobol 列。サンプル番号も画素ごとにシャッフルする
+// 方向数を用意した次元を使い切ったらハッシュ値を返す
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: usize,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: i64, y: i64, index: u64) {
+        self.pixel = pixel_seed(self.seed, x, y);
+        self.index = nested_uniform_scramble(index as u32, self.pixel as u32);
+        self.dimension = 0;
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        let d = self.dimension;
+        self.dimension += 1;
+        let hash = (pixel_seed(self.pixel, d as i64, 0) >> 32) as u32;
+        if d < SOBOL_DIMENSIONS {
+            to_unit(nested_uniform_scramble(sobol(self.index, d), hash))
+        } else {
+            to_unit((pixel_seed(hash as u64, self.index as i64, 1) >> 32) as u32)
+        }
+    }
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+// Ulichney 1993, "The void-and-cluster method for dither array generation"
+// 64x64 の画素に 0..4096 の順位を付ける。どの閾値で切っても 1 の画素が均等に散らばる
+// 最初に使うときに一度だけ作る
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        const N: usize = BLUE_NOISE_SIZE;
+        const SIGMA: f64 = 1.5;
+        // 周期境界で測った距離のガウス関数
+        let kernel: Vec<f64> = (0..N * N)
+            .map(|i| {
+                let wrap = |d: usize| d.min(N - d) as f64;
+                let (dx, dy) = (wrap(i % N), wrap(i / N));
+                (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+        let toggle = |energy: &mut [f64], pattern: &mut [bool], p: usize| {
+            pattern[p] = !pattern[p];
+            let sign = if pattern[p] { 1.0 } else { -1.0 };
+            let (px, py) = (p % N, p / N);
+            for (q, e) in energy.iter_mut().enumerate() {
+                let (dx, dy) = ((q % N + N - px) % N, (q / N + N - py) % N);
+                *e += sign * kernel[dy * N + dx];
+            }
+        };
+        // 1 の中でいちばん混んでいる画素と、0 の中でいちばん空いている画素
+        let tightest = |energy: &[f64], pattern: &[bool]| {
+            (0..N * N)
+                .filter(|&p| pattern[p])
+                .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+        let largest_void = |energy: &[f64], pattern: &[bool]| {
+            (0..N * N)
+                .filter(|&p| !pattern[p])
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap()
+        };
+
+        // 1 割ほどの画素を選び、混んでいる 1 を空いている所へ動かして均す
+        let mut pattern = vec![false; N * N];
+        let mut energy = vec![0.0; N * N];
+        for p in 0..N * N {
+            if pixel_seed(0, p as i64, 0).is_multiple_of(10) {
+                toggle(&mut energy, &mut pattern, p);
+            }
+        }
+        loop {
+            let cluster = tightest(&energy, &pattern);
+            toggle(&mut energy, &mut pattern, cluster);
+            let void = largest_void(&energy, &pattern);
+            toggle(&mut energy, &mut pattern, void);
+            if void == cluster {
+                break;
+            }
+        }
+        let ones = pattern.iter().filter(|&&one| one).count();
+
+        let mut rank = vec![0; N * N];
+        // 初期配置の 1 は混んでいる順に抜いて、順位を下から付ける
+        let (mut e, mut pat) = (energy.clone(), pattern.clone());
+        for r in (0..ones).rev() {
+            let cluster = tightest(&e, &pat);
+            toggle(&mut e, &mut pat, cluster);
+            rank[cluster] = r;
+        }
+        // 残りは空いている所から順に埋める
+        for r in ones..N * N {
+            let void = largest_void(&energy, &pattern);
+            toggle(&mut energy, &mut pattern, void);
+            rank[void] = r;
+        }
+        rank.into_iter()
+            .map(|r| (r as f64 + 0.5) / (N * N) as f64)
+            .collect()
+    })
+}
+
+// 全画素で同じ Sobol 列を使い、画素ごとに青色雑音のマスクの値だけずらす (Cranley-Patterson 回転)
+// 隣り合う画素の誤差が高周波に散るので、少ないサンプル数のプレビューでもざらつきが目立たない
+// 次元ごとにマスクを引く位置を変えて、次元の間の相関を避ける
+pub struct BlueNoiseSampler {
+    seed: u64,
+    x: i64,
+    y: i64,
+    index: u32,
+    dimension: usize,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            x: 0,
+            y: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, x: i64, y: i64, index: u64) {
+        self.x = x;
+        self.y = y;
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        let d = self.dimension;
+        self.dimension += 1;
+        let hash = pixel_seed(self.seed, d as i64, 0);
+        let n = BLUE_NOISE_SIZE as i64;
+        let mx = (self.x + (hash % n as u64) as i64).rem_euclid(n);
+        let my = (self.y + ((hash >> 32) % n as u64) as i64).rem_euclid(n);
+        let shift = blue_noise_mask()[(my * n + mx) as usize];
+        let point = if d < SOBOL_DIMENSIONS {
+            to_unit(nested_uniform_scramble(
+                sobol(self.index, d),
+                (hash >> 16) as u32,
+            ))
+        } else {
+            to_unit((pixel_seed(hash, self.index as i64, 1) >> 32) as u32)
+        };
+        (point + shift).fract()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 5] = [
+        SamplerKind::Random,
+        SamplerKind::Pcg,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+        SamplerKind::BlueNoise,
+    ];
+
+    // 用意した素数と Sobol の方向数を使い切る次元まで取る
+    const DIMENSIONS: usize = 40;
+
+    fn draw(sampler: &mut dyn Sampler, n: usize) -> Vec<f64> {
+        (0..n).map(|_| sampler.next_f64()).collect()
+    }
+
+    #[test]
+    fn radical_inverse_matches_the_van_der_corput_sequence() {
+        let base2 = (0..8).map(|i| radical_inverse(2, i)).collect::<Vec<_>>();
+        assert_eq!(base2, [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875]);
+        let base3 = (1..5).map(|i| radical_inverse(3, i)).collect::<Vec<_>>();
+        let expected = [1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0, 4.0 / 9.0];
+        for (x, e) in base3.iter().zip(expected) {
+            assert!((x - e).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn sobol_matches_the_first_points() {
+        let point = |i| (to_unit(sobol(i, 0)), to_unit(sobol(i, 1)));
+        let expected = [
+            (0.0, 0.0),
+            (0.5, 0.5),
+            (0.25, 0.75),
+            (0.75, 0.25),
+            (0.125, 0.625),
+            (0.625, 0.125),
+            (0.375, 0.375),
+            (0.875, 0.875),
+        ];
+        for (i, e) in expected.into_iter().enumerate() {
+            assert_eq!(point(i as u32), e, "index {}", i);
+        }
+    }
+
+    #[test]
+    fn halton_sampler_shifts_the_sequence_per_pixel() {
+        let mut sampler = HaltonSampler::new(3);
+        let mut first_dimension = |index| {
+            sampler.start_sample(5, 7, index);
+            sampler.next_f64()
+        };
+        let shift = first_dimension(0);
+        for index in 1..8 {
+            let x = (first_dimension(index) - shift).rem_euclid(1.0);
+            assert!((x - radical_inverse(2, index)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn samples_are_in_the_unit_interval() {
+        for kind in KINDS {
+            let mut sampler = kind.create(1);
+            for (x, y) in [(0, 0), (13, -4), (-100, 77)] {
+                for index in [0, 1, 2, 1023, u32::MAX as u64] {
+                    sampler.start_sample(x, y, index);
+                    for v in draw(sampler.as_mut(), DIMENSIONS) {
+                        assert!((0.0..1.0).contains(&v), "{:?} returned {}", kind, v);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn start_sample_makes_the_sequence_reproducible() {
+        // Random は start_sample を無視するので除く
+        for kind in &KINDS[1..] {
+            let mut a = kind.create(9);
+            let mut b = kind.create(9);
+            // 直前に何を引いていても、同じ画素と番号なら同じ列になる
+            b.start_sample(1, 2, 3);
+            draw(b.as_mut(), 5);
+            a.start_sample(4, 5, 6);
+            b.start_sample(4, 5, 6);
+            let first = draw(a.as_mut(), DIMENSIONS);
+            assert_eq!(first, draw(b.as_mut(), DIMENSIONS), "{:?}", kind);
+            a.start_sample(4, 5, 7);
+            assert_ne!(first, draw(a.as_mut(), DIMENSIONS), "{:?}", kind);
+            a.start_sample(5, 4, 6);
+            assert_ne!(first, draw(a.as_mut(), DIMENSIONS), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn random_sampler_repeats_for_the_same_seed() {
+        let mut a = RandomSampler::new(5);
+        let mut b = RandomSampler::new(5);
+        assert_eq!(draw(&mut a, 8), draw(&mut b, 8));
+    }
+}
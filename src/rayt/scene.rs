@@ -0,0 +1,454 @@
+use crate::rayt::*;
+
+use rayon::prelude::*;
+use std::sync::Arc;
+
+pub trait WorldScene: SceneWithDepth {
+    fn world(&self) -> &ShapeList;
+    fn background(&self) -> &dyn Background;
+    // コースティクスを経路追跡の代わりに集めるフォトンマップ
+    fn photon_map(&self) -> Option<&PhotonMap> {
+        None
+    }
+    // 形状を持たない光源 (点光源など)
+    fn lights(&self) -> &[Box<dyn Light>] {
+        &[]
+    }
+    // group の光を入れる、light_groups の中の番号。光る物体や背景は None で聞く
+    // 光源グループを分けないシーンでは None
+    fn light_group_index(&self, _group: Option<&str>) -> Option<usize> {
+        None
+    }
+    // 経路のすべての区間にかかる霧
+    fn fog(&self) -> Option<&Fog> {
+        None
+    }
+    // 影を求める光線が透明物体を通り抜けるとき、その色で減衰させる (false なら真っ黒な影)
+    fn colored_shadows(&self) -> bool {
+        false
+    }
+}
+
+pub const PHOTON_MAX_BOUNCES: usize = 8;
+
+// 片面だけが光る平行四辺形の光源。フォトンを飛ばすのに使う
+pub struct AreaLight {
+    corner: Point3,
+    edge_u: Vec3,
+    edge_v: Vec3,
+    radiance: Color,
+}
+
+impl AreaLight {
+    // edge_u x edge_v の向きに光る
+    pub fn new(corner: Point3, edge_u: Vec3, edge_v: Vec3, radiance: Color) -> Self {
+        Self {
+            corner,
+            edge_u,
+            edge_v,
+            radiance,
+        }
+    }
+
+    // ランバート面なので放射束は L A π
+    pub fn power(&self) -> Color {
+        self.radiance * self.edge_u.cross(self.edge_v).length() * PI
+    }
+
+    pub fn emit(&self) -> Ray {
+        let [a, b] = random_array();
+        let origin = self.corner + a * self.edge_u + b * self.edge_v;
+        let pdf = CosinePdf::new(self.edge_u.cross(self.edge_v));
+        Ray::new(origin, pdf.generate())
+    }
+}
+
+// 光源ごとに同じ数のフォトンを飛ばし、鏡面・屈折面を経由して拡散面に届いたものを集める
+pub fn trace_photons(
+    world: &ShapeList,
+    lights: &[AreaLight],
+    photons: usize,
+    seed: Option<u64>,
+) -> PhotonMap {
+    let count = photons / lights.len().max(1);
+    let stored = lights
+        .iter()
+        .enumerate()
+        .flat_map(|(l, light)| {
+            let power = light.power() / count as f64;
+            (0..count)
+                .into_par_iter()
+                .filter_map(|i| {
+                    // 画素の系列と重ならないように y を負にする
+                    if let Some(seed) = seed {
+                        reseed(pixel_seed(seed, i as i64, -1 - l as i64));
+                    }
+                    trace_photon(world, light.emit(), power)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    PhotonMap::new(stored)
+}
+
+pub fn trace_photon(world: &ShapeList, mut ray: Ray, mut power: Color) -> Option<Photon> {
+    let mut specular = false;
+    for _ in 0..PHOTON_MAX_BOUNCES {
+        let hit = world.hit(&ray, 0.001, f64::MAX)?;
+        if !hit.m.is_specular() {
+            return specular.then(|| Photon {
+                position: hit.p,
+                direction: ray.direction.normalize(),
+                power,
+            });
+        }
+        let scatter = hit.m.scatter(&ray, &hit)?;
+        power *= scatter.attenuation(&ray, &hit);
+        ray = scatter.ray.with_time(ray.time);
+        specular = true;
+    }
+    None
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathState {
+    after_diffuse: bool,
+    in_caustic: bool,
+    // この光線を出した面で direct_lighting が光源を狙った (ポータルの先の背景は数え済み)
+    direct_sampled: bool,
+    media: MediumStack,
+}
+
+// これより深く入れ子になった誘電体は、いちばん外側からの屈折率で扱う
+pub const MAX_NESTED_MEDIA: usize = 4;
+
+// 経路がいま内側にいる誘電体 (グラスの中の水、その中の氷など) を入った順に並べたもの
+// マテリアルは Arc のアドレスで見分けるので、入れ子にする物体はそれぞれ別のマテリアルにする
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MediumStack {
+    // (マテリアル, 屈折率, 優先度)
+    media: [(usize, f64, u32); MAX_NESTED_MEDIA],
+    len: usize,
+}
+
+impl MediumStack {
+    fn entries(&self) -> &[(usize, f64, u32)] {
+        &self.media[..self.len]
+    }
+
+    // 優先度がいちばん高い媒質 (同じなら後から入ったほう)
+    fn top(&self) -> Option<(usize, f64, u32)> {
+        self.entries()
+            .iter()
+            .copied()
+            .max_by_key(|&(_, _, priority)| priority)
+    }
+
+    // 今いる場所の屈折率。どの誘電体の内側にもいなければ空気とみなす
+    pub fn ior(&self) -> f64 {
+        self.top().map_or(1.0, |(_, ior, _)| ior)
+    }
+
+    fn enter(&mut self, id: usize, ior: f64, priority: u32) {
+        if self.len < MAX_NESTED_MEDIA && !self.entries().iter().any(|m| m.0 == id) {
+            self.media[self.len] = (id, ior, priority);
+            self.len += 1;
+        }
+    }
+
+    fn exit(&mut self, id: usize) {
+        if let Some(i) = self.entries().iter().position(|m| m.0 == id) {
+            self.media.copy_within(i + 1..self.len, i);
+            self.len -= 1;
+        }
+    }
+
+    // hit の面を越える前と越えた後
+    // 内側から出るのに記録がなければ (カメラが内側にあるなど)、内側にいたことにする
+    fn cross(&self, ray: &Ray, hit: &HitInfo, ior: f64, priority: u32) -> (Self, Self) {
+        let id = Arc::as_ptr(&hit.m) as *const () as usize;
+        let mut before = *self;
+        let mut after = *self;
+        if ray.direction.dot(hit.n) > 0.0 {
+            before.enter(id, ior, priority);
+            after = before;
+            after.exit(id);
+        } else {
+            after.enter(id, ior, priority);
+        }
+        (before, after)
+    }
+}
+
+// 優先度の低い誘電体の面のうち、優先度の高い誘電体の内側にあるものは飛ばす
+// 重ねて置いた物体の重なり部分の面がこれにあたる。飛ばした面の出入りは media に記録する
+fn nested_hit(scene: &impl WorldScene, ray: &Ray, media: &mut MediumStack) -> Option<HitInfo> {
+    let mut t0 = 0.001;
+    loop {
+        let hit = scene.world().hit(ray, t0, f64::MAX)?;
+        let Some((ior, priority)) = hit.m.interior() else {
+            return Some(hit);
+        };
+        let (before, after) = media.cross(ray, &hit, ior, priority);
+        if before.top().is_none_or(|(_, _, top)| priority >= top) {
+            return Some(hit);
+        }
+        *media = after;
+        t0 = hit.t;
+    }
+}
+
+// 誘電体は両側の媒質の屈折率の比で散乱し、面を越えたら経路の媒質を入れ替える
+fn scatter_nested(
+    ray: &Ray,
+    hit: &HitInfo,
+    media: MediumStack,
+) -> (Option<ScatterInfo>, MediumStack) {
+    let Some((ior, priority)) = hit.m.interior() else {
+        return (hit.m.scatter(ray, hit), media);
+    };
+    let (before, after) = media.cross(ray, hit, ior, priority);
+    let scatter = hit.m.scatter_between(ray, hit, before.ior(), after.ior());
+    let side = ray.direction.dot(hit.n);
+    let crossed = scatter
+        .as_ref()
+        .is_some_and(|s| s.ray.direction.dot(hit.n) * side > 0.0);
+    (scatter, if crossed { after } else { before })
+}
+
+// 次の散乱を何本追うかと、その先の経路の状態
+pub fn scatter_samples(
+    scene: &impl WorldScene,
+    specular: bool,
+    depth: usize,
+    state: PathState,
+) -> (usize, PathState) {
+    // 拡散面で跳ね返ったあとに鏡面・屈折面を経由する経路がコースティクス
+    let caustic = specular && state.after_diffuse;
+    let caustics = scene.caustics();
+    let traced = caustics.enabled && scene.photon_map().is_none();
+    let samples = if depth == 0 || (caustic && !traced) {
+        0
+    } else if caustic && !state.in_caustic {
+        caustics.sample_multiplier.max(1)
+    } else {
+        1
+    };
+    let next = PathState {
+        after_diffuse: state.after_diffuse || !specular,
+        in_caustic: state.in_caustic || caustic,
+        direct_sampled: false,
+        media: state.media,
+    };
+    (samples, next)
+}
+
+// ray.at(0) から ray.at(t) までの霧。霧の色は背景と同じく DEFAULT_LIGHT_GROUP に入る
+// 先から届く光に掛ける割合と、途中で足す霧の色 (光源の光を散乱したものを含む) を返す
+fn fog_segment(scene: &impl WorldScene, ray: &Ray, t: f64) -> (f64, GroupRadiance) {
+    let Some(fog) = scene.fog() else {
+        return (1.0, GroupRadiance::zero());
+    };
+    let transmittance = fog.transmittance(ray, t);
+    let color = fog.color * (1.0 - transmittance);
+    let color = GroupRadiance::new(scene.light_group_index(None), color);
+    (transmittance, color + fog_scattering(scene, ray, t))
+}
+
+pub fn trace_scene(scene: &impl WorldScene, ray: Ray, depth: usize) -> Color {
+    trace_scene_groups(scene, ray, depth).total
+}
+
+// trace_scene と同じ光を、光源グループごとの内訳と一緒に返す
+// 偏光フィルタを通した光はグループに分けない
+pub fn trace_scene_groups(scene: &impl WorldScene, ray: Ray, depth: usize) -> GroupRadiance {
+    match scene.polarizer() {
+        // 偏光フィルタの角度はカメラの水平軸から測る
+        Some(angle) => {
+            let d = ray.direction.normalize();
+            let horizontal = scene.camera().right();
+            let frame = (horizontal - d * d.dot(horizontal)).normalize();
+            let stokes = trace_world_polarized(scene, ray, frame, depth, PathState::default());
+            GroupRadiance::new(
+                None,
+                (Mueller::linear_polarizer(angle.to_radians()) * stokes).i,
+            )
+        }
+        None => trace_path(&WorldPath(scene), ray, depth),
+    }
+}
+
+// 1 回分の反射を受け持ち、経路は trace_path が追う
+pub struct WorldPath<'a, S>(&'a S);
+
+impl<S: WorldScene> PathTracer for WorldPath<'_, S> {
+    type State = PathState;
+
+    fn bounce(
+        &self,
+        ray: &Ray,
+        depth: usize,
+        state: PathState,
+        next: &mut Vec<PathSegment<PathState>>,
+    ) -> GroupRadiance {
+        let scene = self.0;
+        // 背景と光る物体は DEFAULT_LIGHT_GROUP に入る
+        let default_group = scene.light_group_index(None);
+        let mut media = state.media;
+        let Some(hit) = nested_hit(scene, ray, &mut media) else {
+            scene.record_path(depth, PathEnd::Escaped);
+            let counted = state.direct_sampled && portal_covers(scene, ray);
+            let background = if !counted {
+                scene.background().value(ray.direction)
+            } else {
+                Color::zero()
+            };
+            let (transmittance, fog) = fog_segment(scene, ray, f64::MAX);
+            let background = GroupRadiance::new(default_group, background) * transmittance + fog;
+            log_bounce(|| BounceRecord {
+                depth,
+                ray: *ray,
+                hit: None,
+                material: "background",
+                emitted: background.total,
+                albedo: None,
+            });
+            return background;
+        };
+        let emitted = hit.m.emitted(ray, &hit);
+        // 最初に当たった拡散面ではフォトンマップからコースティクスを集める
+        let photons = scene
+            .photon_map()
+            .filter(|_| !hit.m.is_specular() && !state.after_diffuse);
+        let state = PathState { media, ..state };
+        let (samples, state) = scatter_samples(scene, hit.m.is_specular(), depth, state);
+        // 当たった点から先の光は、ここまでの霧で弱まる
+        let (transmittance, fog) = fog_segment(scene, ray, hit.t);
+        let mut radiance = GroupRadiance::new(default_group, emitted);
+        for _ in 0..samples {
+            let (scatter_info, media) = scatter_nested(ray, &hit, state.media);
+            log_bounce(|| BounceRecord {
+                depth,
+                ray: *ray,
+                hit: Some((hit.p, hit.n)),
+                material: hit.m.name(),
+                emitted,
+                albedo: scatter_info.as_ref().map(|s| s.attenuation(ray, &hit)),
+            });
+            if let Some(scatter) = scatter_info {
+                radiance += direct_lighting(scene, ray, &hit, &scatter) / samples as f64;
+                if let Some(map) = photons {
+                    let caustics = scene.caustics();
+                    let n = if ray.direction.dot(hit.n) > 0.0 {
+                        -hit.n
+                    } else {
+                        hit.n
+                    };
+                    let irradiance =
+                        map.irradiance(hit.p, n, caustics.photon_neighbors, caustics.photon_radius);
+                    let caustic = scatter.albedo * irradiance * FRAC_1_PI / samples as f64;
+                    radiance += GroupRadiance::new(default_group, caustic);
+                }
+                next.push(PathSegment {
+                    // 散乱した光線も同じ時刻のシーンを見る
+                    ray: scatter.ray.with_time(ray.time),
+                    weight: scatter.attenuation(ray, &hit) * transmittance / samples as f64,
+                    state: PathState {
+                        direct_sampled: scatter.pdf.is_some(),
+                        media,
+                        ..state
+                    },
+                });
+            }
+        }
+        if samples == 0 {
+            log_bounce(|| BounceRecord {
+                depth,
+                ray: *ray,
+                hit: Some((hit.p, hit.n)),
+                material: hit.m.name(),
+                emitted,
+                albedo: None,
+            });
+        }
+        if next.is_empty() {
+            scene.record_path(
+                depth,
+                if depth > 0 {
+                    PathEnd::Absorbed
+                } else {
+                    PathEnd::MaxDepth
+                },
+            );
+        }
+        radiance * transmittance + fog
+    }
+
+    fn radiance_clamp(&self) -> RadianceClamp {
+        self.0.radiance_clamp()
+    }
+}
+
+// frame はこの光線で届く光のストークスベクトルの基準軸
+pub fn trace_world_polarized(
+    scene: &impl WorldScene,
+    ray: Ray,
+    frame: Vec3,
+    depth: usize,
+    state: PathState,
+) -> Stokes {
+    let mut media = state.media;
+    let Some(hit) = nested_hit(scene, &ray, &mut media) else {
+        scene.record_path(depth, PathEnd::Escaped);
+        let counted = state.direct_sampled && portal_covers(scene, &ray);
+        let background = if !counted {
+            scene.background().value(ray.direction)
+        } else {
+            Color::zero()
+        };
+        let (transmittance, fog) = fog_segment(scene, &ray, f64::MAX);
+        return Stokes::unpolarized(background * transmittance + fog.total);
+    };
+    let state = PathState { media, ..state };
+    let (samples, next) = scatter_samples(scene, hit.m.is_specular(), depth, state);
+    // 入射面に垂直な s 方向を基準軸にして反射・屈折のミュラー行列を掛ける
+    let s = ray.direction.cross(hit.n);
+    let s = if s.near_zero() { frame } else { s.normalize() };
+    let emitted = hit.m.emitted(&ray, &hit);
+    let mut stokes = Stokes::unpolarized(emitted);
+    let mut absorbed = true;
+    for _ in 0..samples {
+        let (scatter, media) = scatter_nested(&ray, &hit, next.media);
+        if let Some(scatter) = scatter {
+            absorbed = false;
+            let scattered = scatter.ray.with_time(ray.time);
+            let next = PathState {
+                direct_sampled: scatter.pdf.is_some(),
+                media,
+                ..next
+            };
+            let incoming = trace_world_polarized(scene, scattered, s, depth - 1, next);
+            let direct = direct_lighting(scene, &ray, &hit, &scatter).total;
+            let outgoing = (hit.m.mueller(&ray, &hit, &scatter.ray) * incoming)
+                .scale(scatter.attenuation(&ray, &hit))
+                + Stokes::unpolarized(direct);
+            stokes = stokes + outgoing.scale(Color::fill(1.0 / samples as f64));
+        }
+    }
+    if absorbed {
+        scene.record_path(
+            depth,
+            if depth > 0 {
+                PathEnd::Absorbed
+            } else {
+                PathEnd::MaxDepth
+            },
+        );
+    }
+    // 霧で散乱した光は偏っていないとみなす
+    let (transmittance, fog) = fog_segment(scene, &ray, hit.t);
+    stokes
+        .reframe(-ray.direction, s, frame)
+        .scale(Color::fill(transmittance))
+        + Stokes::unpolarized(fog.total)
+}
@@ -0,0 +1,1035 @@
+use crate::rayt::*;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// シーンを TOML で書いたもの (scenes/example.toml を参照)
+// マテリアルは名前を付けて並べ、形状からその名前で参照する
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(deny_unknown_fields)
+)]
+struct SceneFile {
+    #[cfg_attr(feature = "serde", serde(default))]
+    render: RenderSection,
+    // 省略すると glTF のカメラを使う
+    camera: Option<CameraSection>,
+    #[cfg_attr(feature = "serde", serde(default = "default_background"))]
+    background: BackgroundSpec,
+    #[cfg_attr(feature = "serde", serde(default))]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    shapes: Vec<ShapeDesc>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    lights: Vec<LightEntry>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    fog: Option<FogSection>,
+}
+
+#[cfg(feature = "serde")]
+fn default_background() -> BackgroundSpec {
+    BackgroundSpec::Color([0.0; 3])
+}
+
+// background = [r, g, b] か、下から上へのグラデーション、パノラマ画像
+// background = { bottom = [r, g, b], top = [r, g, b] }
+// background = { environment = "sky.png", intensity = 1.0 }
+// background = { sun = [x, y, z], turbidity = 3.0, intensity = 1.0 }  (太陽へ向かう方向)
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(untagged))]
+pub enum BackgroundSpec {
+    Color([f64; 3]),
+    Gradient {
+        bottom: [f64; 3],
+        top: [f64; 3],
+    },
+    Environment {
+        environment: String,
+        #[cfg_attr(feature = "serde", serde(default = "default_intensity"))]
+        intensity: f64,
+    },
+    Sky {
+        sun: [f64; 3],
+        #[cfg_attr(feature = "serde", serde(default = "default_turbidity"))]
+        turbidity: f64,
+        #[cfg_attr(feature = "serde", serde(default = "default_intensity"))]
+        intensity: f64,
+    },
+}
+
+#[cfg(feature = "serde")]
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+#[cfg(feature = "serde")]
+fn default_intensity() -> f64 {
+    1.0
+}
+
+impl BackgroundSpec {
+    pub fn build(&self) -> Result<Box<dyn Background>, Error> {
+        Ok(match self {
+            BackgroundSpec::Color(color) => Box::new(ColorBackground::new(vec3(*color))),
+            BackgroundSpec::Gradient { bottom, top } => {
+                Box::new(GradientBackground::new(vec3(*bottom), vec3(*top)))
+            }
+            BackgroundSpec::Environment {
+                environment,
+                intensity,
+            } => Box::new(EnvironmentMap::new(environment, *intensity)?),
+            BackgroundSpec::Sky {
+                sun,
+                turbidity,
+                intensity,
+            } => Box::new(PhysicalSky::new(vec3(*sun), *turbidity, *intensity)),
+        })
+    }
+}
+
+// [fog] color = [r, g, b], density = 0.05
+// height = [基準の高さ, 上へ薄くなる割合] を足すと地面付近に溜まる霧になる
+// scattering = 0.8 のように霧のアルベドを書くと、光源の光を散乱して光の筋が出る
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(deny_unknown_fields)
+)]
+struct FogSection {
+    color: [f64; 3],
+    density: f64,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    height: Option<[f64; 2]>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+    scattering: f64,
+}
+
+impl FogSection {
+    fn build(&self) -> Fog {
+        let fog = Fog::new(vec3(self.color), self.density).with_scattering(self.scattering);
+        match self.height {
+            Some([base, falloff]) => fog.with_height(base, falloff),
+            None => fog,
+        }
+    }
+
+    fn describe(fog: &Fog) -> Self {
+        Self {
+            color: fog.color.to_array(),
+            density: fog.density,
+            height: fog.height.map(|(base, falloff)| [base, falloff]),
+            scattering: fog.scattering,
+        }
+    }
+}
+
+// コマンドラインで指定がなければこちらを使う
+#[derive(Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(deny_unknown_fields)
+)]
+struct RenderSection {
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    width: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    height: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    samples: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    max_depth: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    output: Option<String>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(deny_unknown_fields)
+)]
+pub struct CameraSection {
+    pub look_from: [f64; 3],
+    pub look_at: [f64; 3],
+    #[cfg_attr(feature = "serde", serde(default = "default_up"))]
+    pub up: [f64; 3],
+    pub fov: f64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub aperture: f64,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub focus_distance: Option<f64>,
+    // [開く時刻, 閉じる時刻]。動く球をぶらすときに使う
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub shutter: Option<[f64; 2]>,
+    // ボケの形。blades を書くとその枚数の羽根の多角形 (blade_rotation 度だけ回す)
+    // aperture_mask を書くとその画像の白いところの形になる。どちらもなければ円
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub blades: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+    pub blade_rotation: f64,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub aperture_mask: Option<String>,
+}
+
+impl CameraSection {
+    pub fn from_look_at(look_at: &LookAt) -> Self {
+        Self {
+            look_from: look_at.origin.to_array(),
+            look_at: look_at.look_at.to_array(),
+            up: look_at.up.to_array(),
+            fov: look_at.fov,
+            aperture: look_at.aperture,
+            focus_distance: None,
+            shutter: None,
+            blades: None,
+            blade_rotation: 0.0,
+            aperture_mask: None,
+        }
+    }
+
+    // 画像は読み込みに失敗することがあるので builder とは別に作る
+    pub fn aperture_shape(&self) -> Result<Aperture, Error> {
+        match (&self.aperture_mask, self.blades) {
+            (Some(_), Some(_)) => Err(Error::Invalid(
+                "camera: use either blades or aperture_mask, not both".to_string(),
+            )),
+            (Some(path), None) => Ok(Aperture::Mask(Arc::new(ApertureMask::load(path)?))),
+            (None, Some(blades)) if blades < 3 => Err(Error::Invalid(format!(
+                "camera: an aperture needs at least 3 blades, not {}",
+                blades
+            ))),
+            (None, Some(blades)) => Ok(Aperture::Polygon {
+                blades,
+                rotation: self.blade_rotation,
+            }),
+            (None, None) => Ok(Aperture::Circle),
+        }
+    }
+
+    // 縦横比はシーンの大きさから決まるので、描くときに足す
+    pub fn builder(&self) -> CameraBuilder {
+        let builder = CameraBuilder::new()
+            .look_from(vec3(self.look_from))
+            .look_at(vec3(self.look_at))
+            .up(vec3(self.up))
+            .fov(self.fov)
+            .aperture(self.aperture);
+        let builder = match self.focus_distance {
+            Some(distance) => builder.focus_distance(distance),
+            None => builder,
+        };
+        match self.shutter {
+            Some([open, close]) => builder.shutter(open, close),
+            None => builder,
+        }
+    }
+}
+
+fn default_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+#[cfg(feature = "serde")]
+fn is_up(axis: &[f64; 3]) -> bool {
+    *axis == default_up()
+}
+
+#[cfg(feature = "serde")]
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+// texture = [r, g, b] か texture = { type = "checker", ... }
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize), serde(untagged))]
+pub enum TextureSpec {
+    Color([f64; 3]),
+    Desc(TextureDesc),
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)
+)]
+pub enum TextureDesc {
+    Color {
+        color: [f64; 3],
+    },
+    Blackbody {
+        kelvin: f64,
+        intensity: f64,
+    },
+    Checker {
+        odd: [f64; 3],
+        even: [f64; 3],
+        freq: f64,
+    },
+    Noise {
+        scale: f64,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+        seed: u64,
+    },
+    Marble {
+        scale: f64,
+        axis: [f64; 3],
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+        seed: u64,
+    },
+    Worley {
+        scale: f64,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+        seed: u64,
+    },
+    Image {
+        path: String,
+    },
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)
+)]
+pub enum MaterialDesc {
+    Lambertian {
+        texture: TextureSpec,
+    },
+    Metal {
+        texture: TextureSpec,
+        #[cfg_attr(feature = "serde", serde(default))]
+        fuzz: f64,
+    },
+    Velvet {
+        texture: TextureSpec,
+        sheen: [f64; 3],
+    },
+    // 重なった誘電体では priority の高いほうを中身として扱う
+    Dielectric {
+        ri: f64,
+        #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+        tint: Option<[f64; 3]>,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+        priority: u32,
+    },
+    DiffuseLight {
+        texture: TextureSpec,
+    },
+}
+
+// 形状のない光源。[[lights]] に type = "point" などと書く
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)
+)]
+pub enum LightDesc {
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+    },
+    // direction は光の進む向き、angular_radius は度
+    Directional {
+        direction: [f64; 3],
+        irradiance: [f64; 3],
+        #[cfg_attr(feature = "serde", serde(default))]
+        angular_radius: f64,
+    },
+    // 背景を取り込む開口部。corner から edge_u, edge_v に張った平行四辺形
+    Portal {
+        corner: [f64; 3],
+        edge_u: [f64; 3],
+        edge_v: [f64; 3],
+    },
+}
+
+// flatten と deny_unknown_fields は併用できない
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+struct LightEntry {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    kind: LightDesc,
+    // 光源グループの名前。グループごとに render_light_<group>.exr も書き出す
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    group: Option<String>,
+}
+
+impl LightEntry {
+    fn build(&self) -> Box<dyn Light> {
+        let light = self.kind.build();
+        match &self.group {
+            Some(group) => Box::new(GroupedLight::new(light, group)),
+            None => light,
+        }
+    }
+}
+
+impl LightDesc {
+    fn build(&self) -> Box<dyn Light> {
+        match self {
+            LightDesc::Point {
+                position,
+                intensity,
+            } => Box::new(PointLight::new(vec3(*position), vec3(*intensity))),
+            LightDesc::Directional {
+                direction,
+                irradiance,
+                angular_radius,
+            } => Box::new(
+                DirectionalLight::new(vec3(*direction), vec3(*irradiance))
+                    .with_angular_radius(*angular_radius),
+            ),
+            LightDesc::Portal {
+                corner,
+                edge_u,
+                edge_v,
+            } => Box::new(PortalLight::new(
+                vec3(*corner),
+                vec3(*edge_u),
+                vec3(*edge_v),
+            )),
+        }
+    }
+}
+
+// flatten と deny_unknown_fields は併用できない
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct ShapeDesc {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    kind: ShapeKind,
+    // obj では省略でき、省略すると MTL のマテリアルを使う
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    material: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "std::ops::Not::not")
+    )]
+    flip_face: bool,
+    // 回転 (rotate_axis まわりに rotate 度) してから平行移動する
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    rotate: Option<f64>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default = "default_up", skip_serializing_if = "is_up")
+    )]
+    rotate_axis: [f64; 3],
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    translate: Option<[f64; 3]>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
+pub enum ShapeKind {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        time0: f64,
+        time1: f64,
+        radius: f64,
+    },
+    RectXy {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+    },
+    RectXz {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+    },
+    RectYz {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+    },
+    Box {
+        p0: [f64; 3],
+        p1: [f64; 3],
+    },
+    Obj {
+        path: String,
+    },
+    Gltf {
+        path: String,
+    },
+}
+
+fn vec3([x, y, z]: [f64; 3]) -> Vec3 {
+    Vec3::new(x, y, z)
+}
+
+impl TextureSpec {
+    fn apply(&self, builder: ShapeBuilder) -> ShapeBuilder {
+        let desc = match self {
+            TextureSpec::Color(color) => return builder.color_texture(vec3(*color)),
+            TextureSpec::Desc(desc) => desc,
+        };
+        match desc {
+            TextureDesc::Color { color } => builder.color_texture(vec3(*color)),
+            TextureDesc::Blackbody { kelvin, intensity } => {
+                builder.blackbody_texture(*kelvin, *intensity)
+            }
+            TextureDesc::Checker { odd, even, freq } => {
+                builder.checker_texture(vec3(*odd), vec3(*even), *freq)
+            }
+            TextureDesc::Noise { scale, seed } => builder.noise_seed(*seed).noise_texture(*scale),
+            TextureDesc::Marble { scale, axis, seed } => builder
+                .noise_seed(*seed)
+                .marble_texture(*scale, vec3(*axis)),
+            TextureDesc::Worley { scale, seed } => builder.noise_seed(*seed).worley_texture(*scale),
+            TextureDesc::Image { path } => builder.image_texture(path),
+        }
+    }
+}
+
+impl MaterialDesc {
+    fn build(&self) -> Result<Arc<dyn Material>, Error> {
+        let builder = ShapeBuilder::new();
+        let builder = match self {
+            MaterialDesc::Lambertian { texture } => texture.apply(builder).lambertian(),
+            MaterialDesc::Metal { texture, fuzz } => texture.apply(builder).metal(*fuzz),
+            MaterialDesc::Velvet { texture, sheen } => texture.apply(builder).velvet(vec3(*sheen)),
+            MaterialDesc::Dielectric { ri, tint, priority } => {
+                builder.nested_dielectric(*ri, tint.map_or(Color::one(), vec3), *priority)
+            }
+            MaterialDesc::DiffuseLight { texture } => texture.apply(builder).diffuse_light(),
+        };
+        builder.build_material()
+    }
+}
+
+impl ShapeDesc {
+    // glTF にカメラがあれば cameras に足す (rotate, translate はカメラにはかけない)
+    fn build(
+        &self,
+        material: Option<Arc<dyn Material>>,
+        cameras: &mut Vec<CameraSection>,
+    ) -> Result<ShapeEnum, Error> {
+        let with_material = || {
+            material
+                .clone()
+                .map(|material| ShapeBuilder::new().material(material))
+                .ok_or_else(|| Error::Invalid("missing material".to_string()))
+        };
+        let builder = match &self.kind {
+            &ShapeKind::Sphere { center, radius } => with_material()?.sphere(vec3(center), radius),
+            &ShapeKind::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+            } => with_material()?.moving_sphere(vec3(center0), vec3(center1), time0, time1, radius),
+            &ShapeKind::RectXy { x0, x1, y0, y1, k } => with_material()?.rect_xy(x0, x1, y0, y1, k),
+            &ShapeKind::RectXz { x0, x1, z0, z1, k } => with_material()?.rect_xz(x0, x1, z0, z1, k),
+            &ShapeKind::RectYz { y0, y1, z0, z1, k } => with_material()?.rect_yz(y0, y1, z0, z1, k),
+            &ShapeKind::Box { p0, p1 } => with_material()?.box_shape(vec3(p0), vec3(p1)),
+            ShapeKind::Obj { path } => match material {
+                Some(material) => ShapeBuilder::new().material(material),
+                None => ShapeBuilder::new(),
+            }
+            .obj(path),
+            ShapeKind::Gltf { path } => {
+                let scene = load_gltf(path, material)?;
+                cameras.extend(scene.camera.map(|camera| CameraSection {
+                    look_from: camera.look_from.to_array(),
+                    look_at: camera.look_at.to_array(),
+                    up: camera.up.to_array(),
+                    fov: camera.fov,
+                    aperture: 0.0,
+                    focus_distance: None,
+                    shutter: None,
+                    blades: None,
+                    blade_rotation: 0.0,
+                    aperture_mask: None,
+                }));
+                ShapeBuilder::new().mesh(scene.mesh)
+            }
+        };
+        self.transform(builder)
+    }
+
+    fn transform(&self, builder: ShapeBuilder) -> Result<ShapeEnum, Error> {
+        let builder = if self.flip_face {
+            builder.flip_face()
+        } else {
+            builder
+        };
+        let builder = match self.rotate {
+            Some(angle) => builder.rotate(vec3(self.rotate_axis), angle),
+            None => builder,
+        };
+        let builder = match self.translate {
+            Some(offset) => builder.translate(vec3(offset)),
+            None => builder,
+        };
+        builder.build()
+    }
+}
+
+// シーンファイルから読み込んだシーン
+pub struct FileScene {
+    world: ShapeList,
+    camera: CameraSection,
+    aperture: Aperture,
+    background: Box<dyn Background>,
+    lights: Vec<Box<dyn Light>>,
+    // lights の光源グループ。光源を足すたびに作り直す
+    groups: Vec<String>,
+    fog: Option<Fog>,
+    render: RenderSection,
+    stats: Option<PathStats>,
+    seed: Option<u64>,
+    polarizer: Option<f64>,
+    distortion: LensDistortion,
+    strata: u32,
+    sampler: SamplerKind,
+    clamp: RadianceClamp,
+    colored_shadows: bool,
+}
+
+// シーンファイルと --config の TOML は serde feature で読み書きする
+#[cfg(feature = "serde")]
+pub(crate) fn from_toml<T: serde::de::DeserializeOwned>(text: &str) -> Result<T, String> {
+    toml::from_str(text).map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn to_toml<T: Serialize>(value: &T) -> Result<String, String> {
+    toml::to_string(value).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "serde"))]
+pub(crate) fn from_toml<T>(_text: &str) -> Result<T, String> {
+    Err(NEEDS_SERDE.into())
+}
+
+#[cfg(not(feature = "serde"))]
+pub(crate) fn to_toml<T>(_value: &T) -> Result<String, String> {
+    Err(NEEDS_SERDE.into())
+}
+
+#[cfg(not(feature = "serde"))]
+const NEEDS_SERDE: &str = "TOML files need rayt built with --features serde";
+
+impl FileScene {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let error = |e: &dyn std::fmt::Display| Error::File(format!("{}: {}", path, e));
+        let text = std::fs::read_to_string(path).map_err(|e| error(&e))?;
+        let file: SceneFile = from_toml(&text).map_err(|e| error(&e))?;
+        let materials = file
+            .materials
+            .iter()
+            .map(|(name, desc)| Ok((name.as_str(), desc.build().map_err(|e| error(&e))?)))
+            .collect::<Result<HashMap<_, _>, Error>>()?;
+        let mut world = ShapeList::new();
+        let mut cameras = Vec::new();
+        for shape in &file.shapes {
+            let material = match &shape.material {
+                Some(name) => {
+                    Some(Arc::clone(materials.get(name.as_str()).ok_or_else(
+                        || error(&format!("unknown material {:?}", name)),
+                    )?))
+                }
+                None => None,
+            };
+            world.push(shape.build(material, &mut cameras).map_err(|e| error(&e))?);
+        }
+        let camera = file
+            .camera
+            .or_else(|| cameras.into_iter().next())
+            .ok_or_else(|| error(&"missing [camera]"))?;
+        camera.builder().build().map_err(|e| error(&e))?;
+        let aperture = camera.aperture_shape().map_err(|e| error(&e))?;
+        let background = file.background.build().map_err(|e| error(&e))?;
+        let lights = file
+            .lights
+            .iter()
+            .map(LightEntry::build)
+            .collect::<Vec<_>>();
+        let groups = light_groups(&lights);
+        if groups.len() > MAX_LIGHT_GROUPS {
+            return Err(error(&format!(
+                "{} light groups (including {:?}), at most {}",
+                groups.len(),
+                DEFAULT_LIGHT_GROUP,
+                MAX_LIGHT_GROUPS
+            )));
+        }
+        Ok(Self {
+            aperture,
+            render: file.render,
+            lights,
+            groups,
+            fog: file.fog.as_ref().map(FogSection::build),
+            ..Self::new(world, camera, background)
+        })
+    }
+
+    // スクリプトなどで組み立てたワールドから作る
+    pub fn new(world: ShapeList, camera: CameraSection, background: Box<dyn Background>) -> Self {
+        Self {
+            world,
+            camera,
+            aperture: Aperture::Circle,
+            background,
+            lights: Vec::new(),
+            groups: Vec::new(),
+            fog: None,
+            render: RenderSection::default(),
+            stats: None,
+            seed: None,
+            polarizer: None,
+            distortion: LensDistortion::default(),
+            strata: 1,
+            sampler: SamplerKind::Random,
+            clamp: RadianceClamp::Off,
+            colored_shadows: false,
+        }
+    }
+
+    // [render] の samples と max_depth を差し替える (スクリプトで指定したときなど)
+    pub fn with_quality(mut self, samples: Option<usize>, max_depth: Option<usize>) -> Self {
+        self.render.samples = samples.or(self.render.samples);
+        self.render.max_depth = max_depth.or(self.render.max_depth);
+        self
+    }
+
+    // ファイルの [render] をコマンドラインで指定しなかった項目に使う
+    pub fn fill_config(&self, config: &mut RenderConfig) -> Result<(), Error> {
+        config.width = config.width.or(self.render.width);
+        config.height = config.height.or(self.render.height);
+        config.spp = config.spp.or(self.render.samples);
+        config.max_depth = config.max_depth.or(self.render.max_depth);
+        match &self.render.output {
+            Some(output) if config.output == OUTPUT_FILENAME => {
+                check_output_format(output)
+                    .map_err(|e| Error::Invalid(format!("{}: {}", output, e)))?;
+                config.output = output.clone();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn with_path_stats(self) -> Self {
+        Self {
+            stats: Some(PathStats::new(MAX_RAY_BOUNCE_DEPTH)),
+            ..self
+        }
+    }
+    pub fn with_seed(self, seed: Option<u64>) -> Self {
+        Self { seed, ..self }
+    }
+    pub fn with_polarizer(self, polarizer: Option<f64>) -> Self {
+        Self { polarizer, ..self }
+    }
+    pub fn with_distortion(self, distortion: LensDistortion) -> Self {
+        Self { distortion, ..self }
+    }
+    pub fn with_strata(self, strata: u32) -> Self {
+        Self { strata, ..self }
+    }
+    pub fn with_sampler(self, sampler: SamplerKind) -> Self {
+        Self { sampler, ..self }
+    }
+    pub fn with_radiance_clamp(self, clamp: RadianceClamp) -> Self {
+        Self { clamp, ..self }
+    }
+    pub fn with_colored_shadows(self, colored_shadows: bool) -> Self {
+        Self {
+            colored_shadows,
+            ..self
+        }
+    }
+    pub fn with_background(self, background: impl Background + 'static) -> Self {
+        Self {
+            background: Box::new(background),
+            ..self
+        }
+    }
+    pub fn with_aperture(self, aperture: Aperture) -> Self {
+        Self { aperture, ..self }
+    }
+    pub fn with_fog(self, fog: Fog) -> Self {
+        Self {
+            fog: Some(fog),
+            ..self
+        }
+    }
+    pub fn with_light(mut self, light: impl Light + 'static) -> Self {
+        self.lights.push(Box::new(light));
+        self.groups = light_groups(&self.lights);
+        self
+    }
+
+    // フレームの合間に形状を足したり取り除いたりする
+    pub fn world_mut(&mut self) -> &mut ShapeList {
+        &mut self.world
+    }
+}
+
+impl WorldScene for FileScene {
+    fn world(&self) -> &ShapeList {
+        &self.world
+    }
+    fn background(&self) -> &dyn Background {
+        &*self.background
+    }
+    fn lights(&self) -> &[Box<dyn Light>] {
+        &self.lights
+    }
+    fn fog(&self) -> Option<&Fog> {
+        self.fog.as_ref()
+    }
+    fn colored_shadows(&self) -> bool {
+        self.colored_shadows
+    }
+    // MAX_LIGHT_GROUPS より後ろのグループは分けない
+    fn light_group_index(&self, group: Option<&str>) -> Option<usize> {
+        let group = group.unwrap_or(DEFAULT_LIGHT_GROUP);
+        self.groups
+            .iter()
+            .take(MAX_LIGHT_GROUPS)
+            .position(|name| name == group)
+    }
+}
+
+impl SceneWithDepth for FileScene {
+    fn camera(&self) -> Box<dyn Camera> {
+        // カメラの値は読み込んだときに確かめてある
+        let camera = self
+            .camera
+            .builder()
+            .aspect(self.aspect())
+            .aperture_shape(self.aperture.clone())
+            .distortion(self.distortion)
+            .build()
+            .unwrap_or_else(|e| panic!("{}", e));
+        Box::new(camera)
+    }
+    fn trace(&self, ray: Ray, depth: usize) -> Color {
+        trace_scene(self, ray, depth)
+    }
+    fn path_stats(&self) -> Option<&PathStats> {
+        self.stats.as_ref()
+    }
+    fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+    fn strata(&self) -> u32 {
+        self.strata
+    }
+    fn sampler(&self) -> SamplerKind {
+        self.sampler
+    }
+    fn radiance_clamp(&self) -> RadianceClamp {
+        self.clamp
+    }
+    fn polarizer(&self) -> Option<f64> {
+        self.polarizer
+    }
+    fn matte(&self, ray: &Ray) -> Option<&'static str> {
+        self.world.hit(ray, 0.001, f64::MAX)?.matte
+    }
+    fn position(&self, ray: &Ray) -> Option<Point3> {
+        self.world.hit(ray, 0.001, f64::MAX).map(|hit| hit.p)
+    }
+    fn aov(&self, ray: &Ray) -> Option<Aov> {
+        self.world.aov(ray)
+    }
+    fn light_groups(&self) -> Vec<String> {
+        self.groups.iter().take(MAX_LIGHT_GROUPS).cloned().collect()
+    }
+    fn trace_groups(&self, ray: Ray, depth: usize) -> GroupRadiance {
+        trace_scene_groups(self, ray, depth)
+    }
+}
+
+impl ShapeDesc {
+    fn new(kind: ShapeKind, material: String) -> Self {
+        Self {
+            kind,
+            material: Some(material),
+            flip_face: false,
+            rotate: None,
+            rotate_axis: default_up(),
+            translate: None,
+        }
+    }
+
+    pub fn flip(&mut self) {
+        self.flip_face = !self.flip_face;
+    }
+
+    pub fn translate_by(&mut self, offset: Vec3) {
+        let translate = self.translate.map_or(Vec3::zero(), vec3) + offset;
+        self.translate = Some(translate.to_array());
+    }
+
+    // 今の変換の外側から回す。別の軸でもう回っていると 1 つの rotate では書けないので false
+    pub fn rotate_by(&mut self, axis: Vec3, angle: f64) -> bool {
+        let axis = axis.normalize();
+        match self.rotate {
+            Some(_) if (vec3(self.rotate_axis).normalize() - axis).length() > 1e-9 => {
+                return false;
+            }
+            Some(current) => self.rotate = Some(current + angle),
+            None => {
+                self.rotate = Some(angle);
+                self.rotate_axis = axis.to_array();
+            }
+        }
+        if let Some(translate) = self.translate {
+            let quat = Quat::from_rot(axis, angle.to_radians());
+            self.translate = Some(quat.rotate(vec3(translate)).to_array());
+        }
+        true
+    }
+}
+
+// 組み立て済みのシーンを書き出すときに、形状とマテリアルを集める
+// シーンファイルで書けない形状 (メッシュや媒質など) は数えるだけで飛ばす
+#[derive(Default)]
+pub struct SceneWriter {
+    // 同じマテリアルを指す形状は同じ名前で参照する
+    names: HashMap<*const (), String>,
+    materials: BTreeMap<String, MaterialDesc>,
+    shapes: Vec<ShapeDesc>,
+    skipped: usize,
+}
+
+impl SceneWriter {
+    pub fn push(&mut self, kind: ShapeKind, material: &Arc<dyn Material>) {
+        match self.material(material) {
+            Some(name) => self.shapes.push(ShapeDesc::new(kind, name)),
+            None => self.skipped += 1,
+        }
+    }
+
+    pub fn skip(&mut self) {
+        self.skipped += 1;
+    }
+
+    pub fn mark(&self) -> usize {
+        self.shapes.len()
+    }
+
+    // mark より後に足した形状に装飾の変換をかける。f が false を返した形状は飛ばす
+    pub fn transform(&mut self, mark: usize, f: impl Fn(&mut ShapeDesc) -> bool) {
+        for mut shape in self.shapes.split_off(mark) {
+            if f(&mut shape) {
+                self.shapes.push(shape);
+            } else {
+                self.skipped += 1;
+            }
+        }
+    }
+
+    fn material(&mut self, material: &Arc<dyn Material>) -> Option<String> {
+        let key = Arc::as_ptr(material) as *const ();
+        if let Some(name) = self.names.get(&key) {
+            return Some(name.clone());
+        }
+        let desc = material.describe()?;
+        let name = format!("{}{}", material.name().to_lowercase(), self.materials.len());
+        self.names.insert(key, name.clone());
+        self.materials.insert(name.clone(), desc);
+        Some(name)
+    }
+}
+
+// 書けない背景は真上と真下の色で、一色かグラデーションとして書く
+fn describe_background(scene: &impl WorldScene) -> BackgroundSpec {
+    if let Some(spec) = scene.background().describe() {
+        return spec;
+    }
+    let bottom = scene.background().value(-Vec3::yaxis());
+    let top = scene.background().value(Vec3::yaxis());
+    if bottom == top {
+        BackgroundSpec::Color(top.to_array())
+    } else {
+        BackgroundSpec::Gradient {
+            bottom: bottom.to_array(),
+            top: top.to_array(),
+        }
+    }
+}
+
+// 組み立て済みのシーンを --scene-file で読める TOML に書き出し、飛ばした形状の数を返す
+// 画像の大きさなどは config で指定がなければシーンの既定値を書く
+pub fn export_scene(
+    scene: &impl WorldScene,
+    config: &RenderConfig,
+    path: &str,
+) -> Result<usize, Error> {
+    let camera = scene.camera();
+    let look_at = camera.look_at().ok_or_else(|| {
+        Error::Invalid(format!(
+            "{}: only perspective cameras can be exported",
+            path
+        ))
+    })?;
+    let shutter = camera.shutter();
+    let mut writer = SceneWriter::default();
+    scene.world().export(&mut writer);
+    let file = SceneFile {
+        render: RenderSection {
+            width: Some(config.width.unwrap_or(scene.width())),
+            height: Some(config.height.unwrap_or(scene.height())),
+            samples: Some(config.spp.unwrap_or(scene.spp())),
+            max_depth: Some(config.max_depth.unwrap_or(scene.max_depth())),
+            output: None,
+        },
+        camera: Some(CameraSection {
+            shutter: (shutter.close > shutter.open).then_some([shutter.open, shutter.close]),
+            ..CameraSection::from_look_at(&look_at)
+        }),
+        background: describe_background(scene),
+        materials: writer.materials,
+        shapes: writer.shapes,
+        lights: scene
+            .lights()
+            .iter()
+            .filter_map(|light| {
+                Some(LightEntry {
+                    kind: light.describe()?,
+                    group: light.group().map(str::to_string),
+                })
+            })
+            .collect(),
+        fog: scene.fog().map(FogSection::describe),
+    };
+    let error = |e: &dyn std::fmt::Display| Error::File(format!("{}: {}", path, e));
+    let text = to_toml(&file).map_err(|e| error(&e))?;
+    std::fs::write(path, text).map_err(|e| error(&e))?;
+    Ok(writer.skipped)
+}
@@ -0,0 +1,887 @@
+use crate::rayt::*;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+pub struct SimpleScene {
+    world: ShapeList,
+    background: Box<dyn Background>,
+    stats: Option<PathStats>,
+    seed: Option<u64>,
+    polarizer: Option<f64>,
+    distortion: LensDistortion,
+    strata: u32,
+    sampler: SamplerKind,
+    clamp: RadianceClamp,
+    colored_shadows: bool,
+}
+
+impl SimpleScene {
+    pub fn new() -> Result<Self, Error> {
+        let mut world = ShapeList::new();
+        // world.push(
+        //     ShapeBuilder::new()
+        //         .image_texture("resources/Bricks082A_1K_Color.jpg")
+        //         .lambertian()
+        //         .sphere(Point3::new(0.6, 0.0, -1.0), 0.5)
+        //         .build(),
+        // );
+        // world.push(
+        //     ShapeBuilder::new()
+        //         .color_texture(Color::new(0.8, 0.8, 0.8))
+        //         .metal(0.4)
+        //         .sphere(Point3::new(-0.6, 0.0, -1.0), 0.5)
+        //         .build(),
+        // );
+        // world.push(
+        //     ShapeBuilder::new()
+        //         .checker_texture(Color::new(0.8, 0.8, 0.0), Color::new(0.8, 0.2, 0.0), 10.0)
+        //         .lambertian()
+        //         .sphere(Point3::new(0.0, -100.5, -1.0), 100.0)
+        //         .build(),
+        // );
+        // world.push(
+        //     ShapeBuilder::new()
+        //         .color_texture(Color::one())
+        //         .diffuse_light()
+        //         .sphere(Point3::new(0.0, 0.0, -1.0), 0.5)
+        //         .build(),
+        // );
+        // world.push(
+        //     ShapeBuilder::new()
+        //         .color_texture(Color::fill(0.8))
+        //         .lambertian()
+        //         .sphere(Point3::new(0.0, -100.5, -1.0), 100.0)
+        //         .build(),
+        // );
+        let mut storage = SceneStorage::new();
+        ShapeBuilder::new()
+            .color_texture(Color::fill(0.5))
+            .lambertian()
+            .add_sphere(&mut storage, Point3::new(0.0, 2.0, 0.0), 2.0)?;
+        ShapeBuilder::new()
+            .color_texture(Color::fill(4.0))
+            .diffuse_light()
+            .rect_xy(3.0, 5.0, 1.0, 3.0, -2.0)
+            .add_to(&mut storage)?;
+        ShapeBuilder::new()
+            .color_texture(Color::fill(0.8))
+            .lambertian()
+            .add_sphere(&mut storage, Point3::new(0.0, -1000.0, 0.0), 1000.0)?;
+        world.push(ShapeEnum::boxed(storage));
+        Ok(Self {
+            world,
+            background: Box::new(ColorBackground::new(Color::fill(0.1))),
+            stats: None,
+            seed: None,
+            polarizer: None,
+            distortion: LensDistortion::default(),
+            strata: 1,
+            sampler: SamplerKind::Random,
+            clamp: RadianceClamp::Off,
+            colored_shadows: false,
+        })
+    }
+    pub fn with_path_stats(self) -> Self {
+        Self {
+            stats: Some(PathStats::new(MAX_RAY_BOUNCE_DEPTH)),
+            ..self
+        }
+    }
+    pub fn with_seed(self, seed: Option<u64>) -> Self {
+        Self { seed, ..self }
+    }
+    pub fn with_polarizer(self, polarizer: Option<f64>) -> Self {
+        Self { polarizer, ..self }
+    }
+    pub fn with_distortion(self, distortion: LensDistortion) -> Self {
+        Self { distortion, ..self }
+    }
+    pub fn with_strata(self, strata: u32) -> Self {
+        Self { strata, ..self }
+    }
+
+    pub fn with_sampler(self, sampler: SamplerKind) -> Self {
+        Self { sampler, ..self }
+    }
+
+    pub fn with_radiance_clamp(self, clamp: RadianceClamp) -> Self {
+        Self { clamp, ..self }
+    }
+
+    pub fn with_colored_shadows(self, colored_shadows: bool) -> Self {
+        Self {
+            colored_shadows,
+            ..self
+        }
+    }
+
+    pub fn with_background(self, background: impl Background + 'static) -> Self {
+        Self {
+            background: Box::new(background),
+            ..self
+        }
+    }
+
+    // フレームの合間に形状を足したり取り除いたりする
+    pub fn world_mut(&mut self) -> &mut ShapeList {
+        &mut self.world
+    }
+}
+
+impl WorldScene for SimpleScene {
+    fn world(&self) -> &ShapeList {
+        &self.world
+    }
+    fn background(&self) -> &dyn Background {
+        &*self.background
+    }
+    fn colored_shadows(&self) -> bool {
+        self.colored_shadows
+    }
+}
+
+impl SceneWithDepth for SimpleScene {
+    fn camera(&self) -> Box<dyn Camera> {
+        // PerspectiveCamera::new(
+        //     Vec3::new(4.0, 0.0, 0.0),
+        //     Vec3::new(0.0, 2.0, 0.0),
+        //     Vec3::new(-2.0, -1.0, -1.0),
+        // )
+        Box::new(
+            PerspectiveCamera::from_look_at(
+                Vec3::new(13.0, 2.0, 3.0),
+                Vec3::yaxis(),
+                Vec3::yaxis(),
+                30.0,
+                self.aspect(),
+            )
+            .with_distortion(self.distortion),
+        )
+    }
+    fn trace(&self, ray: Ray, depth: usize) -> Color {
+        trace_scene(self, ray, depth)
+    }
+    fn path_stats(&self) -> Option<&PathStats> {
+        self.stats.as_ref()
+    }
+    fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+    fn strata(&self) -> u32 {
+        self.strata
+    }
+    fn sampler(&self) -> SamplerKind {
+        self.sampler
+    }
+    fn radiance_clamp(&self) -> RadianceClamp {
+        self.clamp
+    }
+    fn polarizer(&self) -> Option<f64> {
+        self.polarizer
+    }
+    fn matte(&self, ray: &Ray) -> Option<&'static str> {
+        self.world.hit(ray, 0.001, f64::MAX)?.matte
+    }
+    fn position(&self, ray: &Ray) -> Option<Point3> {
+        self.world.hit(ray, 0.001, f64::MAX).map(|hit| hit.p)
+    }
+    fn aov(&self, ray: &Ray) -> Option<Aov> {
+        self.world.aov(ray)
+    }
+}
+
+// 「週末レイトレーシング」の最後のシーン。seed を渡すと小球の並びも固定する
+pub struct RandomScene {
+    world: ShapeList,
+    background: Box<dyn Background>,
+    stats: Option<PathStats>,
+    seed: Option<u64>,
+    polarizer: Option<f64>,
+    distortion: LensDistortion,
+    strata: u32,
+    sampler: SamplerKind,
+    clamp: RadianceClamp,
+    colored_shadows: bool,
+}
+
+pub fn random_color(rng: &mut StdRng, min: f64, max: f64) -> Color {
+    Color::new(
+        rng.gen_range(min..max),
+        rng.gen_range(min..max),
+        rng.gen_range(min..max),
+    )
+}
+
+impl RandomScene {
+    pub fn new(seed: Option<u64>) -> Result<Self, Error> {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut storage = SceneStorage::new();
+        ShapeBuilder::new()
+            .color_texture(Color::fill(0.5))
+            .lambertian()
+            .add_sphere(&mut storage, Point3::new(0.0, -1000.0, 0.0), 1000.0)?;
+        // Small spheres
+        for a in -11..11 {
+            for b in -11..11 {
+                let center = Point3::new(
+                    a as f64 + 0.9 * rng.gen::<f64>(),
+                    0.2,
+                    b as f64 + 0.9 * rng.gen::<f64>(),
+                );
+                let material_choice = rng.gen::<f64>();
+                if (center - Point3::new(4.0, 0.2, 0.0)).length() <= 0.9 {
+                    continue;
+                }
+                let builder = if material_choice < 0.8 {
+                    let albedo =
+                        random_color(&mut rng, 0.0, 1.0) * random_color(&mut rng, 0.0, 1.0);
+                    ShapeBuilder::new().color_texture(albedo).lambertian()
+                } else if material_choice < 0.95 {
+                    let albedo = random_color(&mut rng, 0.5, 1.0);
+                    let fuzz = rng.gen_range(0.0..0.5);
+                    ShapeBuilder::new().color_texture(albedo).metal(fuzz)
+                } else {
+                    ShapeBuilder::new().dielectric(1.5)
+                };
+                builder.add_sphere(&mut storage, center, 0.2)?;
+            }
+        }
+        // Big spheres
+        ShapeBuilder::new().dielectric(1.5).add_sphere(
+            &mut storage,
+            Point3::new(0.0, 1.0, 0.0),
+            1.0,
+        )?;
+        ShapeBuilder::new()
+            .color_texture(Color::new(0.4, 0.2, 0.1))
+            .lambertian()
+            .add_sphere(&mut storage, Point3::new(-4.0, 1.0, 0.0), 1.0)?;
+        ShapeBuilder::new()
+            .color_texture(Color::new(0.7, 0.6, 0.5))
+            .metal(0.0)
+            .add_sphere(&mut storage, Point3::new(4.0, 1.0, 0.0), 1.0)?;
+        let mut world = ShapeList::new();
+        world.push(ShapeEnum::boxed(storage));
+        Ok(Self {
+            world,
+            background: Box::new(GradientBackground::new(
+                Color::one(),
+                Color::new(0.5, 0.7, 1.0),
+            )),
+            stats: None,
+            seed,
+            polarizer: None,
+            distortion: LensDistortion::default(),
+            strata: 1,
+            sampler: SamplerKind::Random,
+            clamp: RadianceClamp::Off,
+            colored_shadows: false,
+        })
+    }
+    pub fn with_path_stats(self) -> Self {
+        Self {
+            stats: Some(PathStats::new(MAX_RAY_BOUNCE_DEPTH)),
+            ..self
+        }
+    }
+    pub fn with_polarizer(self, polarizer: Option<f64>) -> Self {
+        Self { polarizer, ..self }
+    }
+    pub fn with_distortion(self, distortion: LensDistortion) -> Self {
+        Self { distortion, ..self }
+    }
+    pub fn with_strata(self, strata: u32) -> Self {
+        Self { strata, ..self }
+    }
+    pub fn with_sampler(self, sampler: SamplerKind) -> Self {
+        Self { sampler, ..self }
+    }
+    pub fn with_radiance_clamp(self, clamp: RadianceClamp) -> Self {
+        Self { clamp, ..self }
+    }
+    pub fn with_colored_shadows(self, colored_shadows: bool) -> Self {
+        Self {
+            colored_shadows,
+            ..self
+        }
+    }
+    pub fn with_background(self, background: impl Background + 'static) -> Self {
+        Self {
+            background: Box::new(background),
+            ..self
+        }
+    }
+
+    // フレームの合間に形状を足したり取り除いたりする
+    pub fn world_mut(&mut self) -> &mut ShapeList {
+        &mut self.world
+    }
+}
+
+impl WorldScene for RandomScene {
+    fn world(&self) -> &ShapeList {
+        &self.world
+    }
+    fn background(&self) -> &dyn Background {
+        &*self.background
+    }
+    fn colored_shadows(&self) -> bool {
+        self.colored_shadows
+    }
+}
+
+impl SceneWithDepth for RandomScene {
+    fn camera(&self) -> Box<dyn Camera> {
+        Box::new(
+            PerspectiveCamera::from_look_at(
+                Point3::new(13.0, 2.0, 3.0),
+                Point3::zero(),
+                Vec3::yaxis(),
+                20.0,
+                self.aspect(),
+            )
+            .with_distortion(self.distortion),
+        )
+    }
+    fn trace(&self, ray: Ray, depth: usize) -> Color {
+        trace_scene(self, ray, depth)
+    }
+    fn path_stats(&self) -> Option<&PathStats> {
+        self.stats.as_ref()
+    }
+    fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+    fn strata(&self) -> u32 {
+        self.strata
+    }
+    fn sampler(&self) -> SamplerKind {
+        self.sampler
+    }
+    fn radiance_clamp(&self) -> RadianceClamp {
+        self.clamp
+    }
+    fn polarizer(&self) -> Option<f64> {
+        self.polarizer
+    }
+    fn matte(&self, ray: &Ray) -> Option<&'static str> {
+        self.world.hit(ray, 0.001, f64::MAX)?.matte
+    }
+    fn position(&self, ray: &Ray) -> Option<Point3> {
+        self.world.hit(ray, 0.001, f64::MAX).map(|hit| hit.p)
+    }
+    fn aov(&self, ray: &Ray) -> Option<Aov> {
+        self.world.aov(ray)
+    }
+}
+
+// 「次の週末」の最後のシーン。箱を敷き詰めた床に動く球、ガラスと金属の球、
+// 煙、Perlin ノイズの球、小球の塊、画像テクスチャの球を並べる
+// 地球の画像は同梱していないので、レンガの画像で代用する
+pub struct FinalScene {
+    world: ShapeList,
+    background: Box<dyn Background>,
+    stats: Option<PathStats>,
+    seed: Option<u64>,
+    polarizer: Option<f64>,
+    distortion: LensDistortion,
+    strata: u32,
+    sampler: SamplerKind,
+    clamp: RadianceClamp,
+    colored_shadows: bool,
+}
+
+impl FinalScene {
+    pub fn new(seed: Option<u64>) -> Result<Self, Error> {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut world = ShapeList::new();
+        // Ground
+        let mut ground = SceneStorage::new();
+        let ground_material = ShapeBuilder::new()
+            .color_texture(Color::new(0.48, 0.83, 0.53))
+            .lambertian()
+            .add_material(&mut ground)?;
+        let boxes_per_side = 20;
+        for i in 0..boxes_per_side {
+            for j in 0..boxes_per_side {
+                let w = 100.0;
+                let x0 = -1000.0 + i as f64 * w;
+                let z0 = -1000.0 + j as f64 * w;
+                let y1 = rng.gen_range(1.0..101.0);
+                ShapeBuilder::new()
+                    .material_id(&ground, ground_material)
+                    .box_shape(Point3::new(x0, 0.0, z0), Point3::new(x0 + w, y1, z0 + w))
+                    .add_to(&mut ground)?;
+            }
+        }
+        world.push(ShapeEnum::boxed(ground));
+        // Light
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(Color::fill(7.0))
+                .diffuse_light()
+                .rect_xz(123.0, 423.0, 147.0, 412.0, 554.0)
+                .flip_face()
+                .build()?,
+        );
+        // Moving sphere
+        let center0 = Point3::new(400.0, 400.0, 200.0);
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(Color::new(0.7, 0.3, 0.1))
+                .lambertian()
+                .moving_sphere(center0, center0 + Vec3::new(30.0, 0.0, 0.0), 0.0, 1.0, 50.0)
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .dielectric(1.5)
+                .sphere(Point3::new(260.0, 150.0, 45.0), 50.0)
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(Color::new(0.8, 0.8, 0.9))
+                .metal(1.0)
+                .sphere(Point3::new(0.0, 150.0, 145.0), 50.0)
+                .build()?,
+        );
+        // Smoke
+        world.push(
+            ShapeBuilder::new()
+                .dielectric(1.5)
+                .sphere(Point3::new(360.0, 150.0, 145.0), 70.0)
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .dielectric(1.5)
+                .sphere(Point3::new(360.0, 150.0, 145.0), 70.0)
+                .color_texture(Color::new(0.2, 0.4, 0.9))
+                .constant_medium(0.2)
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .dielectric(1.5)
+                .sphere(Point3::zero(), 5000.0)
+                .color_texture(Color::one())
+                .constant_medium(0.0001)
+                .build()?,
+        );
+        // Textured spheres
+        world.push(
+            ShapeBuilder::new()
+                .image_texture("resources/Bricks082A_1K_Color.jpg")
+                .lambertian()
+                .sphere(Point3::new(400.0, 200.0, 400.0), 100.0)
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .marble_texture(0.1, Vec3::zaxis())
+                .lambertian()
+                .sphere(Point3::new(220.0, 280.0, 300.0), 80.0)
+                .build()?,
+        );
+        // Sphere cluster
+        let mut cluster = SceneStorage::new();
+        let white = ShapeBuilder::new()
+            .color_texture(Color::fill(0.73))
+            .lambertian()
+            .add_material(&mut cluster)?;
+        for _ in 0..1000 {
+            let center = Point3::new(
+                rng.gen_range(0.0..165.0),
+                rng.gen_range(0.0..165.0),
+                rng.gen_range(0.0..165.0),
+            );
+            cluster.add_sphere(center, 10.0, white);
+        }
+        world.push(ShapeEnum::boxed(Translate::new(
+            Box::new(Rotate::new(Box::new(cluster), Vec3::yaxis(), 15.0)),
+            Point3::new(-100.0, 270.0, 395.0),
+        )));
+        Ok(Self {
+            world,
+            background: Box::new(ColorBackground::new(Color::zero())),
+            stats: None,
+            seed,
+            polarizer: None,
+            distortion: LensDistortion::default(),
+            strata: 1,
+            sampler: SamplerKind::Random,
+            clamp: RadianceClamp::Off,
+            colored_shadows: false,
+        })
+    }
+    pub fn with_path_stats(self) -> Self {
+        Self {
+            stats: Some(PathStats::new(MAX_RAY_BOUNCE_DEPTH)),
+            ..self
+        }
+    }
+    pub fn with_polarizer(self, polarizer: Option<f64>) -> Self {
+        Self { polarizer, ..self }
+    }
+    pub fn with_distortion(self, distortion: LensDistortion) -> Self {
+        Self { distortion, ..self }
+    }
+    pub fn with_strata(self, strata: u32) -> Self {
+        Self { strata, ..self }
+    }
+    pub fn with_sampler(self, sampler: SamplerKind) -> Self {
+        Self { sampler, ..self }
+    }
+    pub fn with_radiance_clamp(self, clamp: RadianceClamp) -> Self {
+        Self { clamp, ..self }
+    }
+    pub fn with_colored_shadows(self, colored_shadows: bool) -> Self {
+        Self {
+            colored_shadows,
+            ..self
+        }
+    }
+    pub fn with_background(self, background: impl Background + 'static) -> Self {
+        Self {
+            background: Box::new(background),
+            ..self
+        }
+    }
+
+    // フレームの合間に形状を足したり取り除いたりする
+    pub fn world_mut(&mut self) -> &mut ShapeList {
+        &mut self.world
+    }
+}
+
+impl WorldScene for FinalScene {
+    fn world(&self) -> &ShapeList {
+        &self.world
+    }
+    fn background(&self) -> &dyn Background {
+        &*self.background
+    }
+    fn colored_shadows(&self) -> bool {
+        self.colored_shadows
+    }
+}
+
+impl SceneWithDepth for FinalScene {
+    fn camera(&self) -> Box<dyn Camera> {
+        Box::new(
+            PerspectiveCamera::from_look_at(
+                Point3::new(478.0, 278.0, -600.0),
+                Point3::new(278.0, 278.0, 0.0),
+                Vec3::yaxis(),
+                40.0,
+                self.aspect(),
+            )
+            .with_shutter(0.0, 1.0)
+            .with_distortion(self.distortion),
+        )
+    }
+    fn trace(&self, ray: Ray, depth: usize) -> Color {
+        trace_scene(self, ray, depth)
+    }
+    fn path_stats(&self) -> Option<&PathStats> {
+        self.stats.as_ref()
+    }
+    fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+    fn strata(&self) -> u32 {
+        self.strata
+    }
+    fn sampler(&self) -> SamplerKind {
+        self.sampler
+    }
+    fn radiance_clamp(&self) -> RadianceClamp {
+        self.clamp
+    }
+    fn polarizer(&self) -> Option<f64> {
+        self.polarizer
+    }
+    fn matte(&self, ray: &Ray) -> Option<&'static str> {
+        self.world.hit(ray, 0.001, f64::MAX)?.matte
+    }
+    fn position(&self, ray: &Ray) -> Option<Point3> {
+        self.world.hit(ray, 0.001, f64::MAX).map(|hit| hit.p)
+    }
+    fn aov(&self, ray: &Ray) -> Option<Aov> {
+        self.world.aov(ray)
+    }
+}
+
+pub struct CornelBoxScene {
+    world: ShapeList,
+    background: Box<dyn Background>,
+    lights: Vec<AreaLight>,
+    photon_map: Option<PhotonMap>,
+    stats: Option<PathStats>,
+    seed: Option<u64>,
+    polarizer: Option<f64>,
+    distortion: LensDistortion,
+    strata: u32,
+    sampler: SamplerKind,
+    clamp: RadianceClamp,
+    colored_shadows: bool,
+}
+
+impl CornelBoxScene {
+    pub fn new() -> Result<Self, Error> {
+        let mut world = ShapeList::new();
+
+        let red = Color::new(0.64, 0.05, 0.05);
+        let white = Color::fill(0.73);
+        let green = Color::new(0.12, 0.45, 0.15);
+
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(green)
+                .lambertian()
+                .rect_yz(0.0, 555.0, 0.0, 555.0, 555.0)
+                .flip_face()
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(red)
+                .lambertian()
+                .rect_yz(0.0, 555.0, 0.0, 555.0, 0.0)
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(Color::fill(15.0))
+                .diffuse_light()
+                .rect_xz(213.0, 343.0, 227.0, 332.0, 554.0)
+                .build()?,
+        );
+        let lights = vec![AreaLight::new(
+            Point3::new(213.0, 554.0, 227.0),
+            Vec3::new(130.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 105.0),
+            Color::fill(15.0),
+        )];
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(white)
+                .lambertian()
+                .rect_xz(0.0, 555.0, 0.0, 555.0, 555.0)
+                .flip_face()
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(white)
+                .lambertian()
+                .rect_xz(0.0, 555.0, 0.0, 555.0, 0.0)
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(white)
+                .lambertian()
+                .rect_xy(0.0, 555.0, 0.0, 555.0, 555.0)
+                .flip_face()
+                .build()?,
+        );
+
+        // world.push(
+        //     ShapeBuilder::new()
+        //         .color_texture(white)
+        //         .lambertian()
+        //         .box_shape(
+        //             Point3::new(130.0, 0.0, 65.0),
+        //             Point3::new(295.0, 165.0, 230.0),
+        //         )
+        //         .build(),
+        // );
+        // world.push(
+        //     ShapeBuilder::new()
+        //         .color_texture(white)
+        //         .lambertian()
+        //         .box_shape(
+        //             Point3::new(265.0, 0.0, 295.0),
+        //             Point3::new(430.0, 330.0, 460.0),
+        //         )
+        //         .build(),
+        // );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(white)
+                .lambertian()
+                .box_shape(Point3::zero(), Point3::fill(165.0))
+                .rotate(Vec3::yaxis(), -18.0)
+                .translate(Point3::new(130.0, 0.0, 65.0))
+                .build()?,
+        );
+        world.push(
+            ShapeBuilder::new()
+                .color_texture(white)
+                .lambertian()
+                .box_shape(Point3::zero(), Point3::new(165.0, 330.0, 165.0))
+                .rotate(Vec3::yaxis(), 15.0)
+                .translate(Point3::new(265.0, 0.0, 295.0))
+                .build()?,
+        );
+
+        Ok(Self {
+            world,
+            background: Box::new(ColorBackground::new(Color::zero())),
+            lights,
+            photon_map: None,
+            stats: None,
+            seed: None,
+            polarizer: None,
+            distortion: LensDistortion::default(),
+            strata: 1,
+            sampler: SamplerKind::Random,
+            clamp: RadianceClamp::Off,
+            colored_shadows: false,
+        })
+    }
+    pub fn with_path_stats(self) -> Self {
+        Self {
+            stats: Some(PathStats::new(MAX_RAY_BOUNCE_DEPTH)),
+            ..self
+        }
+    }
+    pub fn with_seed(self, seed: Option<u64>) -> Self {
+        Self { seed, ..self }
+    }
+    pub fn with_polarizer(self, polarizer: Option<f64>) -> Self {
+        Self { polarizer, ..self }
+    }
+    pub fn with_distortion(self, distortion: LensDistortion) -> Self {
+        Self { distortion, ..self }
+    }
+    pub fn with_strata(self, strata: u32) -> Self {
+        Self { strata, ..self }
+    }
+
+    pub fn with_sampler(self, sampler: SamplerKind) -> Self {
+        Self { sampler, ..self }
+    }
+
+    pub fn with_radiance_clamp(self, clamp: RadianceClamp) -> Self {
+        Self { clamp, ..self }
+    }
+
+    pub fn with_colored_shadows(self, colored_shadows: bool) -> Self {
+        Self {
+            colored_shadows,
+            ..self
+        }
+    }
+
+    pub fn with_background(self, background: impl Background + 'static) -> Self {
+        Self {
+            background: Box::new(background),
+            ..self
+        }
+    }
+
+    // フレームの合間に形状を足したり取り除いたりする
+    // フォトンマップは作り直さないので、変えたあとは with_photons をもう一度呼ぶ
+    pub fn world_mut(&mut self) -> &mut ShapeList {
+        &mut self.world
+    }
+
+    // 手前右の床にガラス球を置く
+    pub fn with_glass_sphere(mut self, ri: f64) -> Result<Self, Error> {
+        self.world.push(
+            ShapeBuilder::new()
+                .dielectric(ri)
+                .sphere(Point3::new(420.0, 70.0, 120.0), 70.0)
+                .build()?,
+        );
+        Ok(self)
+    }
+
+    // シーンを組み終えてから呼ぶ。seed があればフォトンの系列も固定する
+    pub fn with_photons(self, photons: usize) -> Self {
+        let photon_map =
+            (photons > 0).then(|| trace_photons(&self.world, &self.lights, photons, self.seed));
+        Self { photon_map, ..self }
+    }
+}
+
+impl WorldScene for CornelBoxScene {
+    fn world(&self) -> &ShapeList {
+        &self.world
+    }
+    fn photon_map(&self) -> Option<&PhotonMap> {
+        self.photon_map.as_ref()
+    }
+    fn background(&self) -> &dyn Background {
+        &*self.background
+    }
+    fn colored_shadows(&self) -> bool {
+        self.colored_shadows
+    }
+}
+
+impl SceneWithDepth for CornelBoxScene {
+    fn camera(&self) -> Box<dyn Camera> {
+        Box::new(
+            PerspectiveCamera::from_look_at(
+                Vec3::new(278.0, 278.0, -800.0),
+                Vec3::new(278.0, 278.0, 0.0),
+                Vec3::yaxis(),
+                40.0,
+                self.aspect(),
+            )
+            .with_distortion(self.distortion),
+        )
+    }
+    fn trace(&self, ray: Ray, depth: usize) -> Color {
+        trace_scene(self, ray, depth)
+    }
+    fn path_stats(&self) -> Option<&PathStats> {
+        self.stats.as_ref()
+    }
+    fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+    fn strata(&self) -> u32 {
+        self.strata
+    }
+    fn sampler(&self) -> SamplerKind {
+        self.sampler
+    }
+    fn radiance_clamp(&self) -> RadianceClamp {
+        self.clamp
+    }
+    fn caustics(&self) -> CausticSettings {
+        CausticSettings {
+            photon_radius: 20.0,
+            ..Default::default()
+        }
+    }
+    fn polarizer(&self) -> Option<f64> {
+        self.polarizer
+    }
+    fn matte(&self, ray: &Ray) -> Option<&'static str> {
+        self.world.hit(ray, 0.001, f64::MAX)?.matte
+    }
+    fn position(&self, ray: &Ray) -> Option<Point3> {
+        self.world.hit(ray, 0.001, f64::MAX).map(|hit| hit.p)
+    }
+    fn aov(&self, ray: &Ray) -> Option<Aov> {
+        self.world.aov(ray)
+    }
+    fn width(&self) -> u32 {
+        200
+    }
+    fn height(&self) -> u32 {
+        200
+    }
+}
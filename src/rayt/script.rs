@@ -0,0 +1,347 @@
+use crate::rayt::*;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rhai::{Engine, EvalAltResult};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// スクリプトの shape() から始まる呼び出しを 1 つずつ記録したもの
+// add() のときに ShapeBuilder で組み立て直すので、途中まで作ったものを使い回せる
+#[derive(Clone)]
+enum Step {
+    Color(Color),
+    Checker(Color, Color, f64),
+    Blackbody(f64, f64),
+    Noise(f64),
+    Marble(f64, Vec3),
+    Worley(f64),
+    Image(String),
+    Lambertian,
+    Metal(f64),
+    Dielectric(f64),
+    NestedDielectric(f64, u32),
+    DiffuseLight,
+    Sphere(Point3, f64),
+    MovingSphere(Point3, Point3, f64),
+    RectXy(f64, f64, f64, f64, f64),
+    RectXz(f64, f64, f64, f64, f64),
+    RectYz(f64, f64, f64, f64, f64),
+    Box(Point3, Point3),
+    Obj(String),
+    FlipFace,
+    Rotate(Vec3, f64),
+    Translate(Vec3),
+    ConstantMedium(f64),
+    NoiseMedium(f64, f64),
+    VoxelMedium(f64, String),
+}
+
+#[derive(Clone, Default)]
+struct ScriptShape {
+    steps: Vec<Step>,
+}
+
+impl ScriptShape {
+    fn then(&self, step: Step) -> Self {
+        let mut steps = self.steps.clone();
+        steps.push(step);
+        Self { steps }
+    }
+
+    // 順番が ShapeBuilder の約束 (テクスチャ → マテリアル → 形状 → 装飾) に
+    // 合っていなければ ShapeBuilder がエラーを返す
+    fn build(&self) -> Result<ShapeEnum, String> {
+        let mut builder = ShapeBuilder::new();
+        for step in &self.steps {
+            builder = match step.clone() {
+                Step::Color(color) => builder.color_texture(color),
+                Step::Checker(odd, even, freq) => builder.checker_texture(odd, even, freq),
+                Step::Blackbody(kelvin, intensity) => builder.blackbody_texture(kelvin, intensity),
+                Step::Noise(scale) => builder.noise_texture(scale),
+                Step::Marble(scale, axis) => builder.marble_texture(scale, axis),
+                Step::Worley(scale) => builder.worley_texture(scale),
+                Step::Image(path) => builder.image_texture(&path),
+                Step::Lambertian => builder.lambertian(),
+                Step::Metal(fuzz) => builder.metal(fuzz),
+                Step::Dielectric(ri) => builder.dielectric(ri),
+                Step::NestedDielectric(ri, priority) => {
+                    builder.nested_dielectric(ri, Color::one(), priority)
+                }
+                Step::DiffuseLight => builder.diffuse_light(),
+                Step::Sphere(center, radius) => builder.sphere(center, radius),
+                Step::MovingSphere(center0, center1, radius) => {
+                    builder.moving_sphere(center0, center1, 0.0, 1.0, radius)
+                }
+                Step::RectXy(x0, x1, y0, y1, k) => builder.rect_xy(x0, x1, y0, y1, k),
+                Step::RectXz(x0, x1, z0, z1, k) => builder.rect_xz(x0, x1, z0, z1, k),
+                Step::RectYz(y0, y1, z0, z1, k) => builder.rect_yz(y0, y1, z0, z1, k),
+                Step::Box(p0, p1) => builder.box_shape(p0, p1),
+                Step::Obj(path) => builder.obj(&path),
+                Step::FlipFace => builder.flip_face(),
+                Step::Rotate(axis, angle) => builder.rotate(axis, angle),
+                Step::Translate(offset) => builder.translate(offset),
+                Step::ConstantMedium(density) => builder.constant_medium(density),
+                Step::NoiseMedium(density, scale) => builder.noise_medium(density, scale),
+                Step::VoxelMedium(density, path) => builder.voxel_medium(density, &path),
+            };
+        }
+        builder.build().map_err(|e| e.to_string())
+    }
+}
+
+// スクリプトの実行中に組み立てていくもの
+struct ScriptState {
+    world: ShapeList,
+    camera: Option<CameraSection>,
+    background: Box<dyn Background>,
+    fog: Option<Fog>,
+    // コマンドラインで指定がなければこちらを使う
+    samples: Option<usize>,
+    max_depth: Option<usize>,
+    rng: StdRng,
+}
+
+fn register_vec3(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<Vec3>("Vec3")
+        .register_fn("vec3", Vec3::new)
+        .register_get("x", |v: &mut Vec3| v.x())
+        .register_get("y", |v: &mut Vec3| v.y())
+        .register_get("z", |v: &mut Vec3| v.z())
+        .register_fn("+", |a: Vec3, b: Vec3| a + b)
+        .register_fn("-", |a: Vec3, b: Vec3| a - b)
+        .register_fn("*", |a: Vec3, b: Vec3| a * b)
+        .register_fn("*", |a: Vec3, s: f64| a * s)
+        .register_fn("*", |s: f64, a: Vec3| s * a)
+        .register_fn("length", |v: &mut Vec3| v.length())
+        .register_fn("normalize", |v: &mut Vec3| v.normalize())
+        .register_fn("to_string", |v: &mut Vec3| {
+            format!("vec3({}, {}, {})", v.x(), v.y(), v.z())
+        });
+}
+
+fn register_shape(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptShape>("Shape")
+        .register_fn("shape", ScriptShape::default)
+        // textures
+        .register_fn("color", |s: &mut ScriptShape, color: Vec3| {
+            s.then(Step::Color(color))
+        })
+        .register_fn(
+            "checker",
+            |s: &mut ScriptShape, odd: Vec3, even: Vec3, freq: f64| {
+                s.then(Step::Checker(odd, even, freq))
+            },
+        )
+        .register_fn(
+            "blackbody",
+            |s: &mut ScriptShape, kelvin: f64, intensity: f64| {
+                s.then(Step::Blackbody(kelvin, intensity))
+            },
+        )
+        .register_fn("noise", |s: &mut ScriptShape, scale: f64| {
+            s.then(Step::Noise(scale))
+        })
+        .register_fn("marble", |s: &mut ScriptShape, scale: f64, axis: Vec3| {
+            s.then(Step::Marble(scale, axis))
+        })
+        .register_fn("worley", |s: &mut ScriptShape, scale: f64| {
+            s.then(Step::Worley(scale))
+        })
+        .register_fn("image", |s: &mut ScriptShape, path: &str| {
+            s.then(Step::Image(path.to_string()))
+        })
+        // materials
+        .register_fn("lambertian", |s: &mut ScriptShape| s.then(Step::Lambertian))
+        .register_fn("metal", |s: &mut ScriptShape, fuzz: f64| {
+            s.then(Step::Metal(fuzz))
+        })
+        .register_fn("dielectric", |s: &mut ScriptShape, ri: f64| {
+            s.then(Step::Dielectric(ri))
+        })
+        .register_fn(
+            "dielectric",
+            |s: &mut ScriptShape, ri: f64, priority: i64| {
+                s.then(Step::NestedDielectric(ri, priority.max(0) as u32))
+            },
+        )
+        .register_fn("diffuse_light", |s: &mut ScriptShape| {
+            s.then(Step::DiffuseLight)
+        })
+        .register_fn("light", |s: &mut ScriptShape, color: Vec3| {
+            s.then(Step::Color(color)).then(Step::DiffuseLight)
+        })
+        // shapes
+        .register_fn(
+            "sphere",
+            |s: &mut ScriptShape, center: Vec3, radius: f64| s.then(Step::Sphere(center, radius)),
+        )
+        .register_fn(
+            "moving_sphere",
+            |s: &mut ScriptShape, center0: Vec3, center1: Vec3, radius: f64| {
+                s.then(Step::MovingSphere(center0, center1, radius))
+            },
+        )
+        .register_fn(
+            "rect_xy",
+            |s: &mut ScriptShape, x0: f64, x1: f64, y0: f64, y1: f64, k: f64| {
+                s.then(Step::RectXy(x0, x1, y0, y1, k))
+            },
+        )
+        .register_fn(
+            "rect_xz",
+            |s: &mut ScriptShape, x0: f64, x1: f64, z0: f64, z1: f64, k: f64| {
+                s.then(Step::RectXz(x0, x1, z0, z1, k))
+            },
+        )
+        .register_fn(
+            "rect_yz",
+            |s: &mut ScriptShape, y0: f64, y1: f64, z0: f64, z1: f64, k: f64| {
+                s.then(Step::RectYz(y0, y1, z0, z1, k))
+            },
+        )
+        .register_fn("box", |s: &mut ScriptShape, p0: Vec3, p1: Vec3| {
+            s.then(Step::Box(p0, p1))
+        })
+        .register_fn("obj", |s: &mut ScriptShape, path: &str| {
+            s.then(Step::Obj(path.to_string()))
+        })
+        // decorators
+        .register_fn("flip_face", |s: &mut ScriptShape| s.then(Step::FlipFace))
+        .register_fn("rotate", |s: &mut ScriptShape, axis: Vec3, angle: f64| {
+            s.then(Step::Rotate(axis, angle))
+        })
+        .register_fn("rotate_y", |s: &mut ScriptShape, angle: f64| {
+            s.then(Step::Rotate(Vec3::yaxis(), angle))
+        })
+        .register_fn("translate", |s: &mut ScriptShape, offset: Vec3| {
+            s.then(Step::Translate(offset))
+        })
+        .register_fn("constant_medium", |s: &mut ScriptShape, density: f64| {
+            s.then(Step::ConstantMedium(density))
+        })
+        .register_fn(
+            "noise_medium",
+            |s: &mut ScriptShape, density: f64, scale: f64| {
+                s.then(Step::NoiseMedium(density, scale))
+            },
+        )
+        .register_fn(
+            "voxel_medium",
+            |s: &mut ScriptShape, density: f64, path: &str| {
+                s.then(Step::VoxelMedium(density, path.to_string()))
+            },
+        );
+}
+
+fn register_scene(engine: &mut Engine, state: &Rc<RefCell<ScriptState>>) {
+    let add = Rc::clone(state);
+    let camera = Rc::clone(state);
+    let background = Rc::clone(state);
+    let gradient = Rc::clone(state);
+    let fog = Rc::clone(state);
+    let height_fog = Rc::clone(state);
+    let fog_scattering = Rc::clone(state);
+    let samples = Rc::clone(state);
+    let max_depth = Rc::clone(state);
+    let rand = Rc::clone(state);
+    let rand_range = Rc::clone(state);
+    engine
+        .register_fn(
+            "add",
+            move |s: &mut ScriptShape| -> Result<(), Box<EvalAltResult>> {
+                add.borrow_mut().world.push(s.build()?);
+                Ok(())
+            },
+        )
+        .register_fn("camera", move |look_from: Vec3, look_at: Vec3, fov: f64| {
+            camera.borrow_mut().camera = Some(CameraSection {
+                look_from: look_from.to_array(),
+                look_at: look_at.to_array(),
+                up: Vec3::yaxis().to_array(),
+                fov,
+                aperture: 0.0,
+                focus_distance: None,
+                shutter: None,
+                blades: None,
+                blade_rotation: 0.0,
+                aperture_mask: None,
+            });
+        })
+        .register_fn("background", move |color: Vec3| {
+            background.borrow_mut().background = Box::new(ColorBackground::new(color));
+        })
+        .register_fn("background", move |bottom: Vec3, top: Vec3| {
+            gradient.borrow_mut().background = Box::new(GradientBackground::new(bottom, top));
+        })
+        .register_fn("fog", move |color: Vec3, density: f64| {
+            fog.borrow_mut().fog = Some(Fog::new(color, density));
+        })
+        .register_fn(
+            "fog",
+            move |color: Vec3, density: f64, base: f64, falloff: f64| {
+                height_fog.borrow_mut().fog =
+                    Some(Fog::new(color, density).with_height(base, falloff));
+            },
+        )
+        .register_fn(
+            "fog_scattering",
+            move |albedo: f64| -> Result<(), Box<EvalAltResult>> {
+                let mut state = fog_scattering.borrow_mut();
+                let fog = state.fog.ok_or("fog_scattering needs fog() first")?;
+                state.fog = Some(fog.with_scattering(albedo));
+                Ok(())
+            },
+        )
+        .register_fn("samples", move |n: i64| {
+            samples.borrow_mut().samples = Some(n.max(1) as usize);
+        })
+        .register_fn("max_depth", move |n: i64| {
+            max_depth.borrow_mut().max_depth = Some(n.max(1) as usize);
+        })
+        // --seed を渡すと乱数の並びも固定する
+        .register_fn("rand", move || rand.borrow_mut().rng.gen::<f64>())
+        .register_fn("rand", move |min: f64, max: f64| {
+            rand_range.borrow_mut().rng.gen_range(min..max)
+        });
+}
+
+// Rhai のスクリプトを実行してシーンを組み立てる (scenes/example.rhai を参照)
+// 数値は 1.0 のように小数で書く (Rhai は整数を小数の引数に変換しない)
+pub fn load_script(path: &str, seed: Option<u64>) -> Result<FileScene, Error> {
+    let state = Rc::new(RefCell::new(ScriptState {
+        world: ShapeList::new(),
+        camera: None,
+        background: Box::new(ColorBackground::new(Color::zero())),
+        fog: None,
+        samples: None,
+        max_depth: None,
+        rng: match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        },
+    }));
+    let mut engine = Engine::new();
+    register_vec3(&mut engine);
+    register_shape(&mut engine);
+    register_scene(&mut engine, &state);
+    engine
+        .run_file(path.into())
+        .map_err(|e| Error::File(format!("{}: {}", path, e)))?;
+    drop(engine);
+    let state = Rc::into_inner(state).unwrap().into_inner();
+    let camera = state
+        .camera
+        .ok_or_else(|| Error::File(format!("{}: camera() was never called", path)))?;
+    camera
+        .builder()
+        .build()
+        .map_err(|e| Error::File(format!("{}: {}", path, e)))?;
+    let scene = FileScene::new(state.world, camera, state.background)
+        .with_quality(state.samples, state.max_depth);
+    Ok(match state.fog {
+        Some(fog) => scene.with_fog(fog),
+        None => scene,
+    })
+}
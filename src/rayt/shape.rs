@@ -0,0 +1,1559 @@
+use crate::rayt::*;
+
+use std::sync::Arc;
+
+pub struct HitInfo {
+    pub t: f64,
+    pub p: Point3,
+    pub n: Vec3,
+    pub m: Arc<dyn Material>,
+    pub u: f64,
+    pub v: f64,
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub footprint: f64,
+    pub matte: Option<&'static str>,
+}
+
+impl HitInfo {
+    pub const fn new(t: f64, p: Point3, n: Vec3, m: Arc<dyn Material>, u: f64, v: f64) -> Self {
+        Self {
+            t,
+            p,
+            n,
+            m,
+            u,
+            v,
+            dpdx: Vec3::zero(),
+            dpdy: Vec3::zero(),
+            footprint: 0.0,
+            matte: None,
+        }
+    }
+
+    // texel は位置の変化を UV の変化の大きさに直す
+    pub fn with_differential<F: Fn(Vec3) -> f64>(self, ray: &Ray, texel: F) -> Self {
+        match ray.footprint(self.p, self.n) {
+            Some((dpdx, dpdy)) => Self {
+                dpdx,
+                dpdy,
+                footprint: texel(dpdx).max(texel(dpdy)),
+                ..self
+            },
+            None => self,
+        }
+    }
+}
+
+pub trait Shape: Send + Sync {
+    // t0 < t < t1 の交点だけを返す。t0 ちょうどは含めないので、当たった t から探し直せば次の交点が取れる
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo>;
+    // 調整パネルに並べるために、使っているマテリアルを列挙する
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        Vec::new()
+    }
+    // シーンファイルに書き出す。書けない形状は飛ばしたことだけ記録する
+    fn export(&self, writer: &mut SceneWriter) {
+        writer.skip();
+    }
+    // origin から direction に向かって自分に当たる確率密度 (立体角あたり)
+    // 光源として狙えない形状は 0 のまま
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3) -> f64 {
+        0.0
+    }
+    // origin から自分に向かう方向を pdf_value の密度で選ぶ
+    fn random(&self, _origin: Point3) -> Vec3 {
+        Vec3::xaxis()
+    }
+}
+
+pub struct Sphere {
+    center: Point3,
+    radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl Sphere {
+    pub const fn new(center: Point3, radius: f64, material: Arc<dyn Material>) -> Self {
+        Self {
+            center,
+            radius,
+            material,
+        }
+    }
+
+    pub fn uv(p: Point3) -> (f64, f64) {
+        let phi = p.z().atan2(p.x());
+        let theta = p.y().asin();
+        (1.0 - (phi + PI) / (2.0 * PI), (theta + PI / 2.0) / PI)
+    }
+
+    // 光線が当たる t のうち (t0, t1) に入る始点に近いほう
+    pub fn intersect(center: Point3, radius: f64, ray: &Ray, t0: f64, t1: f64) -> Option<f64> {
+        let oc = ray.origin - center;
+        let a = ray.direction.dot(ray.direction);
+        let b = 2.0 * ray.direction.dot(oc);
+        let c = oc.dot(oc) - radius.powi(2);
+        let d = b * b - 4.0 * a * c;
+        if d > 0.0 {
+            // こちらの解のほうが始点に近いので先に判定
+            let temp = (-b - d.sqrt()) / (2.0 * a);
+            if t0 < temp && temp < t1 {
+                return Some(temp);
+            }
+            // 始点から近いほうの解が光線の衝突範囲含まれないときは遠い方の解を評価
+            let temp = (-b + d.sqrt()) / (2.0 * a);
+            if t0 < temp && temp < t1 {
+                return Some(temp);
+            }
+        }
+        None
+    }
+
+    pub fn hit_info(
+        center: Point3,
+        radius: f64,
+        material: &Arc<dyn Material>,
+        ray: &Ray,
+        t: f64,
+    ) -> HitInfo {
+        let p = ray.at(t);
+        let n = (p - center) / radius;
+        let (u, v) = Self::uv(n);
+        // 経度方向は赤道での値を使う
+        HitInfo::new(t, p, n, Arc::clone(material), u, v)
+            .with_differential(ray, |d| d.length() / (PI * radius))
+    }
+
+    // 動く球からも使えるように中心を外から与える
+    pub fn hit_at(&self, center: Point3, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let t = Self::intersect(center, self.radius, ray, t0, t1)?;
+        Some(Self::hit_info(center, self.radius, &self.material, ray, t))
+    }
+}
+
+impl Shape for Sphere {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.hit_at(self.center, ray, t0, t1)
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![Arc::clone(&self.material)]
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        let kind = ShapeKind::Sphere {
+            center: self.center.to_array(),
+            radius: self.radius,
+        };
+        writer.push(kind, &self.material);
+    }
+
+    // 球が見える円錐の中で一様に選ぶ
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        if self
+            .hit(&Ray::new(origin, direction), 0.001, f64::MAX)
+            .is_none()
+        {
+            return 0.0;
+        }
+        let dist_sq = (self.center - origin).length_squared();
+        let cos_max = (1.0 - self.radius.powi(2) / dist_sq).max(0.0).sqrt();
+        let solid_angle = PI2 * (1.0 - cos_max);
+        solid_angle.recip()
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        let direction = self.center - origin;
+        let dist_sq = direction.length_squared();
+        Onb::from_w(direction).local_vec(Vec3::random_to_sphere(self.radius, dist_sq))
+    }
+}
+
+// time0 から time1 の間に center0 から center1 へ動く球
+pub struct MovingSphere {
+    sphere: Sphere,
+    center1: Point3,
+    time0: f64,
+    time1: f64,
+}
+
+impl MovingSphere {
+    pub fn new(sphere: Sphere, center1: Point3, time0: f64, time1: f64) -> Self {
+        Self {
+            sphere,
+            center1,
+            time0,
+            time1,
+        }
+    }
+
+    pub fn center(&self, time: f64) -> Point3 {
+        let t = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.sphere.center.lerp(self.center1, t)
+    }
+}
+
+impl Shape for MovingSphere {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.sphere.hit_at(self.center(ray.time), ray, t0, t1)
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.sphere.materials()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        let kind = ShapeKind::MovingSphere {
+            center0: self.sphere.center.to_array(),
+            center1: self.center1.to_array(),
+            time0: self.time0,
+            time1: self.time1,
+            radius: self.sphere.radius,
+        };
+        writer.push(kind, &self.sphere.material);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum RectAxisType {
+    XY,
+    XZ,
+    YZ,
+}
+
+pub struct Rect {
+    x0: f64,
+    x1: f64,
+    y0: f64,
+    y1: f64,
+    k: f64,
+    axis: RectAxisType,
+    material: Arc<dyn Material>,
+}
+
+impl Rect {
+    pub fn new(
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        axis: RectAxisType,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            x0,
+            x1,
+            y0,
+            y1,
+            k,
+            axis,
+            material,
+        }
+    }
+
+    // 矩形の面内の 2 軸を x, y に、法線方向を z に並べ替える
+    pub fn swizzle(&self, p: Vec3) -> Vec3 {
+        match self.axis {
+            RectAxisType::XY => p,
+            RectAxisType::XZ => Vec3::new(p.x(), p.z(), p.y()),
+            RectAxisType::YZ => Vec3::new(p.y(), p.z(), p.x()),
+        }
+    }
+
+    // swizzle の逆
+    pub fn unswizzle(&self, p: Vec3) -> Vec3 {
+        match self.axis {
+            RectAxisType::XY => p,
+            RectAxisType::XZ => Vec3::new(p.x(), p.z(), p.y()),
+            RectAxisType::YZ => Vec3::new(p.z(), p.x(), p.y()),
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    pub fn texel(&self, d: Vec3) -> f64 {
+        let d = self.swizzle(d);
+        (d.x() / (self.x1 - self.x0))
+            .abs()
+            .max((d.y() / (self.y1 - self.y0)).abs())
+    }
+}
+
+impl Shape for Rect {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let origin = self.swizzle(ray.origin);
+        let direction = self.swizzle(ray.direction);
+        let axis = match self.axis {
+            RectAxisType::XY => Vec3::zaxis(),
+            RectAxisType::XZ => Vec3::yaxis(),
+            RectAxisType::YZ => Vec3::xaxis(),
+        };
+        let t = (self.k - origin.z()) / direction.z();
+        if t <= t0 || t >= t1 {
+            return None;
+        }
+        let x = origin.x() + t * direction.x();
+        let y = origin.y() + t * direction.y();
+        if x < self.x0 || x > self.x1 || y < self.y0 || y > self.y1 {
+            return None;
+        }
+        Some(
+            HitInfo::new(
+                t,
+                ray.at(t),
+                axis,
+                Arc::clone(&self.material),
+                (x - self.x0) / (self.x1 - self.x0),
+                (y - self.y0) / (self.y1 - self.y0),
+            )
+            .with_differential(ray, |d| self.texel(d)),
+        )
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![Arc::clone(&self.material)]
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        let (x0, x1, y0, y1, k) = (self.x0, self.x1, self.y0, self.y1, self.k);
+        let kind = match self.axis {
+            RectAxisType::XY => ShapeKind::RectXy { x0, x1, y0, y1, k },
+            RectAxisType::XZ => ShapeKind::RectXz {
+                x0,
+                x1,
+                z0: y0,
+                z1: y1,
+                k,
+            },
+            RectAxisType::YZ => ShapeKind::RectYz {
+                y0: x0,
+                y1: x1,
+                z0: y0,
+                z1: y1,
+                k,
+            },
+        };
+        writer.push(kind, &self.material);
+    }
+
+    // 面上の一様な点を選び、面積あたりの密度を立体角あたりに直す
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        match self.hit(&Ray::new(origin, direction), 0.001, f64::MAX) {
+            Some(hit) => area_pdf(hit.t, hit.n, direction, self.area()),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        let [r1, r2] = random_array();
+        let p = Vec3::new(
+            self.x0 + r1 * (self.x1 - self.x0),
+            self.y0 + r2 * (self.y1 - self.y0),
+            self.k,
+        );
+        self.unswizzle(p) - origin
+    }
+}
+
+// 面積 area の面を一様に選んだときの、距離 t (direction の長さ単位) で当たった向きの密度
+fn area_pdf(t: f64, n: Vec3, direction: Vec3, area: f64) -> f64 {
+    let dist_sq = t * t * direction.length_squared();
+    let cosine = (direction.dot(n) / (direction.length() * n.length())).abs();
+    if cosine == 0.0 || area == 0.0 {
+        return 0.0;
+    }
+    dist_sq / (cosine * area)
+}
+
+pub struct Box3D {
+    p0: Point3,
+    p1: Point3,
+    shapes: ShapeList,
+}
+
+impl Box3D {
+    pub fn new(p0: Point3, p1: Point3, material: Arc<dyn Material>) -> Self {
+        let mut shapes = ShapeList::new();
+        // 向かい合う面の組ごとに、手前の面と裏返した奥の面を足す
+        let faces = [
+            (
+                RectAxisType::XY,
+                (p0.x(), p1.x(), p0.y(), p1.y()),
+                p0.z(),
+                p1.z(),
+            ),
+            (
+                RectAxisType::XZ,
+                (p0.x(), p1.x(), p0.z(), p1.z()),
+                p0.y(),
+                p1.y(),
+            ),
+            (
+                RectAxisType::YZ,
+                (p0.y(), p1.y(), p0.z(), p1.z()),
+                p0.x(),
+                p1.x(),
+            ),
+        ];
+        for (axis, (x0, x1, y0, y1), near, far) in faces {
+            let rect = |k| Rect::new(x0, x1, y0, y1, k, axis, Arc::clone(&material));
+            shapes.push(ShapeEnum::Rect(rect(far)));
+            shapes.push(ShapeEnum::boxed(FlipFace::new(Box::new(rect(near)))));
+        }
+
+        Self { p0, p1, shapes }
+    }
+}
+
+impl Shape for Box3D {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        self.shapes.hit(ray, t0, t1)
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.shapes.materials()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        let kind = ShapeKind::Box {
+            p0: self.p0.to_array(),
+            p1: self.p1.to_array(),
+        };
+        writer.push(kind, &self.shapes.materials()[0]);
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.shapes.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        self.shapes.random(origin)
+    }
+}
+
+pub struct Triangle {
+    p: [Point3; 3],
+    // 頂点法線 (なければ面の法線を使う)
+    n: Option<[Vec3; 3]>,
+    uv: [(f64, f64); 3],
+    // 位置の変化を UV の変化に直す係数
+    texel_scale: f64,
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(
+        p: [Point3; 3],
+        n: Option<[Vec3; 3]>,
+        uv: Option<[(f64, f64); 3]>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let uv = uv.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        let area = (p[1] - p[0]).cross(p[2] - p[0]).length();
+        let (du1, dv1) = (uv[1].0 - uv[0].0, uv[1].1 - uv[0].1);
+        let (du2, dv2) = (uv[2].0 - uv[0].0, uv[2].1 - uv[0].1);
+        let uv_area = (du1 * dv2 - du2 * dv1).abs();
+        let texel_scale = if area > 0.0 {
+            (uv_area / area).sqrt()
+        } else {
+            0.0
+        };
+        Self {
+            p,
+            n,
+            uv,
+            texel_scale,
+            material,
+        }
+    }
+}
+
+impl Shape for Triangle {
+    // Moller-Trumbore 法
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let [p0, p1, p2] = self.p;
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let pvec = ray.direction.cross(e2);
+        let det = e1.dot(pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = det.recip();
+        let tvec = ray.origin - p0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(e1);
+        let b2 = ray.direction.dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = e2.dot(qvec) * inv_det;
+        if t <= t0 || t >= t1 {
+            return None;
+        }
+        let b0 = 1.0 - b1 - b2;
+        let face = e1.cross(e2).normalize();
+        let n = match self.n {
+            // 補間した法線が面の裏に回らないようにする
+            Some([n0, n1, n2]) => {
+                let n = (b0 * n0 + b1 * n1 + b2 * n2).normalize();
+                if n.dot(face) < 0.0 {
+                    -n
+                } else {
+                    n
+                }
+            }
+            None => face,
+        };
+        let [(u0, v0), (u1, v1), (u2, v2)] = self.uv;
+        Some(
+            HitInfo::new(
+                t,
+                ray.at(t),
+                n,
+                Arc::clone(&self.material),
+                b0 * u0 + b1 * u1 + b2 * u2,
+                b0 * v0 + b1 * v1 + b2 * v2,
+            )
+            .with_differential(ray, |d| d.length() * self.texel_scale),
+        )
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![Arc::clone(&self.material)]
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let [p0, p1, p2] = self.p;
+        let area = 0.5 * (p1 - p0).cross(p2 - p0).length();
+        match self.hit(&Ray::new(origin, direction), 0.001, f64::MAX) {
+            Some(hit) => area_pdf(hit.t, hit.n, direction, area),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        // 平行四辺形の中で選び、外に出たら折り返す
+        let [mut b1, mut b2] = random_array();
+        if b1 + b2 > 1.0 {
+            (b1, b2) = (1.0 - b1, 1.0 - b2);
+        }
+        let [p0, p1, p2] = self.p;
+        p0 + b1 * (p1 - p0) + b2 * (p2 - p0) - origin
+    }
+}
+
+// 三角形の集まり。包む球に当たらない光線は三角形を調べずに返す
+pub struct Mesh {
+    center: Point3,
+    radius: f64,
+    triangles: ShapeList,
+}
+
+impl Mesh {
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        let count = (triangles.len() * 3).max(1) as f64;
+        let center = triangles
+            .iter()
+            .flat_map(|triangle| triangle.p)
+            .fold(Point3::zero(), |sum, p| sum + p)
+            / count;
+        let radius = triangles
+            .iter()
+            .flat_map(|triangle| triangle.p)
+            .map(|p| (p - center).length())
+            .fold(0.0, f64::max);
+        let mut list = ShapeList::new();
+        for triangle in triangles {
+            list.push(ShapeEnum::boxed(triangle));
+        }
+        Self {
+            center,
+            radius,
+            triangles: list,
+        }
+    }
+}
+
+impl Shape for Mesh {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let oc = ray.origin - self.center;
+        let a = ray.direction.dot(ray.direction);
+        let b = ray.direction.dot(oc);
+        let c = oc.dot(oc) - self.radius.powi(2);
+        if b * b - a * c < 0.0 {
+            return None;
+        }
+        self.triangles.hit(ray, t0, t1)
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.triangles.materials()
+    }
+}
+
+pub struct FlipFace {
+    shape: Box<dyn Shape>,
+}
+
+impl FlipFace {
+    pub fn new(shape: Box<dyn Shape>) -> Self {
+        Self { shape }
+    }
+}
+
+impl Shape for FlipFace {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        if let Some(hit) = self.shape.hit(ray, t0, t1) {
+            Some(HitInfo { n: -hit.n, ..hit })
+        } else {
+            None
+        }
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.shape.materials()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        let mark = writer.mark();
+        self.shape.export(writer);
+        writer.transform(mark, |shape| {
+            shape.flip();
+            true
+        });
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.shape.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        self.shape.random(origin)
+    }
+}
+
+pub struct BackFace {
+    shape: Box<dyn Shape>,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl BackFace {
+    pub fn new(shape: Box<dyn Shape>, material: Option<Arc<dyn Material>>) -> Self {
+        Self { shape, material }
+    }
+}
+
+impl Shape for BackFace {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let mut t_min = t0;
+        while let Some(hit) = self.shape.hit(ray, t_min, t1) {
+            if ray.direction.dot(hit.n) < 0.0 {
+                return Some(hit);
+            }
+            // 裏面は別マテリアルで描くか、なければ素通りさせる
+            if let Some(material) = &self.material {
+                return Some(HitInfo {
+                    m: Arc::clone(material),
+                    ..hit
+                });
+            }
+            t_min = hit.t;
+        }
+        None
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        let mut materials = self.shape.materials();
+        materials.extend(self.material.iter().cloned());
+        materials
+    }
+}
+
+pub struct Translate {
+    shape: Box<dyn Shape>,
+    offset: Point3,
+}
+
+impl Translate {
+    pub fn new(shape: Box<dyn Shape>, offset: Point3) -> Self {
+        Self { shape, offset }
+    }
+}
+
+impl Shape for Translate {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let moved_ray = ray.transform(|p| p - self.offset, |d| d);
+        if let Some(hit) = self.shape.hit(&moved_ray, t0, t1) {
+            Some(HitInfo {
+                p: hit.p + self.offset,
+                ..hit
+            })
+        } else {
+            None
+        }
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.shape.materials()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        let mark = writer.mark();
+        self.shape.export(writer);
+        writer.transform(mark, |shape| {
+            shape.translate_by(self.offset);
+            true
+        });
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.shape.pdf_value(origin - self.offset, direction)
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        self.shape.random(origin - self.offset)
+    }
+}
+
+// 時刻に比例して平行移動する
+pub struct Motion {
+    shape: Box<dyn Shape>,
+    velocity: Vec3,
+}
+
+impl Motion {
+    pub fn new(shape: Box<dyn Shape>, velocity: Vec3) -> Self {
+        Self { shape, velocity }
+    }
+}
+
+impl Shape for Motion {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let offset = self.velocity * ray.time;
+        let moved_ray = ray.transform(|p| p - offset, |d| d);
+        self.shape.hit(&moved_ray, t0, t1).map(|hit| HitInfo {
+            p: hit.p + offset,
+            ..hit
+        })
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.shape.materials()
+    }
+}
+
+pub struct Rotate {
+    shape: Box<dyn Shape>,
+    quat: Quat,
+    // 書き出し用に、回転の軸と角度 (度) も持っておく
+    axis: Vec3,
+    angle: f64,
+}
+
+impl Rotate {
+    pub fn new(shape: Box<dyn Shape>, axis: Vec3, angle: f64) -> Self {
+        Self {
+            shape,
+            quat: Quat::from_rot(axis, angle.to_radians()),
+            axis,
+            angle,
+        }
+    }
+}
+
+impl Shape for Rotate {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let revq = self.quat.conj();
+        let rotated_ray = revq.rotate_ray(ray);
+        if let Some(hit) = self.shape.hit(&rotated_ray, t0, t1) {
+            Some(HitInfo {
+                p: self.quat.rotate(hit.p),
+                n: self.quat.rotate(hit.n),
+                dpdx: self.quat.rotate(hit.dpdx),
+                dpdy: self.quat.rotate(hit.dpdy),
+                ..hit
+            })
+        } else {
+            None
+        }
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.shape.materials()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        let mark = writer.mark();
+        self.shape.export(writer);
+        writer.transform(mark, |shape| shape.rotate_by(self.axis, self.angle));
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let revq = self.quat.conj();
+        self.shape
+            .pdf_value(revq.rotate(origin), revq.rotate(direction))
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        let revq = self.quat.conj();
+        self.quat.rotate(self.shape.random(revq.rotate(origin)))
+    }
+}
+
+pub struct StochasticAlpha {
+    shape: Box<dyn Shape>,
+    opacity: f64,
+}
+
+impl StochasticAlpha {
+    pub fn new(shape: Box<dyn Shape>, opacity: f64) -> Self {
+        Self { shape, opacity }
+    }
+}
+
+impl Shape for StochasticAlpha {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        // マテリアルを評価せずに確率的に素通りさせ、奥の交点を探す
+        let mut t_min = t0;
+        while let Some(hit) = self.shape.hit(ray, t_min, t1) {
+            if Vec3::random_fill().x() < self.opacity {
+                return Some(hit);
+            }
+            t_min = hit.t;
+        }
+        None
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.shape.materials()
+    }
+}
+
+// 当たった物体にマット AOV 用の名前を付ける
+pub struct Matte {
+    shape: Box<dyn Shape>,
+    name: &'static str,
+}
+
+impl Matte {
+    pub fn new(shape: Box<dyn Shape>, name: &'static str) -> Self {
+        Self { shape, name }
+    }
+}
+
+impl Shape for Matte {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        // 入れ子になっているときは内側の名前を優先する
+        self.shape.hit(ray, t0, t1).map(|hit| HitInfo {
+            matte: hit.matte.or(Some(self.name)),
+            ..hit
+        })
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.shape.materials()
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        self.shape.export(writer);
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.shape.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        self.shape.random(origin)
+    }
+}
+
+// 閉じた形状の内側を一様な密度の媒質 (煙や霧) で満たす
+// 境界は入口と出口を求めるだけに使い、境界のマテリアルは使わない
+pub struct ConstantMedium {
+    boundary: Box<dyn Shape>,
+    neg_inv_density: f64,
+    phase: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Shape>, density: f64, phase: Arc<dyn Material>) -> Self {
+        Self {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase,
+        }
+    }
+}
+
+impl Shape for ConstantMedium {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        // 境界の外からでも内側からでも入口と出口が取れるように、直線全体で探す
+        let enter = self.boundary.hit(ray, f64::MIN, f64::MAX)?;
+        let exit = self.boundary.hit(ray, enter.t + 0.0001, f64::MAX)?;
+        let enter_t = enter.t.max(t0).max(0.0);
+        let exit_t = exit.t.min(t1);
+        if enter_t >= exit_t {
+            return None;
+        }
+        let ray_length = ray.direction.length();
+        let distance_inside = (exit_t - enter_t) * ray_length;
+        let hit_distance = self.neg_inv_density * random_f64().ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+        let t = enter_t + hit_distance / ray_length;
+        // 法線に意味はないので適当な向きにしておく
+        Some(HitInfo::new(
+            t,
+            ray.at(t),
+            Vec3::xaxis(),
+            Arc::clone(&self.phase),
+            0.0,
+            0.0,
+        ))
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![Arc::clone(&self.phase)]
+    }
+}
+
+// 場所によって密度が変わる媒質 (雲や煙)。密度は density * field の輝度 (0..1 に切り詰める)
+// density を上限にして delta tracking で散乱する位置を選ぶ
+pub struct HeterogeneousMedium {
+    boundary: Box<dyn Shape>,
+    density: f64,
+    field: Box<dyn Texture>,
+    phase: Arc<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(
+        boundary: Box<dyn Shape>,
+        density: f64,
+        field: Box<dyn Texture>,
+        phase: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            boundary,
+            density,
+            field,
+            phase,
+        }
+    }
+
+    fn density_at(&self, p: Point3) -> f64 {
+        self.density * self.field.value(0.0, 0.0, p).luminance().clamp(0.0, 1.0)
+    }
+}
+
+impl Shape for HeterogeneousMedium {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let enter = self.boundary.hit(ray, f64::MIN, f64::MAX)?;
+        let exit = self.boundary.hit(ray, enter.t + 0.0001, f64::MAX)?;
+        let enter_t = enter.t.max(t0).max(0.0);
+        let exit_t = exit.t.min(t1);
+        if enter_t >= exit_t || self.density <= 0.0 {
+            return None;
+        }
+        // 上限の密度で一様な媒質として進み、実際の密度との比で本当に散乱したかを決める
+        // 散乱しなかったもの (null collision) はそのまま先へ進む
+        let step = -1.0 / (self.density * ray.direction.length());
+        let mut t = enter_t;
+        loop {
+            t += step * random_f64().ln();
+            if t >= exit_t {
+                return None;
+            }
+            let p = ray.at(t);
+            if random_f64() * self.density < self.density_at(p) {
+                return Some(HitInfo::new(
+                    t,
+                    p,
+                    Vec3::xaxis(),
+                    Arc::clone(&self.phase),
+                    0.0,
+                    0.0,
+                ));
+            }
+        }
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![Arc::clone(&self.phase)]
+    }
+}
+
+// よく使う形状は enum のまま持ち、hit を仮想関数を介さずに呼ぶ
+// それ以外 (装飾を重ねたものや外から足した形状) は Dyn に入れる
+pub enum ShapeEnum {
+    Sphere(Sphere),
+    MovingSphere(MovingSphere),
+    Rect(Rect),
+    Box3D(Box3D),
+    Dyn(Box<dyn Shape>),
+}
+
+impl ShapeEnum {
+    pub fn boxed(shape: impl Shape + 'static) -> Self {
+        ShapeEnum::Dyn(Box::new(shape))
+    }
+
+    pub fn into_box(self) -> Box<dyn Shape> {
+        match self {
+            ShapeEnum::Dyn(shape) => shape,
+            shape => Box::new(shape),
+        }
+    }
+}
+
+impl From<Box<dyn Shape>> for ShapeEnum {
+    fn from(shape: Box<dyn Shape>) -> Self {
+        ShapeEnum::Dyn(shape)
+    }
+}
+
+impl Shape for ShapeEnum {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        match self {
+            ShapeEnum::Sphere(shape) => shape.hit(ray, t0, t1),
+            ShapeEnum::MovingSphere(shape) => shape.hit(ray, t0, t1),
+            ShapeEnum::Rect(shape) => shape.hit(ray, t0, t1),
+            ShapeEnum::Box3D(shape) => shape.hit(ray, t0, t1),
+            ShapeEnum::Dyn(shape) => shape.hit(ray, t0, t1),
+        }
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        match self {
+            ShapeEnum::Sphere(shape) => shape.materials(),
+            ShapeEnum::MovingSphere(shape) => shape.materials(),
+            ShapeEnum::Rect(shape) => shape.materials(),
+            ShapeEnum::Box3D(shape) => shape.materials(),
+            ShapeEnum::Dyn(shape) => shape.materials(),
+        }
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        match self {
+            ShapeEnum::Sphere(shape) => shape.export(writer),
+            ShapeEnum::MovingSphere(shape) => shape.export(writer),
+            ShapeEnum::Rect(shape) => shape.export(writer),
+            ShapeEnum::Box3D(shape) => shape.export(writer),
+            ShapeEnum::Dyn(shape) => shape.export(writer),
+        }
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        match self {
+            ShapeEnum::Sphere(shape) => shape.pdf_value(origin, direction),
+            ShapeEnum::MovingSphere(shape) => shape.pdf_value(origin, direction),
+            ShapeEnum::Rect(shape) => shape.pdf_value(origin, direction),
+            ShapeEnum::Box3D(shape) => shape.pdf_value(origin, direction),
+            ShapeEnum::Dyn(shape) => shape.pdf_value(origin, direction),
+        }
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        match self {
+            ShapeEnum::Sphere(shape) => shape.random(origin),
+            ShapeEnum::MovingSphere(shape) => shape.random(origin),
+            ShapeEnum::Rect(shape) => shape.random(origin),
+            ShapeEnum::Box3D(shape) => shape.random(origin),
+            ShapeEnum::Dyn(shape) => shape.random(origin),
+        }
+    }
+}
+
+// ShapeList に入れた形状の番号。ほかの形状を取り除いても変わらない
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId(u64);
+
+pub struct ShapeList {
+    objects: Vec<ShapeEnum>,
+    // objects と同じ順に並べた番号と名前
+    ids: Vec<ObjectId>,
+    names: Vec<Option<String>>,
+    next_id: u64,
+}
+
+impl ShapeList {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            ids: Vec::new(),
+            names: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn push(&mut self, object: impl Into<ShapeEnum>) -> ObjectId {
+        self.insert(object.into(), None)
+    }
+
+    // find で探せるように名前を付けて入れる
+    pub fn push_named(&mut self, name: &str, object: impl Into<ShapeEnum>) -> ObjectId {
+        self.insert(object.into(), Some(name.to_string()))
+    }
+
+    fn insert(&mut self, object: ShapeEnum, name: Option<String>) -> ObjectId {
+        let id = ObjectId(self.next_id);
+        self.next_id += 1;
+        self.objects.push(object);
+        self.ids.push(id);
+        self.names.push(name);
+        id
+    }
+
+    fn index(&self, id: ObjectId) -> Option<usize> {
+        // 番号は増える一方なので ids は常に昇順
+        self.ids.binary_search(&id).ok()
+    }
+
+    // 取り除いた形状を返す。残りの順番は変わらない
+    pub fn remove(&mut self, id: ObjectId) -> Option<ShapeEnum> {
+        let index = self.index(id)?;
+        self.ids.remove(index);
+        self.names.remove(index);
+        Some(self.objects.remove(index))
+    }
+
+    pub fn get(&self, id: ObjectId) -> Option<&ShapeEnum> {
+        self.index(id).map(|index| &self.objects[index])
+    }
+
+    pub fn get_mut(&mut self, id: ObjectId) -> Option<&mut ShapeEnum> {
+        self.index(id).map(move |index| &mut self.objects[index])
+    }
+
+    // 同じ名前が複数あれば最初に入れたもの
+    pub fn find(&self, name: &str) -> Option<ObjectId> {
+        let index = self.names.iter().position(|n| n.as_deref() == Some(name))?;
+        Some(self.ids[index])
+    }
+
+    pub fn name(&self, id: ObjectId) -> Option<&str> {
+        self.names[self.index(id)?].as_deref()
+    }
+
+    // 名前を付け替える。None なら名前を外す。id がなければ false
+    pub fn set_name(&mut self, id: ObjectId, name: Option<&str>) -> bool {
+        match self.index(id) {
+            Some(index) => {
+                self.names[index] = name.map(str::to_string);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ObjectId, &ShapeEnum)> {
+        self.ids.iter().copied().zip(&self.objects)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (ObjectId, &mut ShapeEnum)> {
+        self.ids.iter().copied().zip(&mut self.objects)
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // 番号は振り直さないので、clear の前の ObjectId が後から入れた形状を指すことはない
+    pub fn clear(&mut self) {
+        self.objects.clear();
+        self.ids.clear();
+        self.names.clear();
+    }
+
+    // ray の t_max までの可視性。colored なら透明物体の色で減衰させ、そうでなければ完全に遮る
+    pub fn shadow_transmittance(&self, ray: &Ray, t_max: f64, colored: bool) -> Color {
+        let mut transmittance = Color::one();
+        let mut t_min = 0.001;
+        while let Some(hit) = self.hit(ray, t_min, t_max) {
+            match hit.m.transmittance(&hit) {
+                Some(tint) if colored => transmittance *= tint,
+                _ => return Color::zero(),
+            }
+            if transmittance.near_zero() {
+                return Color::zero();
+            }
+            t_min = hit.t;
+        }
+        transmittance
+    }
+
+    // id は当たったマテリアルが materials() の何番目か
+    pub fn aov(&self, ray: &Ray) -> Option<Aov> {
+        let hit = self.hit(ray, 0.001, f64::MAX)?;
+        let albedo = match hit.m.scatter(ray, &hit) {
+            Some(scatter) => scatter.albedo,
+            None => hit.m.emitted(ray, &hit).saturate(),
+        };
+        let materials = self.materials();
+        let id = materials.iter().position(|m| Arc::ptr_eq(m, &hit.m));
+        Some(Aov {
+            normal: hit.n.normalize(),
+            depth: hit.t * ray.direction.length(),
+            albedo,
+            id: id.unwrap_or(materials.len()) as u32,
+            uv: (hit.u, hit.v),
+        })
+    }
+}
+
+impl Default for ShapeList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Into<ShapeEnum>> Extend<S> for ShapeList {
+    fn extend<I: IntoIterator<Item = S>>(&mut self, iter: I) {
+        for object in iter {
+            self.push(object);
+        }
+    }
+}
+
+impl<S: Into<ShapeEnum>> FromIterator<S> for ShapeList {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut list = Self::new();
+        list.extend(iter);
+        list
+    }
+}
+
+impl Shape for ShapeList {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let mut hit_info: Option<HitInfo> = None;
+        let mut closest_so_far = t1;
+        for object in &self.objects {
+            let mut t_min = t0;
+            while let Some(info) = object.hit(ray, t_min, closest_so_far) {
+                // 切り抜かれた部分は存在しないものとして奥を探す
+                if info.m.is_cutout(&info) {
+                    t_min = info.t;
+                    continue;
+                }
+                closest_so_far = info.t;
+                hit_info = Some(info);
+                break;
+            }
+        }
+        hit_info
+    }
+
+    // 複数の形状で共有しているマテリアルは 1 つにまとめる
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        let mut materials: Vec<Arc<dyn Material>> = Vec::new();
+        for material in self.objects.iter().flat_map(|object| object.materials()) {
+            if !materials.iter().any(|m| Arc::ptr_eq(m, &material)) {
+                materials.push(material);
+            }
+        }
+        materials
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        for object in &self.objects {
+            object.export(writer);
+        }
+    }
+
+    // どの形状も同じ確率で選ぶ
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random(&self, origin: Point3) -> Vec3 {
+        let n = self.objects.len();
+        match n {
+            0 => Vec3::xaxis(),
+            _ => self.objects[((random_f64() * n as f64) as usize).min(n - 1)].random(origin),
+        }
+    }
+}
+
+// SceneStorage に入れたマテリアルの番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaterialId(u32);
+
+// SceneStorage に入れた形状の番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeId {
+    Sphere(u32),
+    Shape(u32),
+}
+
+pub struct SphereRecord {
+    center: Point3,
+    radius: f64,
+    material: MaterialId,
+}
+
+// 形状とマテリアルを種類ごとの配列にまとめて持つ
+// 球は Box や Arc を介さずに並べるので、大量の球を総当たりで調べるときにキャッシュに乗りやすい
+// 球以外は Box<dyn Shape> のまま持つ
+pub struct SceneStorage {
+    materials: Vec<Arc<dyn Material>>,
+    spheres: Vec<SphereRecord>,
+    shapes: ShapeList,
+}
+
+impl SceneStorage {
+    pub fn new() -> Self {
+        Self {
+            materials: Vec::new(),
+            spheres: Vec::new(),
+            shapes: ShapeList::new(),
+        }
+    }
+
+    pub fn add_material(&mut self, material: Arc<dyn Material>) -> MaterialId {
+        self.materials.push(material);
+        MaterialId(self.materials.len() as u32 - 1)
+    }
+
+    pub fn material(&self, id: MaterialId) -> &Arc<dyn Material> {
+        &self.materials[id.0 as usize]
+    }
+
+    pub fn add_sphere(&mut self, center: Point3, radius: f64, material: MaterialId) -> ShapeId {
+        self.spheres.push(SphereRecord {
+            center,
+            radius,
+            material,
+        });
+        ShapeId::Sphere(self.spheres.len() as u32 - 1)
+    }
+
+    pub fn add_shape(&mut self, shape: impl Into<ShapeEnum>) -> ShapeId {
+        self.shapes.push(shape);
+        ShapeId::Shape(self.shapes.len() as u32 - 1)
+    }
+}
+
+impl Default for SceneStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shape for SceneStorage {
+    fn hit(&self, ray: &Ray, t0: f64, t1: f64) -> Option<HitInfo> {
+        let mut hit_info: Option<HitInfo> = None;
+        let mut closest_so_far = t1;
+        for sphere in &self.spheres {
+            let mut t_min = t0;
+            // HitInfo を作るのは今までより近いときだけ
+            while let Some(t) =
+                Sphere::intersect(sphere.center, sphere.radius, ray, t_min, closest_so_far)
+            {
+                let material = self.material(sphere.material);
+                let info = Sphere::hit_info(sphere.center, sphere.radius, material, ray, t);
+                if info.m.is_cutout(&info) {
+                    t_min = t;
+                    continue;
+                }
+                closest_so_far = t;
+                hit_info = Some(info);
+                break;
+            }
+        }
+        self.shapes.hit(ray, t0, closest_so_far).or(hit_info)
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        let mut materials = self.materials.clone();
+        for material in self.shapes.materials() {
+            if !materials.iter().any(|m| Arc::ptr_eq(m, &material)) {
+                materials.push(material);
+            }
+        }
+        materials
+    }
+
+    fn export(&self, writer: &mut SceneWriter) {
+        for sphere in &self.spheres {
+            let kind = ShapeKind::Sphere {
+                center: sphere.center.to_array(),
+                radius: sphere.radius,
+            };
+            writer.push(kind, self.material(sphere.material));
+        }
+        self.shapes.export(writer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lambertian() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Box::new(ColorTexture::new(Color::one()))))
+    }
+
+    // z = 0 にある 1 辺 2 の正方形
+    fn rect(material: Arc<dyn Material>) -> Box<dyn Shape> {
+        Box::new(Rect::new(
+            -1.0,
+            1.0,
+            -1.0,
+            1.0,
+            0.0,
+            RectAxisType::XY,
+            material,
+        ))
+    }
+
+    fn triangle(material: Arc<dyn Material>) -> Box<dyn Shape> {
+        let p = [
+            Point3::new(-1.0, -1.0, 0.0),
+            Point3::new(1.0, -1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ];
+        Box::new(Triangle::new(p, None, None, material))
+    }
+
+    // z = 1 から -z 向きに撃つ光線は、法線 +z の面を表から見る
+    fn front_ray() -> Ray {
+        Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    // 法線 +z の面を裏から見る
+    fn back_ray() -> Ray {
+        Ray::new(Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn hit_at_t0_is_excluded() {
+        let sphere: Box<dyn Shape> = Box::new(Sphere::new(Point3::zero(), 0.5, lambertian()));
+        for shape in [rect(lambertian()), triangle(lambertian()), sphere] {
+            let hit = shape.hit(&front_ray(), 0.001, f64::MAX).unwrap();
+            let next = shape.hit(&front_ray(), hit.t, f64::MAX);
+            assert!(next.is_none_or(|next| next.t > hit.t));
+            assert!(shape.hit(&front_ray(), 0.001, hit.t).is_none());
+        }
+    }
+
+    #[test]
+    fn back_face_is_skipped_from_behind() {
+        for shape in [rect(lambertian()), triangle(lambertian())] {
+            let single = BackFace::new(shape, None);
+            assert!(single.hit(&back_ray(), 0.001, f64::MAX).is_none());
+            assert!(single.hit(&front_ray(), 0.001, f64::MAX).is_some());
+        }
+    }
+
+    #[test]
+    fn back_face_material_is_used_from_behind() {
+        for shape in [rect(lambertian()), triangle(lambertian())] {
+            let back = lambertian();
+            let single = BackFace::new(shape, Some(Arc::clone(&back)));
+            let hit = single.hit(&back_ray(), 0.001, f64::MAX).unwrap();
+            assert!(Arc::ptr_eq(&hit.m, &back));
+        }
+    }
+
+    #[test]
+    fn cutout_rect_lets_rays_through_to_the_shape_behind() {
+        let mask = AlphaMask::new(Box::new(ColorTexture::new(Color::zero())), 0.5);
+        let cutout = Arc::new(
+            Lambertian::new(Box::new(ColorTexture::new(Color::one()))).with_mask(Some(mask)),
+        );
+        let mut world = ShapeList::new();
+        world.push(rect(cutout));
+        assert!(world.hit(&front_ray(), 0.001, f64::MAX).is_none());
+        let behind = Rect::new(-1.0, 1.0, -1.0, 1.0, -1.0, RectAxisType::XY, lambertian());
+        world.push(Box::new(behind) as Box<dyn Shape>);
+        let hit = world.hit(&front_ray(), 0.001, f64::MAX).unwrap();
+        assert!((hit.t - 2.0).abs() < EPS);
+    }
+
+    #[test]
+    fn stochastic_alpha_with_zero_opacity_passes_through() {
+        for shape in [rect(lambertian()), triangle(lambertian())] {
+            let alpha = StochasticAlpha::new(shape, 0.0);
+            assert!(alpha.hit(&front_ray(), 0.001, f64::MAX).is_none());
+        }
+    }
+
+    #[test]
+    fn stochastic_alpha_with_full_opacity_hits() {
+        let alpha = StochasticAlpha::new(rect(lambertian()), 1.0);
+        let hit = alpha.hit(&front_ray(), 0.001, f64::MAX).unwrap();
+        assert!((hit.t - 1.0).abs() < EPS);
+    }
+
+    fn glass(tint: Color) -> Arc<dyn Material> {
+        Arc::new(Dielectric::tinted(1.5, tint))
+    }
+
+    #[test]
+    fn shadows_through_tinted_glass_are_colored() {
+        let tint = Color::new(1.0, 0.5, 0.25);
+        let mut world = ShapeList::new();
+        // 表と裏の 2 回当たる球と、その手前の板
+        world.push(rect(glass(tint)));
+        world.push(ShapeEnum::Sphere(Sphere::new(
+            Point3::new(0.0, 0.0, -2.0),
+            0.5,
+            glass(tint),
+        )));
+        let transmittance = world.shadow_transmittance(&front_ray(), 4.0, true);
+        let expected = tint * tint * tint;
+        assert!((transmittance - expected).near_zero());
+        assert!(world
+            .shadow_transmittance(&front_ray(), 4.0, false)
+            .near_zero());
+        // 光源が板と球のあいだにあれば板の色だけかかる
+        let transmittance = world.shadow_transmittance(&front_ray(), 1.5, true);
+        assert!((transmittance - tint).near_zero());
+    }
+
+    #[test]
+    fn clear_glass_does_not_darken_shadows() {
+        let mut world = ShapeList::new();
+        world.push(rect(glass(Color::one())));
+        let transmittance = world.shadow_transmittance(&front_ray(), 2.0, true);
+        assert!((transmittance - Color::one()).near_zero());
+    }
+
+    #[test]
+    fn opaque_shapes_block_colored_shadows() {
+        let mut world = ShapeList::new();
+        world.push(rect(glass(Color::new(1.0, 0.5, 0.25))));
+        world.push(ShapeEnum::Rect(Rect::new(
+            -1.0,
+            1.0,
+            -1.0,
+            1.0,
+            -0.5,
+            RectAxisType::XY,
+            lambertian(),
+        )));
+        assert!(world
+            .shadow_transmittance(&front_ray(), 2.0, true)
+            .near_zero());
+    }
+}
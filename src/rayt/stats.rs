@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{fs, io, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathEnd {
+    Escaped,
+    Absorbed,
+    MaxDepth,
+    Roulette,
+}
+
+impl PathEnd {
+    const ALL: [PathEnd; 4] = [
+        PathEnd::Escaped,
+        PathEnd::Absorbed,
+        PathEnd::MaxDepth,
+        PathEnd::Roulette,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            PathEnd::Escaped => "escaped",
+            PathEnd::Absorbed => "absorbed",
+            PathEnd::MaxDepth => "max depth",
+            PathEnd::Roulette => "roulette",
+        }
+    }
+}
+
+pub struct PathStats {
+    lengths: Vec<AtomicU64>,
+    ends: [AtomicU64; 4],
+}
+
+impl PathStats {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            lengths: (0..=max_depth).map(|_| AtomicU64::new(0)).collect(),
+            ends: Default::default(),
+        }
+    }
+
+    pub fn record(&self, bounces: usize, end: PathEnd) {
+        let i = bounces.min(self.lengths.len() - 1);
+        self.lengths[i].fetch_add(1, Ordering::Relaxed);
+        self.ends[end as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for n in self.lengths.iter().chain(self.ends.iter()) {
+            n.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn count(&self, end: PathEnd) -> u64 {
+        self.ends[end as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.ends.iter().map(|x| x.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn report(&self) -> String {
+        let total = self.total().max(1) as f64;
+        let mut text = String::from("termination\n");
+        for end in PathEnd::ALL {
+            let n = self.count(end);
+            text += &format!(
+                "  {:<10}{:>12} ({:5.1}%)\n",
+                end.name(),
+                n,
+                100.0 * n as f64 / total
+            );
+        }
+        text += "bounces\n";
+        for (i, n) in self.lengths.iter().enumerate() {
+            let n = n.load(Ordering::Relaxed);
+            if n > 0 {
+                text += &format!("  {:<10}{:>12} ({:5.1}%)\n", i, n, 100.0 * n as f64 / total);
+            }
+        }
+        text
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.report())
+    }
+}
@@ -0,0 +1,1004 @@
+use crate::rayt::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::thread::{self, JoinHandle};
+
+pub trait Texture: Sync + Send {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+    // footprint は 1 画素が覆う UV 上の幅
+    fn value_filtered(&self, u: f64, v: f64, p: Point3, _footprint: f64) -> Color {
+        self.value(u, v, p)
+    }
+    fn value_at(&self, hit: &HitInfo) -> Color {
+        self.value_filtered(hit.u, hit.v, hit.p, hit.footprint)
+    }
+    // シーンファイルでの書き方。書けないものは None
+    fn describe(&self) -> Option<TextureSpec> {
+        None
+    }
+}
+
+pub struct ColorTexture {
+    color: Color,
+}
+
+impl ColorTexture {
+    pub const fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Texture for ColorTexture {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.color
+    }
+
+    fn describe(&self) -> Option<TextureSpec> {
+        Some(TextureSpec::Color(self.color.to_array()))
+    }
+}
+
+pub struct CheckerTexture {
+    odd: Box<dyn Texture>,
+    even: Box<dyn Texture>,
+    freq: f64,
+}
+
+impl CheckerTexture {
+    pub fn new(odd: Box<dyn Texture>, even: Box<dyn Texture>, freq: f64) -> Self {
+        Self { odd, even, freq }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let sines = p.iter().fold(1.0, |acc, x| acc * (x * self.freq).sin());
+        if sines < 0.0 {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+
+    fn describe(&self) -> Option<TextureSpec> {
+        match (self.odd.describe()?, self.even.describe()?) {
+            (TextureSpec::Color(odd), TextureSpec::Color(even)) => {
+                Some(TextureSpec::Desc(TextureDesc::Checker {
+                    odd,
+                    even,
+                    freq: self.freq,
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct BrickTexture {
+    brick: Box<dyn Texture>,
+    mortar: Box<dyn Texture>,
+    width: f64,
+    height: f64,
+    mortar_width: f64,
+    offset: f64,
+}
+
+impl BrickTexture {
+    pub fn new(
+        brick: Box<dyn Texture>,
+        mortar: Box<dyn Texture>,
+        width: f64,
+        height: f64,
+        mortar_width: f64,
+        offset: f64,
+    ) -> Self {
+        Self {
+            brick,
+            mortar,
+            width,
+            height,
+            mortar_width,
+            offset,
+        }
+    }
+}
+
+impl Texture for BrickTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        // 段ごとに offset だけ横にずらし、レンガ内の位置 (0..1) で目地かどうかを判定する
+        let row = (v / self.height).floor();
+        let bu = u / self.width + row * self.offset;
+        let bu = bu - bu.floor();
+        let bv = v / self.height - row;
+        let mu = 0.5 * self.mortar_width / self.width;
+        let mv = 0.5 * self.mortar_width / self.height;
+        if bu < mu || bu > 1.0 - mu || bv < mv || bv > 1.0 - mv {
+            self.mortar.value(u, v, p)
+        } else {
+            self.brick.value(u, v, p)
+        }
+    }
+}
+
+pub struct UvTransform {
+    inner: Box<dyn Texture>,
+    pub(crate) scale: (f64, f64),
+    pub(crate) offset: (f64, f64),
+    pub(crate) rotation: f64,
+}
+
+impl UvTransform {
+    pub fn new(inner: Box<dyn Texture>) -> Self {
+        Self {
+            inner,
+            scale: (1.0, 1.0),
+            offset: (0.0, 0.0),
+            rotation: 0.0,
+        }
+    }
+}
+
+impl Texture for UvTransform {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.value_filtered(u, v, p, 0.0)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: Point3, footprint: f64) -> Color {
+        let (su, sv) = (u * self.scale.0, v * self.scale.1);
+        let (s, c) = self.rotation.sin_cos();
+        let tu = c * su - s * sv + self.offset.0;
+        let tv = s * su + c * sv + self.offset.1;
+        let scale = self.scale.0.abs().max(self.scale.1.abs());
+        self.inner.value_filtered(tu, tv, p, footprint * scale)
+    }
+}
+
+pub struct TriplanarTexture {
+    inner: Box<dyn Texture>,
+    scale: f64,
+    sharpness: f64,
+}
+
+impl TriplanarTexture {
+    pub fn new(inner: Box<dyn Texture>, scale: f64, sharpness: f64) -> Self {
+        Self {
+            inner,
+            scale,
+            sharpness,
+        }
+    }
+}
+
+impl Texture for TriplanarTexture {
+    // 法線がわからないときはシェイプの UV をそのまま使う
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.inner.value(u, v, p)
+    }
+
+    fn value_at(&self, hit: &HitInfo) -> Color {
+        let [wx, wy, wz] = hit.n.to_array().map(|x| x.abs().powf(self.sharpness));
+        let sum = wx + wy + wz;
+        let [x, y, z] = (hit.p * self.scale).to_array();
+        let footprint = hit.dpdx.length().max(hit.dpdy.length()) * self.scale;
+        (wx * self.inner.value_filtered(y, z, hit.p, footprint)
+            + wy * self.inner.value_filtered(x, z, hit.p, footprint)
+            + wz * self.inner.value_filtered(x, y, hit.p, footprint))
+            / sum
+    }
+}
+
+pub const TURBULENCE_DEPTH: usize = 7;
+
+pub struct NoiseTexture {
+    noise: Perlin,
+    scale: f64,
+    seed: u64,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64, seed: u64) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            scale,
+            seed,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        Color::one() * self.noise.turb(self.scale * p, TURBULENCE_DEPTH)
+    }
+
+    fn describe(&self) -> Option<TextureSpec> {
+        Some(TextureSpec::Desc(TextureDesc::Noise {
+            scale: self.scale,
+            seed: self.seed,
+        }))
+    }
+}
+
+pub struct MarbleTexture {
+    noise: Perlin,
+    scale: f64,
+    axis: Vec3,
+    seed: u64,
+}
+
+impl MarbleTexture {
+    pub fn new(scale: f64, axis: Vec3, seed: u64) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            scale,
+            axis: axis.normalize(),
+            seed,
+        }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let phase = self.scale * p.dot(self.axis) + 10.0 * self.noise.turb(p, TURBULENCE_DEPTH);
+        Color::one() * 0.5 * (1.0 + phase.sin())
+    }
+
+    fn describe(&self) -> Option<TextureSpec> {
+        Some(TextureSpec::Desc(TextureDesc::Marble {
+            scale: self.scale,
+            axis: self.axis.to_array(),
+            seed: self.seed,
+        }))
+    }
+}
+
+pub struct WorleyTexture {
+    noise: Worley,
+    scale: f64,
+    seed: u64,
+}
+
+impl WorleyTexture {
+    pub fn new(scale: f64, seed: u64) -> Self {
+        Self {
+            noise: Worley::new(seed),
+            scale,
+            seed,
+        }
+    }
+}
+
+impl Texture for WorleyTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        Color::one() * self.noise.distance(self.scale * p).min(1.0)
+    }
+
+    fn describe(&self) -> Option<TextureSpec> {
+        Some(TextureSpec::Desc(TextureDesc::Worley {
+            scale: self.scale,
+            seed: self.seed,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+
+impl WrapMode {
+    pub fn apply(self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        match self {
+            WrapMode::Clamp => i.clamp(0, n - 1) as usize,
+            WrapMode::Repeat => i.rem_euclid(n) as usize,
+            WrapMode::Mirror => {
+                // 2n 周期で折り返し、端の画素は二重に並ぶ
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m as usize
+                } else {
+                    (2 * n - 1 - m) as usize
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    Trilinear,
+}
+
+pub struct ImageData {
+    pixels: Vec<Color>,
+    width: usize,
+    height: usize,
+    // 2 段目以降のミップマップ (Trilinear で初めて参照されたときに作る)
+    mips: OnceLock<Vec<ImageData>>,
+}
+
+impl ImageData {
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let rgb_img = image::open(path)
+            .map_err(|e| Error::File(format!("{}: {}", path, e)))?
+            .to_rgb8();
+        let (w, h) = rgb_img.dimensions();
+        let mut image = vec![Color::zero(); (w * h) as usize];
+        for (i, (_, _, pixel)) in image.iter_mut().zip(rgb_img.enumerate_pixels()) {
+            *i = Color::from_rgb(pixel[0], pixel[1], pixel[2]);
+        }
+        Ok(Self {
+            pixels: image,
+            width: w as usize,
+            height: h as usize,
+            mips: OnceLock::new(),
+        })
+    }
+
+    // 読めなかった画像の代わり。1 画素のマゼンタにして目立たせる
+    pub fn missing() -> Self {
+        Self {
+            pixels: vec![MISSING_TEXTURE_COLOR],
+            width: 1,
+            height: 1,
+            mips: OnceLock::new(),
+        }
+    }
+
+    pub fn sample(&self, u: i64, v: i64, wrap: WrapMode) -> Color {
+        let tu = wrap.apply(u, self.width);
+        let tv = wrap.apply(v, self.height);
+        self.pixels[tu + self.width * tv]
+    }
+
+    // 2x2 画素の平均で半分の解像度にする (奇数幅の端は端の画素を繰り返す)
+    pub fn downsample(&self) -> Self {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let sum = self.sample(2 * x, 2 * y, WrapMode::Clamp)
+                    + self.sample(2 * x + 1, 2 * y, WrapMode::Clamp)
+                    + self.sample(2 * x, 2 * y + 1, WrapMode::Clamp)
+                    + self.sample(2 * x + 1, 2 * y + 1, WrapMode::Clamp);
+                pixels.push(sum / 4.0);
+            }
+        }
+        Self {
+            pixels,
+            width,
+            height,
+            mips: OnceLock::new(),
+        }
+    }
+
+    pub fn level_count(&self) -> usize {
+        1 + self.mips().len()
+    }
+
+    pub fn level(&self, level: usize) -> &Self {
+        if level == 0 {
+            self
+        } else {
+            &self.mips()[level - 1]
+        }
+    }
+
+    pub fn mips(&self) -> &[Self] {
+        self.mips.get_or_init(|| {
+            let mut mips = Vec::<Self>::new();
+            let mut last = self;
+            while last.width > 1 || last.height > 1 {
+                mips.push(last.downsample());
+                last = mips.last().unwrap();
+            }
+            mips
+        })
+    }
+
+    // u, v は [0, 1] の画像座標 (v は上から下)
+    pub fn filtered(&self, u: f64, v: f64, wrap: WrapMode, filter: FilterMode) -> Color {
+        let x = u * self.width as f64;
+        let y = v * self.height as f64;
+        match filter {
+            FilterMode::Nearest => self.sample(x.floor() as i64, y.floor() as i64, wrap),
+            FilterMode::Bilinear | FilterMode::Trilinear => {
+                // 画素の中心が整数 + 0.5 にあるので半画素ずらしてから補間する
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let top = self
+                    .sample(x0, y0, wrap)
+                    .lerp(self.sample(x0 + 1, y0, wrap), fx);
+                let bottom = self
+                    .sample(x0, y0 + 1, wrap)
+                    .lerp(self.sample(x0 + 1, y0 + 1, wrap), fx);
+                top.lerp(bottom, fy)
+            }
+        }
+    }
+}
+
+// デコードは別スレッドで進め、最初に参照されたときに待ち合わせる
+pub struct SharedImage {
+    path: String,
+    image: OnceLock<ImageData>,
+    loader: Mutex<Option<JoinHandle<Result<ImageData, Error>>>>,
+}
+
+impl SharedImage {
+    // ファイルが開けて形式がわかることだけをここで確かめ、デコードは後に回す
+    pub fn load(path: &str) -> Result<Self, Error> {
+        image::io::Reader::open(path)
+            .map_err(Error::from)
+            .and_then(|reader| Ok(reader.with_guessed_format()?.into_dimensions()?))
+            .map_err(|e| Error::File(format!("{}: {}", path, e)))?;
+        let path = path.to_string();
+        Ok(Self {
+            path: path.clone(),
+            image: OnceLock::new(),
+            // JPEG のデコーダは rayon を使う。共有のスレッドプールは描画で埋まっていて
+            // そこで待つと描画スレッドと互いに待ち合ってしまうので、専用のプールで読む
+            loader: Mutex::new(Some(thread::spawn(move || {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(1)
+                    .build()
+                    .map_err(|e| Error::File(format!("{}: {}", path, e)))?;
+                pool.install(|| ImageData::load(&path))
+            }))),
+        })
+    }
+
+    // 形式は確かめてあるので、ここで失敗するのは壊れたファイルだけ
+    // 描画の途中では返せないので、一度だけ知らせて MISSING_TEXTURE_COLOR で描く
+    pub fn get(&self) -> &ImageData {
+        self.image.get_or_init(|| {
+            let loader = self.loader.lock().unwrap().take();
+            let result = match loader.map(JoinHandle::join) {
+                Some(Ok(result)) => result,
+                _ => Err(Error::File(format!("{}: decoder failed", self.path))),
+            };
+            result.unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                ImageData::missing()
+            })
+        })
+    }
+}
+
+// 同じ画像ファイルは一度だけデコードして使い回す
+// どのテクスチャからも参照されなくなった画像は解放される
+pub(crate) struct TextureCache {
+    images: Mutex<HashMap<String, Weak<SharedImage>>>,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self {
+            images: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn global() -> &'static Self {
+        static CACHE: OnceLock<TextureCache> = OnceLock::new();
+        CACHE.get_or_init(Self::new)
+    }
+
+    pub fn get(&self, path: &str) -> Result<Arc<SharedImage>, Error> {
+        let mut images = self.images.lock().unwrap();
+        if let Some(image) = images.get(path).and_then(Weak::upgrade) {
+            return Ok(image);
+        }
+        let image = Arc::new(SharedImage::load(path)?);
+        images.insert(path.to_string(), Arc::downgrade(&image));
+        Ok(image)
+    }
+}
+
+pub struct ImageTexture {
+    image: Arc<SharedImage>,
+    wrap: WrapMode,
+    filter: FilterMode,
+}
+
+impl ImageTexture {
+    pub fn new(path: &str) -> Result<Self, Error> {
+        Self::with_wrap(path, WrapMode::Clamp)
+    }
+
+    pub fn with_wrap(path: &str, wrap: WrapMode) -> Result<Self, Error> {
+        Self::with_sampling(path, wrap, FilterMode::Nearest)
+    }
+
+    pub fn with_sampling(path: &str, wrap: WrapMode, filter: FilterMode) -> Result<Self, Error> {
+        let image = TextureCache::global().get(path)?;
+        Ok(Self::from_image(image, wrap, filter))
+    }
+
+    pub fn from_image(image: Arc<SharedImage>, wrap: WrapMode, filter: FilterMode) -> Self {
+        Self {
+            image,
+            wrap,
+            filter,
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.value_filtered(u, v, p, 0.0)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, _p: Point3, footprint: f64) -> Color {
+        let image = self.image.get();
+        if self.filter != FilterMode::Trilinear {
+            return image.filtered(u, 1.0 - v, self.wrap, self.filter);
+        }
+        // 1 画素が 1 テクセルに収まる段を選び、上下の段を補間する
+        let levels = image.level_count();
+        let texels = footprint * image.width.max(image.height) as f64;
+        let level = texels.max(1.0).log2().min((levels - 1) as f64);
+        let lower = level.floor() as usize;
+        let color = image
+            .level(lower)
+            .filtered(u, 1.0 - v, self.wrap, self.filter);
+        if lower + 1 < levels {
+            let upper = image
+                .level(lower + 1)
+                .filtered(u, 1.0 - v, self.wrap, self.filter);
+            color.lerp(upper, level - lower as f64)
+        } else {
+            color
+        }
+    }
+
+    // シーンファイルの画像テクスチャは既定の折り返しと補間だけ
+    fn describe(&self) -> Option<TextureSpec> {
+        (self.wrap == WrapMode::Clamp && self.filter == FilterMode::Nearest).then(|| {
+            TextureSpec::Desc(TextureDesc::Image {
+                path: self.image.path.clone(),
+            })
+        })
+    }
+}
+
+// 読めなかった画像やタイルの色
+pub const MISSING_TEXTURE_COLOR: Color = Color::new(1.0, 0.0, 1.0);
+
+pub const TEXTURE_TILE_SIZE: usize = 64;
+// タイルのキャッシュを分ける数。別々のタイルを読むスレッドは別々のロックを取る
+pub const TEXTURE_CACHE_SHARDS: usize = 16;
+
+struct LruEntry<K, V> {
+    key: K,
+    value: V,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+// 使った順に並べた双方向リストを Vec の中に持ち、参照も追加も追い出しも O(1) で行う
+struct Lru<K, V> {
+    entries: Vec<LruEntry<K, V>>,
+    index: HashMap<K, usize>,
+    // head が最近使ったもの、tail が最も古いもの
+    head: Option<usize>,
+    tail: Option<usize>,
+    capacity: usize,
+}
+
+impl<K: Copy + Eq + std::hash::Hash, V> Lru<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+            head: None,
+            tail: None,
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.entries[i].prev, self.entries[i].next);
+        match prev {
+            Some(prev) => self.entries[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.entries[next].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.entries[i].prev = None;
+        self.entries[i].next = self.head;
+        if let Some(head) = self.head {
+            self.entries[head].prev = Some(i);
+        }
+        self.head = Some(i);
+        if self.tail.is_none() {
+            self.tail = Some(i);
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let i = *self.index.get(key)?;
+        if self.head != Some(i) {
+            self.unlink(i);
+            self.push_front(i);
+        }
+        Some(&self.entries[i].value)
+    }
+
+    // 一杯なら最も古いものの場所を使い回す
+    fn insert(&mut self, key: K, value: V) {
+        let i = if self.entries.len() < self.capacity {
+            self.entries.push(LruEntry {
+                key,
+                value,
+                prev: None,
+                next: None,
+            });
+            self.entries.len() - 1
+        } else {
+            let i = self.tail.unwrap();
+            self.unlink(i);
+            self.index.remove(&self.entries[i].key);
+            self.entries[i].key = key;
+            self.entries[i].value = value;
+            i
+        };
+        self.index.insert(key, i);
+        self.push_front(i);
+    }
+}
+
+// タイルの位置から、読み込んだタイルの画素を引く
+type TileCache = Lru<(usize, usize), Arc<Vec<Color>>>;
+
+// 生の PPM (P6) はランダムアクセスできるので、参照されたタイルだけを読み込む
+// キャッシュはタイルごとに TEXTURE_CACHE_SHARDS 個に分け、それぞれで古いタイルから追い出す
+pub struct StreamedTexture {
+    path: String,
+    file: Mutex<File>,
+    width: usize,
+    height: usize,
+    // 1 成分のバイト数 (最大値が 255 以下なら 1、それより大きければ 2)
+    bytes: usize,
+    max_value: f64,
+    data_offset: u64,
+    shards: Vec<Mutex<TileCache>>,
+    wrap: WrapMode,
+    // 読めなかったタイルを知らせたか (何度も出さないように最初の 1 回だけ)
+    reported: AtomicBool,
+}
+
+impl StreamedTexture {
+    pub fn new(path: &str, max_tiles: usize) -> Result<Self, Error> {
+        let error = |message: String| Error::File(format!("{}: {}", path, message));
+        let mut file = File::open(path).map_err(|e| error(e.to_string()))?;
+        let mut header = [0; 512];
+        let len = file.read(&mut header).map_err(|e| error(e.to_string()))?;
+        let mut tokens = Vec::new();
+        let mut token = String::new();
+        let mut i = 0;
+        while tokens.len() < 4 && i < len {
+            let c = header[i] as char;
+            if c == '#' && token.is_empty() {
+                while i < len && header[i] != b'\n' {
+                    i += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            } else {
+                token.push(c);
+            }
+            i += 1;
+        }
+        if tokens.first().map(String::as_str) != Some("P6") || tokens.len() != 4 {
+            return Err(error(
+                "unsupported format, only binary PPM (P6) can be streamed".to_string(),
+            ));
+        }
+        let number = |token: &str| {
+            token
+                .parse::<usize>()
+                .map_err(|_| error(format!("bad number {:?}", token)))
+        };
+        let (width, height) = (number(&tokens[1])?, number(&tokens[2])?);
+        let max_value = number(&tokens[3])?;
+        if !(1..=65535).contains(&max_value) {
+            return Err(error(format!("unsupported max value {}", max_value)));
+        }
+        let bytes = if max_value < 256 { 1 } else { 2 };
+        // 途中で読めなくならないように、画素がすべてそろっているかを先に確かめる
+        let data_len = (width * height * 3 * bytes) as u64;
+        let file_len = file.metadata().map_err(|e| error(e.to_string()))?.len();
+        if file_len < i as u64 + data_len {
+            return Err(error("truncated pixel data".to_string()));
+        }
+        let max_tiles = max_tiles.max(1);
+        let shards = max_tiles.min(TEXTURE_CACHE_SHARDS);
+        Ok(Self {
+            path: path.to_string(),
+            file: Mutex::new(file),
+            width,
+            height,
+            bytes,
+            max_value: max_value as f64,
+            data_offset: i as u64,
+            shards: (0..shards)
+                .map(|_| Mutex::new(Lru::new(max_tiles / shards)))
+                .collect(),
+            wrap: WrapMode::Repeat,
+            reported: AtomicBool::new(false),
+        })
+    }
+
+    pub fn load_tile(&self, tx: usize, ty: usize) -> Result<Vec<Color>, Error> {
+        let x0 = tx * TEXTURE_TILE_SIZE;
+        let y0 = ty * TEXTURE_TILE_SIZE;
+        let w = TEXTURE_TILE_SIZE.min(self.width - x0);
+        let h = TEXTURE_TILE_SIZE.min(self.height - y0);
+        let mut row = vec![0; w * 3 * self.bytes];
+        let mut tile = Vec::with_capacity(w * h);
+        let mut file = self.file.lock().unwrap();
+        for y in y0..y0 + h {
+            let offset = self.data_offset + ((y * self.width + x0) * 3 * self.bytes) as u64;
+            // 大きさは開いたときに確かめてあるので、失敗するのは開いた後で書き換えられたときだけ
+            file.seek(SeekFrom::Start(offset))
+                .and_then(|_| file.read_exact(&mut row))
+                .map_err(|e| Error::File(format!("{}: {}", self.path, e)))?;
+            let sample = |c: &[u8]| match self.bytes {
+                1 => c[0] as f64,
+                _ => u16::from_be_bytes([c[0], c[1]]) as f64,
+            } / self.max_value;
+            tile.extend(row.chunks(3 * self.bytes).map(|c| {
+                let [r, g, b] = [0, 1, 2].map(|k| sample(&c[k * self.bytes..]));
+                Color::new(r, g, b)
+            }));
+        }
+        Ok(tile)
+    }
+
+    pub fn tile(&self, tx: usize, ty: usize) -> Arc<Vec<Color>> {
+        let tiles_x = self.width.div_ceil(TEXTURE_TILE_SIZE);
+        let shard = &self.shards[(ty * tiles_x + tx) % self.shards.len()];
+        if let Some(tile) = shard.lock().unwrap().get(&(tx, ty)) {
+            return Arc::clone(tile);
+        }
+        // 読み込む間は他のスレッドを止めない。同時に読んだときは後から入れたほうが残る
+        // 読めなかったタイルは MISSING_TEXTURE_COLOR で埋めてキャッシュし、読み直さない
+        let pixels = self.load_tile(tx, ty).unwrap_or_else(|e| {
+            if !self.reported.swap(true, Ordering::Relaxed) {
+                eprintln!("{}", e);
+            }
+            let w = TEXTURE_TILE_SIZE.min(self.width - tx * TEXTURE_TILE_SIZE);
+            let h = TEXTURE_TILE_SIZE.min(self.height - ty * TEXTURE_TILE_SIZE);
+            vec![MISSING_TEXTURE_COLOR; w * h]
+        });
+        let pixels = Arc::new(pixels);
+        shard.lock().unwrap().insert((tx, ty), Arc::clone(&pixels));
+        pixels
+    }
+
+    // キャッシュに残っているタイルの数
+    pub fn cached_tiles(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().len()).sum()
+    }
+}
+
+impl Texture for StreamedTexture {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        let x = self
+            .wrap
+            .apply((u * self.width as f64).floor() as i64, self.width);
+        let y = self
+            .wrap
+            .apply(((1.0 - v) * self.height as f64).floor() as i64, self.height);
+        let tile = self.tile(x / TEXTURE_TILE_SIZE, y / TEXTURE_TILE_SIZE);
+        let tile_width =
+            TEXTURE_TILE_SIZE.min(self.width - x / TEXTURE_TILE_SIZE * TEXTURE_TILE_SIZE);
+        tile[(x % TEXTURE_TILE_SIZE) + tile_width * (y % TEXTURE_TILE_SIZE)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 一時ディレクトリに書いた画像。テストごとに名前を分ける
+    fn temp_file(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("rayt_{}_{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    // 赤に x、緑に y を入れた w x h の 8 ビット PPM
+    fn gradient_ppm(name: &str, w: usize, h: usize) -> String {
+        let mut bytes = format!("P6\n# test\n{} {}\n255\n", w, h).into_bytes();
+        for y in 0..h {
+            for x in 0..w {
+                bytes.extend([x as u8, y as u8, 0]);
+            }
+        }
+        temp_file(name, &bytes)
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let mut lru = Lru::new(2);
+        lru.insert(1, "a");
+        lru.insert(2, "b");
+        assert_eq!(lru.get(&1), Some(&"a"));
+        lru.insert(3, "c");
+        assert_eq!(lru.get(&2), None);
+        assert_eq!(lru.get(&1), Some(&"a"));
+        assert_eq!(lru.get(&3), Some(&"c"));
+        assert_eq!(lru.len(), 2);
+    }
+
+    #[test]
+    fn streamed_texture_reuses_cached_tiles() {
+        let texture = StreamedTexture::new(&gradient_ppm("hit.ppm", 128, 64), 4).unwrap();
+        let first = texture.tile(1, 0);
+        let second = texture.tile(1, 0);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(texture.cached_tiles(), 1);
+        assert_eq!(first[0], Color::from_rgb(64, 0, 0));
+    }
+
+    #[test]
+    fn streamed_texture_evicts_old_tiles() {
+        let texture = StreamedTexture::new(&gradient_ppm("evict.ppm", 128, 64), 1).unwrap();
+        let first = texture.tile(0, 0);
+        texture.tile(1, 0);
+        assert_eq!(texture.cached_tiles(), 1);
+        let reloaded = texture.tile(0, 0);
+        assert!(!Arc::ptr_eq(&first, &reloaded));
+        assert_eq!(first, reloaded);
+    }
+
+    #[test]
+    fn streamed_texture_samples_texels() {
+        let texture = StreamedTexture::new(&gradient_ppm("value.ppm", 100, 70), 4).unwrap();
+        // 右下のタイルは端で切れている
+        let (x, y) = (90, 66);
+        let u = (x as f64 + 0.5) / 100.0;
+        let v = 1.0 - (y as f64 + 0.5) / 70.0;
+        let color = texture.value(u, v, Point3::zero());
+        assert_eq!(color, Color::from_rgb(x, y, 0));
+    }
+
+    #[test]
+    fn streamed_texture_fills_unreadable_tiles() {
+        let path = gradient_ppm("truncated.ppm", 128, 64);
+        let texture = StreamedTexture::new(&path, 4).unwrap();
+        // 開いた後でファイルが短くなった
+        std::fs::write(&path, b"P6 128 64 255\n").unwrap();
+        let tile = texture.tile(1, 0);
+        assert!(tile.iter().all(|&c| c == MISSING_TEXTURE_COLOR));
+        assert!(Arc::ptr_eq(&tile, &texture.tile(1, 0)));
+    }
+
+    #[test]
+    fn shared_image_falls_back_when_decoding_fails() {
+        // ヘッダーは読めるが画素がない
+        let image = SharedImage::load(&temp_file("no_pixels.ppm", b"P6 4 4 255\n")).unwrap();
+        let data = image.get();
+        assert_eq!(data.level_count(), 1);
+        assert_eq!(data.sample(0, 0, WrapMode::Clamp), MISSING_TEXTURE_COLOR);
+    }
+
+    #[test]
+    fn streamed_texture_reads_16_bit_samples() {
+        let mut bytes = b"P6 1 1 65535\n".to_vec();
+        bytes.extend([0xff, 0xff, 0x00, 0x00, 0x80, 0x00]);
+        let texture = StreamedTexture::new(&temp_file("wide.ppm", &bytes), 1).unwrap();
+        let color = texture.value(0.5, 0.5, Point3::zero());
+        assert_eq!(color, Color::new(1.0, 0.0, 32768.0 / 65535.0));
+    }
+
+    #[test]
+    fn streamed_texture_rejects_unsupported_files() {
+        let ascii = temp_file("ascii.ppm", b"P3\n1 1\n255\n0 0 0\n");
+        assert!(matches!(
+            StreamedTexture::new(&ascii, 1),
+            Err(Error::File(_))
+        ));
+        let png = temp_file("image.png", b"\x89PNG\r\n\x1a\n");
+        assert!(matches!(StreamedTexture::new(&png, 1), Err(Error::File(_))));
+        let truncated = temp_file("truncated.ppm", b"P6\n2 2\n255\n\x00\x00\x00");
+        assert!(matches!(
+            StreamedTexture::new(&truncated, 1),
+            Err(Error::File(_))
+        ));
+    }
+
+    // 4 x 2 の gradient_ppm を読んだ ImageTexture
+    fn gradient_texture(name: &str, wrap: WrapMode, filter: FilterMode) -> ImageTexture {
+        ImageTexture::with_sampling(&gradient_ppm(name, 4, 2), wrap, filter).unwrap()
+    }
+
+    // その 4 x 2 の画像で y 行目の中心を通る v
+    fn row(y: f64) -> f64 {
+        1.0 - (y + 0.5) / 2.0
+    }
+
+    fn assert_close(a: Color, b: Color) {
+        assert!((a - b).near_zero(), "{:?} != {:?}", a, b);
+    }
+
+    // 赤が x / 255、緑が y / 255 の、画素の間も含めた値
+    fn gradient(x: f64, y: f64) -> Color {
+        Color::new(x, y, 0.0) / 255.0
+    }
+
+    #[test]
+    fn mirror_wrap_reflects_at_both_edges() {
+        assert_eq!(WrapMode::Mirror.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Mirror.apply(-2, 4), 1);
+        assert_eq!(WrapMode::Mirror.apply(4, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(5, 4), 2);
+        assert_eq!(WrapMode::Mirror.apply(8, 4), 0);
+        let texture = gradient_texture("mirror.ppm", WrapMode::Mirror, FilterMode::Bilinear);
+        let p = Point3::zero();
+        // 端で折り返すので、外側の値は内側に映した位置の値と同じ
+        let left = texture.value(-0.25, row(0.0), p);
+        assert_close(left, texture.value(0.25, row(0.0), p));
+        assert_close(left, gradient(0.5, 0.0));
+        let right = texture.value(1.25, row(0.0), p);
+        assert_close(right, texture.value(0.75, row(0.0), p));
+        assert_close(right, gradient(2.5, 0.0));
+        let nearest = gradient_texture("mirror_nearest.ppm", WrapMode::Mirror, FilterMode::Nearest);
+        assert_close(nearest.value(-0.2, row(1.0), p), gradient(0.0, 1.0));
+        assert_close(nearest.value(1.2, row(1.0), p), gradient(3.0, 1.0));
+    }
+
+    #[test]
+    fn bilinear_returns_texels_at_their_centers() {
+        let texture = gradient_texture("centers.ppm", WrapMode::Clamp, FilterMode::Bilinear);
+        for y in 0..2 {
+            for x in 0..4 {
+                let u = (x as f64 + 0.5) / 4.0;
+                let color = texture.value(u, row(y as f64), Point3::zero());
+                assert_close(color, gradient(x as f64, y as f64));
+            }
+        }
+    }
+
+    #[test]
+    fn bilinear_weights_between_and_at_edges() {
+        let p = Point3::zero();
+        let clamp = gradient_texture("weights.ppm", WrapMode::Clamp, FilterMode::Bilinear);
+        // 画素の中心から、横も縦も隣の画素へ 1/4 だけ寄った位置
+        assert_close(
+            clamp.value(1.75 / 4.0, 1.0 - 0.75 / 2.0, p),
+            gradient(1.25, 0.25),
+        );
+        // 画素の境目では両側を半分ずつ
+        assert_close(clamp.value(0.5, row(0.0), p), gradient(1.5, 0.0));
+        // 画像の端では、Clamp は端の画素そのもの、Repeat は反対側と混ぜる
+        assert_close(clamp.value(0.0, 1.0, p), gradient(0.0, 0.0));
+        assert_close(clamp.value(1.0, 0.0, p), gradient(3.0, 1.0));
+        let repeat = gradient_texture("weights_repeat.ppm", WrapMode::Repeat, FilterMode::Bilinear);
+        assert_close(repeat.value(0.0, row(0.0), p), gradient(1.5, 0.0));
+        assert_close(repeat.value(0.0, 1.0, p), gradient(1.5, 0.5));
+    }
+}
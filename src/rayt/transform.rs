@@ -0,0 +1,345 @@
+use crate::rayt::*;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// 行優先の 4x4 行列。m.0[i] が i 行目で、点や向きは列ベクトルとして右からかける
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Mat4(pub [[f64; 4]; 4]);
+
+impl Mat4 {
+    pub const IDENTITY: Self = Self([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    // glTF などの列優先の並び
+    pub fn from_cols(cols: [[f64; 4]; 4]) -> Self {
+        Self(cols).transpose()
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        let [x, y, z] = offset.to_array();
+        Self([
+            [1.0, 0.0, 0.0, x],
+            [0.0, 1.0, 0.0, y],
+            [0.0, 0.0, 1.0, z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotation(q: Quat) -> Self {
+        let [r0, r1, r2] = q.to_matrix();
+        Self([
+            [r0[0], r0[1], r0[2], 0.0],
+            [r1[0], r1[1], r1[2], 0.0],
+            [r2[0], r2[1], r2[2], 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling(scale: Vec3) -> Self {
+        let [x, y, z] = scale.to_array();
+        Self([
+            [x, 0.0, 0.0, 0.0],
+            [0.0, y, 0.0, 0.0],
+            [0.0, 0.0, z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let m = &self.0;
+        Self(std::array::from_fn(|i| std::array::from_fn(|j| m[j][i])))
+    }
+
+    // i 列目の上 3 成分。アフィン変換なら 0..3 は軸、3 は平行移動
+    pub fn column(&self, i: usize) -> Vec3 {
+        Vec3::new(self.0[0][i], self.0[1][i], self.0[2][i])
+    }
+
+    // 左上 3x3 の行列式。負なら裏返る (左手系になる) 変換
+    pub fn determinant3(&self) -> f64 {
+        self.column(0).cross(self.column(1)).dot(self.column(2))
+    }
+
+    // 部分ピボット付きの掃き出し法。特異なら None
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.0;
+        let mut inv = Self::IDENTITY.0;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let recip = a[col][col].recip();
+            for k in 0..4 {
+                a[col][k] *= recip;
+                inv[col][k] *= recip;
+            }
+            for row in (0..4).filter(|&row| row != col) {
+                let factor = a[row][col];
+                for k in 0..4 {
+                    a[row][k] -= factor * a[col][k];
+                    inv[row][k] -= factor * inv[col][k];
+                }
+            }
+        }
+        Some(Self(inv))
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let [x, y, z] = p.to_array();
+        self.column(0) * x + self.column(1) * y + self.column(2) * z + self.column(3)
+    }
+
+    // 平行移動を無視する
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let [x, y, z] = v.to_array();
+        self.column(0) * x + self.column(1) * y + self.column(2) * z
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl std::ops::Mul<Mat4> for Mat4 {
+    type Output = Self;
+    fn mul(self, rhs: Mat4) -> Self {
+        let (a, b) = (&self.0, &rhs.0);
+        Self(std::array::from_fn(|i| {
+            std::array::from_fn(|j| (0..4).map(|k| a[i][k] * b[k][j]).sum())
+        }))
+    }
+}
+
+// 行列とその逆行列の組。a * b は b を先に適用する
+// インスタンスや読み込んだモデル、カメラの配置で同じものを使う
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Transform {
+    matrix: Mat4,
+    inverse: Mat4,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            matrix: Mat4::IDENTITY,
+            inverse: Mat4::IDENTITY,
+        }
+    }
+
+    // 逆行列がなければ None
+    pub fn from_matrix(matrix: Mat4) -> Option<Self> {
+        Some(Self {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        Self {
+            matrix: Mat4::translation(offset),
+            inverse: Mat4::translation(-offset),
+        }
+    }
+
+    pub fn rotate(q: Quat) -> Self {
+        Self {
+            matrix: Mat4::rotation(q),
+            inverse: Mat4::rotation(q.conj()),
+        }
+    }
+
+    // 0 を含む拡大率は逆がないので None
+    pub fn scale(scale: Vec3) -> Option<Self> {
+        if scale.iter().any(|s| s == 0.0) {
+            return None;
+        }
+        Some(Self {
+            matrix: Mat4::scaling(scale),
+            inverse: Mat4::scaling(Vec3::one() / scale),
+        })
+    }
+
+    // 拡大縮小、回転、平行移動の順に適用する (glTF の TRS と同じ)
+    pub fn from_trs(translation: Vec3, rotation: Quat, scale: Vec3) -> Option<Self> {
+        Some(Self::translate(translation) * Self::rotate(rotation) * Self::scale(scale)?)
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn flips_handedness(&self) -> bool {
+        self.matrix.determinant3() < 0.0
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    // 法線には逆行列の転置をかける。長さは保たないので、必要なら呼び出し側で正規化する
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+
+    pub fn ray(&self, ray: &Ray) -> Ray {
+        ray.transform(|p| self.point(p), |d| self.vector(d))
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl std::ops::Mul<Transform> for Transform {
+    type Output = Self;
+    fn mul(self, rhs: Transform) -> Self {
+        Self {
+            matrix: self.matrix * rhs.matrix,
+            inverse: rhs.inverse * self.inverse,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_PI_2;
+
+    // f32 feature でも通るように、比べるのは 1e-5 まで
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_identity(m: Mat4) {
+        for (i, row) in m.0.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-5, "{:?}", m);
+            }
+        }
+    }
+
+    fn sample() -> Transform {
+        Transform::from_trs(
+            Vec3::new(1.0, -2.0, 3.0),
+            Quat::from_euler(0.3, -0.7, 1.1),
+            Vec3::new(2.0, 0.5, -1.5),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn composition_applies_the_right_hand_side_first() {
+        let translate = Transform::translate(Vec3::xaxis());
+        let scale = Transform::scale(Vec3::fill(2.0)).unwrap();
+        let p = Point3::xaxis();
+        assert_near((translate * scale).point(p), Point3::new(3.0, 0.0, 0.0));
+        assert_near((scale * translate).point(p), Point3::new(4.0, 0.0, 0.0));
+        let rotate = Transform::rotate(Quat::from_rot_z(FRAC_PI_2));
+        let moved = Transform::translate(Vec3::zaxis()) * rotate;
+        assert_near(moved.point(p), Point3::new(0.0, 1.0, 1.0));
+        let m = *sample().matrix() * Mat4::translation(Vec3::one());
+        assert_near(
+            m.transform_point(p),
+            sample().point(Mat4::translation(Vec3::one()).transform_point(p)),
+        );
+    }
+
+    #[test]
+    fn from_trs_scales_then_rotates_then_translates() {
+        let (t, r, s) = (
+            Vec3::new(1.0, -2.0, 3.0),
+            Quat::from_euler(0.3, -0.7, 1.1),
+            Vec3::new(2.0, 0.5, -1.5),
+        );
+        let p = Point3::new(0.5, 1.0, -2.0);
+        assert_near(sample().point(p), r.rotate(p * s) + t);
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let t = sample();
+        let p = Point3::new(0.5, 1.0, -2.0);
+        assert_near(t.inverse().point(t.point(p)), p);
+        assert_near(t.point(t.inverse().point(p)), p);
+        assert_identity(*t.matrix() * *t.inverse().matrix());
+        assert_identity(*(t * t.inverse()).matrix());
+        // 組み立てた逆行列と掃き出し法で求めた逆行列は同じ
+        let solved = Transform::from_matrix(*t.matrix()).unwrap();
+        assert_identity(*solved.inverse().matrix() * *t.matrix());
+    }
+
+    #[test]
+    fn singular_transforms_have_no_inverse() {
+        assert!(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        assert!(Transform::from_matrix(Mat4([[0.0; 4]; 4])).is_none());
+        assert!(Transform::scale(Vec3::new(1.0, 1.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn vectors_ignore_translation() {
+        let t = Transform::translate(Vec3::new(5.0, 6.0, 7.0));
+        assert_near(t.vector(Vec3::xaxis()), Vec3::xaxis());
+        let ray = t.ray(&Ray::new(Point3::zero(), Vec3::zaxis()));
+        assert_near(ray.origin, Point3::new(5.0, 6.0, 7.0));
+        assert_near(ray.direction, Vec3::zaxis());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        // 平面 x + y = 1 の法線と接線
+        let (n, tangent) = (Vec3::new(1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+        let t = Transform::scale(Vec3::new(2.0, 1.0, 1.0)).unwrap();
+        assert_near(t.normal(n), Vec3::new(0.5, 1.0, 0.0));
+        assert!(t.normal(n).dot(t.vector(tangent)).abs() < 1e-9);
+        // ただの vector では垂直でなくなる
+        assert!(t.vector(n).dot(t.vector(tangent)).abs() > 1.0);
+        let s = sample();
+        assert!(s.normal(n).dot(s.vector(tangent)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn mirroring_flips_handedness() {
+        assert!(Transform::scale(Vec3::new(-1.0, 1.0, 1.0))
+            .unwrap()
+            .flips_handedness());
+        assert!(sample().flips_handedness());
+        assert!(!Transform::rotate(Quat::from_rot_y(1.0)).flips_handedness());
+    }
+
+    #[test]
+    fn from_cols_reads_column_major() {
+        let m = Mat4::from_cols([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [4.0, 5.0, 6.0, 1.0],
+        ]);
+        assert_eq!(m, Mat4::translation(Vec3::new(4.0, 5.0, 6.0)));
+    }
+}
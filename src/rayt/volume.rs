@@ -0,0 +1,103 @@
+use crate::rayt::*;
+
+use std::path::Path;
+
+// 箱 min..max に並べた格子点の値 (煙のシミュレーション結果など)。箱の外は 0
+// HeterogeneousMedium の密度に使う
+pub struct VoxelGrid {
+    min: Point3,
+    max: Point3,
+    size: [usize; 3],
+    // x が一番速く変わり、次に y、z の順
+    data: Vec<f64>,
+}
+
+impl VoxelGrid {
+    pub fn new(min: Point3, max: Point3, size: [usize; 3], data: Vec<f64>) -> Result<Self, Error> {
+        if size.contains(&0) || data.len() != size.iter().product::<usize>() {
+            return Err(Error::Invalid(format!(
+                "voxel grid of {:?} needs {} values, got {}",
+                size,
+                size.iter().product::<usize>(),
+                data.len()
+            )));
+        }
+        Ok(Self {
+            min,
+            max,
+            size,
+            data,
+        })
+    }
+
+    fn at(&self, x: usize, y: usize, z: usize) -> f64 {
+        let [nx, ny, _] = self.size;
+        self.data[(z * ny + y) * nx + x]
+    }
+
+    // 格子点の間は 3 重線形補間する
+    pub fn sample(&self, p: Point3) -> f64 {
+        let (p, min, max) = (p.to_array(), self.min.to_array(), self.max.to_array());
+        let mut index = [0; 3];
+        let mut frac = [0.0; 3];
+        for i in 0..3 {
+            let f = (p[i] - min[i]) / (max[i] - min[i]) * (self.size[i] - 1) as f64;
+            if !(0.0..=(self.size[i] - 1) as f64).contains(&f) {
+                return 0.0;
+            }
+            index[i] = (f as usize).min(self.size[i].saturating_sub(2));
+            frac[i] = f - index[i] as f64;
+        }
+        let [x, y, z] = index;
+        let [nx, ny, nz] = self.size;
+        let (x1, y1, z1) = (
+            (x + 1).min(nx - 1),
+            (y + 1).min(ny - 1),
+            (z + 1).min(nz - 1),
+        );
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let [fx, fy, fz] = frac;
+        let c00 = lerp(self.at(x, y, z), self.at(x1, y, z), fx);
+        let c10 = lerp(self.at(x, y1, z), self.at(x1, y1, z), fx);
+        let c01 = lerp(self.at(x, y, z1), self.at(x1, y, z1), fx);
+        let c11 = lerp(self.at(x, y1, z1), self.at(x1, y1, z1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+}
+
+impl Texture for VoxelGrid {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        Color::fill(self.sample(p))
+    }
+}
+
+// Mitsuba の .vol (float32 の格子)。複数チャンネルなら最初のチャンネルを使う
+// "VOL" 3, encoding (1 = float32), xres, yres, zres, channels, 箱の min と max, 値の順に並ぶ
+pub fn load_voxel_grid(path: &str) -> Result<VoxelGrid, Error> {
+    let error = |e: &dyn std::fmt::Display| Error::File(format!("{}: {}", path, e));
+    let bytes = std::fs::read(Path::new(path)).map_err(|e| error(&e))?;
+    if bytes.len() < 48 || &bytes[..3] != b"VOL" || bytes[3] != 3 {
+        return Err(error(&"not a version 3 .vol file"));
+    }
+    let word = |i: usize| -> [u8; 4] { bytes[4 + 4 * i..8 + 4 * i].try_into().unwrap() };
+    let int = |i: usize| i32::from_le_bytes(word(i));
+    let float = |i: usize| f32::from_le_bytes(word(i)) as f64;
+    if int(0) != 1 {
+        return Err(error(&"only float32 encoding is supported"));
+    }
+    let size = [int(1), int(2), int(3)].map(|n| n.max(0) as usize);
+    let channels = int(4).max(1) as usize;
+    let min = Point3::new(float(5), float(6), float(7));
+    let max = Point3::new(float(8), float(9), float(10));
+    let count = size.iter().product::<usize>();
+    let body = &bytes[48..];
+    if body.len() < count * channels * 4 {
+        return Err(error(&format!("expected {} values", count * channels)));
+    }
+    let data = body
+        .chunks_exact(4 * channels)
+        .take(count)
+        .map(|value| f32::from_le_bytes(value[..4].try_into().unwrap()) as f64)
+        .collect();
+    VoxelGrid::new(min, max, size, data).map_err(|e| error(&e))
+}
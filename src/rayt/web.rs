@@ -0,0 +1,138 @@
+use crate::rayt::*;
+
+// スレッドもファイルも使わずに 1 タイルずつ 1 spp を足していく描画
+// ブラウザでは requestAnimationFrame ごとに step を呼び、rgba を canvas の ImageData に写す
+pub struct ProgressiveCanvas {
+    width: u32,
+    height: u32,
+    spp: usize,
+    // pass 番目のタイルを描く
+    render: Box<dyn Fn(u64, Tile) -> Vec<Color>>,
+    color: ColorConfig,
+    tiles: Vec<Tile>,
+    next: usize,
+    pass: u64,
+    sum: Vec<Color>,
+    rgba: Vec<u8>,
+}
+
+impl ProgressiveCanvas {
+    pub fn new(scene: impl SceneWithDepth + 'static) -> Self {
+        let (w, h) = (scene.width(), scene.height());
+        Self {
+            width: w,
+            height: h,
+            spp: scene.spp(),
+            render: Box::new(move |pass, tile| render_tile(&scene, w, h, 0, 1, pass, tile)),
+            color: ColorConfig::default(),
+            tiles: tiles(w, h),
+            next: 0,
+            pass: 0,
+            sum: vec![Color::zero(); (w * h) as usize],
+            rgba: [0, 0, 0, 255].repeat((w * h) as usize),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // 描き終えた pass の数
+    pub fn passes(&self) -> u64 {
+        self.pass
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    // 最大 count 個のタイルを描く。シーンの spp 回重ね終えていたら false
+    pub fn step(&mut self, count: usize) -> bool {
+        let w = self.width;
+        for _ in 0..count {
+            if self.pass as usize >= self.spp {
+                return false;
+            }
+            let tile = self.tiles[self.next];
+            let colors = (self.render)(self.pass, tile);
+            let (x0, y0, x1, _) = tile;
+            let tile_w = x1 - x0;
+            // 画素ごとに描いた回数で割るので、pass の途中でも明るさがそろう
+            let samples = (self.pass + 1) as f64;
+            for (i, color) in colors.into_iter().enumerate() {
+                let (x, y) = (x0 + i as u32 % tile_w, y0 + i as u32 / tile_w);
+                let index = (y * w + x) as usize;
+                self.sum[index] += color;
+                let [r, g, b] = self.color.to_display(self.sum[index] / samples).to_rgb();
+                self.rgba[4 * index..4 * index + 3].copy_from_slice(&[r, g, b]);
+            }
+            self.next += 1;
+            if self.next == self.tiles.len() {
+                self.next = 0;
+                self.pass += 1;
+            }
+        }
+        true
+    }
+}
+
+// JavaScript から呼ぶ API (web/index.html を参照)
+#[cfg(target_arch = "wasm32")]
+mod bindings {
+    use super::*;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen::Clamped;
+
+    #[wasm_bindgen]
+    pub struct WebRenderer {
+        canvas: ProgressiveCanvas,
+    }
+
+    #[wasm_bindgen]
+    impl WebRenderer {
+        // ファイルを読まずに組み立てられるシーン (random, simple, cornell) を描く
+        // 大きさと spp はシーンのまま
+        #[wasm_bindgen(constructor)]
+        pub fn new(scene: &str, seed: u32) -> Result<WebRenderer, JsError> {
+            let seed = Some(seed as u64);
+            let error = |e: Error| JsError::new(&e.to_string());
+            let canvas = match scene {
+                "random" => ProgressiveCanvas::new(RandomScene::new(seed).map_err(error)?),
+                "simple" => {
+                    ProgressiveCanvas::new(SimpleScene::new().map_err(error)?.with_seed(seed))
+                }
+                "cornell" => {
+                    ProgressiveCanvas::new(CornelBoxScene::new().map_err(error)?.with_seed(seed))
+                }
+                _ => return Err(JsError::new(&format!("unknown scene: {}", scene))),
+            };
+            Ok(Self { canvas })
+        }
+
+        pub fn width(&self) -> u32 {
+            self.canvas.width()
+        }
+
+        pub fn height(&self) -> u32 {
+            self.canvas.height()
+        }
+
+        pub fn passes(&self) -> u32 {
+            self.canvas.passes() as u32
+        }
+
+        // 続きがあれば true
+        pub fn step(&mut self, tiles: usize) -> bool {
+            self.canvas.step(tiles)
+        }
+
+        // new ImageData(renderer.pixels(), width, height) にそのまま渡せる RGBA
+        pub fn pixels(&self) -> Clamped<Vec<u8>> {
+            Clamped(self.canvas.rgba().to_vec())
+        }
+    }
+}
@@ -1,58 +1,438 @@
+use crate::rayt::{Overlay, Tile};
+
 use image::RgbImage;
-use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use std::borrow::Cow;
+
+pub fn draw_in_window(backup_filename: Option<&str>, pixels: RgbImage) -> minifb::Result<()> {
+    let (image_width, image_height) = pixels.dimensions();
+    let mut window = PreviewWindow::new(image_width, image_height)?;
+    window.update(&pixels)?;
+    window.wait(backup_filename, |_, _| {})
+}
+
+fn to_buffer(pixels: &RgbImage) -> Vec<u32> {
+    pixels
+        .pixels()
+        .map(|pixel| u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]))
+        .collect()
+}
+
+const MAX_ZOOM: usize = 32;
+const TILE_OUTLINE: u32 = 0x00ff_a030;
+
+// 描き終えていない所を暗くし、描いているタイルを枠で囲む
+fn draw_tiles(buffer: &[u32], width: usize, active: &[Tile], done: &[Tile]) -> Vec<u32> {
+    let mut shown = buffer
+        .iter()
+        .map(|&color| (color >> 1) & 0x007f_7f7f)
+        .collect::<Vec<_>>();
+    for &(x0, y0, x1, y1) in done {
+        for y in y0 as usize..y1 as usize {
+            let row = y * width + x0 as usize..y * width + x1 as usize;
+            shown[row.clone()].copy_from_slice(&buffer[row]);
+        }
+    }
+    for &(x0, y0, x1, y1) in active.iter().filter(|(x0, y0, x1, y1)| x0 < x1 && y0 < y1) {
+        let (x0, y0, x1, y1) = (x0 as usize, y0 as usize, x1 as usize, y1 as usize);
+        for x in x0..x1 {
+            shown[y0 * width + x] = TILE_OUTLINE;
+            shown[(y1 - 1) * width + x] = TILE_OUTLINE;
+        }
+        for y in y0..y1 {
+            shown[y * width + x0] = TILE_OUTLINE;
+            shown[y * width + x1 - 1] = TILE_OUTLINE;
+        }
+    }
+    shown
+}
+
+// これより動かさずに離した左ボタンはクリックとみなす
+const CLICK_SLOP: f32 = 3.0;
+
+// 拡大表示の状態。zoom 倍に拡大し、画像の (x, y) をウィンドウの左上に置く
+// ホイールでマウスの位置を中心に拡大縮小し、左ドラッグで動かす。0 キーで等倍に戻す
+#[derive(Debug, Clone, Copy)]
+struct Zoom {
+    zoom: usize,
+    x: f64,
+    y: f64,
+    last_mouse: Option<(f32, f32)>,
+    // 左ボタンを押してから動かした距離
+    dragged: f32,
+}
+
+impl Zoom {
+    fn new() -> Self {
+        Self {
+            zoom: 1,
+            x: 0.0,
+            y: 0.0,
+            last_mouse: None,
+            dragged: 0.0,
+        }
+    }
+
+    // クリックされたら、その画像上の画素を返す
+    fn handle_input(&mut self, window: &Window, width: usize, height: usize) -> Option<(u32, u32)> {
+        if window.is_key_pressed(Key::Key0, KeyRepeat::No) {
+            *self = Self::new();
+            return None;
+        }
+        if let (Some((_, scroll)), Some((mx, my))) = (
+            window.get_scroll_wheel(),
+            window.get_mouse_pos(MouseMode::Clamp),
+        ) {
+            let zoom = match scroll {
+                s if s > 0.0 => (self.zoom * 2).min(MAX_ZOOM),
+                s if s < 0.0 => (self.zoom / 2).max(1),
+                _ => self.zoom,
+            };
+            // マウスの下にある画素が動かないようにする
+            let (mx, my) = (mx as f64, my as f64);
+            self.x += mx / self.zoom as f64 - mx / zoom as f64;
+            self.y += my / self.zoom as f64 - my / zoom as f64;
+            self.zoom = zoom;
+        }
+        let mouse = match window.get_mouse_down(MouseButton::Left) {
+            true => window.get_mouse_pos(MouseMode::Pass),
+            false => None,
+        };
+        if let (Some((x, y)), Some((last_x, last_y))) = (mouse, self.last_mouse) {
+            self.x -= (x - last_x) as f64 / self.zoom as f64;
+            self.y -= (y - last_y) as f64 / self.zoom as f64;
+            self.dragged += (x - last_x).abs() + (y - last_y).abs();
+        }
+        let click = match (mouse, self.last_mouse) {
+            (None, Some((x, y))) if self.dragged < CLICK_SLOP => {
+                let px = (self.x as usize + x as usize / self.zoom).min(width - 1);
+                let py = (self.y as usize + y as usize / self.zoom).min(height - 1);
+                Some((px as u32, py as u32))
+            }
+            _ => None,
+        };
+        if mouse.is_none() {
+            self.dragged = 0.0;
+        }
+        self.last_mouse = mouse;
+        let zoom = self.zoom as f64;
+        self.x = self.x.clamp(0.0, width as f64 - width as f64 / zoom);
+        self.y = self.y.clamp(0.0, height as f64 - height as f64 / zoom);
+        click
+    }
+
+    // 最近傍で拡大したバッファ。等倍ならそのまま返す
+    fn apply<'a>(&self, buffer: &'a [u32], width: usize, height: usize) -> Cow<'a, [u32]> {
+        if self.zoom == 1 {
+            return Cow::Borrowed(buffer);
+        }
+        let (x0, y0) = (self.x as usize, self.y as usize);
+        let zoomed = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let sx = (x0 + x / self.zoom).min(width - 1);
+                let sy = (y0 + y / self.zoom).min(height - 1);
+                buffer[sy * width + sx]
+            })
+            .collect();
+        Cow::Owned(zoomed)
+    }
+}
+
+// 描画中のウィンドウでの操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewAction {
+    Continue,
+    // SPACE: 蓄積を打ち切って結果を眺める
+    Stop,
+    // ESC かウィンドウを閉じた: 打ち切って、そこまでの結果を書き出して終わる
+    Abort,
+    // R: 蓄積を捨てて描き直す
+    Restart,
+    // S: 今の途中経過を保存して続ける
+    Save,
+    // クリックした画素の経路を調べる
+    Inspect(u32, u32),
+}
+
+// 描画の途中経過を表示するウィンドウ
+pub struct PreviewWindow {
+    window: Option<Window>,
+    buffer: Vec<u32>,
+    width: usize,
+    height: usize,
+    zoom: Zoom,
+}
+
+impl PreviewWindow {
+    pub fn new(width: u32, height: u32) -> minifb::Result<Self> {
+        let (width, height) = (width as usize, height as usize);
+        let window = if cfg!(test) {
+            None
+        } else {
+            let mut window = Window::new(
+                "SPACE to stop, S to save, R to restart, ESC to exit",
+                width,
+                height,
+                WindowOptions {
+                    topmost: true,
+                    ..WindowOptions::default()
+                },
+            )?;
+            // Limit to max ~30 fps update here
+            window.limit_update_rate(Some(std::time::Duration::from_micros(16600 * 2)));
+            Some(window)
+        };
+        Ok(Self {
+            window,
+            buffer: vec![0; width * height],
+            width,
+            height,
+            zoom: Zoom::new(),
+        })
+    }
+
+    // 表示を pixels に差し替え、その間に押されたキーを返す
+    pub fn update(&mut self, pixels: &RgbImage) -> minifb::Result<PreviewAction> {
+        self.buffer = to_buffer(pixels);
+        self.present(None)
+    }
+
+    // pass の途中の表示。描いているタイル active を枠で囲み、描き終えていない所を暗くする
+    pub fn update_with_tiles(
+        &mut self,
+        pixels: &RgbImage,
+        active: &[Tile],
+        done: &[Tile],
+    ) -> minifb::Result<PreviewAction> {
+        self.buffer = to_buffer(pixels);
+        self.present(Some((active, done)))
+    }
+
+    fn present(&mut self, tiles: Option<(&[Tile], &[Tile])>) -> minifb::Result<PreviewAction> {
+        let Some(window) = self.window.as_mut() else {
+            return Ok(PreviewAction::Continue);
+        };
+        let overlaid;
+        let buffer = match tiles {
+            Some((active, done)) => {
+                overlaid = draw_tiles(&self.buffer, self.width, active, done);
+                &overlaid
+            }
+            None => &self.buffer,
+        };
+        let click = self.zoom.handle_input(window, self.width, self.height);
+        let shown = self.zoom.apply(buffer, self.width, self.height);
+        window.update_with_buffer(&shown, self.width, self.height)?;
+        let pressed = |key| window.is_key_pressed(key, KeyRepeat::No);
+        Ok(if !window.is_open() || window.is_key_down(Key::Escape) {
+            PreviewAction::Abort
+        } else if pressed(Key::Space) {
+            PreviewAction::Stop
+        } else if pressed(Key::R) {
+            PreviewAction::Restart
+        } else if pressed(Key::S) {
+            PreviewAction::Save
+        } else if let Some((x, y)) = click {
+            PreviewAction::Inspect(x, y)
+        } else {
+            PreviewAction::Continue
+        })
+    }
 
-pub fn draw_in_window(backup_filename: &str, pixels: RgbImage) -> minifb::Result<()> {
+    // 閉じられるまで最後の画像を表示する。D で前回の描画結果と切り替える
+    // W で左に今回、右に前回を並べて比べ、右ボタンのドラッグで境目を動かす
+    // 画素をクリックすると inspect に渡す
+    pub fn wait(
+        mut self,
+        backup_filename: Option<&str>,
+        mut inspect: impl FnMut(u32, u32),
+    ) -> minifb::Result<()> {
+        let Some(mut window) = self.window else {
+            return Ok(());
+        };
+        // 大きさが違う前回の結果は比べられないので読まない
+        let (width, height) = (self.width, self.height);
+        let backup_buffer = backup_filename
+            .and_then(|filename| image::open(filename).ok())
+            .map(|img| img.to_rgb8())
+            .filter(|img| img.dimensions() == (width as u32, height as u32))
+            .map(|img| to_buffer(&img));
+        let mut show_backup = false;
+        let mut wipe: Option<usize> = None;
+
+        while window.is_open() && !window.is_key_down(Key::Escape) {
+            if backup_buffer.is_some() && window.is_key_pressed(Key::D, KeyRepeat::No) {
+                show_backup = !show_backup;
+                wipe = None;
+            }
+            if backup_buffer.is_some() && window.is_key_pressed(Key::W, KeyRepeat::No) {
+                wipe = match wipe {
+                    Some(_) => None,
+                    None => Some(width / 2),
+                };
+                show_backup = false;
+            }
+            if let (Some(split), true) = (&mut wipe, window.get_mouse_down(MouseButton::Right)) {
+                if let Some((x, _)) = window.get_mouse_pos(MouseMode::Clamp) {
+                    *split = (x as usize).min(width);
+                }
+            }
+
+            if let Some((x, y)) = self.zoom.handle_input(&window, width, height) {
+                inspect(x, y);
+            }
+            let current = self.zoom.apply(&self.buffer, width, height);
+            let shown = match (&backup_buffer, wipe, show_backup) {
+                (Some(backup), Some(split), _) => {
+                    let backup = self.zoom.apply(backup, width, height);
+                    Cow::Owned(wipe_buffers(&current, &backup, width, split))
+                }
+                (Some(backup), None, true) => self.zoom.apply(backup, width, height),
+                _ => current,
+            };
+            window.update_with_buffer(&shown, width, height)?;
+        }
+        Ok(())
+    }
+}
+
+const WIPE_LINE: u32 = 0x00ff_ffff;
+
+// 境目 split より左を a、右を b から取り、境目に線を引く
+fn wipe_buffers(a: &[u32], b: &[u32], width: usize, split: usize) -> Vec<u32> {
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .map(|(i, (&a, &b))| match (i % width).cmp(&split) {
+            std::cmp::Ordering::Less => a,
+            std::cmp::Ordering::Equal => WIPE_LINE,
+            std::cmp::Ordering::Greater => b,
+        })
+        .collect()
+}
+
+// フライスルーの 1 フレームの間の操作
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FlyInput {
+    // 押されているキーの向き (-1, 0, 1)
+    pub right: f64,
+    pub up: f64,
+    pub forward: f64,
+    // 左ボタンでドラッグした画素数 (右、下が正)
+    pub drag_x: f64,
+    pub drag_y: f64,
+}
+
+impl FlyInput {
+    pub fn is_idle(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// WASD か矢印キーで前後左右、E と Q で上下に動き、左ドラッグで向きを変える
+// render は毎フレームその間の操作を受け取って描く。ENTER で閉じたら true、ESC なら false
+pub fn draw_fly_through<R>(width: u32, height: u32, mut render: R) -> minifb::Result<bool>
+where
+    R: FnMut(FlyInput) -> RgbImage,
+{
     if cfg!(test) {
-        return Ok(());
+        return Ok(false);
     }
-    let (image_width, image_height) = pixels.dimensions();
-    let mut buffer: Vec<u32> = vec![0; (image_width * image_height) as usize];
+    let (width, height) = (width as usize, height as usize);
     let mut window = Window::new(
-        "ESC to exit",
-        image_width as usize,
-        image_height as usize,
+        "WASD/QE to move, drag to look, ENTER to render, ESC to exit",
+        width,
+        height,
         WindowOptions {
             topmost: true,
             ..WindowOptions::default()
         },
-    )
-    .unwrap_or_else(|e| panic!("{}", e));
-
-    // Limit to max ~30 fps update here
+    )?;
     window.limit_update_rate(Some(std::time::Duration::from_micros(16600 * 2)));
 
-    for (i, (_, _, pixel)) in buffer.iter_mut().zip(pixels.enumerate_pixels()) {
-        *i = u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]);
+    let mut last_mouse = None;
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        if window.is_key_pressed(Key::Enter, KeyRepeat::No) {
+            return Ok(true);
+        }
+        let axis = |plus: [Key; 2], minus: [Key; 2]| {
+            let down = |keys: [Key; 2]| keys.iter().any(|&key| window.is_key_down(key));
+            down(plus) as i32 as f64 - down(minus) as i32 as f64
+        };
+        let mut input = FlyInput {
+            right: axis([Key::D, Key::Right], [Key::A, Key::Left]),
+            up: axis([Key::E, Key::PageUp], [Key::Q, Key::PageDown]),
+            forward: axis([Key::W, Key::Up], [Key::S, Key::Down]),
+            ..FlyInput::default()
+        };
+        let mouse = match window.get_mouse_down(MouseButton::Left) {
+            true => window.get_mouse_pos(MouseMode::Pass),
+            false => None,
+        };
+        if let (Some((x, y)), Some((last_x, last_y))) = (mouse, last_mouse) {
+            input.drag_x = (x - last_x) as f64;
+            input.drag_y = (y - last_y) as f64;
+        }
+        last_mouse = mouse;
+        let pixels = render(input);
+        window.update_with_buffer(&to_buffer(&pixels), width, height)?;
     }
+    Ok(false)
+}
 
-    window.update_with_buffer(&buffer, image_width as usize, image_height as usize)?;
+const LOOK_DEV_PANEL_WIDTH: usize = 260;
+const LOOK_DEV_MIN_HEIGHT: usize = 360;
 
-    let mut backup_buffer: Option<Vec<u32>> = None;
-    let mut show_backup = false;
+// 左に描画結果、右に調整パネルを出す
+// render は蓄積をやり直すかどうかを受け取り、panel は値が変わったら true を返す
+pub fn draw_look_dev<R, P>(
+    width: u32,
+    height: u32,
+    mut render: R,
+    mut panel: P,
+) -> minifb::Result<()>
+where
+    R: FnMut(bool) -> RgbImage,
+    P: FnMut(&mut egui::Ui) -> bool,
+{
+    if cfg!(test) {
+        return Ok(());
+    }
+    let (image_width, image_height) = (width as usize, height as usize);
+    let window_width = image_width + LOOK_DEV_PANEL_WIDTH;
+    let window_height = image_height.max(LOOK_DEV_MIN_HEIGHT);
+    let mut buffer: Vec<u32> = vec![0; window_width * window_height];
+    let mut window = Window::new(
+        "ESC to exit",
+        window_width,
+        window_height,
+        WindowOptions {
+            topmost: true,
+            ..WindowOptions::default()
+        },
+    )?;
+    window.limit_update_rate(Some(std::time::Duration::from_micros(16600 * 2)));
 
+    let mut overlay = Overlay::new();
+    let mut restart = true;
     while window.is_open() && !window.is_key_down(Key::Escape) {
-        if window.is_key_pressed(Key::D, KeyRepeat::No) {
-            if backup_buffer.is_none() {
-                if let Ok(img) = image::open(backup_filename) {
-                    let backup_image = img.to_rgb8();
-                    let (w, h) = backup_image.dimensions();
-                    let mut buf = vec![0; (w * h) as usize];
-                    for (i, (_, _, pixel)) in buf.iter_mut().zip(backup_image.enumerate_pixels()) {
-                        *i = u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]);
-                    }
-                    backup_buffer = Some(buf);
-                }
-            }
-            show_backup = !show_backup;
-        }
-
-        let mut current_buffer = &buffer;
-        if show_backup {
-            if let Some(ref x) = backup_buffer {
-                current_buffer = x;
-            }
+        let pixels = render(restart);
+        buffer.fill(0);
+        for (x, y, pixel) in pixels.enumerate_pixels() {
+            buffer[x as usize + y as usize * window_width] =
+                u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]);
         }
-        window.update_with_buffer(current_buffer, image_width as usize, image_height as usize)?;
+        let mut changed = false;
+        overlay.run(&window, &mut buffer, window_width, window_height, |ctx| {
+            egui::SidePanel::right("look_dev")
+                .exact_width(LOOK_DEV_PANEL_WIDTH as f32)
+                .show(ctx, |ui| {
+                    egui::ScrollArea::vertical().show(ui, |ui| changed = panel(ui));
+                });
+        });
+        restart = changed;
+        window.update_with_buffer(&buffer, window_width, window_height)?;
     }
     Ok(())
 }
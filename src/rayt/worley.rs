@@ -0,0 +1,54 @@
+use crate::rayt::*;
+
+// セル状ノイズ (各セルに 1 つずつ特徴点を置く)
+// 特徴点はセルの座標と seed のハッシュで決まるので乱数の状態に依存しない
+pub struct Worley {
+    seed: u64,
+}
+
+impl Worley {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    fn hash(&self, cell: [i64; 3], salt: u64) -> u64 {
+        let mut z = self.seed
+            ^ (cell[0] as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ (cell[1] as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+            ^ (cell[2] as u64).wrapping_mul(0x1656_67b1_9e37_79f9)
+            ^ salt;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn feature_point(&self, cell: [i64; 3]) -> Point3 {
+        let unit = |salt| (self.hash(cell, salt) >> 11) as f64 / (1u64 << 53) as f64;
+        Point3::new(
+            cell[0] as f64 + unit(1),
+            cell[1] as f64 + unit(2),
+            cell[2] as f64 + unit(3),
+        )
+    }
+
+    // 最も近い特徴点までの距離
+    pub fn distance(&self, p: Point3) -> f64 {
+        let [x, y, z] = p.to_array().map(|x| x.floor() as i64);
+        let mut nearest = f64::MAX;
+        for i in x - 1..=x + 1 {
+            for j in y - 1..=y + 1 {
+                for k in z - 1..=z + 1 {
+                    let d = (self.feature_point([i, j, k]) - p).length_squared();
+                    nearest = nearest.min(d);
+                }
+            }
+        }
+        nearest.sqrt()
+    }
+}
+
+impl Default for Worley {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}